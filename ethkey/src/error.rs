@@ -10,4 +10,6 @@ pub enum Error {
     Secp256k1Error(#[from] secp256k1::Error),
     #[error("Serde JSON error: {0}")]
     SerdeJsonError(#[from] serde_json::Error),
+    #[error("Crypto error: {0}")]
+    CryptoError(String),
 }
@@ -5,6 +5,7 @@
 //!   * key serialization/deserialization
 //!   * keystore password change
 //!   * signing and verification
+//!   * encryption to a public key (ECIES)
 //!
 //! [geth]: https://github.com/ethereum/go-ethereum
 //! [parity]: https://github.com/paritytech/parity-ethereum
@@ -51,7 +52,9 @@ use ethsign::{
 };
 pub use ethsign::{PublicKey, SecretKey, Signature};
 use log::info;
+use parity_crypto::{aes, Keccak256};
 use rand::{thread_rng, RngCore};
+use secp256k1::{ecdh::SharedSecret, Secp256k1};
 
 pub use address::Address;
 
@@ -78,6 +81,8 @@ pub const KEYSTORE_VERSION: u64 = 3;
 /// [Secp256k1]: https://en.bitcoin.it/wiki/Secp256k1
 pub struct EthAccount {
     secret: SecretKey,
+    /// the same secret, for key agreement
+    ecdh_secret: secp256k1::SecretKey,
     public: PublicKey,
     address: Address,
     kestore_path: PathBuf,
@@ -109,6 +114,27 @@ impl EthAccount {
         Ok(self.public.verify(sig, msg)?)
     }
 
+    /// decrypts a message encrypted with [`encrypt`] to self public key
+    pub fn decrypt(&self, encrypted: &[u8]) -> Result<Vec<u8>> {
+        if encrypted.len() < ECIES_OVERHEAD {
+            return Err(Error::CryptoError("message too short".into()));
+        }
+        let (ephemeral, rest) = encrypted.split_at(65);
+        let (iv, rest) = rest.split_at(16);
+        let (ciphertext, mac) = rest.split_at(rest.len() - 32);
+
+        let ephemeral = secp256k1::PublicKey::from_slice(ephemeral)?;
+        let (ekey, mkey) = derive_keys(&ephemeral, &self.ecdh_secret);
+        if !constant_time_eq(&message_mac(&mkey, iv, ciphertext), mac) {
+            return Err(Error::CryptoError("invalid MAC".into()));
+        }
+
+        let mut plain = vec![0u8; ciphertext.len()];
+        aes::decrypt_128_ctr(&ekey, iv, ciphertext, &mut plain)
+            .map_err(|e| Error::CryptoError(e.to_string()))?;
+        Ok(plain)
+    }
+
     /// reads keys from disk or generates new ones and stores to disk; password needed
     pub fn load_or_generate<P, W>(file_path: P, password: W) -> Result<Box<Self>>
     where
@@ -116,22 +142,23 @@ impl EthAccount {
         W: Into<Password>,
     {
         let pwd = password.into();
-        let (secret, log_msg) = match File::open(&file_path) {
+        let (raw_secret, log_msg) = match File::open(&file_path) {
             Ok(file) => {
                 let key_file: KeyFile = serde_json::from_reader(file)?;
-                let secret = key_file.to_secret_key(&pwd)?;
-                (secret, "loaded")
+                (Protected::from(key_file.crypto.decrypt(&pwd)?), "loaded")
             }
             Err(_e) => {
-                let secret = SecretKey::from_raw(&random_bytes())?;
-                save_key(&secret, &file_path, pwd)?;
-                (secret, "generated and saved")
+                let raw_secret = Protected::from(random_bytes().to_vec());
+                save_key(&SecretKey::from_raw(raw_secret.as_ref())?, &file_path, pwd)?;
+                (raw_secret, "generated and saved")
             }
         };
+        let secret = SecretKey::from_raw(raw_secret.as_ref())?;
 
         let eth_account = EthAccount {
             address: secret.public().address().as_ref().into(),
             public: secret.public(),
+            ecdh_secret: secp256k1::SecretKey::from_slice(raw_secret.as_ref())?,
             secret,
             kestore_path: ::std::fs::canonicalize(file_path)?,
         };
@@ -172,6 +199,61 @@ where
     Ok(())
 }
 
+/// ephemeral public key, IV and MAC
+const ECIES_OVERHEAD: usize = 65 + 16 + 32;
+
+/// Encrypts the message to the public key.
+///
+/// The result is an ephemeral public key, AES-128-CTR IV, the ciphertext and
+/// a Keccak-256 MAC of the MAC key, IV and ciphertext. Encryption and MAC
+/// keys are the halves of Keccak-256 of the ECDH secret of the ephemeral
+/// secret and the recipient public key (SHA-256 of the compressed shared point).
+pub fn encrypt(public: &PublicKey, plain: &[u8]) -> Result<Vec<u8>> {
+    let secp = Secp256k1::new();
+    let mut uncompressed = [4u8; 65];
+    uncompressed[1..].copy_from_slice(&public.bytes()[..]);
+    let public = secp256k1::PublicKey::from_slice(&uncompressed)?;
+
+    let ephemeral_secret = secp256k1::SecretKey::from_slice(&random_bytes())?;
+    let ephemeral = secp256k1::PublicKey::from_secret_key(&secp, &ephemeral_secret);
+    let (ekey, mkey) = derive_keys(&public, &ephemeral_secret);
+    let mut iv = [0u8; 16];
+    thread_rng().fill_bytes(&mut iv);
+
+    let mut ciphertext = vec![0u8; plain.len()];
+    aes::encrypt_128_ctr(&ekey, &iv, plain, &mut ciphertext)
+        .map_err(|e| Error::CryptoError(e.to_string()))?;
+
+    let mut encrypted = Vec::with_capacity(ECIES_OVERHEAD + plain.len());
+    encrypted.extend_from_slice(&ephemeral.serialize_uncompressed()[..]);
+    encrypted.extend_from_slice(&iv);
+    encrypted.extend_from_slice(&ciphertext);
+    encrypted.extend_from_slice(&message_mac(&mkey, &iv, &ciphertext));
+    Ok(encrypted)
+}
+
+/// encryption and MAC keys
+fn derive_keys(
+    public: &secp256k1::PublicKey,
+    secret: &secp256k1::SecretKey,
+) -> ([u8; 16], [u8; 16]) {
+    let shared = SharedSecret::new(public, secret);
+    let shared: &[u8] = &shared[..];
+    let key: [u8; 32] = shared.keccak256();
+    let (mut ekey, mut mkey) = ([0u8; 16], [0u8; 16]);
+    ekey.copy_from_slice(&key[..16]);
+    mkey.copy_from_slice(&key[16..]);
+    (ekey, mkey)
+}
+
+fn message_mac(mkey: &[u8], iv: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    [mkey, iv, ciphertext].concat().keccak256()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 fn random_bytes() -> [u8; 32] {
     let mut secret = [0u8; 32];
     thread_rng().fill_bytes(&mut secret);
@@ -207,7 +289,7 @@ pub mod prelude {
     //!
     //! The prelude may grow over time.
 
-    pub use super::{Address, EthAccount, Password, PublicKey, SecretKey, Signature};
+    pub use super::{encrypt, Address, EthAccount, Password, PublicKey, SecretKey, Signature};
 }
 
 #[cfg(test)]
@@ -256,6 +338,25 @@ mod tests {
         assert_eq!(key0.public().bytes()[..], key1.public().bytes()[..]);
     }
 
+    #[test]
+    fn should_decrypt_encrypted() {
+        // given
+        let key = EthAccount::load_or_generate(&tmp_path(), "pwd").unwrap();
+        let message = b"registry credentials";
+
+        // when
+        let mut encrypted = encrypt(key.public(), message).unwrap();
+
+        // then
+        assert_eq!(key.decrypt(&encrypted).unwrap(), message.to_vec());
+
+        // when
+        encrypted[70] ^= 1;
+
+        // then
+        assert!(key.decrypt(&encrypted).is_err());
+    }
+
     #[test]
     fn should_not_generate_when_path_points_dir() {
         // given
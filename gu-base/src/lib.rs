@@ -11,6 +11,8 @@ extern crate tar;
 
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate log;
 extern crate env_logger;
 
 #[cfg(unix)]
@@ -25,6 +27,7 @@ pub mod daemon_lib;
 pub mod cli;
 pub mod empty;
 pub mod files;
+pub mod metrics;
 mod output;
 mod run_once;
 
@@ -0,0 +1,288 @@
+//! Operational metrics served at `/metrics` in the Prometheus text format.
+//!
+//! Subsystems register counters, gauges and summaries once (usually in a
+//! `lazy_static`) and update them without locking. A name registered with
+//! another type is logged and gets a series which is not exported.
+
+use actix_web::{App, HttpRequest, HttpResponse};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicIsize, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::Module;
+
+lazy_static! {
+    static ref REGISTRY: Mutex<BTreeMap<&'static str, Family>> = Mutex::new(BTreeMap::new());
+}
+
+/// Monotonically increasing value
+#[derive(Clone)]
+pub struct Counter(Arc<AtomicUsize>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1)
+    }
+
+    pub fn inc_by(&self, v: usize) {
+        self.0.fetch_add(v, Ordering::Relaxed);
+    }
+}
+
+/// Value that goes up and down
+#[derive(Clone)]
+pub struct Gauge(Arc<AtomicIsize>);
+
+impl Gauge {
+    pub fn set(&self, v: isize) {
+        self.0.store(v, Ordering::Relaxed)
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Count and total duration of observed events
+#[derive(Clone)]
+pub struct Summary {
+    /// microseconds
+    sum: Arc<AtomicUsize>,
+    count: Arc<AtomicUsize>,
+}
+
+impl Summary {
+    pub fn observe(&self, duration: Duration) {
+        let micros = duration.as_secs() as usize * 1_000_000 + duration.subsec_micros() as usize;
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone)]
+enum Series {
+    Counter(Counter),
+    Gauge(Gauge),
+    Summary(Summary),
+}
+
+impl Series {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Series::Counter(_) => "counter",
+            Series::Gauge(_) => "gauge",
+            Series::Summary(_) => "summary",
+        }
+    }
+}
+
+struct Family {
+    help: &'static str,
+    /// by rendered labels
+    series: BTreeMap<String, Series>,
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Returns the registered series or registers a new one; fails if the name
+/// is registered with another type
+fn series(
+    name: &'static str,
+    help: &'static str,
+    labels: &[(&str, &str)],
+    series: Series,
+) -> Result<Series, String> {
+    let mut registry = REGISTRY.lock().unwrap();
+    let family = registry.entry(name).or_insert_with(|| Family {
+        help,
+        series: BTreeMap::new(),
+    });
+    let other = family
+        .series
+        .values()
+        .map(Series::type_name)
+        .find(|type_name| *type_name != series.type_name());
+
+    if let Some(other) = other {
+        return Err(format!(
+            "metric {} registered as {} and {}",
+            name,
+            other,
+            series.type_name()
+        ));
+    }
+    Ok(family
+        .series
+        .entry(render_labels(labels))
+        .or_insert(series)
+        .clone())
+}
+
+/// Registered series, or the given one left unregistered on error
+fn series_or_detached(
+    name: &'static str,
+    help: &'static str,
+    labels: &[(&str, &str)],
+    new: fn() -> Series,
+) -> Series {
+    series(name, help, labels, new()).unwrap_or_else(|e| {
+        error!("{}", e);
+        new()
+    })
+}
+
+pub fn counter(name: &'static str, help: &'static str) -> Counter {
+    labeled_counter(name, help, &[])
+}
+
+pub fn labeled_counter(name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Counter {
+    match series_or_detached(name, help, labels, || {
+        Series::Counter(Counter(Arc::new(AtomicUsize::new(0))))
+    }) {
+        Series::Counter(counter) => counter,
+        _ => unreachable!(),
+    }
+}
+
+pub fn gauge(name: &'static str, help: &'static str) -> Gauge {
+    labeled_gauge(name, help, &[])
+}
+
+pub fn labeled_gauge(name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Gauge {
+    match series_or_detached(name, help, labels, || {
+        Series::Gauge(Gauge(Arc::new(AtomicIsize::new(0))))
+    }) {
+        Series::Gauge(gauge) => gauge,
+        _ => unreachable!(),
+    }
+}
+
+pub fn summary(name: &'static str, help: &'static str) -> Summary {
+    labeled_summary(name, help, &[])
+}
+
+pub fn labeled_summary(name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Summary {
+    match series_or_detached(name, help, labels, || {
+        Series::Summary(Summary {
+            sum: Arc::new(AtomicUsize::new(0)),
+            count: Arc::new(AtomicUsize::new(0)),
+        })
+    }) {
+        Series::Summary(summary) => summary,
+        _ => unreachable!(),
+    }
+}
+
+/// Renders all registered metrics
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    for (name, family) in registry.iter() {
+        let type_name = match family.series.values().next() {
+            Some(series) => series.type_name(),
+            None => continue,
+        };
+        let _ = writeln!(out, "# HELP {} {}", name, family.help);
+        let _ = writeln!(out, "# TYPE {} {}", name, type_name);
+
+        for (labels, series) in family.series.iter() {
+            let _ = match series {
+                Series::Counter(Counter(v)) => {
+                    writeln!(out, "{}{} {}", name, labels, v.load(Ordering::Relaxed))
+                }
+                Series::Gauge(Gauge(v)) => {
+                    writeln!(out, "{}{} {}", name, labels, v.load(Ordering::Relaxed))
+                }
+                Series::Summary(Summary { sum, count }) => writeln!(
+                    out,
+                    "{}_sum{} {}\n{}_count{} {}",
+                    name,
+                    labels,
+                    sum.load(Ordering::Relaxed) as f64 / 1e6,
+                    name,
+                    labels,
+                    count.load(Ordering::Relaxed)
+                ),
+            };
+        }
+    }
+
+    out
+}
+
+struct MetricsModule;
+
+impl Module for MetricsModule {
+    fn decorate_webapp<S: 'static>(&self, app: App<S>) -> App<S> {
+        app.resource("/metrics", |r| {
+            r.get().f(|_: &HttpRequest<S>| {
+                HttpResponse::Ok()
+                    .content_type("text/plain; version=0.0.4")
+                    .body(render())
+            })
+        })
+    }
+}
+
+pub fn module() -> impl Module {
+    MetricsModule
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let routed = labeled_counter("test_messages_total", "Messages", &[("kind", "a\"b")]);
+        routed.inc();
+        labeled_counter("test_messages_total", "Messages", &[("kind", "a\"b")]).inc_by(2);
+        gauge("test_peers", "Peers").set(3);
+        summary("test_latency_seconds", "Latency").observe(Duration::from_millis(1500));
+
+        let out = render();
+        assert!(out.contains(
+            "# HELP test_messages_total Messages\n# TYPE test_messages_total counter\n\
+             test_messages_total{kind=\"a\\\"b\"} 3\n"
+        ));
+        assert!(out.contains("# TYPE test_peers gauge\ntest_peers 3\n"));
+        assert!(out.contains("test_latency_seconds_sum 1.5\ntest_latency_seconds_count 1\n"));
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        counter("test_mismatch", "Mismatch").inc();
+        let gauge = gauge("test_mismatch", "Mismatch");
+        gauge.set(5);
+
+        assert!(series("test_mismatch", "Mismatch", &[], Series::Gauge(gauge)).is_err());
+        assert!(render().contains("# TYPE test_mismatch counter\ntest_mismatch 1\n"));
+    }
+}
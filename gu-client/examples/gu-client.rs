@@ -537,23 +537,29 @@ struct Crop {
     outfilebasename: String,
 }
 
-fn run_worker<S: Stream<Item = (Blob, BlenderTaskSpec), Error = Error>>(
+/// Renders frames taken from `frames`, then uploads all of them to
+/// `result_blob` at once and unpacks them into `output_path`
+fn run_worker<S: Stream<Item = BlenderTaskSpec, Error = Error>>(
     session: &PeerSession,
-    frame: S,
+    frames: S,
+    result_blob: Blob,
     output_path: PathBuf,
     logger: Addr<AsyncProgress>,
     docker_mode: bool,
 ) -> impl Future<Item = (), Error = Error> {
     let session = session.clone();
+    let upload_session = session.clone();
     let worker_id = session.node_id();
+    let output_dir = if docker_mode {
+        "golem/output"
+    } else {
+        "output"
+    };
 
-    frame
-        .for_each(move |(blob, spec)| {
+    frames
+        .and_then(move |spec| {
             let node_id = session.node_id();
-            let result_blob = blob.clone();
             let &frame = spec.frames.first().unwrap();
-            let outf = format!("output/outf_{:04}.png", frame);
-            let output_path = output_path.join(format!("frame_{:04}.png", frame));
             let logger = logger.clone();
 
             session
@@ -561,73 +567,62 @@ fn run_worker<S: Stream<Item = (Blob, BlenderTaskSpec), Error = Error>>(
                     vec![
                         Command::WriteFile {
                             file_path: "golem/resources/spec.json".to_owned(),
-                            content: serde_json::to_string(&spec).unwrap(),
+                            content: serde_json::to_string(&spec).unwrap().into(),
+                            mode: None,
                         },
                         Command::Open,
                         Command::Wait,
-                        Command::UploadFile {
-                            uri: blob.uri(),
-                            file_path: format!("golem/output/outf_{:04}.png", frame),
-                            format: ResourceFormat::Raw,
-                        },
                         Command::Close,
                     ]
                 } else {
                     vec![
                         Command::WriteFile {
                             file_path: "resources/spec.json".to_owned(),
-                            content: serde_json::to_string(&spec).unwrap(),
+                            content: serde_json::to_string(&spec).unwrap().into(),
+                            mode: None,
                         },
                         Command::Exec {
                             executable: "./gu-render".into(),
                             args: Vec::new(),
                             working_dir: None,
                         },
-                        Command::UploadFile {
-                            uri: blob.uri(),
-                            file_path: outf.clone(),
-                            format: ResourceFormat::Raw,
-                        },
                     ]
                 })
                 .and_then(move |results| {
-                    use std::io::prelude::*;
-                    log::debug!(
-                        "{:?}, results={:?}, output_file={}",
-                        node_id,
-                        results,
-                        output_path.display()
-                    );
+                    log::debug!("{:?}, frame={}, results={:?}", node_id, frame, results);
 
                     if results
                         .iter()
                         .any(|s| s.starts_with("failed to execute command"))
                     {
-                        return future::Either::B(future::err(Error::Other(format!(
-                            "{:?}",
-                            results
-                        ))));
+                        return Err(Error::Other(format!("{:?}", results)));
                     }
-
-                    let mut f = match fs::OpenOptions::new()
-                        .create_new(true)
-                        .write(true)
-                        .open(output_path)
-                    {
-                        Ok(f) => f,
-                        Err(err) => return future::Either::B(future::err(err.into())),
-                    };
-
-                    future::Either::A(result_blob.download().for_each(move |b| {
-                        f.write_all(b.as_ref())?;
-                        Ok(())
-                    }))
-                })
-                .and_then(move |_| {
                     logger.do_send(MainSteps::Frame(frame));
-                    Ok(())
+                    Ok(format!("{}/outf_{:04}.png", output_dir, frame))
                 })
         })
+        .collect()
+        .and_then(move |patterns| {
+            if patterns.is_empty() {
+                return future::Either::B(future::ok(()));
+            }
+            // entries keep their session paths, e.g. `output/outf_0001.png`
+            future::Either::A(
+                upload_session
+                    .update(vec![Command::UploadFiles {
+                        uri: result_blob.uri(),
+                        patterns,
+                        exclude: Vec::new(),
+                        format: ResourceFormat::Tar,
+                    }])
+                    .and_then(move |_| result_blob.download().concat2())
+                    .and_then(move |tar| {
+                        tar::Archive::new(tar.as_ref())
+                            .unpack(&output_path)
+                            .map_err(Error::from)
+                    }),
+            )
+        })
         .then(move |r| match r {
             Ok(v) => Ok(log::debug!("done: {:?}: {:?}", v, worker_id)),
             Err(e) => Ok(log::error!("err: {:?}: {:?}", e, worker_id)),
@@ -708,6 +703,7 @@ fn blender_deployment_spec(
             image: Image {
                 url: "http://52.31.143.91/images/x86_64/linux/gu-blender.hdi".to_string(),
                 hash: "SHA1:213fad4e020ded42e6a949f61cb660cb69bc9845".to_string(),
+                mirrors: Vec::new(),
             },
             name: "".to_string(),
             tags: vec!["gu:render".into(), "gu:blender".into()],
@@ -724,6 +720,7 @@ fn blender_deployment_spec(
                     url: "prekucki/gu-render-blender".to_string(),
                     hash: "sha256:53d11e6866835986b625e9fb07aa73b31dc667da39fe04f56da0ef06a50e0083"
                         .to_string(),
+                    mirrors: Vec::new(),
                 },
                 name: "".to_string(),
                 tags: vec!["gu:render".into(), "gu:blender".into()],
@@ -900,12 +897,8 @@ fn render_task(
                 .map(move |_| blob.uri())
             })
         });
-        let tasks = future::join_all(
-            tasks
-                .map(|spec| session.new_blob().map(|blob| (blob, spec)))
-                .collect::<Vec<_>>(),
-        )
-        .and_then(|t| Ok(TaskList::new(t)));
+        let tasks = TaskList::new(tasks);
+        let results_session = session.clone();
 
         let peers_session: Handle<HubSession> = session.clone();
         let prepare_workers = peers.and_then(move |peers| {
@@ -926,8 +919,8 @@ fn render_task(
         // TODO: let mut frames_done = 0;
 
         prepare_workers
-            .join3(upload_blob, tasks)
-            .and_then(move |(workers, blob_uri, tasks)| {
+            .join(upload_blob)
+            .and_then(move |(workers, blob_uri)| {
                 log::debug!("workers={:?}, blob_id={:?}", workers, blob_uri);
 
                 let workers = futures::future::join_all(
@@ -942,6 +935,7 @@ fn render_task(
                                         "resources".into()
                                     },
                                     format: ResourceFormat::Tar,
+                                    hash: None,
                                 }])
                                 .then(|r| match r {
                                     Ok(_) => {
@@ -968,13 +962,19 @@ fn render_task(
                         // Scene downloaded to nodes.
                         futures::future::join_all(workers.into_iter().filter_map(|w| w).map(
                             move |w| {
-                                run_worker(
-                                    &w,
-                                    tasks.clone().map_err(|_| unreachable!()),
-                                    output.clone(),
-                                    mb.clone(),
-                                    docker_mode,
-                                )
+                                let tasks = tasks.clone();
+                                let output = output.clone();
+                                let mb = mb.clone();
+                                results_session.new_blob().and_then(move |result_blob| {
+                                    run_worker(
+                                        &w,
+                                        tasks.map_err(|_| unreachable!()),
+                                        result_blob,
+                                        output,
+                                        mb,
+                                        docker_mode,
+                                    )
+                                })
                             },
                         ))
                         .and_then(|_| {
@@ -86,6 +86,7 @@ fn main() {
                                     url: "http://52.31.143.91/images/gu-factor-linux.tar.gz"
                                         .to_string(),
                                     hash: "not_implemented".to_string(),
+                                    mirrors: Vec::new(),
                                 },
                                 name: "peer_session".to_string(),
                                 tags: vec![],
@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{env, str};
@@ -8,11 +10,12 @@ use futures::{future, prelude::*};
 use log::{debug, info};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use tokio_timer::Interval;
 use url::Url;
 
 use gu_actix::release::{AsyncRelease, Handle};
 use gu_model::{
-    deployment::DeploymentInfo,
+    deployment::{DeploymentInfo, DownloadProgress},
     envman,
     peers::PeerInfo,
     session::{self, BlobInfo, HubExistingSession, HubSessionSpec, Metadata},
@@ -25,6 +28,8 @@ use crate::error::Error;
 
 pub type HubSessionRef = Handle<HubSession>;
 
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
 /// Connection to a single hub.
 #[derive(Clone, Debug)]
 pub struct HubConnection {
@@ -496,7 +501,7 @@ impl Peer {
         future::Either::B(
             request
                 .send()
-                .timeout(Duration::from_secs(3600))
+                .timeout(envman::CREATE_SESSION_TIMEOUT)
                 .from_err()
                 .and_then(|response| {
                     if response.status() != http::StatusCode::CREATED {
@@ -514,6 +519,57 @@ impl Peer {
                 }),
         )
     }
+    /// creates new peer session reporting progress of the image download
+    ///
+    /// Pending deployments of the peer are polled for the download of the
+    /// session image until the session is created.
+    pub fn new_session_with_progress<Options: Serialize, F>(
+        &self,
+        session_info: envman::CreateSession<Options>,
+        progress: F,
+    ) -> impl Future<Item = PeerSession, Error = Error>
+    where
+        F: FnMut(DownloadProgress) + 'static,
+    {
+        let image_hash = session_info.image.hash.clone();
+        let provider = ProviderRef::from(self.clone());
+        let progress = Rc::new(RefCell::new(progress));
+
+        let poll = Interval::new_interval(PROGRESS_POLL_INTERVAL)
+            .map_err(|e| Error::Other(format!("timer: {}", e)))
+            .for_each(move |_| {
+                let image_hash = image_hash.clone();
+                let progress = progress.clone();
+
+                provider.deployments().then(move |r| {
+                    match r {
+                        Ok(deployments) => {
+                            if let Some(download) = deployments
+                                .into_iter()
+                                .filter_map(|deployment| deployment.info.download)
+                                .find(|download| download.image_hash == image_hash)
+                            {
+                                (*progress.borrow_mut())(download)
+                            }
+                        }
+                        Err(e) => debug!("cannot poll download progress: {}", e),
+                    }
+                    Ok(())
+                })
+            });
+
+        self.new_session(session_info)
+            .select2(poll)
+            .then(|r| match r {
+                Ok(future::Either::A((session, _))) => future::Either::A(future::ok(session)),
+                Err(future::Either::A((e, _))) => future::Either::A(future::err(e)),
+                // polling failed; wait for the session without progress
+                Ok(future::Either::B((_, session))) | Err(future::Either::B((_, session))) => {
+                    future::Either::B(session)
+                }
+            })
+    }
+
     /// gets peer information
     pub fn info(&self) -> impl Future<Item = PeerInfo, Error = Error> {
         let url = format!(
@@ -657,6 +713,11 @@ impl DeploymentRef {
         self.info.note.as_ref().map(AsRef::as_ref)
     }
 
+    /// image download progress of a pending deployment
+    pub fn download(&self) -> Option<&DownloadProgress> {
+        self.info.download.as_ref()
+    }
+
     pub fn delete(self) -> impl Future<Item = (), Error = Error> {
         let url = format!(
             "{}peers/{:?}/deployments/{}",
@@ -25,6 +25,9 @@ pub enum Error {
     #[fail(display = "Canceled")]
     Canceled,
 
+    #[fail(display = "content does not match hash {}", _0)]
+    HashMismatch(String),
+
     #[fail(display = "{}", _0)]
     Other(String),
 }
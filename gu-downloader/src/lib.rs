@@ -8,14 +8,21 @@ use actix::prelude::*;
 use actix_web::http::header;
 use derive_builder::*;
 use futures::prelude::*;
+use futures::unsync::mpsc;
 use futures_cpupool::CpuPool;
 
 use gu_actix::prelude::*;
+use gu_model::hash::{Error as HashError, ParsedHash};
 
 pub use self::error::Error;
-use self::sync_io::{CheckType, DownloadFile, LogMetadata, Proxy};
+use self::limit::RateMeter;
+pub use self::limit::{rate_limiter, RateLimit, RateLimiter, RateSchedule};
+use self::mirrors::{Mirror, Mirrors};
+use self::sync_io::{CheckType, DownloadFile, Proxy};
 
 mod error;
+mod limit;
+mod mirrors;
 mod sync_io;
 
 #[derive(Builder, Clone)]
@@ -28,29 +35,61 @@ pub struct DownloadOptions {
     chunk_timeout: time::Duration,
     #[builder(default = "3")]
     connections: u16,
+    #[builder(default = "limit::rate_limiter()")]
+    rate_limiter: RateLimiter,
+    #[builder(default, private, setter(name = "expected_hash_str"))]
+    expected_hash: Option<String>,
 }
 
+/// Downloads failing the hash check are restarted from scratch this many times
+const MAX_RESTARTS: u32 = 1;
+
 impl DownloadOptions {
     pub fn download(
         self,
         url: &str,
         dest_file: String,
     ) -> impl Stream<Item = ProgressStatus, Error = Error> {
-        download(self, url, dest_file)
+        download(self, vec![url.to_owned()], dest_file)
+    }
+
+    /// Downloads the same file from any of the urls.
+    ///
+    /// The first url identifies the download when resuming.
+    pub fn download_mirrors<I: IntoIterator<Item = String>>(
+        self,
+        urls: I,
+        dest_file: String,
+    ) -> impl Stream<Item = ProgressStatus, Error = Error> {
+        download(self, urls.into_iter().collect(), dest_file)
     }
 }
 
 impl DownloadOptionsBuilder {
+    /// Content is verified against the hash before the download completes
+    pub fn expected_hash(&mut self, hash: &ParsedHash) -> Result<&mut Self, HashError> {
+        let _ = hash.checker()?;
+        Ok(self.expected_hash_str(Some(hash.to_hash_str()?)))
+    }
+
     pub fn download(
         &self,
         url: &str,
         dest_file: String,
     ) -> impl Stream<Item = ProgressStatus, Error = Error> {
-        let url = url.to_owned();
+        self.download_mirrors(vec![url.to_owned()], dest_file)
+    }
+
+    pub fn download_mirrors<I: IntoIterator<Item = String>>(
+        &self,
+        urls: I,
+        dest_file: String,
+    ) -> impl Stream<Item = ProgressStatus, Error = Error> {
+        let urls: Vec<String> = urls.into_iter().collect();
 
         self.build()
             .into_future()
-            .and_then(move |o| Ok(o.download(&url, dest_file)))
+            .and_then(move |o| Ok(o.download_mirrors(urls, dest_file)))
             .map_err(|e| Error::Other(e.into()))
             .flatten_stream()
     }
@@ -67,7 +106,7 @@ trait ProgressReport: Clone {
 }
 
 fn download_chunk(
-    meta: Arc<LogMetadata>,
+    mirrors: Mirrors,
     options: Arc<DownloadOptions>,
     proxy: Proxy<DownloadFile>,
     chunk_nr: u32,
@@ -75,55 +114,133 @@ fn download_chunk(
     to: u64,
 ) -> impl Future<Item = Chunk, Error = Error> {
     use actix_web::{client, HttpMessage};
+    use bytes::BytesMut;
     use futures::future::{self, loop_fn, Loop};
     let limit = (to - from) as usize;
+    // every mirror gets a chance before retries run out
+    let retries = usize::from(options.connect_retry) + mirrors.len().max(1) - 1;
 
-    loop_fn(options.connect_retry, move |n_retries| {
-        let proxy = proxy.clone();
-        let meta = meta.clone();
-        let options = options.clone();
+    loop_fn(
+        (retries, Vec::new()),
+        move |(n_retries, mut tried): (usize, Vec<usize>)| {
+            let proxy = proxy.clone();
+            let options = options.clone();
+            let mirrors = mirrors.clone();
 
-        proxy
-            .with(move |df| df.check_chunk(chunk_nr))
-            .from_err()
-            .and_then(move |v| match v {
-                Ok(true) => {
-                    return future::Either::A(future::ok(Loop::Break(Chunk {
-                        chunk_nr,
-                        from,
-                        to,
-                    })));
-                }
-                _ => future::Either::B(
-                    client::get(&meta.url)
-                        .header(header::IF_RANGE, format!("{}", meta.to_if_range().unwrap()))
-                        .header(header::RANGE, format!("bytes={}-{}", from, to - 1))
-                        .finish()
-                        .unwrap()
-                        .send()
-                        .timeout(options.chunk_timeout)
-                        .map_err(|e| Error::Other(format!("{}", e)))
-                        .and_then(move |resp| {
-                            resp.body()
-                                .limit(limit)
-                                .map_err(|e| Error::Other(format!("resp: {}", e)))
-                        })
-                        .and_then(move |bytes| {
-                            proxy
-                                .with(move |df| df.add_chunk(from, to, bytes.as_ref()))
-                                .from_err()
-                        })
-                        .and_then(move |_| Ok(Loop::Break(Chunk { chunk_nr, from, to })))
-                        .or_else(move |e| {
-                            if n_retries > 0 {
-                                Ok(Loop::Continue(n_retries - 1))
-                            } else {
-                                Err(e)
+            proxy
+                .with(move |df| df.check_chunk(chunk_nr))
+                .from_err()
+                .and_then(move |v| match v {
+                    Ok(true) => {
+                        return future::Either::A(future::ok(Loop::Break(Chunk {
+                            chunk_nr,
+                            from,
+                            to,
+                            downloaded: false,
+                        })));
+                    }
+                    _ => {
+                        if tried.len() >= mirrors.len() {
+                            tried.clear();
+                        }
+                        let source = match mirrors.select(&tried) {
+                            Some(source) => source,
+                            None => {
+                                return future::Either::A(future::err(Error::Other(
+                                    "no mirror available".into(),
+                                )))
                             }
-                        }),
-                ),
-            })
-    })
+                        };
+                        tried.push(source.mirror);
+
+                        let mut request = client::get(&source.url);
+                        if let Some(if_range) = source.if_range {
+                            request.header(header::IF_RANGE, if_range);
+                        }
+                        let mirror = source.mirror;
+                        let chunk_timeout = options.chunk_timeout;
+
+                        future::Either::B(
+                            request
+                                .header(header::RANGE, format!("bytes={}-{}", from, to - 1))
+                                .finish()
+                                .into_future()
+                                .from_err()
+                                .and_then(move |request| {
+                                    request.send().timeout(chunk_timeout).from_err()
+                                })
+                                .and_then(|resp| {
+                                    if resp.status().is_success() {
+                                        Ok(resp)
+                                    } else {
+                                        Err(Error::Other(format!(
+                                            "invalid response status: {}",
+                                            resp.status()
+                                        )))
+                                    }
+                                })
+                                .and_then(move |resp| {
+                                    let rate_limiter = options.rate_limiter.clone();
+                                    resp.payload()
+                                        .map_err(|e| Error::Other(format!("resp: {}", e)))
+                                        .fold(
+                                            BytesMut::with_capacity(limit),
+                                            move |mut buf, bytes| {
+                                                if buf.len() + bytes.len() > limit {
+                                                    return future::Either::A(future::err(
+                                                        Error::Other(
+                                                            "resp: payload overflow".into(),
+                                                        ),
+                                                    ));
+                                                }
+                                                buf.extend_from_slice(bytes.as_ref());
+                                                future::Either::B(
+                                                    rate_limiter
+                                                        .acquire(bytes.len())
+                                                        .map(move |()| buf),
+                                                )
+                                            },
+                                        )
+                                })
+                                .and_then(move |bytes| {
+                                    if bytes.len() == limit {
+                                        Ok(bytes)
+                                    } else {
+                                        Err(Error::Other(format!(
+                                            "invalid chunk size: {}",
+                                            bytes.len()
+                                        )))
+                                    }
+                                })
+                                .and_then(move |bytes| {
+                                    proxy
+                                        .with(move |df| df.add_chunk(from, to, bytes.as_ref()))
+                                        .flatten_fut()
+                                })
+                                .then(move |r| match r {
+                                    Ok(()) => {
+                                        mirrors.success(mirror);
+                                        Ok(Loop::Break(Chunk {
+                                            chunk_nr,
+                                            from,
+                                            to,
+                                            downloaded: true,
+                                        }))
+                                    }
+                                    Err(e) => {
+                                        mirrors.failure(mirror);
+                                        if n_retries > 0 {
+                                            Ok(Loop::Continue((n_retries - 1, tried)))
+                                        } else {
+                                            Err(e)
+                                        }
+                                    }
+                                }),
+                        )
+                    }
+                })
+        },
+    )
 }
 
 pub struct UrlInfo {
@@ -196,33 +313,127 @@ fn check_url(url: &str) -> impl Future<Item = UrlInfo, Error = Error> {
     })
 }
 
+/// Mirror whose metadata identifies the download, with its size and
+/// validators, and mirrors serving content of that size
+type CheckedMirrors = (String, u64, CheckType, Vec<Mirror>);
+
+/*
+  Checks all mirrors; unavailable ones and ones serving content of different
+  size than the first available mirror are skipped.
+*/
+fn check_mirrors(urls: Vec<String>) -> impl Future<Item = CheckedMirrors, Error = Error> {
+    use futures::future;
+
+    future::join_all(
+        urls.into_iter()
+            .map(|url| check_url(&url).then(move |r| Ok::<_, Error>((url, r)))),
+    )
+    .and_then(select_mirrors)
+}
+
+fn select_mirrors(results: Vec<(String, Result<UrlInfo, Error>)>) -> Result<CheckedMirrors, Error> {
+    let mut first: Option<(String, u64, CheckType)> = None;
+    let mut mirrors = Vec::new();
+    let mut last_error = None;
+
+    for (url, result) in results {
+        let info = match result {
+            Ok(UrlInfo {
+                download_url,
+                size: Some(size),
+                check,
+            }) => (download_url, size, check),
+            Ok(_) => {
+                log::warn!("mirror {} skipped: unknown content length", url);
+                last_error = Some(Error::Other("unknown content length".into()));
+                continue;
+            }
+            Err(e) => {
+                log::warn!("mirror {} unavailable: {}", url, e);
+                last_error = Some(e);
+                continue;
+            }
+        };
+
+        match first {
+            Some((_, size, _)) if size != info.1 => {
+                log::warn!(
+                    "mirror {} skipped: size {} differs from {}",
+                    url,
+                    info.1,
+                    size
+                );
+                continue;
+            }
+            Some(_) => (),
+            None => first = Some((url, info.1, info.2.clone())),
+        }
+        mirrors.push(Mirror::new(info.0, info.2));
+    }
+
+    match first {
+        Some((url, size, check)) => Ok((url, size, check, mirrors)),
+        None => Err(last_error.unwrap_or_else(|| Error::Other("no download url".into()))),
+    }
+}
+
 fn download(
     options: DownloadOptions,
-    url: &str,
+    urls: Vec<String>,
     dest_file: String,
 ) -> impl Stream<Item = ProgressStatus, Error = Error> {
-    use futures::{prelude::*, stream, unsync::mpsc};
+    use futures::future::{loop_fn, Loop};
 
     let (tx, rx) = mpsc::unbounded();
+    let err_tx = tx.clone();
+    let options = Arc::new(options);
+
+    Arbiter::spawn(
+        loop_fn(0, move |restarts| {
+            download_once(options.clone(), urls.clone(), dest_file.clone(), tx.clone()).then(
+                move |r| match r {
+                    Err(Error::HashMismatch(ref hash)) if restarts < MAX_RESTARTS => {
+                        log::warn!("content does not match hash {}, restarting download", hash);
+                        Ok(Loop::Continue(restarts + 1))
+                    }
+                    Ok(()) => Ok(Loop::Break(())),
+                    Err(e) => Err(e),
+                },
+            )
+        })
+        .map_err(move |e| {
+            let _ = err_tx.unbounded_send(Err(e));
+        }),
+    );
+
+    rx.map_err(|e| Error::Other(format!("receiver error: {:?}", e)))
+        .and_then(|progress| progress)
+}
+
+fn download_once(
+    options: Arc<DownloadOptions>,
+    urls: Vec<String>,
+    dest_file: String,
+    tx: mpsc::UnboundedSender<Result<ProgressStatus, Error>>,
+) -> impl Future<Item = (), Error = Error> {
+    use futures::stream;
 
     let init_tx = tx.clone();
-    let mut end_tx = tx.clone();
-    let options = Arc::new(options);
+    let expected_hash = options.expected_hash.clone();
     let connections = options.connections;
-
-    let chunks_stream = check_url(url)
-        .and_then(|info| {
+    let init_rate_limiter = options.rate_limiter.clone();
+    check_mirrors(urls)
+        // validators of the mirror identify the download in the tracking
+        // file, so a resumed download is checked against the same server
+        .and_then(move |(file_url, size, check, mirrors)| {
+            let mirrors = Mirrors::new(mirrors);
             Proxy::new(cpu_pool(), move || {
-                DownloadFile::new(
-                    dest_file.as_ref(),
-                    &info.download_url,
-                    info.check,
-                    info.size.unwrap(),
-                )
+                DownloadFile::new(dest_file.as_ref(), &file_url, check, size)
             })
             .from_err()
+            .map(move |download_file| (download_file, mirrors))
         })
-        .and_then(move |download_file| {
+        .and_then(move |(download_file, mirrors)| {
             download_file
                 .with(|df: &mut DownloadFile| {
                     let meta = df.meta();
@@ -236,25 +447,31 @@ fn download(
                     (Arc::new(meta), v)
                 })
                 .and_then(move |(meta, chunks)| {
-                    let _ = init_tx.unbounded_send(ProgressStatus {
+                    let _ = init_tx.unbounded_send(Ok(ProgressStatus {
                         total_to_download: Some(meta.size),
                         downloaded_bytes: 0,
-                    });
-                    Ok((download_file, meta, chunks))
+                        rate: 0,
+                        rate_limit: init_rate_limiter.current_rate(),
+                    }));
+                    Ok((download_file, mirrors, meta, chunks))
                 })
                 .from_err()
         })
         .and_then(
-            move |(download_file, meta, chunks): (Proxy<DownloadFile>, _, _)| {
+            move |(download_file, mirrors, meta, chunks): (Proxy<DownloadFile>, Mirrors, _, _)| {
                 let init_progress = ProgressStatus {
                     total_to_download: Some(meta.size),
                     downloaded_bytes: 0,
+                    rate: 0,
+                    rate_limit: None,
                 };
                 let df = download_file.clone();
+                let rate_limiter = options.rate_limiter.clone();
+                let mut rate_meter = RateMeter::new();
 
                 stream::iter_ok(chunks.into_iter().map(move |(from, to, n)| {
                     download_chunk(
-                        meta.clone(),
+                        mirrors.clone(),
                         options.clone(),
                         download_file.clone(),
                         n,
@@ -264,26 +481,23 @@ fn download(
                 }))
                 .buffer_unordered(connections as usize)
                 .fold(init_progress, move |mut progress, chunk| {
+                    let now = time::Instant::now();
+                    // chunks found in the part file don't count into the rate
+                    if chunk.downloaded {
+                        rate_meter.add(chunk.to - chunk.from, now);
+                    }
                     progress.downloaded_bytes += chunk.to - chunk.from;
-                    let _ = tx.unbounded_send(progress.clone());
+                    progress.rate = rate_meter.rate(now);
+                    progress.rate_limit = rate_limiter.current_rate();
+                    let _ = tx.unbounded_send(Ok(progress.clone()));
                     Ok::<_, Error>(progress)
                 })
-                .and_then(|_| {
-                    df.close(|df| df.finish()).flatten_fut().and_then(move |_| {
-                        let _ = end_tx.close();
-                        Ok(())
-                    })
+                .and_then(move |_| {
+                    df.close(move |df| df.finish(expected_hash.as_ref().map(AsRef::as_ref)))
+                        .flatten_fut()
                 })
             },
-        );
-
-    Arbiter::spawn(
-        chunks_stream
-            .and_then(|_| Ok(()))
-            .map_err(|e| eprintln!("ERR: {}", e)),
-    );
-
-    rx.map_err(|e| Error::Other(format!("receiver error: {:?}", e)))
+        )
 }
 
 #[derive(Clone, Debug)]
@@ -291,12 +505,18 @@ struct Chunk {
     chunk_nr: u32,
     from: u64,
     to: u64,
+    /// false for chunks recovered from the part file
+    downloaded: bool,
 }
 
 #[derive(Clone, Debug)]
 pub struct ProgressStatus {
     pub downloaded_bytes: u64,
     pub total_to_download: Option<u64>,
+    /// bytes per second over the last few seconds
+    pub rate: u64,
+    /// bandwidth limit in force; bytes per second
+    pub rate_limit: Option<u64>,
 }
 
 #[cfg(test)]
@@ -313,5 +533,48 @@ mod test {
         assert_eq!(b.connect_retry, 5);
         assert_eq!(b.chunk_timeout, time::Duration::from_secs(120));
         assert_eq!(b.chunk_size, 3000);
+        assert_eq!(b.expected_hash, None);
+    }
+
+    #[test]
+    fn test_expected_hash() {
+        let hash =
+            ParsedHash::from_hash_bytes(b"SHA1:c04e69c52dc35d93389a23189c333d150cadd719").unwrap();
+        let b: DownloadOptions = DownloadOptionsBuilder::default()
+            .expected_hash(&hash)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            b.expected_hash,
+            Some("SHA1:c04e69c52dc35d93389a23189c333d150cadd719".into())
+        );
+
+        let hash = ParsedHash::from_hash_bytes(b"MD5:1234").unwrap();
+        assert!(DownloadOptionsBuilder::default()
+            .expected_hash(&hash)
+            .is_err());
+    }
+
+    #[test]
+    fn test_select_mirrors() {
+        let info = |url: &str, size, etag: &str| UrlInfo {
+            download_url: url.into(),
+            size: Some(size),
+            check: CheckType::ETag(etag.into()),
+        };
+        let (url, size, check, mirrors) = select_mirrors(vec![
+            ("http://a/image".into(), Err(Error::Other("down".into()))),
+            ("http://b/image".into(), Ok(info("http://b/image", 10, "b"))),
+            ("http://c/image".into(), Ok(info("http://c/image", 11, "c"))),
+        ])
+        .unwrap();
+
+        assert_eq!(url, "http://b/image");
+        assert_eq!(size, 10);
+        assert_eq!(check, CheckType::ETag("b".into()));
+        assert_eq!(mirrors.len(), 1);
+        assert!(select_mirrors(Vec::new()).is_err());
     }
 }
@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::NaiveTime;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio_timer::Delay;
+
+use super::Error;
+
+/// Time of day window with its own rate limit
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateSchedule {
+    /// local time; "HH:MM"
+    #[serde(with = "hh_mm")]
+    pub from: NaiveTime,
+    /// local time; windows ending before they start span midnight
+    #[serde(with = "hh_mm")]
+    pub to: NaiveTime,
+    /// bytes per second; no limit if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<u64>,
+}
+
+impl RateSchedule {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+/// Download bandwidth limit with optional time of day schedules
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    /// bytes per second outside of schedules; no limit if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<RateSchedule>,
+}
+
+impl RateLimit {
+    pub fn is_unlimited(&self) -> bool {
+        self.rate.is_none() && self.schedules.iter().all(|s| s.rate.is_none())
+    }
+
+    /// Rate in force at the given local time; the first matching schedule wins
+    pub fn rate_at(&self, time: NaiveTime) -> Option<u64> {
+        self.schedules
+            .iter()
+            .find(|s| s.contains(time))
+            .map(|s| s.rate)
+            .unwrap_or(self.rate)
+    }
+}
+
+struct Bucket {
+    limit: RateLimit,
+    /// available bytes; negative when downloads are ahead of the rate
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    /// Takes tokens for `bytes` and returns how long to hold the data back
+    fn take(&mut self, bytes: u64, rate: Option<u64>, now: Instant) -> Duration {
+        let elapsed = now.duration_since(self.last);
+        self.last = now;
+
+        let rate = match rate {
+            Some(rate) => rate.max(1) as f64,
+            None => {
+                self.tokens = 0.0;
+                return Duration::from_secs(0);
+            }
+        };
+
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        // bursts up to one second of transfer
+        self.tokens = (self.tokens + elapsed * rate).min(rate) - bytes as f64;
+
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_nanos((-self.tokens / rate * 1e9) as u64)
+        }
+    }
+}
+
+/// Token bucket shared by downloads
+#[derive(Clone)]
+pub struct RateLimiter {
+    inner: Arc<Mutex<Bucket>>,
+}
+
+lazy_static! {
+    static ref RATE_LIMITER: RateLimiter = RateLimiter::new(RateLimit::default());
+}
+
+/// Limiter shared by all downloads of the process (unless set otherwise
+/// in `DownloadOptions`)
+pub fn rate_limiter() -> RateLimiter {
+    RATE_LIMITER.clone()
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            inner: Arc::new(Mutex::new(Bucket {
+                limit,
+                tokens: 0.0,
+                last: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn set_limit(&self, limit: RateLimit) {
+        self.inner.lock().unwrap().limit = limit;
+    }
+
+    /// Rate in force now
+    pub fn current_rate(&self) -> Option<u64> {
+        self.inner
+            .lock()
+            .unwrap()
+            .limit
+            .rate_at(chrono::Local::now().time())
+    }
+
+    /// Resolves when `bytes` fit into the limit
+    pub fn acquire(&self, bytes: usize) -> impl Future<Item = (), Error = Error> {
+        let wait = {
+            let mut bucket = self.inner.lock().unwrap();
+            let rate = bucket.limit.rate_at(chrono::Local::now().time());
+            bucket.take(bytes as u64, rate, Instant::now())
+        };
+
+        if wait == Duration::from_secs(0) {
+            Either::A(future::ok(()))
+        } else {
+            Either::B(
+                Delay::new(Instant::now() + wait)
+                    .map_err(|e| Error::Other(format!("rate limiter timer: {}", e))),
+            )
+        }
+    }
+}
+
+/// Transfer rate over the last few seconds
+pub(super) struct RateMeter {
+    start: Instant,
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateMeter {
+    pub fn new() -> Self {
+        RateMeter {
+            start: Instant::now(),
+            window: Duration::from_secs(5),
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn add(&mut self, bytes: u64, now: Instant) {
+        self.samples.push_back((now, bytes));
+        while let Some(&(ts, _)) = self.samples.front() {
+            if now.duration_since(ts) > self.window {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Bytes per second
+    pub fn rate(&self, now: Instant) -> u64 {
+        let period = now.duration_since(self.start).min(self.window);
+        let millis = period.as_secs() * 1000 + u64::from(period.subsec_millis());
+        let bytes: u64 = self.samples.iter().map(|&(_, bytes)| bytes).sum();
+
+        bytes * 1000 / millis.max(1)
+    }
+}
+
+mod hh_mm {
+    use chrono::NaiveTime;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&time.format("%H:%M").to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveTime, D::Error> {
+        let s = String::deserialize(d)?;
+        NaiveTime::parse_from_str(&s, "%H:%M").map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms(h, m, 0)
+    }
+
+    #[test]
+    fn test_schedules() {
+        let limit: RateLimit = serde_json::from_str(
+            r#"{
+                "rate": 1000,
+                "schedules": [
+                    {"from": "08:00", "to": "17:00", "rate": 100},
+                    {"from": "22:00", "to": "06:00"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(limit.rate_at(time(12, 0)), Some(100));
+        assert_eq!(limit.rate_at(time(17, 0)), Some(1000));
+        assert_eq!(limit.rate_at(time(23, 30)), None);
+        assert_eq!(limit.rate_at(time(3, 0)), None);
+        assert!(!limit.is_unlimited());
+        assert!(RateLimit::default().is_unlimited());
+    }
+
+    #[test]
+    fn test_bucket() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            limit: RateLimit::default(),
+            tokens: 0.0,
+            last: start,
+        };
+
+        assert_eq!(bucket.take(500, None, start), Duration::from_secs(0));
+        assert_eq!(
+            bucket.take(500, Some(1000), start),
+            Duration::from_millis(500)
+        );
+        // the debt is paid off after half a second
+        assert_eq!(
+            bucket.take(1000, Some(1000), start + Duration::from_millis(500)),
+            Duration::from_secs(1)
+        );
+        // burst is limited to one second of transfer
+        assert_eq!(
+            bucket.take(1000, Some(1000), start + Duration::from_secs(10)),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn test_rate_meter() {
+        let mut meter = RateMeter::new();
+        let start = meter.start;
+
+        meter.add(1000, start + Duration::from_secs(1));
+        assert_eq!(meter.rate(start + Duration::from_secs(2)), 500);
+
+        meter.add(4000, start + Duration::from_secs(10));
+        assert_eq!(meter.rate(start + Duration::from_secs(10)), 800);
+    }
+}
@@ -0,0 +1,132 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::sync_io::CheckType;
+
+/// Download source with its health record
+#[derive(Debug)]
+pub(super) struct Mirror {
+    url: String,
+    check: CheckType,
+    successes: u32,
+    failures: u32,
+}
+
+impl Mirror {
+    pub fn new(url: String, check: CheckType) -> Self {
+        Mirror {
+            url,
+            check,
+            successes: 0,
+            failures: 0,
+        }
+    }
+
+    /// Estimated chunk success rate; unknown mirrors start at 0.5
+    fn score(&self) -> f64 {
+        f64::from(self.successes + 1) / f64::from(self.successes + self.failures + 2)
+    }
+}
+
+/// Chunk request target picked from mirrors
+pub(super) struct Source {
+    pub mirror: usize,
+    pub url: String,
+    pub if_range: Option<String>,
+}
+
+/// Mirrors of a single download shared by all its chunk requests
+#[derive(Clone)]
+pub(super) struct Mirrors {
+    inner: Rc<RefCell<Vec<Mirror>>>,
+}
+
+impl Mirrors {
+    pub fn new(mirrors: Vec<Mirror>) -> Self {
+        Mirrors {
+            inner: Rc::new(RefCell::new(mirrors)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.borrow().len()
+    }
+
+    /// Picks the healthiest mirror not tried yet for the chunk.
+    ///
+    /// Ties are resolved by the mirror order. Once all mirrors were tried,
+    /// all of them are available again.
+    pub fn select(&self, tried: &[usize]) -> Option<Source> {
+        let mirrors = self.inner.borrow();
+        let best = |skip_tried: bool| {
+            mirrors
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| !skip_tried || !tried.contains(idx))
+                .fold(
+                    None,
+                    |best: Option<(usize, &Mirror)>, (idx, mirror)| match best {
+                        Some((_, b)) if b.score() >= mirror.score() => best,
+                        _ => Some((idx, mirror)),
+                    },
+                )
+        };
+
+        best(true)
+            .or_else(|| best(false))
+            .map(|(idx, mirror)| Source {
+                mirror: idx,
+                url: mirror.url.clone(),
+                if_range: mirror.check.to_if_range().map(Into::into),
+            })
+    }
+
+    pub fn success(&self, mirror: usize) {
+        if let Some(m) = self.inner.borrow_mut().get_mut(mirror) {
+            m.successes += 1;
+        }
+    }
+
+    pub fn failure(&self, mirror: usize) {
+        if let Some(m) = self.inner.borrow_mut().get_mut(mirror) {
+            log::warn!("chunk download from {} failed", m.url);
+            m.failures += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mirrors() -> Mirrors {
+        Mirrors::new(vec![
+            Mirror::new("http://a/image".into(), CheckType::None),
+            Mirror::new("http://b/image".into(), CheckType::ETag("\"b\"".into())),
+        ])
+    }
+
+    #[test]
+    fn test_select_in_order() {
+        let m = mirrors();
+
+        assert_eq!(m.select(&[]).unwrap().mirror, 0);
+        assert_eq!(m.select(&[0]).unwrap().mirror, 1);
+        assert_eq!(m.select(&[0, 1]).unwrap().mirror, 0);
+        assert_eq!(m.select(&[0]).unwrap().if_range, Some("\"b\"".into()));
+    }
+
+    #[test]
+    fn test_select_by_score() {
+        let m = mirrors();
+
+        m.failure(0);
+        assert_eq!(m.select(&[]).unwrap().mirror, 1);
+
+        m.success(0);
+        m.success(0);
+        m.failure(1);
+        assert_eq!(m.select(&[]).unwrap().mirror, 0);
+        assert!(Mirrors::new(Vec::new()).select(&[]).is_none());
+    }
+}
@@ -9,6 +9,7 @@ use futures_cpupool::CpuPool;
 use serde::{Deserialize, Serialize};
 
 use gu_actix::safe::*;
+use gu_model::hash::{self, ContentChecker};
 
 use super::Error;
 
@@ -41,9 +42,9 @@ pub(super) struct LogMetadata {
     ts: chrono::DateTime<chrono::Utc>,
 }
 
-impl LogMetadata {
+impl CheckType {
     pub fn to_if_range(&self) -> Option<&str> {
-        match &self.check {
+        match self {
             CheckType::ETag(etag) => Some(etag.as_ref()),
             CheckType::ModTime(mod_time) => Some(mod_time.as_ref()),
             CheckType::None => None,
@@ -251,6 +252,27 @@ impl DownloadFile {
         Ok(())
     }
 
+    fn verify_hash(&mut self, expected_hash: &str) -> Result<bool, Error> {
+        let mut checker = hash::checker(expected_hash)
+            .map_err(|e| Error::Other(format!("invalid hash {}: {}", expected_hash, e)))?;
+        let mut buf = [0u8; 4096];
+        let mut left = self.meta.size;
+
+        self.inner.seek(io::SeekFrom::Start(0))?;
+        while left > 0 {
+            let n_bytes = if left < buf.len() as u64 {
+                left as usize
+            } else {
+                buf.len()
+            };
+            self.inner.read_exact(&mut buf[..n_bytes])?;
+            checker.update(&buf[..n_bytes]);
+            left -= n_bytes as u64;
+        }
+
+        Ok(checker.verify())
+    }
+
     pub fn check_chunk(&mut self, chunk_nr: u32) -> Result<bool, Error> {
         use crc::Hasher64;
 
@@ -297,7 +319,18 @@ impl DownloadFile {
         }
     }
 
-    pub fn finish(self) -> Result<(), Error> {
+    /// Verifies the content against `expected_hash` ("ALGO:hex") and moves
+    /// it into place. On mismatch the part file is removed, so the next
+    /// attempt starts from scratch.
+    pub fn finish(mut self, expected_hash: Option<&str>) -> Result<(), Error> {
+        if let Some(expected_hash) = expected_hash {
+            if !self.verify_hash(expected_hash)? {
+                drop(self.inner);
+                fs::remove_file(&self.temp_file_name)?;
+                return Err(Error::HashMismatch(expected_hash.into()));
+            }
+        }
+
         self.inner.set_len(self.meta.size)?;
         let file_name = self.meta.file_name;
         drop(self.inner);
@@ -1,3 +1,5 @@
+use std::{env, path::PathBuf};
+
 use actix::{Actor, ActorResponse, Addr, ArbiterService, Handler, Message, WrapFuture};
 use futures::Future;
 use hostname::get_hostname;
@@ -13,7 +15,17 @@ pub use crate::ram::{RamInfo, RamQuery};
 use crate::storage::storage_info;
 pub use crate::storage::{StorageInfo, StorageQuery};
 
-use super::gpuinfo::{gpu_count, GpuCount};
+use super::cpuinfo::cpu_info;
+pub use super::cpuinfo::CpuInfo;
+pub use super::gpuinfo::GpuInfo;
+use super::gpuinfo::{gpu_count, gpu_list, GpuCount};
+
+/// Root of procfs and sysfs; `GU_HARDWARE_ROOT` points it at another tree
+fn system_root() -> PathBuf {
+    env::var_os("GU_HARDWARE_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/"))
+}
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct HardwareQuery;
@@ -44,6 +56,10 @@ fn os_type() -> Option<OsType> {
 pub struct Hardware {
     #[serde(skip_serializing_if = "Option::is_none")]
     gpu: Option<GpuCount>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    gpus: Vec<GpuInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cpu: Option<CpuInfo>,
     #[serde(skip_serializing_if = "Option::is_none")]
     ram: Option<RamInfo>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -63,6 +79,14 @@ impl Hardware {
     pub fn os(&self) -> Option<&OsType> {
         self.os.as_ref()
     }
+
+    pub fn cpu(&self) -> Option<&CpuInfo> {
+        self.cpu.as_ref()
+    }
+
+    pub fn gpus(&self) -> &[GpuInfo] {
+        &self.gpus
+    }
 }
 
 impl Message for HardwareQuery {
@@ -72,6 +96,8 @@ impl Message for HardwareQuery {
 #[derive(Debug, Default)]
 pub struct HardwareActor {
     gpu_count: Option<GpuCount>,
+    gpus: Vec<GpuInfo>,
+    cpu: Option<CpuInfo>,
     hostname: Option<String>,
 }
 
@@ -81,9 +107,17 @@ impl Actor for HardwareActor {
     fn started(&mut self, ctx: &mut Self::Context) {
         ctx.bind::<HardwareQuery>(HardwareQuery::ID);
 
+        let root = system_root();
+
         self.gpu_count = gpu_count()
             .or_else(|e| Err(error!("gpu detection: {}", e)))
             .ok();
+        self.gpus = gpu_list(&root)
+            .or_else(|e| Err(error!("gpu detection: {}", e)))
+            .unwrap_or_default();
+        self.cpu = cpu_info(&root)
+            .or_else(|e| Err(error!("cpu detection: {}", e)))
+            .ok();
         self.hostname = get_hostname()
     }
 }
@@ -122,6 +156,8 @@ impl Handler<HardwareQuery> for HardwareActor {
     ) -> <Self as Handler<HardwareQuery>>::Result {
         let inner = InnerActor::from_registry();
         let gpu = self.gpu_count.clone();
+        let gpus = self.gpus.clone();
+        let cpu = self.cpu.clone();
         let hostname = self.hostname.clone();
 
         ActorResponse::r#async(
@@ -130,6 +166,8 @@ impl Handler<HardwareQuery> for HardwareActor {
                 .and_then(move |(ram, disk)| {
                     Ok(Hardware {
                         gpu,
+                        gpus,
+                        cpu,
                         ram,
                         disk,
                         os: os_type(),
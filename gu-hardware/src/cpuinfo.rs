@@ -0,0 +1,148 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CpuInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vendor: Option<String>,
+    architecture: String,
+    /// instruction set extensions, eg. "avx2" or "avx512f"
+    flags: Vec<String>,
+    logical_cores: usize,
+    physical_cores: usize,
+    /// MHz
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_frequency: Option<u32>,
+    /// MHz
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_frequency: Option<u32>,
+}
+
+impl CpuInfo {
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn flags(&self) -> &[String] {
+        &self.flags
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    pub fn logical_cores(&self) -> usize {
+        self.logical_cores
+    }
+
+    pub fn physical_cores(&self) -> usize {
+        self.physical_cores
+    }
+}
+
+/// Parses `/proc/cpuinfo` and cpufreq entries of sysfs under `root`
+pub fn cpu_info(root: &Path) -> Result<CpuInfo> {
+    let cpuinfo = fs::read_to_string(root.join("proc/cpuinfo")).map_err(Error::Io)?;
+    let mut info = parse_cpuinfo(&cpuinfo);
+
+    let freq = |name: &str| {
+        fs::read_to_string(root.join("sys/devices/system/cpu/cpu0/cpufreq").join(name))
+            .ok()
+            .and_then(|khz| khz.trim().parse::<u32>().ok())
+            .map(|khz| khz / 1000)
+    };
+    info.min_frequency = freq("cpuinfo_min_freq");
+    info.max_frequency = freq("cpuinfo_max_freq").or(info.max_frequency);
+
+    Ok(info)
+}
+
+fn parse_cpuinfo(cpuinfo: &str) -> CpuInfo {
+    let mut info = CpuInfo {
+        architecture: std::env::consts::ARCH.to_string(),
+        ..CpuInfo::default()
+    };
+    let mut cores = HashSet::new();
+    let mut physical_id = None;
+    let mut max_mhz: Option<f64> = None;
+
+    for line in cpuinfo.lines() {
+        let mut kv = line.splitn(2, ':');
+        let (key, value) = match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => continue,
+        };
+
+        match key {
+            "processor" => info.logical_cores += 1,
+            "model name" if info.model.is_none() => info.model = Some(value.to_string()),
+            "vendor_id" if info.vendor.is_none() => info.vendor = Some(value.to_string()),
+            "flags" if info.flags.is_empty() => {
+                info.flags = value.split_whitespace().map(String::from).collect();
+                info.flags.sort();
+            }
+            "physical id" => physical_id = Some(value.to_string()),
+            "core id" => {
+                cores.insert((physical_id.clone(), value.to_string()));
+            }
+            "cpu MHz" => {
+                if let Ok(mhz) = value.parse::<f64>() {
+                    max_mhz = Some(max_mhz.map_or(mhz, |max| max.max(mhz)));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // no topology in virtual machines and on some architectures
+    info.physical_cores = if cores.is_empty() {
+        info.logical_cores
+    } else {
+        cores.len()
+    };
+    info.max_frequency = max_mhz.map(|mhz| mhz.round() as u32);
+    info
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test-data/sysroot")
+    }
+
+    #[test]
+    fn test_cpu_info() {
+        let info = cpu_info(&fixture()).unwrap();
+
+        assert_eq!(
+            info.model(),
+            Some("Intel(R) Core(TM) i7-7700 CPU @ 3.60GHz")
+        );
+        assert_eq!(info.vendor, Some("GenuineIntel".into()));
+        assert_eq!(info.logical_cores(), 4);
+        assert_eq!(info.physical_cores(), 2);
+        assert!(info.has_flag("avx2"));
+        assert!(!info.has_flag("avx512f"));
+        assert_eq!(info.min_frequency, Some(800));
+        assert_eq!(info.max_frequency, Some(4200));
+    }
+
+    #[test]
+    fn test_no_topology() {
+        let info = parse_cpuinfo("processor\t: 0\ncpu MHz\t\t: 2399.998\n\nprocessor\t: 1\n");
+
+        assert_eq!(info.logical_cores(), 2);
+        assert_eq!(info.physical_cores(), 2);
+        assert_eq!(info.max_frequency, Some(2400));
+        assert!(info.flags().is_empty());
+    }
+}
@@ -16,8 +16,9 @@ pub struct PciDevices {
     inner: ReadDir,
 }
 
-pub fn pci_devices() -> io::Result<PciDevices> {
-    let inner = fs::read_dir("/sys/bus/pci/devices")?;
+/// Devices listed in sysfs under `root`
+pub fn pci_devices(root: &path::Path) -> io::Result<PciDevices> {
+    let inner = fs::read_dir(root.join("sys/bus/pci/devices"))?;
 
     Ok(PciDevices { inner })
 }
@@ -81,4 +82,40 @@ impl PciDevice {
     pub fn vendor_code(&self) -> io::Result<u16> {
         self.decode_hex("vendor").and_then(|code| Ok(code as u16))
     }
+
+    pub fn device_code(&self) -> io::Result<u16> {
+        self.decode_hex("device").and_then(|code| Ok(code as u16))
+    }
+
+    pub fn is_gpu(&self) -> bool {
+        match self.class_code() {
+            Ok(code) => code == CL_DEVICE_TYPE_GPU || code == CL_DEVICE_TYPE_ACCELERATOR,
+            Err(_) => false,
+        }
+    }
+
+    /// PCI address, eg. "0000:01:00.0"
+    pub fn slot(&self) -> String {
+        self.inner
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Name of the bound kernel driver
+    pub fn driver(&self) -> Option<String> {
+        fs::read_link(self.inner.join("driver"))
+            .ok()
+            .and_then(|link| {
+                link.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+    }
+
+    /// Video memory in bytes; exposed by the amdgpu driver only
+    pub fn memory(&self) -> Option<u64> {
+        fs::read_to_string(self.inner.join("mem_info_vram_total"))
+            .ok()
+            .and_then(|bytes| bytes.trim().parse().ok())
+    }
 }
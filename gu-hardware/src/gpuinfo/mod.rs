@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use serde::{Deserialize, Serialize};
 
 use super::error::Result;
@@ -13,10 +15,31 @@ pub struct GpuCount {
     pub other: u8,
 }
 
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GpuInfo {
+    /// PCI address, eg. "0000:01:00.0"
+    pub pci_id: String,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    /// bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
+}
+
 #[cfg(target_os = "linux")]
-#[cfg(not(feature = "clinfo"))]
+#[cfg_attr(feature = "clinfo", allow(dead_code))]
 mod linux_pci_scan;
 
+#[cfg(target_os = "linux")]
+mod pci_ids;
+
 #[cfg(feature = "clinfo")]
 mod clinfo;
 
@@ -25,13 +48,10 @@ mod clinfo;
 pub fn gpu_count() -> Result<GpuCount> {
     use self::linux_pci_scan::*;
 
-    Ok(pci_devices()
+    Ok(pci_devices(Path::new("/"))
         .map_err(|e| super::error::Error::Io(e))?
         .filter_map(|device_ref| device_ref.ok())
-        .filter(|device| match device.class_code() {
-            Ok(code) => code == CL_DEVICE_TYPE_GPU || code == CL_DEVICE_TYPE_ACCELERATOR,
-            Err(_) => false,
-        })
+        .filter(|device| device.is_gpu())
         .fold(GpuCount::default(), |gpu, device| {
             match device.vendor_code() {
                 Ok(VENDOR_CODE_AMD) => GpuCount {
@@ -90,6 +110,41 @@ pub fn gpu_count() -> Result<GpuCount> {
     // compile_error!("gpu detection supported only on ubuntu or with clinfo feature")
 }
 
+/// GPUs found in sysfs under `root`
+#[cfg(target_os = "linux")]
+pub fn gpu_list(root: &Path) -> Result<Vec<GpuInfo>> {
+    use self::linux_pci_scan::*;
+
+    let mut gpus: Vec<GpuInfo> = pci_devices(root)
+        .map_err(|e| super::error::Error::Io(e))?
+        .filter_map(|device_ref| device_ref.ok())
+        .filter(|device| device.is_gpu())
+        .filter_map(|device| {
+            let vendor_id = device.vendor_code().ok()?;
+            let device_id = device.device_code().ok()?;
+            let (vendor, name) = pci_ids::lookup(vendor_id, device_id);
+
+            Some(GpuInfo {
+                pci_id: device.slot(),
+                vendor_id,
+                device_id,
+                vendor: vendor.map(Into::into),
+                device: name.map(Into::into),
+                driver: device.driver(),
+                memory: device.memory(),
+            })
+        })
+        .collect();
+    gpus.sort_by(|a, b| a.pci_id.cmp(&b.pci_id));
+
+    Ok(gpus)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn gpu_list(_root: &Path) -> Result<Vec<GpuInfo>> {
+    Ok(Vec::new())
+}
+
 #[cfg(test)]
 mod test {
     #[cfg(any(target_os = "linux", feature = "clinfo"))]
@@ -100,4 +155,24 @@ mod test {
     fn test_gpu_count() {
         eprintln!("gpu={:?}", gpu_count().unwrap());
     }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_gpu_list() {
+        let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test-data/sysroot");
+        let gpus = super::gpu_list(&root).unwrap();
+
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].pci_id, "0000:01:00.0");
+        assert_eq!(gpus[0].vendor_id, 0x1002);
+        assert_eq!(
+            gpus[0].device.as_ref().unwrap(),
+            "Ellesmere [Radeon RX 470/480/570/570X/580/580X/590]"
+        );
+        assert_eq!(gpus[0].driver, Some("amdgpu".into()));
+        assert_eq!(gpus[0].memory, Some(8_589_934_592));
+        assert_eq!(gpus[1].vendor, Some("NVIDIA Corporation".into()));
+        assert_eq!(gpus[1].device, Some("GP104 [GeForce GTX 1080]".into()));
+        assert_eq!(gpus[1].memory, None);
+    }
 }
@@ -0,0 +1,49 @@
+//! Vendor and device names from the bundled subset of `pci.ids`.
+
+const PCI_IDS: &str = include_str!("pci.ids");
+
+fn parse_id(s: &str) -> Option<(u16, &str)> {
+    let mut it = s.splitn(2, "  ");
+    let id = u16::from_str_radix(it.next()?, 16).ok()?;
+    Some((id, it.next()?.trim()))
+}
+
+/// Names of the vendor and the device, if known
+pub fn lookup(vendor_id: u16, device_id: u16) -> (Option<&'static str>, Option<&'static str>) {
+    let mut vendor = None;
+
+    for line in PCI_IDS.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('\t') {
+            match (vendor, parse_id(&line[1..])) {
+                (Some(_), Some((id, name))) if id == device_id => return (vendor, Some(name)),
+                _ => (),
+            }
+        } else if vendor.is_some() {
+            break;
+        } else {
+            vendor = parse_id(line)
+                .filter(|&(id, _)| id == vendor_id)
+                .map(|(_, name)| name);
+        }
+    }
+
+    (vendor, None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(
+            lookup(0x10de, 0x1eb8),
+            (Some("NVIDIA Corporation"), Some("TU104GL [Tesla T4]"))
+        );
+        assert_eq!(lookup(0x8086, 0xffff), (Some("Intel Corporation"), None));
+        assert_eq!(lookup(0x1234, 0x1111), (None, None));
+    }
+}
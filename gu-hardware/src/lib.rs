@@ -7,6 +7,7 @@ use gu_base::Module;
 use gu_net::rpc::RemotingSystemService;
 
 pub mod actor;
+pub mod cpuinfo;
 pub mod gpuinfo;
 
 mod disk;
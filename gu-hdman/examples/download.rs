@@ -1,6 +1,6 @@
 use actix::prelude::*;
 use futures::stream::Stream;
-use gu_hdman::download::DownloadOptionsBuilder;
+use gu_hdman::download::{DownloadOptionsBuilder, RateLimit, RateLimiter};
 use pbr::ProgressBar;
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -12,6 +12,12 @@ struct DownloadOpts {
     download_url: String,
     #[structopt(short = "r", long = "retry")]
     connect_retry: Option<u16>,
+    /// Alternative urls of the same file
+    #[structopt(short = "m", long = "mirror")]
+    mirrors: Vec<String>,
+    /// Bandwidth limit in bytes per second
+    #[structopt(short = "l", long = "limit")]
+    limit: Option<u64>,
 }
 
 fn main() {
@@ -27,11 +33,17 @@ fn main() {
     if let Some(connect_retry) = opt.connect_retry {
         download_options.connect_retry(connect_retry);
     }
+    if let Some(rate) = opt.limit {
+        download_options.rate_limiter(RateLimiter::new(RateLimit {
+            rate: Some(rate),
+            schedules: Vec::new(),
+        }));
+    }
 
     let _ = sys.block_on(
         download_options
-            .download(
-                &opt.download_url,
+            .download_mirrors(
+                std::iter::once(opt.download_url).chain(opt.mirrors),
                 //"http://52.31.143.91/images/x86_64/linux/gu-blend.hdi",
                 "/tmp/gu-blend.hdi".into(),
             )
@@ -1,15 +1,53 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 use actix::prelude::*;
 use failure::Fail;
-use futures::prelude::*;
 use futures::sync::oneshot::Canceled;
+use futures::{future, prelude::*};
 
+use gu_base::metrics::{self, Counter, Gauge};
 use gu_model::envman::Image;
 use gu_model::hash::{Error as HashParseError, ParsedHash};
 
 use super::cache::{resolve, CacheProvider};
-use super::download::DownloadOptionsBuilder;
+use super::download::{DownloadOptionsBuilder, ProgressStatus};
+
+lazy_static::lazy_static! {
+    static ref DOWNLOADS: Mutex<HashMap<String, ProgressStatus>> = Mutex::new(HashMap::new());
+    static ref CACHE_HITS: Counter = cache_lookups("hit");
+    static ref CACHE_MISSES: Counter = cache_lookups("miss");
+    static ref DOWNLOADS_OK: Counter = downloads("ok");
+    static ref DOWNLOADS_FAILED: Counter = downloads("failed");
+    static ref DOWNLOADS_ACTIVE: Gauge =
+        metrics::gauge("gu_image_downloads_active", "Image downloads in flight");
+    static ref DOWNLOADED_BYTES: Counter = metrics::counter(
+        "gu_image_downloaded_bytes_total",
+        "Bytes of successfully downloaded images"
+    );
+}
+
+fn cache_lookups(result: &str) -> Counter {
+    metrics::labeled_counter(
+        "gu_image_cache_lookups_total",
+        "Image cache lookups",
+        &[("result", result)],
+    )
+}
+
+fn downloads(result: &str) -> Counter {
+    metrics::labeled_counter(
+        "gu_image_downloads_total",
+        "Finished image downloads",
+        &[("result", result)],
+    )
+}
+
+/// Progress of the image download in flight, by image hash
+pub fn download_progress(hash: &str) -> Option<ProgressStatus> {
+    DOWNLOADS.lock().unwrap().get(hash).cloned()
+}
 
 #[derive(Clone, Debug, Fail)]
 pub enum Error {
@@ -59,20 +97,51 @@ impl CacheProvider for ImageCacheProvider {
         let p = self.path(key)?;
 
         if p.exists() {
+            CACHE_HITS.inc();
             Ok(Some(p))
         } else {
+            CACHE_MISSES.inc();
             Ok(None)
         }
     }
 
     fn fetch(&mut self, hash: Self::Key, image: Self::Hint) -> Self::FetchResult {
         let p = self.path(&hash).unwrap();
+        let key = hash.clone();
+        let mut options = DownloadOptionsBuilder::default();
+
+        if let Err(e) = ParsedHash::from_hash_bytes(hash.as_bytes())
+            .and_then(|h| options.expected_hash(&h).map(|_| ()))
+        {
+            return Box::new(future::err(e.into()));
+        }
 
+        DOWNLOADS_ACTIVE.inc();
         Box::new(
-            DownloadOptionsBuilder::default()
-                .download(&image.url, p.to_string_lossy().into())
-                .for_each(|progress| Ok(eprintln!("progress={:?}", progress)))
-                .and_then(|_v| Ok(p))
+            options
+                .download_mirrors(
+                    std::iter::once(image.url).chain(image.mirrors),
+                    p.to_string_lossy().into(),
+                )
+                .for_each(move |progress| {
+                    let _ = DOWNLOADS.lock().unwrap().insert(hash.clone(), progress);
+                    Ok(())
+                })
+                .then(move |r| {
+                    let _ = DOWNLOADS.lock().unwrap().remove(&key);
+                    DOWNLOADS_ACTIVE.dec();
+                    match r {
+                        Ok(_) => DOWNLOADS_OK.inc(),
+                        Err(_) => DOWNLOADS_FAILED.inc(),
+                    }
+                    r
+                })
+                .and_then(|_v| {
+                    if let Ok(meta) = p.metadata() {
+                        DOWNLOADED_BYTES.inc_by(meta.len() as usize);
+                    }
+                    Ok(p)
+                })
                 .map_err(|e| Error::Other(format!("{}", e))),
         )
     }
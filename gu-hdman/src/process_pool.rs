@@ -31,6 +31,12 @@ impl From<u32> for Pid {
     }
 }
 
+impl From<Pid> for u32 {
+    fn from(pid: Pid) -> Self {
+        pid.0
+    }
+}
+
 impl FromStr for Pid {
     type Err = <u32 as FromStr>::Err;
 
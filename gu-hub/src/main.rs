@@ -42,6 +42,7 @@ mod local_service;
 mod peer;
 mod plugins;
 mod proxy_service;
+mod registry;
 mod repo;
 mod server;
 mod sessions;
@@ -64,9 +65,11 @@ fn main() {
             .chain(proxy_service::module())
             .chain(local_service::module())
             .chain(peer::PeerModule::new())
+            .chain(gu_base::metrics::module())
             .chain(AutocompleteModule::new())
             .chain(hub_info::module())
             .chain(repo::module())
+            .chain(registry::module())
             .chain(server::ServerModule::new()),
     );
 }
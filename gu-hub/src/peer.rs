@@ -4,19 +4,23 @@ use actix::prelude::*;
 use actix_web::{
     self,
     http::{Method, StatusCode},
-    AsyncResponder, FromRequest, HttpRequest, HttpResponse, Json, Path, Responder, Scope,
+    AsyncResponder, FromRequest, HttpRequest, HttpResponse, Json, Path, Query, Responder, Scope,
 };
-use futures::prelude::*;
-use log::error;
+use futures::{future, prelude::*};
+use log::{error, info, warn};
 use prettytable::{cell, row};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 
 use gu_actix::prelude::*;
 use gu_base::{cli, App, AppSettings, ArgMatches, Decorator, Module, SubCommand};
+use gu_model::metrics::GetMetrics;
 use gu_model::peers as peers_api;
 use gu_net::{
-    rpc::{peer, public_destination, reply::CallRemoteUntyped, reply::SendError, ReplyRouter},
+    rpc::{
+        peer, public_destination, reply::CallRemoteUntyped, reply::SendError, FromPeer,
+        PublicMessage, RemotingContext, RemotingSystemService, ReplyRouter,
+    },
     NodeId,
 };
 
@@ -85,6 +89,10 @@ pub fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
         .route("", Method::GET, list_peers)
         .resource("/{nodeId}", |r| r.get().with(fetch_peer))
         .resource("/{nodeId}/hardware", |r| r.get().with(fetch_peer_hardware))
+        .resource("/{nodeId}/metrics", |r| r.get().with(fetch_peer_metrics))
+        .resource("/{nodeId}/registry-auth", |r| {
+            r.post().with(encrypt_registry_auth)
+        })
         .resource("/{nodeId}/deployments", |r| {
             r.get().with(fetch_deployments);
             r.post().with(new_deployment)
@@ -138,6 +146,14 @@ pub fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
                     })
             })
         })
+        .resource("/{nodeId}/deployments/{deploymentId}/fs", |r| {
+            r.get().with_async(get_deployment_file);
+            r.delete().with_async(delete_deployment_file)
+        })
+        .resource("/{nodeId}/deployments/{deploymentId}/fs/{path:.*}", |r| {
+            r.get().with_async(get_deployment_file);
+            r.delete().with_async(delete_deployment_file)
+        })
         .route("/send-to", Method::POST, peer_send)
         .route(
             "/send-to/{nodeId}/{destinationId}",
@@ -146,6 +162,70 @@ pub fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
         )
 }
 
+/// Receives state changes pushed by providers
+#[derive(Default)]
+struct PeerStatus;
+
+impl Actor for PeerStatus {
+    type Context = RemotingContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.bind_from_peer::<peers_api::AvailabilityChanged>(peers_api::AvailabilityChanged::ID);
+        ctx.bind_from_peer::<peers_api::EnvStatusChanged>(peers_api::EnvStatusChanged::ID);
+    }
+}
+
+impl RemotingSystemService for PeerStatus {}
+
+impl Handler<FromPeer<peers_api::AvailabilityChanged>> for PeerStatus {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: FromPeer<peers_api::AvailabilityChanged>,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let FromPeer { sender, body } = msg;
+
+        match body.availability.reason {
+            Some(ref reason) => info!("provider {:?} unavailable: {}", sender, reason),
+            None => info!("provider {:?} available", sender),
+        }
+        if !body.stopping.is_empty() {
+            warn!(
+                "provider {:?} stops deployments: {}",
+                sender,
+                body.stopping.join(", ")
+            );
+        }
+        peer::PeerManager::from_registry()
+            .do_send(peer::SetAvailable(sender, body.availability.available));
+    }
+}
+
+impl Handler<FromPeer<peers_api::EnvStatusChanged>> for PeerStatus {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: FromPeer<peers_api::EnvStatusChanged>,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let exec_envs = msg
+            .body
+            .exec_envs
+            .iter()
+            .map(|(name, status)| format!("{}:{}", name, status.code()))
+            .collect();
+
+        peer::PeerManager::from_registry().do_send(peer::SetExecEnvs(msg.sender, exec_envs));
+    }
+}
+
+pub fn start() {
+    let _ = PeerStatus::from_registry();
+}
+
 fn list_peers<S>(_r: HttpRequest<S>) -> impl Responder {
     peer::PeerManager::from_registry()
         .send(peer::ListPeers)
@@ -171,18 +251,48 @@ struct DeploymentPath {
 }
 
 fn fetch_peer(info: Path<PeerPath>) -> impl Responder {
+    let node_id = info.node_id;
+
     peer::PeerManager::from_registry()
-        .send(peer::GetPeer(info.node_id))
+        .send(peer::GetPeer(node_id))
         .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
-        .and_then(|res| match res {
-            None => Ok(HttpResponse::build(StatusCode::NOT_FOUND).body("Peer not found")),
-            Some(info) => Ok(HttpResponse::Ok().json(peers_api::PeerDetails {
-                node_id: info.node_id,
-                node_name: Some(info.node_name),
-                peer_addr: info.peer_addr.unwrap_or_else(|| "Error".into()),
-                tags: info.tags.into_iter().collect(),
-                sessions: Vec::new(),
-            })),
+        .and_then(move |res| match res {
+            None => future::Either::A(future::ok(
+                HttpResponse::build(StatusCode::NOT_FOUND).body("Peer not found"),
+            )),
+            // older providers don't know the availability, env status and key messages
+            Some(info) => future::Either::B(
+                peer(node_id)
+                    .into_endpoint()
+                    .send(peers_api::GetAvailability::default())
+                    .then(|r| -> Result<_, actix_web::Error> { Ok(r.ok().and_then(Result::ok)) })
+                    .join3(
+                        peer(node_id)
+                            .into_endpoint()
+                            .send(peers_api::GetEnvStatus::default())
+                            .then(|r| -> Result<_, actix_web::Error> {
+                                Ok(r.ok().and_then(Result::ok))
+                            }),
+                        peer(node_id)
+                            .into_endpoint()
+                            .send(gu_model::envman::GetPublicKey::default())
+                            .then(|r| -> Result<_, actix_web::Error> {
+                                Ok(r.ok().and_then(Result::ok))
+                            }),
+                    )
+                    .and_then(move |(availability, exec_envs, public_key)| {
+                        Ok(HttpResponse::Ok().json(peers_api::PeerDetails {
+                            node_id: info.node_id,
+                            node_name: Some(info.node_name),
+                            peer_addr: info.peer_addr.unwrap_or_else(|| "Error".into()),
+                            tags: info.tags.into_iter().collect(),
+                            sessions: Vec::new(),
+                            availability,
+                            exec_envs,
+                            public_key,
+                        }))
+                    }),
+            ),
         })
         .responder()
 }
@@ -211,6 +321,24 @@ fn fetch_peer_hardware(info: Path<PeerPath>) -> impl Responder {
         .responder()
 }
 
+fn fetch_peer_metrics((info, query): (Path<PeerPath>, Query<GetMetrics>)) -> impl Responder {
+    peer(info.node_id)
+        .into_endpoint()
+        .send(query.into_inner())
+        .map_err(|e| match e {
+            SendError::NoDestination => actix_web::error::ErrorNotFound("peer not found"),
+            SendError::NotConnected(node_id) => {
+                actix_web::error::ErrorNotFound(format!("Peer not connected: {:?}", node_id))
+            }
+            _ => actix_web::error::ErrorInternalServerError(format!("{}", e)),
+        })
+        .and_then(|metrics_result| match metrics_result {
+            Ok(samples) => Ok(HttpResponse::Ok().json(samples)),
+            Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+        })
+        .responder()
+}
+
 fn fetch_deployments(info: Path<PeerPath>) -> impl Responder {
     use gu_model::deployment::DeploymentInfo;
     use gu_model::envman::GetSessions;
@@ -243,12 +371,14 @@ fn new_deployment(
 ) -> impl Responder {
     peer(info.node_id)
         .into_endpoint()
+        .timeout(gu_model::envman::CREATE_SESSION_TIMEOUT)
         .send(body.into_inner())
         .map_err(|e| match e {
             SendError::NoDestination => actix_web::error::ErrorNotFound("peer not found"),
             SendError::NotConnected(node_id) => {
                 actix_web::error::ErrorNotFound(format!("Peer not found {:?}", node_id))
             }
+            SendError::Expired => actix_web::error::ErrorGatewayTimeout("request expired"),
             _ => actix_web::error::ErrorInternalServerError(format!("{}", e)),
         })
         .and_then(|session_result| match session_result {
@@ -258,6 +388,154 @@ fn new_deployment(
         .responder()
 }
 
+/// Encrypts the credentials to the hex encoded provider key, as expected in
+/// the `registryAuth` deployment option
+fn encrypt_auth(
+    public_key: &str,
+    auth: &gu_model::dockerman::RegistryAuth,
+) -> Result<String, String> {
+    let bytes = (0..public_key.len())
+        .step_by(2)
+        .map(|i| {
+            public_key
+                .get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| format!("invalid public key: {}", public_key))?;
+    let public = ethkey::PublicKey::from_slice(&bytes)
+        .map_err(|e| format!("invalid public key: {:?}", e))?;
+    let plain = serde_json::to_vec(auth).map_err(|e| e.to_string())?;
+
+    ethkey::encrypt(&public, &plain)
+        .map(|encrypted| base64::encode(&encrypted))
+        .map_err(|e| e.to_string())
+}
+
+fn encrypt_registry_auth(
+    (info, body): (Path<PeerPath>, Json<gu_model::dockerman::RegistryAuth>),
+) -> impl Responder {
+    peer(info.node_id)
+        .into_endpoint()
+        .send(gu_model::envman::GetPublicKey::default())
+        .map_err(|e| match e {
+            SendError::NoDestination => actix_web::error::ErrorNotFound("peer not found"),
+            SendError::NotConnected(node_id) => {
+                actix_web::error::ErrorNotFound(format!("Peer not found {:?}", node_id))
+            }
+            _ => actix_web::error::ErrorInternalServerError(format!("{}", e)),
+        })
+        .and_then(move |public_key| {
+            public_key
+                .and_then(|public_key| encrypt_auth(&public_key, &body))
+                .map_err(actix_web::error::ErrorInternalServerError)
+        })
+        .and_then(|encrypted| Ok(HttpResponse::Ok().json(encrypted)))
+        .responder()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeploymentFilePath {
+    node_id: NodeId,
+    deployment_id: String,
+    #[serde(default)]
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct FileQuery {
+    #[serde(default)]
+    stat: bool,
+    #[serde(default)]
+    offset: u64,
+    length: Option<u64>,
+}
+
+fn workspace_fs(
+    path: DeploymentFilePath,
+    op: gu_model::envman::FsOp,
+) -> impl Future<Item = gu_model::envman::FsResult, Error = actix_web::Error> {
+    use gu_model::envman::{Error, WorkspaceFs};
+
+    peer(path.node_id)
+        .into_endpoint()
+        .send(WorkspaceFs {
+            session_id: path.deployment_id,
+            path: path.path,
+            op,
+        })
+        .map_err(|e| match e {
+            SendError::NoDestination => actix_web::error::ErrorNotFound("peer not found"),
+            SendError::NotConnected(node_id) => {
+                actix_web::error::ErrorNotFound(format!("Peer not found {:?}", node_id))
+            }
+            _ => actix_web::error::ErrorInternalServerError(format!("{}", e)),
+        })
+        .and_then(|fs_result| {
+            fs_result.map_err(|e| match e {
+                Error::NoSuchSession(_) | Error::NoSuchFile(_) => {
+                    actix_web::error::ErrorNotFound(e.to_string())
+                }
+                Error::AccessDenied(_) => actix_web::error::ErrorForbidden(e.to_string()),
+                Error::IncorrectOptions(_) => actix_web::error::ErrorBadRequest(e.to_string()),
+                e => actix_web::error::ErrorInternalServerError(e.to_string()),
+            })
+        })
+}
+
+fn get_deployment_file(
+    (path, query): (Path<DeploymentFilePath>, Query<FileQuery>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    use gu_model::envman::{FsOp, FsResult};
+
+    let op = if query.stat {
+        FsOp::Stat
+    } else {
+        FsOp::Get {
+            offset: query.offset,
+            length: query.length,
+        }
+    };
+
+    workspace_fs(path.into_inner(), op).and_then(|fs_result| match fs_result {
+        FsResult::Listing(files) => Ok(HttpResponse::Ok().json(files)),
+        FsResult::Stat(info) => Ok(HttpResponse::Ok().json(info)),
+        FsResult::Content(content) => {
+            let end = content.offset + content.data.len() as u64;
+            let mut response = if content.offset == 0 && end == content.total_size {
+                HttpResponse::Ok()
+            } else {
+                HttpResponse::PartialContent()
+            };
+            if !content.data.is_empty() {
+                response.header(
+                    "content-range",
+                    format!(
+                        "bytes {}-{}/{}",
+                        content.offset,
+                        end - 1,
+                        content.total_size
+                    ),
+                );
+            }
+            Ok(response
+                .content_type("application/octet-stream")
+                .body(content.data))
+        }
+        FsResult::Deleted => Err(actix_web::error::ErrorInternalServerError(
+            "unexpected response",
+        )),
+    })
+}
+
+fn delete_deployment_file(
+    path: Path<DeploymentFilePath>,
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    workspace_fs(path.into_inner(), gu_model::envman::FsOp::Delete)
+        .and_then(|_| Ok(HttpResponse::NoContent().finish()))
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct SendMessage {
@@ -309,14 +587,27 @@ fn peer_send_path<S: 'static>(r: HttpRequest<S>) -> impl Responder {
 
 fn format_peer_table(peers: Vec<peer::PeerInfo>) {
     cli::format_table(
-        row!["Node id", "Name", "Connection", "Sessions"],
+        row![
+            "Node id",
+            "Name",
+            "Connection",
+            "Sessions",
+            "Environments",
+            "Available"
+        ],
         || "No peers connected",
         peers.into_iter().map(|peer| {
             row![
                 peer.node_id,
                 peer.node_name,
                 peer.peer_addr.unwrap_or_else(|| String::default()),
-                peer.sessions.len()
+                peer.sessions.len(),
+                peer.exec_envs.join(", "),
+                match peer.available {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "",
+                }
             ]
         }),
     )
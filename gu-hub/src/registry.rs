@@ -0,0 +1,305 @@
+//! Registry of nodes for networks not covered by mDNS.
+//!
+//! Nodes register with `PUT /registry/{service}` and renew the registration
+//! periodically; `GET /registry/{service}` lists live registrations.
+//!
+//! The registry is disabled unless a token is configured; every request must
+//! present it in the `X-GU-Registry-Token` header.
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use actix::prelude::*;
+use actix_web::{self, App, HttpRequest, HttpResponse, Json, Path, Scope};
+use futures::{future, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use gu_actix::prelude::*;
+use gu_base::Module;
+use gu_lan::registry::{RegisteredInstance, REGISTRATION_TTL, TOKEN_HEADER};
+use gu_persist::config::{ConfigManager, GetConfig, HasSectionId};
+
+/// Longest host name or instance name accepted
+const MAX_NAME_LEN: usize = 253;
+/// TXT records are limited like DNS character strings
+const MAX_TXT_ENTRIES: usize = 16;
+const MAX_TXT_LEN: usize = 255;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegistryConfig {
+    /// Shared secret of registering nodes; the registry is off without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    /// Limit of live registrations of all services
+    #[serde(default = "RegistryConfig::default_max_entries")]
+    max_entries: usize,
+}
+
+impl Default for RegistryConfig {
+    fn default() -> Self {
+        RegistryConfig {
+            token: None,
+            max_entries: Self::default_max_entries(),
+        }
+    }
+}
+
+impl RegistryConfig {
+    fn default_max_entries() -> usize {
+        1024
+    }
+}
+
+impl HasSectionId for RegistryConfig {
+    const SECTION_ID: &'static str = "registry";
+}
+
+pub fn module() -> RegistryModule {
+    RegistryModule
+}
+
+pub struct RegistryModule;
+
+impl Module for RegistryModule {
+    fn decorate_webapp<S: 'static>(&self, app: App<S>) -> App<S> {
+        app.scope("/registry", scope)
+    }
+}
+
+fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
+    scope
+        .resource("/{service}", |r| {
+            r.get().with_async(list_instances);
+            r.put().with_async(register_instance);
+        })
+        .resource("/{service}/{name}", |r| {
+            r.delete().with_async(unregister_instance);
+        })
+}
+
+/// Compares without an early exit, so timing does not reveal the token
+fn token_matches(expected: &str, presented: &[u8]) -> bool {
+    expected.len() == presented.len()
+        && expected
+            .bytes()
+            .zip(presented)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Registry config, once the request presents the configured token
+fn authorize<S>(
+    r: &HttpRequest<S>,
+) -> impl Future<Item = Arc<RegistryConfig>, Error = actix_web::Error> {
+    let presented = r
+        .headers()
+        .get(TOKEN_HEADER)
+        .map(|value| value.as_bytes().to_vec());
+
+    ConfigManager::from_registry()
+        .send(GetConfig::new())
+        .flatten_fut()
+        .map_err(actix_web::error::ErrorInternalServerError)
+        .and_then(move |config: Arc<RegistryConfig>| {
+            let authorized = match (&config.token, presented) {
+                (Some(token), Some(presented)) => token_matches(token, &presented),
+                _ => false,
+            };
+            match authorized {
+                true => Ok(config),
+                false => Err(actix_web::error::ErrorUnauthorized(
+                    "invalid registry token",
+                )),
+            }
+        })
+}
+
+fn validate(instance: &RegisteredInstance) -> Result<(), String> {
+    if instance.name.is_empty() || instance.name.len() > MAX_NAME_LEN {
+        return Err("invalid instance name".into());
+    }
+    if instance.host_name.len() > MAX_NAME_LEN {
+        return Err("host name too long".into());
+    }
+    if instance.txt.len() > MAX_TXT_ENTRIES
+        || instance.txt.iter().any(|txt| txt.len() > MAX_TXT_LEN)
+    {
+        return Err("too many or too long txt entries".into());
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct InstancePath {
+    service: String,
+    name: String,
+}
+
+fn list_instances<S>(
+    (r, service): (HttpRequest<S>, Path<(String,)>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    authorize(&r).and_then(move |_| {
+        Registry::from_registry()
+            .send(List(service.into_inner().0))
+            .map_err(actix_web::error::ErrorInternalServerError)
+            .and_then(|instances| Ok(HttpResponse::Ok().json(instances)))
+    })
+}
+
+fn register_instance<S>(
+    (r, service, instance): (HttpRequest<S>, Path<(String,)>, Json<RegisteredInstance>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    let mut instance = instance.into_inner();
+    if instance.ip.is_none() {
+        instance.ip = r.peer_addr().map(|addr| addr.ip());
+    }
+    if let Err(e) = validate(&instance) {
+        return future::Either::A(future::ok(HttpResponse::BadRequest().body(e)));
+    }
+
+    future::Either::B(authorize(&r).and_then(move |config| {
+        Registry::from_registry()
+            .send(Register {
+                service: service.into_inner().0,
+                instance,
+                max_entries: config.max_entries,
+            })
+            .map_err(actix_web::error::ErrorInternalServerError)
+            .and_then(|registered| match registered {
+                true => Ok(HttpResponse::NoContent().finish()),
+                false => Ok(HttpResponse::ServiceUnavailable().body("registry is full")),
+            })
+    }))
+}
+
+fn unregister_instance<S>(
+    (r, path): (HttpRequest<S>, Path<InstancePath>),
+) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    let path = path.into_inner();
+
+    authorize(&r).and_then(move |_| {
+        Registry::from_registry()
+            .send(Unregister(path.service, path.name))
+            .map_err(actix_web::error::ErrorInternalServerError)
+            .and_then(|removed| match removed {
+                true => Ok(HttpResponse::NoContent().finish()),
+                false => Ok(HttpResponse::NotFound().finish()),
+            })
+    })
+}
+
+/// Registered instances with their expiration time, by service and name
+#[derive(Default)]
+struct Registry {
+    services: HashMap<String, HashMap<String, (RegisteredInstance, Instant)>>,
+}
+
+impl Registry {
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        for instances in self.services.values_mut() {
+            instances.retain(|_, (_, expires)| *expires > now);
+        }
+        self.services.retain(|_, instances| !instances.is_empty());
+    }
+
+    fn len(&self) -> usize {
+        self.services.values().map(HashMap::len).sum()
+    }
+}
+
+impl Actor for Registry {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(REGISTRATION_TTL, |act, _ctx| act.remove_expired());
+    }
+}
+
+impl Supervised for Registry {}
+
+impl SystemService for Registry {}
+
+struct List(String);
+
+impl Message for List {
+    type Result = Vec<RegisteredInstance>;
+}
+
+impl Handler<List> for Registry {
+    type Result = MessageResult<List>;
+
+    fn handle(&mut self, msg: List, _ctx: &mut Self::Context) -> Self::Result {
+        let now = Instant::now();
+
+        MessageResult(
+            self.services
+                .get(&msg.0)
+                .map(|instances| {
+                    instances
+                        .values()
+                        .filter(|(_, expires)| *expires > now)
+                        .map(|(instance, _)| instance.clone())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/// Registers the instance unless that exceeds `max_entries`
+struct Register {
+    service: String,
+    instance: RegisteredInstance,
+    max_entries: usize,
+}
+
+impl Message for Register {
+    type Result = bool;
+}
+
+impl Handler<Register> for Registry {
+    type Result = bool;
+
+    fn handle(&mut self, msg: Register, _ctx: &mut Self::Context) -> bool {
+        let Register {
+            service,
+            instance,
+            max_entries,
+        } = msg;
+        let renewal = self
+            .services
+            .get(&service)
+            .map(|instances| instances.contains_key(&instance.name))
+            .unwrap_or_default();
+
+        if !renewal && self.len() >= max_entries {
+            self.remove_expired();
+            if self.len() >= max_entries {
+                return false;
+            }
+        }
+        self.services.entry(service).or_default().insert(
+            instance.name.clone(),
+            (instance, Instant::now() + REGISTRATION_TTL),
+        );
+        true
+    }
+}
+
+struct Unregister(String, String);
+
+impl Message for Unregister {
+    type Result = bool;
+}
+
+impl Handler<Unregister> for Registry {
+    type Result = bool;
+
+    fn handle(&mut self, msg: Unregister, _ctx: &mut Self::Context) -> bool {
+        self.services
+            .get_mut(&msg.0)
+            .and_then(|instances| instances.remove(&msg.1))
+            .is_some()
+    }
+}
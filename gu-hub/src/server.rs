@@ -16,7 +16,8 @@ use gu_base::daemon_lib::{DaemonCommand, DaemonHandler};
 #[cfg(windows)]
 use gu_base::SubCommand;
 use gu_base::{Decorator, Module};
-use gu_lan::MdnsPublisher;
+use gu_lan::actor::{Continuous, MdnsActor};
+use gu_lan::{MdnsPublisher, RegisteredInstance, RegistryPublisher};
 use gu_net::{
     rpc::{self, mock},
     NodeId,
@@ -35,6 +36,12 @@ pub(crate) struct HubConfig {
     control_socket: Option<String>,
     #[serde(default = "HubConfig::publish_service")]
     pub(crate) publish_service: bool,
+    /// URLs of hubs hosting registries this hub registers itself with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) registry_urls: Vec<String>,
+    /// Token configured in those registries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) registry_token: Option<String>,
 }
 
 pub(crate) type HubClient = ServerClient<HubConfig>;
@@ -45,6 +52,8 @@ impl Default for HubConfig {
             p2p_port: Self::default_p2p_port(),
             control_socket: None,
             publish_service: Self::publish_service(),
+            registry_urls: Vec::new(),
+            registry_token: None,
         }
     }
 }
@@ -158,13 +167,34 @@ impl Module for ServerModule {
 }
 
 fn mdns_publisher(port: u16, node_id: NodeId) -> std::io::Result<MdnsPublisher> {
-    let _ = mdns::Responder::new()?;
+    MdnsActor::<Continuous>::check_socket()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
 
     let mut publisher = MdnsPublisher::init_publisher(port, node_id.to_string(), true);
     publisher.start();
     Ok(publisher)
 }
 
+fn registry_publisher(
+    urls: Vec<String>,
+    token: Option<String>,
+    port: u16,
+    node_id: NodeId,
+) -> RegistryPublisher {
+    RegistryPublisher::new(
+        urls,
+        "hub",
+        RegisteredInstance {
+            name: node_id.to_string(),
+            host_name: hostname::get_hostname().unwrap_or_else(|| "unknown".to_string()),
+            ip: None,
+            port,
+            txt: vec![format!("node_id={}", node_id)],
+        },
+    )
+    .token(token)
+}
+
 fn chat_route(
     req: &actix_web::HttpRequest<NodeId>,
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
@@ -200,6 +230,8 @@ impl<D: Decorator + 'static + Sync + Send> ServerConfigurer<D> {
         let decorator = self.decorator.clone();
         let node_id = NodeId::from(key.address().as_ref());
 
+        super::peer::start();
+
         match self.decorator.extract::<super::hub_info::InfoModule>() {
             Some(v) => {
                 v.set_node_id(node_id);
@@ -254,6 +286,16 @@ impl<D: Decorator + 'static + Sync + Send> ServerConfigurer<D> {
             }
         }
 
+        if !c.registry_urls.is_empty() {
+            registry_publisher(
+                c.registry_urls.clone(),
+                c.registry_token.clone(),
+                c.p2p_port,
+                node_id,
+            )
+            .start();
+        }
+
         Ok(())
     }
 }
@@ -20,6 +20,7 @@ use sha1::Sha1;
 
 use gu_actix::prelude::*;
 use gu_base::files::{read_async, write_async};
+use gu_base::metrics;
 
 use super::responses::*;
 
@@ -281,6 +282,12 @@ impl Blob {
         Payload: Stream<Item = bytes::Bytes, Error = Error>,
         Error: Debug,
     {
+        let uploaded = metrics::counter(
+            "gu_hub_blob_bytes_uploaded_total",
+            "Bytes uploaded to hub session blobs",
+        );
+        let fut = fut.inspect(move |chunk| uploaded.inc_by(chunk.len()));
+
         self.lock
             .send(WriteAccessRequest)
             .flatten_fut()
@@ -298,6 +305,13 @@ impl Blob {
                 NamedFile::open(&self.path)
                     .map_err(|e| SessionErr::FileError(e.to_string()))
                     .map(|f| {
+                        if let Ok(meta) = f.file().metadata() {
+                            metrics::counter(
+                                "gu_hub_blob_bytes_downloaded_total",
+                                "Bytes downloaded from hub session blobs",
+                            )
+                            .inc_by(meta.len() as usize);
+                        }
                         (
                             f,
                             HeaderValue::from_str(&access.sha1.digest().to_string()).unwrap(),
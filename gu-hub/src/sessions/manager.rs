@@ -3,14 +3,24 @@
 //! Manages hub session state.
 //!
 
-use std::{cmp, collections::HashMap, fs, path::PathBuf};
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
 
 use actix::prelude::*;
 use futures::{Future, IntoFuture};
-use log::error;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 
-use gu_net::NodeId;
+use gu_base::metrics;
+use gu_model::envman::GetSessions;
+use gu_net::{
+    rpc::peer::{self, PeerEvent, PeerManager},
+    NodeId,
+};
 use gu_persist::config::ConfigModule;
 
 use super::session::Session;
@@ -31,7 +41,9 @@ pub struct SessionsManager {
 impl Actor for SessionsManager {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut <Self as Actor>::Context) {
+    fn started(&mut self, ctx: &mut <Self as Actor>::Context) {
+        PeerManager::from_registry().do_send(peer::Subscribe(ctx.address().recipient()));
+
         let path = ConfigModule::new().work_dir().join("hub-sessions");
 
         fs::DirBuilder::new()
@@ -161,10 +173,12 @@ impl SessionsManager {
         self.next_id = cmp::max(id, self.next_id) + 1;
         self.version += 1;
 
-        match self.sessions.insert(id, session) {
+        let result = match self.sessions.insert(id, session) {
             Some(_) => Err(SessionErr::OverwriteError),
             None => Ok(id),
-        }
+        };
+        self.update_metrics();
+        result
     }
 
     pub fn create_session(
@@ -177,11 +191,15 @@ impl SessionsManager {
     }
 
     pub fn create_blob(&mut self, id: u64) -> Result<(u64, Blob), SessionErr> {
-        self.session_mut_fn(id, |s| s.new_blob())
+        let result = self.session_mut_fn(id, |s| s.new_blob());
+        self.update_metrics();
+        result
     }
 
     pub fn set_blob(&mut self, id: u64, b_id: u64, blob: Blob) -> SessionResult {
-        self.session_mut_fn(id, |s| s.set_blob(b_id, blob))
+        let result = self.session_mut_fn(id, |s| s.set_blob(b_id, blob));
+        self.update_metrics();
+        result
     }
 
     pub fn get_blob(&self, id: u64, b_id: u64) -> SessionResult {
@@ -189,7 +207,19 @@ impl SessionsManager {
     }
 
     pub fn delete_blob(&mut self, id: u64, b_id: u64) -> SessionResult {
-        self.session_mut_fn(id, |s| s.delete_blob(b_id))
+        let result = self.session_mut_fn(id, |s| s.delete_blob(b_id));
+        self.update_metrics();
+        result
+    }
+
+    fn update_metrics(&self) {
+        metrics::gauge("gu_hub_sessions", "Hub sessions").set(self.sessions.len() as isize);
+        metrics::gauge("gu_hub_blobs", "Blobs stored in hub sessions").set(
+            self.sessions
+                .values()
+                .map(Session::blob_count)
+                .sum::<usize>() as isize,
+        );
     }
 }
 
@@ -236,6 +266,7 @@ impl Handler<Delete> for SessionsManager {
             None => return ActorResponse::reply(Err(SessionErr::SessionNotFoundError)),
             Some(session) => session,
         };
+        self.update_metrics();
 
         // TODO: This should by async
         match session.clean_directory() {
@@ -438,3 +469,66 @@ impl Handler<UpdateDeployment> for SessionsManager {
         }
     }
 }
+
+impl SessionsManager {
+    fn resync_peer(&mut self, node_id: NodeId, present: HashSet<String>) {
+        let mut changed = false;
+        for (session_id, session) in self.sessions.iter_mut() {
+            let vanished = match session.resync_deployments(node_id, &present) {
+                Some(vanished) => vanished,
+                None => continue,
+            };
+            changed = true;
+            for deployment_id in vanished {
+                warn!(
+                    "deployment {} of session {} vanished from {:?}",
+                    deployment_id, session_id, node_id
+                );
+            }
+        }
+        if changed {
+            self.version += 1;
+        }
+    }
+}
+
+/// Keeps deployments of disconnected providers and re-associates them with
+/// the ones reported after the provider reconnects.
+impl Handler<PeerEvent> for SessionsManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: PeerEvent, ctx: &mut Self::Context) {
+        let node_id = match msg {
+            PeerEvent::Connected(node_id) => node_id,
+            PeerEvent::Disconnected(node_id) => {
+                info!("{:?} disconnected, keeping its deployments", node_id);
+                return;
+            }
+        };
+
+        if !self
+            .sessions
+            .values()
+            .any(|session| session.has_deployments(node_id))
+        {
+            return;
+        }
+
+        ctx.spawn(
+            gu_net::rpc::peer(node_id)
+                .into_endpoint()
+                .send(GetSessions::default())
+                .map_err(move |e| error!("cannot resync deployments of {:?}: {}", node_id, e))
+                .and_then(move |r| {
+                    r.map_err(|()| error!("cannot resync deployments of {:?}", node_id))
+                })
+                .into_actor(self)
+                .map(move |deployments, act, _ctx| {
+                    act.resync_peer(
+                        node_id,
+                        deployments.into_iter().map(|info| info.id).collect(),
+                    )
+                }),
+        );
+    }
+}
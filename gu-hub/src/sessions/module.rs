@@ -10,7 +10,6 @@ use serde::Deserialize;
 
 use gu_actix::prelude::*;
 use gu_base::Module;
-use gu_model::deployment::DeploymentInfo;
 use gu_model::session::HubSessionSpec;
 use gu_net::NodeId;
 
@@ -110,14 +109,7 @@ fn scope<S: 'static>(scope: Scope<S>) -> Scope<S> {
                         session.list_deployments(node_id)
                     }))
                     .flatten_fut()
-                    .and_then(|deployments| {
-                        Ok(HttpResponse::Ok().json(
-                            deployments
-                                .into_iter()
-                                .map(|s| s.into())
-                                .collect::<Vec<DeploymentInfo>>(),
-                        ))
-                    })
+                    .and_then(|deployments| Ok(HttpResponse::Ok().json(deployments)))
             })
         })
         .resource(
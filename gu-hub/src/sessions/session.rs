@@ -14,6 +14,7 @@ use serde::{Deserialize, Serialize};
 use serde_json;
 
 use gu_base::files::{read_async, write_async};
+use gu_model::deployment::{DeploymentInfo, DeploymentStatus};
 use gu_model::session::{BlobInfo, Metadata};
 use gu_net::rpc::peer::PeerSessionInfo;
 use gu_net::{rpc::peer, NodeId};
@@ -55,6 +56,8 @@ impl Default for SessionInfo {
 #[derive(Default)]
 struct PeerState {
     deployments: HashSet<String>,
+    /// deployments missing on the provider after it reconnected
+    lost: HashSet<String>,
 }
 
 pub(crate) fn entries_id_iter(path: &PathBuf) -> impl Iterator<Item = u64> {
@@ -245,6 +248,10 @@ impl Session {
             .ok_or(SessionErr::BlobNotFoundError)
     }*/
 
+    pub fn blob_count(&self) -> usize {
+        self.storage.len()
+    }
+
     pub fn list_blobs(&self) -> Vec<BlobInfo> {
         self.storage
             .keys()
@@ -275,8 +282,45 @@ impl Session {
     pub fn remove_deployment(&mut self, node_id: NodeId, deployment_id: String) -> bool {
         match self.peers.get_mut(&node_id) {
             None => false,
-            Some(peer) => peer.deployments.remove(&deployment_id),
+            Some(peer) => {
+                peer.deployments.remove(&deployment_id) || peer.lost.remove(&deployment_id)
+            }
+        }
+    }
+
+    pub fn has_deployments(&self, node_id: NodeId) -> bool {
+        self.peers
+            .get(&node_id)
+            .map(|peer| !peer.deployments.is_empty() || !peer.lost.is_empty())
+            .unwrap_or(false)
+    }
+
+    /// Re-associates deployments with the ones present on the reconnected
+    /// provider. Returns ids of deployments that vanished, or `None` if
+    /// nothing changed.
+    pub fn resync_deployments(
+        &mut self,
+        node_id: NodeId,
+        present: &HashSet<String>,
+    ) -> Option<Vec<String>> {
+        let peer = self.peers.get_mut(&node_id)?;
+
+        let found: Vec<String> = peer.lost.intersection(present).cloned().collect();
+        let vanished: Vec<String> = peer.deployments.difference(present).cloned().collect();
+        if found.is_empty() && vanished.is_empty() {
+            return None;
         }
+
+        for id in found {
+            peer.lost.remove(&id);
+            peer.deployments.insert(id);
+        }
+        for id in &vanished {
+            peer.deployments.remove(id);
+            peer.lost.insert(id.clone());
+        }
+        self.version += 1;
+        Some(vanished)
     }
 
     pub fn add_deployment(&mut self, node_id: NodeId, deployment_id: String) {
@@ -297,6 +341,7 @@ impl Session {
         future::Either::B(
             peer(node_id)
                 .into_endpoint()
+                .timeout(gu_model::envman::CREATE_SESSION_TIMEOUT)
                 .send(body)
                 .map_err(|_| SessionErr::CannotCreatePeerDeployment)
                 .and_then(|v| {
@@ -308,13 +353,14 @@ impl Session {
     pub fn list_deployments(
         &self,
         node_id: NodeId,
-    ) -> impl Future<Item = Vec<PeerSessionInfo>, Error = SessionErr> {
+    ) -> impl Future<Item = Vec<DeploymentInfo>, Error = SessionErr> {
         let peer_state = match self.peers.get(&node_id) {
             None => return future::Either::A(future::err(SessionErr::NodeNotFound(node_id))),
             Some(peer) => peer,
         };
         // TODO: Add reference counting here
         let session_deployments = peer_state.deployments.clone();
+        let lost_deployments = peer_state.lost.clone();
 
         future::Either::B(
             peer(node_id)
@@ -328,6 +374,8 @@ impl Session {
                         .filter(move |deployment_info: &PeerSessionInfo| {
                             session_deployments.contains(&deployment_info.id)
                         })
+                        .map(DeploymentInfo::from)
+                        .chain(lost_deployments.into_iter().map(lost_deployment))
                         .collect();
                     Ok(v)
                 }),
@@ -339,8 +387,13 @@ impl Session {
         node_id: NodeId,
         deployment_id: String,
     ) -> impl Future<Item = (), Error = SessionErr> {
-        if self.peers.get(&node_id).is_none() {
-            return future::Either::A(future::err(SessionErr::NodeNotFound(node_id)));
+        match self.peers.get(&node_id) {
+            None => return future::Either::A(future::err(SessionErr::NodeNotFound(node_id))),
+            // nothing left to destroy on the provider
+            Some(peer) if peer.lost.contains(&deployment_id) => {
+                return future::Either::A(future::ok(()))
+            }
+            Some(_) => (),
         }
         future::Either::B(
             peer(node_id)
@@ -359,6 +412,7 @@ impl Session {
                 .iter_mut()
                 .map(|(node_id_ref, peer_info)| {
                     let node_id = *node_id_ref;
+                    peer_info.lost.clear();
                     peer_info
                         .deployments
                         .drain()
@@ -399,6 +453,19 @@ impl Session {
     }
 }
 
+fn lost_deployment(id: String) -> DeploymentInfo {
+    DeploymentInfo {
+        id,
+        name: String::new(),
+        status: DeploymentStatus::LOST,
+        tags: Default::default(),
+        note: Some("vanished from the provider".into()),
+        processes: Default::default(),
+        download: None,
+        egress: None,
+    }
+}
+
 fn drop_peer_deployment(
     node_id: NodeId,
     session_id: String,
@@ -1,10 +1,10 @@
 use std::{
     collections::HashMap,
-    net::{Ipv4Addr, SocketAddrV4},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6},
 };
 use std::{
     collections::HashSet,
-    net::SocketAddr::{self, V4},
+    net::SocketAddr::{self, V4, V6},
     time::Duration,
 };
 
@@ -13,6 +13,7 @@ use actix::AsyncContext;
 use futures::prelude::*;
 use futures::sync::mpsc;
 use futures::sync::oneshot;
+use get_if_addrs;
 use socket2::{Domain, Protocol, Socket, Type};
 use tokio::prelude::*;
 use tokio::{
@@ -20,22 +21,28 @@ use tokio::{
     reactor::Handle,
 };
 
-use codec::MdnsCodec;
-use codec::ParsedPacket;
+use codec::{MdnsCodec, MdnsPacket, ParsedPacket};
 use continuous::{
-    ContinuousInstancesList, ForeignMdnsQueryInfo, NewInstance, ReceivedMdnsInstance, Subscribe,
-    Subscription,
+    ContinuousInstancesList, ForeignMdnsQueryInfo, NewInstance, ReceivedMdnsGoodbye,
+    ReceivedMdnsInstance, Subscribe, Subscription,
 };
 use errors::{Error, ErrorKind, Result};
 use gu_actix::FlattenFuture;
-use service::{ServiceDescription, Services};
+use service::{PublishedService, ServiceDescription, Services};
 use service::{ServiceInstance, ServicesDescription};
 
+/// TTL of records in our announcements and responses
+const ANNOUNCE_TTL: u32 = 120;
+/// Delay between the two announcements sent on publishing
+const ANNOUNCE_REPEAT: Duration = Duration::from_secs(1);
+
+pub(crate) type PacketSender = mpsc::Sender<(MdnsPacket, SocketAddr)>;
+
 /// Actor resolving mDNS services names into list of IPs
 #[derive(Debug, Default)]
 pub struct MdnsActor<T: MdnsConnection> {
     /// Interior, indirect responder sink
-    sender: Option<mpsc::Sender<((ServicesDescription, u16), SocketAddr)>>,
+    sender: Option<PacketSender>,
     data: Box<T>,
 }
 
@@ -44,7 +51,12 @@ pub trait MdnsConnection: 'static + Default + Sized {
 
     fn unicast_query() -> bool;
 
-    fn handle_packet(&mut self, packet: ParsedPacket, src: SocketAddr);
+    /// Returns packets to send in response
+    fn handle_packet(
+        &mut self,
+        packet: ParsedPacket,
+        src: SocketAddr,
+    ) -> Vec<(MdnsPacket, SocketAddr)>;
 }
 
 pub type OneShotResponse<T> = ActorResponse<MdnsActor<T>, HashSet<ServiceInstance>, Error>;
@@ -62,6 +74,10 @@ pub struct OneShot {
 pub struct Continuous {
     /// Services for given id
     map: HashMap<String, Addr<ContinuousInstancesList>>,
+    /// Services published by this node, by instance name
+    published: HashMap<String, PublishedService>,
+    /// Goodbye packets are sent on termination signals
+    signals_subscribed: bool,
 }
 
 impl MdnsConnection for OneShot {
@@ -73,17 +89,104 @@ impl MdnsConnection for OneShot {
         true
     }
 
-    fn handle_packet(&mut self, packet: ParsedPacket, src: SocketAddr) {
+    fn handle_packet(
+        &mut self,
+        packet: ParsedPacket,
+        src: SocketAddr,
+    ) -> Vec<(MdnsPacket, SocketAddr)> {
         if let Some(services) = self.map.get_mut(&packet.id) {
             for mut service in packet.instances {
                 match src {
                     V4(sock) => service.addrs_v4 = biggest_mask_ipv4(&service.addrs_v4, sock.ip()),
-                    _ => (),
+                    V6(sock) => set_scope(&mut service.addrs_v6, &sock),
                 }
 
                 services.add_instance(service);
             }
         }
+
+        Vec::new()
+    }
+}
+
+/// Uses the sender's address if there are no AAAA records and gives
+/// link-local addresses the scope of the interface the packet came from
+fn set_scope(addrs_v6: &mut Vec<SocketAddrV6>, src: &SocketAddrV6) {
+    if addrs_v6.is_empty() {
+        addrs_v6.push(SocketAddrV6::new(*src.ip(), 0, 0, src.scope_id()));
+    }
+    for addr in addrs_v6.iter_mut() {
+        if addr.ip().segments()[0] & 0xffc0 == 0xfe80 && addr.scope_id() == 0 {
+            addr.set_scope_id(src.scope_id());
+        }
+    }
+}
+
+/// Announcements carry no address records; the sender's address is used instead
+fn fill_address(instance: &mut ServiceInstance, src: &SocketAddr) {
+    match src {
+        V4(sock) if instance.addrs_v4.is_empty() && instance.addrs_v6.is_empty() => {
+            instance.addrs_v4.push(*sock.ip())
+        }
+        V4(_) => (),
+        V6(sock) if instance.addrs_v4.is_empty() => set_scope(&mut instance.addrs_v6, sock),
+        V6(_) => (),
+    }
+}
+
+fn multicast_addr(src: &SocketAddr) -> SocketAddr {
+    match src {
+        V4(_) => SocketAddrV4::new(MULTICAST_IPV4, 5353).into(),
+        V6(_) => SocketAddrV6::new(MULTICAST_IPV6, 5353, 0, 0).into(),
+    }
+}
+
+fn multicast(packet: MdnsPacket) -> Vec<(MdnsPacket, SocketAddr)> {
+    vec![
+        (
+            packet.clone(),
+            SocketAddrV4::new(MULTICAST_IPV4, 5353).into(),
+        ),
+        (packet, SocketAddrV6::new(MULTICAST_IPV6, 5353, 0, 0).into()),
+    ]
+}
+
+impl Continuous {
+    /// Responds to PTR queries for services published by this node
+    fn respond(&self, packet: &ParsedPacket, src: SocketAddr) -> Option<(MdnsPacket, SocketAddr)> {
+        let services: Vec<PublishedService> = self
+            .published
+            .values()
+            .filter(|service| {
+                packet
+                    .queried_services
+                    .iter()
+                    .any(|name| name == service.service_name())
+            })
+            .cloned()
+            .collect();
+
+        if services.is_empty() {
+            return None;
+        }
+        // legacy (non 5353 port) queries and QU questions get unicast response
+        let (id, dst) = if src.port() != 5353 {
+            (packet.id, src)
+        } else if packet.prefer_unicast {
+            (0, src)
+        } else {
+            (0, multicast_addr(&src))
+        };
+
+        Some((
+            MdnsPacket::Response {
+                id,
+                services,
+                addrs: host_addrs(),
+                ttl: ANNOUNCE_TTL,
+            },
+            dst,
+        ))
     }
 }
 
@@ -96,18 +199,36 @@ impl MdnsConnection for Continuous {
         false
     }
 
-    fn handle_packet(&mut self, packet: ParsedPacket, _src: SocketAddr) {
+    fn handle_packet(
+        &mut self,
+        packet: ParsedPacket,
+        src: SocketAddr,
+    ) -> Vec<(MdnsPacket, SocketAddr)> {
+        let response = self.respond(&packet, src);
+
         for name in packet.questions {
             self.map
                 .get(&name)
                 .map(|list| list.do_send(ForeignMdnsQueryInfo));
         }
 
-        for instance in packet.instances {
+        for mut instance in packet.instances {
+            if self.published.contains_key(&instance.name) {
+                continue;
+            }
+            fill_address(&mut instance, &src);
             self.map
                 .get(&instance.service())
                 .map(|list| list.do_send(ReceivedMdnsInstance::new(instance)));
         }
+
+        for instance in packet.goodbyes {
+            self.map
+                .get(&instance.service())
+                .map(|list| list.do_send(ReceivedMdnsGoodbye::new(instance)));
+        }
+
+        response.into_iter().collect()
     }
 }
 
@@ -116,10 +237,22 @@ impl<T: MdnsConnection> MdnsActor<T> {
         MdnsActor::default()
     }
 
+    /// Checks if mDNS socket can be created
+    pub fn check_socket() -> Result<()> {
+        Self::create_mdns_socket().map(|_| ())
+    }
+
+    fn send(&self, packets: Vec<(MdnsPacket, SocketAddr)>, ctx: &mut Context<Self>) {
+        ctx.spawn(
+            send_packets(self.sender.clone(), packets)
+                .map_err(|e| error!("mDNS send error: {:?}", e))
+                .into_actor(self),
+        );
+    }
+
     fn create_mdns_socket() -> Result<UdpSocket> {
         let socket = Socket::new(Domain::ipv4(), Type::dgram(), Some(Protocol::udp()))?;
 
-        let multicast_ip = Ipv4Addr::new(224, 0, 0, 251);
         let any_ip = Ipv4Addr::new(0, 0, 0, 0);
 
         let socket_address = SocketAddrV4::new(any_ip, T::port());
@@ -128,27 +261,51 @@ impl<T: MdnsConnection> MdnsActor<T> {
         socket.set_reuse_port(true)?;
         socket.set_reuse_address(true)?;
         socket.set_multicast_loop_v4(true)?;
-        socket.join_multicast_v4(&multicast_ip, &any_ip)?;
+        socket.join_multicast_v4(&MULTICAST_IPV4, &any_ip)?;
+        socket.bind(&socket_address.into())?;
+
+        UdpSocket::from_std(socket.into_udp_socket(), &Handle::default()).map_err(Error::from)
+    }
+
+    fn create_mdns_socket_v6() -> Result<UdpSocket> {
+        let socket = Socket::new(Domain::ipv6(), Type::dgram(), Some(Protocol::udp()))?;
+
+        let socket_address = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, T::port(), 0, 0);
+
+        #[cfg(not(windows))]
+        socket.set_reuse_port(true)?;
+        socket.set_reuse_address(true)?;
+        socket.set_only_v6(true)?;
+        socket.set_multicast_loop_v6(true)?;
+        socket.join_multicast_v6(&MULTICAST_IPV6, 0)?;
         socket.bind(&socket_address.into())?;
 
         UdpSocket::from_std(socket.into_udp_socket(), &Handle::default()).map_err(Error::from)
     }
 }
 
-pub fn send_mdns_query(
-    sender: Option<mpsc::Sender<((ServicesDescription, u16), SocketAddr)>>,
+/// mDNS multicast group for IPv4 (224.0.0.251)
+const MULTICAST_IPV4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// mDNS link-local multicast group for IPv6 (ff02::fb)
+const MULTICAST_IPV6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Sends query to both IPv4 and IPv6 mDNS multicast groups
+pub(crate) fn send_mdns_query(
+    sender: Option<PacketSender>,
     services: ServicesDescription,
     id: u16,
 ) -> impl Future<Item = (), Error = Error> {
-    let multicast_ip = Ipv4Addr::new(224, 0, 0, 251);
-    let addr = SocketAddrV4::new(multicast_ip, 5353).into();
-
-    let message = ((services, id), addr);
+    send_packets(sender, multicast(MdnsPacket::Query(services, id)))
+}
 
+fn send_packets(
+    sender: Option<PacketSender>,
+    packets: Vec<(MdnsPacket, SocketAddr)>,
+) -> impl Future<Item = (), Error = Error> {
     match sender {
         Some(a) => future::Either::A(
-            a.send(message)
-                .and_then(|sender| Ok(sender.flush()))
+            a.send_all(futures::stream::iter_ok(packets))
+                .and_then(|(sender, _)| Ok(sender.flush()))
                 .and_then(|_| Ok(()))
                 .map_err(|e| {
                     error!("{}", e);
@@ -237,15 +394,20 @@ impl Message for PacketPair {
 impl<T: MdnsConnection> Handler<PacketPair> for MdnsActor<T> {
     type Result = ();
 
-    fn handle(&mut self, msg: PacketPair, _ctx: &mut Context<MdnsActor<T>>) -> () {
-        T::handle_packet(&mut self.data, msg.packet, msg.socket)
+    fn handle(&mut self, msg: PacketPair, ctx: &mut Context<MdnsActor<T>>) -> () {
+        let packets = T::handle_packet(&mut self.data, msg.packet, msg.socket);
+        if !packets.is_empty() {
+            self.send(packets, ctx)
+        }
     }
 }
 
 impl<T: MdnsConnection> Actor for MdnsActor<T> {
     type Context = Context<Self>;
 
-    /// Creates stream handler for incoming mDNS packets
+    /// Creates stream handlers for incoming mDNS packets.
+    ///
+    /// IPv6 is optional; if its socket can't be created only IPv4 is used.
     fn started(&mut self, ctx: &mut Self::Context) {
         let socket = Self::create_mdns_socket().expect("Creation of mDNS socket failed");
         let (sink, stream) = UdpFramed::new(socket, MdnsCodec(T::unicast_query())).split();
@@ -256,10 +418,36 @@ impl<T: MdnsConnection> Actor for MdnsActor<T> {
                 .map_err(|_| ()),
         );
 
+        let sink_v6 = match Self::create_mdns_socket_v6() {
+            Ok(socket) => {
+                let (sink, stream) = UdpFramed::new(socket, MdnsCodec(T::unicast_query())).split();
+                ctx.add_message_stream(
+                    stream
+                        .map(|(packet, socket)| PacketPair { packet, socket })
+                        .map_err(|_| ()),
+                );
+                Some(sink)
+            }
+            Err(e) => {
+                warn!("IPv6 mDNS disabled: {}", e);
+                None
+            }
+        };
+
         let (tx, rx) = mpsc::channel(16);
         ctx.spawn(
-            rx.map_err(|_| ErrorKind::UninitializedChannelReceiver)
-                .forward(sink)
+            rx.map_err(|_| Error::from(ErrorKind::UninitializedChannelReceiver))
+                .fold((sink, sink_v6), |(sink, sink_v6), message| {
+                    match message.1 {
+                        V4(_) => future::Either::A(sink.send(message).map(|sink| (sink, sink_v6))),
+                        V6(_) => match sink_v6 {
+                            Some(sink_v6) => future::Either::B(future::Either::A(
+                                sink_v6.send(message).map(|sink_v6| (sink, Some(sink_v6))),
+                            )),
+                            None => future::Either::B(future::Either::B(future::ok((sink, None)))),
+                        },
+                    }
+                })
                 .map_err(|e| error!("{:?}", e))
                 .and_then(|_| Ok(()))
                 .into_actor(self),
@@ -332,6 +520,104 @@ impl Handler<SubscribeInstance> for MdnsActor<Continuous> {
     }
 }
 
+/// Publishes service instance: announces it and responds to queries for it
+pub(crate) struct Publish(pub PublishedService);
+
+impl Message for Publish {
+    type Result = ();
+}
+
+/// Withdraws published service instance sending goodbye packets
+pub(crate) struct Unpublish(pub PublishedService);
+
+impl Message for Unpublish {
+    type Result = ();
+}
+
+/// Addresses of this host sent with published services
+fn host_addrs() -> Vec<IpAddr> {
+    match get_if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces
+            .into_iter()
+            .filter(|interface| !interface.is_loopback())
+            .map(|interface| interface.ip())
+            .collect(),
+        Err(e) => {
+            warn!("cannot list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn announcement(services: Vec<PublishedService>, ttl: u32) -> Vec<(MdnsPacket, SocketAddr)> {
+    // goodbyes leave the addresses, other services may share the host name
+    let addrs = match ttl {
+        0 => Vec::new(),
+        _ => host_addrs(),
+    };
+    multicast(MdnsPacket::Response {
+        id: 0,
+        services,
+        addrs,
+        ttl,
+    })
+}
+
+impl Handler<Publish> for MdnsActor<Continuous> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Publish, ctx: &mut Self::Context) -> () {
+        use actix::actors::signal;
+
+        if !self.data.signals_subscribed {
+            self.data.signals_subscribed = true;
+            signal::ProcessSignals::from_registry()
+                .do_send(signal::Subscribe(ctx.address().recipient()));
+        }
+
+        let service = msg.0;
+        self.data
+            .published
+            .insert(service.instance_name(), service.clone());
+
+        self.send(announcement(vec![service.clone()], ANNOUNCE_TTL), ctx);
+        ctx.run_later(ANNOUNCE_REPEAT, move |act, ctx| {
+            if act.data.published.contains_key(&service.instance_name()) {
+                act.send(announcement(vec![service], ANNOUNCE_TTL), ctx)
+            }
+        });
+    }
+}
+
+impl Handler<Unpublish> for MdnsActor<Continuous> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unpublish, ctx: &mut Self::Context) -> () {
+        if let Some(service) = self.data.published.remove(&msg.0.instance_name()) {
+            self.send(announcement(vec![service], 0), ctx);
+        }
+    }
+}
+
+impl Handler<actix::actors::signal::Signal> for MdnsActor<Continuous> {
+    type Result = ();
+
+    fn handle(&mut self, msg: actix::actors::signal::Signal, ctx: &mut Self::Context) -> () {
+        use actix::actors::signal::SignalType;
+
+        match msg.0 {
+            // shutting down is up to the application
+            SignalType::Int | SignalType::Term | SignalType::Quit => {
+                let services: Vec<_> = self.data.published.drain().map(|(_, s)| s).collect();
+                if !services.is_empty() {
+                    self.send(announcement(services, 0), ctx);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use actor::{MdnsActor, OneShot};
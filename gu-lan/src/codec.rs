@@ -4,15 +4,15 @@ use tokio_codec::{Decoder, Encoder};
 
 use dns_parser::{
     rdata::{
-        a::Record,
-        RData::{A, SRV, TXT},
+        a, aaaa,
+        RData::{A, AAAA, SRV, TXT},
     },
     Builder, Packet, QueryClass, QueryType, Question, ResourceRecord,
 };
-use service::{ServiceInstance, ServicesDescription};
+use service::{addr_v6, PublishedService, ServiceInstance, ServicesDescription};
 use std::{
-    collections::{HashMap, HashSet},
-    net::Ipv4Addr,
+    collections::{BTreeSet, HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     str::from_utf8,
 };
 
@@ -20,18 +20,47 @@ use std::{
 pub struct ParsedPacket {
     pub id: u16,
     pub instances: Vec<ServiceInstance>,
+    /// instances announced with TTL=0
+    pub goodbyes: Vec<ServiceInstance>,
     pub questions: Vec<String>,
+    /// services asked for with PTR questions
+    pub queried_services: Vec<String>,
+    /// any of the questions asks for unicast response
+    pub prefer_unicast: bool,
+}
+
+/// Outgoing mDNS packet
+#[derive(Clone, Debug)]
+pub enum MdnsPacket {
+    Query(ServicesDescription, u16),
+    /// Response with PTR, SRV and TXT records of the services and A/AAAA
+    /// records of their hosts with `addrs`; TTL=0 for goodbye
+    Response {
+        id: u16,
+        services: Vec<PublishedService>,
+        addrs: Vec<IpAddr>,
+        ttl: u32,
+    },
 }
 
 #[derive(Debug)]
 pub(crate) struct MdnsCodec(pub bool);
 
 fn parse_question(question: Question, parse_sets: &mut QuestionParseSets) {
-    if question.prefer_unicast || question.qclass != QueryClass::IN {
+    if question.qclass != QueryClass::IN {
         return;
     }
     let name = question.qname.to_string();
 
+    if question.qtype == QueryType::PTR {
+        parse_sets.prefer_unicast |= question.prefer_unicast;
+        parse_sets.ptr.insert(name);
+        return;
+    }
+    if question.prefer_unicast {
+        return;
+    }
+
     match question.qtype {
         QueryType::SRV => {
             parse_sets.srv.insert(name);
@@ -54,6 +83,9 @@ fn combine_questions(mut parse_sets: QuestionParseSets, questions: &mut Vec<Stri
 fn parse_answer(answer: ResourceRecord, parse_maps: &mut ResponseParseMaps) {
     match answer.data {
         SRV(data) => {
+            if answer.ttl == 0 {
+                parse_maps.expired.insert(answer.name.to_string());
+            }
             let key = (answer.name.to_string(), data.target.clone().to_string());
             parse_maps.srv.entry(key).or_default().push(data.port);
         }
@@ -66,20 +98,34 @@ fn parse_answer(answer: ResourceRecord, parse_maps: &mut ResponseParseMaps) {
             );
         }
         A(data) => {
-            let Record(arr) = data;
+            let a::Record(arr) = data;
             parse_maps
                 .a
                 .entry(answer.name.to_string())
                 .or_default()
                 .push(arr.into());
         }
+        AAAA(data) => {
+            let aaaa::Record(arr) = data;
+            parse_maps
+                .aaaa
+                .entry(answer.name.to_string())
+                .or_default()
+                .push(arr.into());
+        }
         _ => (),
     }
 }
 
-fn combine_answers(parse_maps: ResponseParseMaps, services: &mut Vec<ServiceInstance>) {
+fn combine_answers(
+    parse_maps: ResponseParseMaps,
+    services: &mut Vec<ServiceInstance>,
+    goodbyes: &mut Vec<ServiceInstance>,
+) {
+    let expired = parse_maps.expired;
     let srv = parse_maps.srv;
     let a = parse_maps.a;
+    let aaaa = parse_maps.aaaa;
     let txt = parse_maps.txt;
     srv.into_iter().for_each(move |e| {
         let pair = e.0;
@@ -88,15 +134,25 @@ fn combine_answers(parse_maps: ResponseParseMaps, services: &mut Vec<ServiceInst
         let ports = e.1;
 
         let addrs_v4 = a.get(&host).map(|a| a.clone()).unwrap_or(Vec::new());
+        let addrs_v6 = aaaa
+            .get(&host)
+            .map(|a| a.iter().cloned().map(addr_v6).collect())
+            .unwrap_or(Vec::new());
         let txt = txt.get(&name).map(|a| a.clone()).unwrap_or(Vec::new());
 
-        services.push(ServiceInstance {
+        let instance = ServiceInstance {
             name,
             host,
             txt,
             addrs_v4,
+            addrs_v6,
             ports,
-        })
+        };
+        if expired.contains(&instance.name) {
+            goodbyes.push(instance)
+        } else {
+            services.push(instance)
+        }
     });
 }
 
@@ -108,12 +164,18 @@ struct ResponseParseMaps {
     pub txt: HashMap<String, Vec<String>>,
     // host -> IPv4
     pub a: HashMap<String, Vec<Ipv4Addr>>,
+    // host -> IPv6
+    pub aaaa: HashMap<String, Vec<Ipv6Addr>>,
+    // services with SRV TTL=0
+    pub expired: HashSet<String>,
 }
 
 #[derive(Default, Debug)]
 struct QuestionParseSets {
     pub srv: HashSet<String>,
     pub txt: HashSet<String>,
+    pub ptr: HashSet<String>,
+    pub prefer_unicast: bool,
 }
 
 impl Decoder for MdnsCodec {
@@ -140,9 +202,12 @@ impl Decoder for MdnsCodec {
         }
 
         let mut services: Vec<ServiceInstance> = Vec::new();
+        let mut goodbyes: Vec<ServiceInstance> = Vec::new();
         let mut questions: Vec<String> = Vec::new();
+        let queried_services = parse_sets.ptr.iter().cloned().collect();
+        let prefer_unicast = parse_sets.prefer_unicast;
 
-        combine_answers(parse_maps, &mut services);
+        combine_answers(parse_maps, &mut services, &mut goodbyes);
         combine_questions(parse_sets, &mut questions);
 
         debug!("Decoded mDNS packet {:?}", services);
@@ -150,29 +215,251 @@ impl Decoder for MdnsCodec {
         Ok(Some(ParsedPacket {
             id,
             instances: services,
+            goodbyes,
             questions,
+            queried_services,
+            prefer_unicast,
         }))
     }
 }
 
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// IN class with the mDNS cache-flush bit set
+const CLASS_IN_UNIQUE: u16 = 0x8001;
+
+fn encode_name(name: &str, dst: &mut Vec<u8>) -> Result<()> {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            return Err(ErrorKind::DnsPacketBuildError(label.as_bytes().to_vec()).into());
+        }
+        dst.push(label.len() as u8);
+        dst.extend_from_slice(label.as_bytes());
+    }
+    dst.push(0);
+    Ok(())
+}
+
+fn encode_record<F>(
+    name: &str,
+    rtype: u16,
+    class: u16,
+    ttl: u32,
+    dst: &mut Vec<u8>,
+    rdata: F,
+) -> Result<()>
+where
+    F: FnOnce(&mut Vec<u8>) -> Result<()>,
+{
+    encode_name(name, dst)?;
+    dst.extend_from_slice(&rtype.to_be_bytes());
+    dst.extend_from_slice(&class.to_be_bytes());
+    dst.extend_from_slice(&ttl.to_be_bytes());
+
+    let mut data = Vec::new();
+    rdata(&mut data)?;
+    dst.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    dst.extend_from_slice(&data);
+    Ok(())
+}
+
+/// Builds authoritative response with PTR, SRV and TXT records of `services`
+/// and address records of their hosts as additional records.
+fn encode_response(
+    id: u16,
+    services: &[PublishedService],
+    addrs: &[IpAddr],
+    ttl: u32,
+) -> Result<Vec<u8>> {
+    let hosts: BTreeSet<String> = services.iter().map(|s| s.host_name()).collect();
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&id.to_be_bytes());
+    // QR + AA flags
+    packet.extend_from_slice(&0x8400u16.to_be_bytes());
+    // questions
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    // answers
+    packet.extend_from_slice(&(3 * services.len() as u16).to_be_bytes());
+    // authority records
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    // additional records
+    packet.extend_from_slice(&((hosts.len() * addrs.len()) as u16).to_be_bytes());
+
+    for service in services {
+        let instance = service.instance_name();
+        encode_record(
+            &service.service_name(),
+            TYPE_PTR,
+            CLASS_IN,
+            ttl,
+            &mut packet,
+            |data| encode_name(&instance, data),
+        )?;
+        encode_record(
+            &instance,
+            TYPE_SRV,
+            CLASS_IN_UNIQUE,
+            ttl,
+            &mut packet,
+            |data| {
+                // priority and weight
+                data.extend_from_slice(&[0, 0, 0, 0]);
+                data.extend_from_slice(&service.port.to_be_bytes());
+                encode_name(&service.host_name(), data)
+            },
+        )?;
+        encode_record(
+            &instance,
+            TYPE_TXT,
+            CLASS_IN_UNIQUE,
+            ttl,
+            &mut packet,
+            |data| {
+                for txt in service.txt.iter().filter(|txt| txt.len() < 256) {
+                    data.push(txt.len() as u8);
+                    data.extend_from_slice(txt.as_bytes());
+                }
+                if data.is_empty() {
+                    data.push(0);
+                }
+                Ok(())
+            },
+        )?;
+    }
+    for host in &hosts {
+        for addr in addrs {
+            let (rtype, octets) = match addr {
+                IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+                IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+            };
+            encode_record(host, rtype, CLASS_IN_UNIQUE, ttl, &mut packet, |data| {
+                data.extend_from_slice(&octets);
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(packet)
+}
+
 impl Encoder for MdnsCodec {
-    type Item = (ServicesDescription, u16);
+    type Item = MdnsPacket;
     type Error = Error;
 
-    fn encode(&mut self, item: (ServicesDescription, u16), dst: &mut BytesMut) -> Result<()> {
-        let mut builder = Builder::new_query(item.1, false);
-        for service in item.0.services().iter() {
-            builder.add_question(
-                service.to_string().as_ref(),
-                self.0,
-                QueryType::PTR,
-                QueryClass::IN,
-            );
-        }
-        let packet = builder.build().map_err(ErrorKind::DnsPacketBuildError)?;
+    fn encode(&mut self, item: MdnsPacket, dst: &mut BytesMut) -> Result<()> {
+        let packet = match item {
+            MdnsPacket::Query(services, id) => {
+                let mut builder = Builder::new_query(id, false);
+                for service in services.services().iter() {
+                    builder.add_question(
+                        service.to_string().as_ref(),
+                        self.0,
+                        QueryType::PTR,
+                        QueryClass::IN,
+                    );
+                }
+                builder.build().map_err(ErrorKind::DnsPacketBuildError)?
+            }
+            MdnsPacket::Response {
+                id,
+                services,
+                addrs,
+                ttl,
+            } => encode_response(id, &services, &addrs, ttl)?,
+        };
         debug!("Encoded packet to send: {:?}", packet);
 
         dst.extend_from_slice(packet.as_ref());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn published() -> PublishedService {
+        PublishedService::new(
+            "_gu_hub._tcp.local",
+            "node1",
+            61622,
+            vec!["node_id=0x01".into()],
+        )
+    }
+
+    #[test]
+    fn decode_announcement() {
+        let mut codec = MdnsCodec(false);
+        let mut packet = BytesMut::new();
+        codec
+            .encode(
+                MdnsPacket::Response {
+                    id: 7,
+                    services: vec![published()],
+                    addrs: vec!["10.0.0.1".parse().unwrap(), "fe80::1".parse().unwrap()],
+                    ttl: 120,
+                },
+                &mut packet,
+            )
+            .unwrap();
+
+        let parsed = codec.decode(&mut packet).unwrap().unwrap();
+
+        assert_eq!(parsed.id, 7);
+        assert!(parsed.goodbyes.is_empty());
+        assert_eq!(parsed.instances.len(), 1);
+        let instance = &parsed.instances[0];
+        assert_eq!(instance.name, "node1._gu_hub._tcp.local");
+        assert_eq!(instance.host, "node1.local");
+        assert_eq!(instance.ports, vec![61622]);
+        assert_eq!(instance.txt, vec!["node_id=0x01".to_string()]);
+        assert_eq!(instance.addrs_v4, vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        assert_eq!(instance.addrs_v6, vec![addr_v6("fe80::1".parse().unwrap())]);
+    }
+
+    #[test]
+    fn decode_goodbye() {
+        let mut codec = MdnsCodec(false);
+        let mut packet = BytesMut::new();
+        codec
+            .encode(
+                MdnsPacket::Response {
+                    id: 0,
+                    services: vec![published()],
+                    addrs: Vec::new(),
+                    ttl: 0,
+                },
+                &mut packet,
+            )
+            .unwrap();
+
+        let parsed = codec.decode(&mut packet).unwrap().unwrap();
+
+        assert!(parsed.instances.is_empty());
+        assert_eq!(parsed.goodbyes.len(), 1);
+    }
+
+    #[test]
+    fn decode_query() {
+        let mut codec = MdnsCodec(true);
+        let mut packet = BytesMut::new();
+        codec
+            .encode(
+                MdnsPacket::Query(ServicesDescription::new(vec!["hub".into()]), 3),
+                &mut packet,
+            )
+            .unwrap();
+
+        let parsed = codec.decode(&mut packet).unwrap().unwrap();
+
+        assert_eq!(
+            parsed.queried_services,
+            vec!["_gu_hub._tcp.local".to_string()]
+        );
+        assert!(parsed.prefer_unicast);
+    }
+}
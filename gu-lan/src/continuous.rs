@@ -1,13 +1,12 @@
 use actix::{prelude::*, Actor, Context, Handler, Message, Recipient};
-use actor::send_mdns_query;
+use actor::{send_mdns_query, PacketSender};
 use errors::ErrorKind;
-use futures::{sync::mpsc, Future};
+use futures::Future;
 use rand::{thread_rng, Rng, ThreadRng};
 use service::{ServiceDescription, ServiceInstance, ServicesDescription};
 use std::{
     cmp::Ordering,
     collections::{BinaryHeap, HashMap, HashSet},
-    net::SocketAddr,
     time::{Duration, Instant},
 };
 
@@ -180,6 +179,14 @@ impl MemoryManager {
         while self.conditionally_destroy_instance(time) {}
     }
 
+    /// Forgets instance immediately (eg. after goodbye packet)
+    pub fn remove(&mut self, data: ServiceInstance) {
+        let id: ServiceInstanceId = data.into();
+
+        self.time_map.remove(&id);
+        self.data_map.remove(&id);
+    }
+
     pub fn memory(&self) -> Vec<ServiceInstance> {
         self.data_map.values().map(|a| a.clone()).collect()
     }
@@ -203,15 +210,12 @@ pub struct ContinuousInstancesList {
     name: ServiceDescription,
     memory: MemoryManager,
     notifier: ExponentialNotify,
-    sender: mpsc::Sender<((ServicesDescription, u16), SocketAddr)>,
+    sender: PacketSender,
     subscribers: HashSet<Recipient<NewInstance>>,
 }
 
 impl ContinuousInstancesList {
-    pub fn new(
-        name: ServiceDescription,
-        sender: mpsc::Sender<((ServicesDescription, u16), SocketAddr)>,
-    ) -> Self {
+    pub(crate) fn new(name: ServiceDescription, sender: PacketSender) -> Self {
         ContinuousInstancesList {
             name,
             memory: MemoryManager::new(Duration::from_secs(SERVICE_TTL)),
@@ -277,6 +281,26 @@ impl ReceivedMdnsInstance {
     }
 }
 
+pub struct ReceivedMdnsGoodbye(ServiceInstance);
+
+impl ReceivedMdnsGoodbye {
+    pub fn new(s: ServiceInstance) -> Self {
+        ReceivedMdnsGoodbye(s)
+    }
+}
+
+impl Message for ReceivedMdnsGoodbye {
+    type Result = ();
+}
+
+impl Handler<ReceivedMdnsGoodbye> for ContinuousInstancesList {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReceivedMdnsGoodbye, _ctx: &mut Context<Self>) -> () {
+        self.memory.remove(msg.0)
+    }
+}
+
 pub struct NewInstance {
     pub data: ServiceInstance,
 }
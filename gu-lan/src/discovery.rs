@@ -0,0 +1,337 @@
+//! Pluggable discovery of Golem Unlimited nodes.
+//!
+//! mDNS only reaches the local network segment. Nodes in routed networks
+//! are found with unicast DNS-SD queries or with a hub-hosted registry
+//! (see `registry` module).
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::from_utf8,
+    time::Duration,
+};
+
+use actix::Addr;
+use dns_parser::{
+    rdata::{a, aaaa, ptr, RData},
+    Builder, Packet, QueryClass, QueryType,
+};
+use futures::{future, prelude::*};
+use rand;
+use tokio::{net::UdpSocket, timer::Timeout};
+
+use actor::{MdnsActor, OneShot};
+use errors::{Error, ErrorKind, Result};
+use gu_actix::FlattenFuture;
+use service::{addr_v6, ServiceDescription, ServiceInstance, ServicesDescription};
+
+const MAX_PACKET_SIZE: usize = 4096;
+
+pub type DiscoveryFuture = Box<dyn Future<Item = HashSet<ServiceInstance>, Error = Error>>;
+
+/// Source of service instances
+pub trait Discovery {
+    /// Finds instances of the service, eg. "hub" or "provider"
+    fn discover(&self, service: &str) -> DiscoveryFuture;
+}
+
+impl Discovery for Addr<MdnsActor<OneShot>> {
+    fn discover(&self, service: &str) -> DiscoveryFuture {
+        Box::new(
+            self.send(ServicesDescription::new(vec![service.into()]))
+                .flatten_fut(),
+        )
+    }
+}
+
+/// Unicast DNS-SD (RFC 6763) lookups against a configured DNS server
+///
+/// Browses `_gu_<service>._tcp.<domain>` PTR records and resolves the
+/// instances with SRV, TXT, A and AAAA queries when the server doesn't
+/// return them as additional records.
+#[derive(Debug, Clone)]
+pub struct DnsSd {
+    server: SocketAddr,
+    domain: String,
+    timeout: Duration,
+}
+
+impl DnsSd {
+    pub fn new<S: Into<String>>(server: SocketAddr, domain: S) -> Self {
+        DnsSd {
+            server,
+            domain: domain.into(),
+            timeout: Duration::from_secs(3),
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Discovery for DnsSd {
+    fn discover(&self, service: &str) -> DiscoveryFuture {
+        let server = self.server;
+        let timeout = self.timeout;
+        let name = ServiceDescription::new(format!("_gu_{}._tcp", service), self.domain.clone())
+            .to_string();
+
+        Box::new(
+            query(server, &name, QueryType::PTR, timeout)
+                .and_then(move |mut records: Records| {
+                    let lookups = records.missing_instance_records();
+                    lookup_all(server, lookups, timeout).map(move |found| {
+                        records.merge(found);
+                        records
+                    })
+                })
+                .and_then(move |mut records: Records| {
+                    let lookups = records.missing_addresses();
+                    lookup_all(server, lookups, timeout).map(move |found| {
+                        records.merge(found);
+                        records
+                    })
+                })
+                .map(|records| records.instances().into_iter().collect()),
+        )
+    }
+}
+
+/// Records collected from DNS responses
+#[derive(Debug, Default)]
+struct Records {
+    /// instance names
+    ptr: HashSet<String>,
+    /// instance -> (host, port)
+    srv: HashMap<String, (String, u16)>,
+    /// instance -> description
+    txt: HashMap<String, Vec<String>>,
+    /// host -> IPv4
+    a: HashMap<String, Vec<Ipv4Addr>>,
+    /// host -> IPv6
+    aaaa: HashMap<String, Vec<Ipv6Addr>>,
+}
+
+impl Records {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let packet = Packet::parse(data)?;
+        let mut records = Records::default();
+
+        for record in packet.answers.into_iter().chain(packet.additional) {
+            let name = record.name.to_string();
+            match record.data {
+                RData::PTR(ptr::Record(instance)) => {
+                    records.ptr.insert(instance.to_string());
+                }
+                RData::SRV(data) => {
+                    records
+                        .srv
+                        .insert(name, (data.target.to_string(), data.port));
+                }
+                RData::TXT(data) => {
+                    records.txt.insert(
+                        name,
+                        data.iter()
+                            .map(|x| from_utf8(x).unwrap_or_default().to_string())
+                            .collect(),
+                    );
+                }
+                RData::A(a::Record(ip)) => records.a.entry(name).or_default().push(ip),
+                RData::AAAA(aaaa::Record(ip)) => records.aaaa.entry(name).or_default().push(ip),
+                _ => (),
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn merge(&mut self, other: Records) {
+        self.ptr.extend(other.ptr);
+        self.srv.extend(other.srv);
+        self.txt.extend(other.txt);
+        self.a.extend(other.a);
+        self.aaaa.extend(other.aaaa);
+    }
+
+    fn missing_instance_records(&self) -> Vec<(String, QueryType)> {
+        let mut lookups = Vec::new();
+        for instance in &self.ptr {
+            if !self.srv.contains_key(instance) {
+                lookups.push((instance.clone(), QueryType::SRV));
+            }
+            if !self.txt.contains_key(instance) {
+                lookups.push((instance.clone(), QueryType::TXT));
+            }
+        }
+        lookups
+    }
+
+    fn missing_addresses(&self) -> Vec<(String, QueryType)> {
+        let hosts: HashSet<&String> = self
+            .srv
+            .values()
+            .map(|(host, _)| host)
+            .filter(|host| !self.a.contains_key(*host) && !self.aaaa.contains_key(*host))
+            .collect();
+
+        hosts
+            .into_iter()
+            .flat_map(|host| {
+                vec![
+                    (host.clone(), QueryType::A),
+                    (host.clone(), QueryType::AAAA),
+                ]
+            })
+            .collect()
+    }
+
+    fn instances(&self) -> Vec<ServiceInstance> {
+        self.ptr
+            .iter()
+            .filter_map(|name| {
+                let (host, port) = self.srv.get(name)?;
+                Some(ServiceInstance {
+                    name: name.clone(),
+                    host: host.clone(),
+                    txt: self.txt.get(name).cloned().unwrap_or_default(),
+                    addrs_v4: self.a.get(host).cloned().unwrap_or_default(),
+                    addrs_v6: self
+                        .aaaa
+                        .get(host)
+                        .map(|ips| ips.iter().cloned().map(addr_v6).collect())
+                        .unwrap_or_default(),
+                    ports: vec![*port],
+                })
+            })
+            .collect()
+    }
+}
+
+/// Sends single question to the DNS server and parses records of the answer
+fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: QueryType,
+    timeout: Duration,
+) -> impl Future<Item = Records, Error = Error> {
+    let id = rand::random::<u16>();
+    let mut builder = Builder::new_query(id, true);
+    builder.add_question(name, false, qtype, QueryClass::IN);
+
+    let bind_addr: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    future::result(
+        builder
+            .build()
+            .map_err(|p| Error::from(ErrorKind::DnsPacketBuildError(p))),
+    )
+    .and_then(move |packet| {
+        let exchange = future::result(UdpSocket::bind(&bind_addr))
+            .and_then(move |socket| socket.send_dgram(packet, &server))
+            .and_then(move |(socket, _)| {
+                future::loop_fn(socket, move |socket| {
+                    socket.recv_dgram(vec![0u8; MAX_PACKET_SIZE]).map(
+                        move |(socket, mut buf, len, src)| {
+                            // skip stray packets
+                            if src == server && len >= 2 && buf[..2] == id.to_be_bytes() {
+                                buf.truncate(len);
+                                future::Loop::Break(buf)
+                            } else {
+                                future::Loop::Continue(socket)
+                            }
+                        },
+                    )
+                })
+            });
+
+        Timeout::new(exchange, timeout).map_err(|e| {
+            Error::from(
+                e.into_inner().unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out")
+                }),
+            )
+        })
+    })
+    .and_then(|buf| Records::parse(&buf))
+}
+
+/// Follow-up queries; failed lookups are logged and skipped
+fn lookup_all(
+    server: SocketAddr,
+    lookups: Vec<(String, QueryType)>,
+    timeout: Duration,
+) -> impl Future<Item = Records, Error = Error> {
+    future::join_all(lookups.into_iter().map(move |(name, qtype)| {
+        query(server, &name, qtype, timeout).or_else(move |e| {
+            warn!("DNS-SD lookup of {} ({:?}) failed: {}", name, qtype, e);
+            Ok(Records::default())
+        })
+    }))
+    .map(|found| {
+        found
+            .into_iter()
+            .fold(Records::default(), |mut records, r| {
+                records.merge(r);
+                records
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use codec::{MdnsCodec, MdnsPacket};
+    use service::PublishedService;
+    use tokio_codec::Encoder;
+
+    #[test]
+    fn resolve_records() {
+        let mut packet = BytesMut::new();
+        MdnsCodec(false)
+            .encode(
+                MdnsPacket::Response {
+                    id: 1,
+                    services: vec![PublishedService::new(
+                        "_gu_hub._tcp.example.org",
+                        "node1",
+                        61622,
+                        vec!["node_id=0x01".into()],
+                    )],
+                    addrs: Vec::new(),
+                    ttl: 120,
+                },
+                &mut packet,
+            )
+            .unwrap();
+
+        let mut records = Records::parse(&packet[..]).unwrap();
+        assert!(records.missing_instance_records().is_empty());
+        assert_eq!(
+            records.missing_addresses(),
+            vec![
+                ("node1.example.org".to_string(), QueryType::A),
+                ("node1.example.org".to_string(), QueryType::AAAA)
+            ]
+        );
+
+        records
+            .a
+            .insert("node1.example.org".into(), vec![Ipv4Addr::new(10, 1, 2, 3)]);
+        let instances = records.instances();
+
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].name, "node1._gu_hub._tcp.example.org");
+        assert_eq!(
+            instances[0].socket_addr(),
+            Some(SocketAddr::new(Ipv4Addr::new(10, 1, 2, 3).into(), 61622))
+        );
+        assert_eq!(instances[0].txt, vec!["node_id=0x01".to_string()]);
+    }
+}
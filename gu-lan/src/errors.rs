@@ -36,6 +36,11 @@ error_chain! {
             display("cannot send message by do_send")
         }
 
+        RegistryError(t: String) {
+            description("registry request failed")
+            display("registry request failed: {}", t)
+        }
+
         Mailbox
     }
 }
@@ -1,5 +1,7 @@
 //! mDNS discovery for Golem Unlimited nodes.
 //!
+//! Unicast DNS-SD and hub-hosted registry backends are available for
+//! routed networks (see `Discovery`).
 
 extern crate actix;
 extern crate actix_web;
@@ -9,13 +11,13 @@ extern crate dns_parser;
 #[macro_use]
 extern crate error_chain;
 extern crate futures;
+extern crate get_if_addrs;
 extern crate gu_actix;
 extern crate gu_base;
 extern crate gu_net;
 extern crate hostname;
 #[macro_use]
 extern crate log;
-extern crate mdns;
 #[macro_use]
 extern crate prettytable;
 extern crate rand;
@@ -27,19 +29,25 @@ extern crate tokio_codec;
 
 use std::net::SocketAddr;
 
-use mdns::{Responder, Service};
+use actix::{Addr, SystemService};
 use serde::{Deserialize, Serialize};
 
+use actor::{Continuous, MdnsActor};
 pub use continuous::{NewInstance, Subscription};
+pub use discovery::{Discovery, DiscoveryFuture, DnsSd};
 use gu_net::NodeId;
-pub use service::ServiceDescription;
+pub use registry::{RegisteredInstance, Registry, RegistryPublisher};
+use service::PublishedService;
+pub use service::{ServiceDescription, ServiceInstance};
 
 pub mod actor;
 mod codec;
 mod continuous;
+mod discovery;
 
 pub mod errors;
 pub mod module;
+pub mod registry;
 mod service;
 
 pub const ID_LAN: u32 = 576411;
@@ -54,6 +62,26 @@ pub struct HubDesc {
     pub node_id: NodeId,
 }
 
+impl HubDesc {
+    fn from_instance(instance: ServiceInstance) -> Option<Self> {
+        let node_id = match instance.extract("node_id") {
+            Some(Ok(node_id)) => node_id,
+            _ => return None,
+        };
+        match instance.socket_addr() {
+            Some(address) => Some(HubDesc {
+                address,
+                host_name: instance.host,
+                node_id,
+            }),
+            None => {
+                warn!("instance not found");
+                None
+            }
+        }
+    }
+}
+
 /// Lists HUBs visible in local network.
 ///
 /// # Example
@@ -84,53 +112,37 @@ pub struct HubDesc {
 /// ```
 pub fn list_hubs() -> impl futures::Future<Item = Vec<HubDesc>, Error = ()> {
     use self::actor::{MdnsActor, OneShot};
-    use self::service::{ServiceInstance, ServicesDescription};
-    use actix::prelude::*;
+
+    find_hubs(&MdnsActor::<OneShot>::from_registry())
+}
+
+/// Lists HUBs found by the given discovery backend.
+pub fn find_hubs<D: Discovery + ?Sized>(
+    discovery: &D,
+) -> impl futures::Future<Item = Vec<HubDesc>, Error = ()> {
     use futures::prelude::*;
-    use gu_actix::prelude::*;
-    use std::collections::HashSet;
-
-    let query = ServicesDescription::new(vec!["hub".into()]);
-
-    MdnsActor::<OneShot>::from_registry()
-        .send(query)
-        .flatten_fut()
-        .and_then(|mut r: HashSet<ServiceInstance>| {
-            Ok(r.drain()
-                .filter_map(|service_instance| {
-                    let node_id = match service_instance.extract("node_id") {
-                        Some(Ok(node_id)) => node_id,
-                        _ => return None,
-                    };
-                    let host_name = service_instance.host;
-                    match (
-                        service_instance.addrs_v4.first(),
-                        service_instance.ports.first(),
-                    ) {
-                        (Some(address), Some(port)) => {
-                            let address = (*address, *port as u16).into();
-                            Some(HubDesc {
-                                address,
-                                host_name,
-                                node_id,
-                            })
-                        }
-                        (_, _) => {
-                            warn!("instance not found");
-                            None
-                        }
-                    }
-                })
+
+    discovery
+        .discover("hub")
+        .and_then(|instances| {
+            Ok(instances
+                .into_iter()
+                .filter_map(HubDesc::from_instance)
                 .collect())
         })
-        .map_err(|_e| ())
+        .map_err(|e| warn!("hub discovery failed: {}", e))
 }
 
+/// Publishes this node in the local network.
+///
+/// Announces the service on start, responds to queries for it and sends
+/// goodbye packets on stop (or on termination signal).
 pub struct MdnsPublisher {
     is_hub: bool,
     port: Option<u16>,
     txt: Vec<String>,
-    service: Option<Service>,
+    /// kept to withdraw the service also outside of a running system
+    published: Option<(PublishedService, Addr<MdnsActor<Continuous>>)>,
 }
 
 impl Default for MdnsPublisher {
@@ -139,7 +151,7 @@ impl Default for MdnsPublisher {
             is_hub: true,
             port: None,
             txt: Vec::new(),
-            service: None,
+            published: None,
         }
     }
 }
@@ -151,18 +163,25 @@ impl MdnsPublisher {
     }
 
     pub fn start(&mut self) {
-        if self.service.is_none() {
-            self.service = self.mdns_publisher()
+        use actor::Publish;
+
+        if self.published.is_none() {
+            let service = self.mdns_service();
+            let actor = MdnsActor::<Continuous>::from_registry();
+            actor.do_send(Publish(service.clone()));
+            self.published = Some((service, actor));
         }
     }
 
     pub fn stop(&mut self) {
-        self.service = None
-    }
+        use actor::Unpublish;
 
-    fn mdns_publisher(&self) -> Option<Service> {
-        use std::iter::FromIterator;
+        if let Some((service, actor)) = self.published.take() {
+            actor.do_send(Unpublish(service));
+        }
+    }
 
+    fn mdns_service(&self) -> PublishedService {
         if self.port.is_none() {
             error!("Cannot start mDNS publisher - server not properly initialized");
             panic!("mDNS publisher not initialized before use");
@@ -173,25 +192,17 @@ impl MdnsPublisher {
             false => "_provider",
         };
 
-        let port = self.port.unwrap();
-        let responder = Responder::new()
-            .or_else(|e| {
-                error!("Failed to run mDNS publisher - {}", e);
-                Err(())
-            })
-            .ok()?;
-
         let name = hostname::get_hostname().unwrap_or_else(|| {
             error!("Couldn't retrieve local hostname");
             "<blank hostname>".to_string()
         });
 
-        Some(responder.register(
-            format!("_gu{}._tcp", service),
+        PublishedService::new(
+            format!("_gu{}._tcp.local", service),
             name,
-            port,
-            &Vec::from_iter(self.txt.iter().map(|s| s.as_str())).as_slice(),
-        ))
+            self.port.unwrap(),
+            self.txt.clone(),
+        )
     }
 
     pub fn init_publisher<S>(port: u16, node_id: S, is_hub: bool) -> Self
@@ -206,3 +217,9 @@ impl MdnsPublisher {
         mdns
     }
 }
+
+impl Drop for MdnsPublisher {
+    fn drop(&mut self) {
+        self.stop()
+    }
+}
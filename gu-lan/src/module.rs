@@ -4,26 +4,24 @@ use actix::{Arbiter, System};
 use actix_web::{http, AsyncResponder, HttpRequest, HttpResponse, Responder, Scope};
 use actor::{MdnsActor, OneShot};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
-use futures::Future;
+use discovery::{Discovery, DnsSd};
+use futures::{future, Future};
 use gu_base::{cli, Decorator, Module};
+use registry::Registry;
 use serde::Serialize;
 use service::{ServiceInstance, ServicesDescription};
-use std::{collections::HashSet, net::Ipv4Addr};
-
-fn format_addresses(addrs_v4: &Vec<Ipv4Addr>, ports: &Vec<u16>) -> String {
-    let mut res = String::new();
-    let addr = addrs_v4
-        .first()
-        .map(|ip| format!("{:?}", ip))
-        .unwrap_or("<missing ip>".to_string());
-
-    for port in ports {
-        res.push_str(addr.as_ref());
-        res.push(':');
-        res.push_str(&format!("{}", &port));
+use std::collections::HashSet;
+
+fn format_addresses(instance: &ServiceInstance) -> String {
+    match instance.ip() {
+        Some(_) => instance
+            .ports
+            .iter()
+            .filter_map(|port| instance.socket_addr_with_port(*port))
+            .map(|addr| addr.to_string())
+            .collect(),
+        None => "<missing ip>".to_string(),
     }
-
-    res
 }
 
 pub fn format_instances_table(instances: &HashSet<ServiceInstance>) {
@@ -34,7 +32,7 @@ pub fn format_instances_table(instances: &HashSet<ServiceInstance>) {
             row![
                 instance.service(),
                 instance.host,
-                format_addresses(&instance.addrs_v4, &instance.ports),
+                format_addresses(instance),
                 instance.txt.join(""),
             ]
         }),
@@ -63,9 +61,28 @@ fn run_client(instances: &String) {
     let _ = sys.run();
 }
 
+fn run_discovery(instances: &String, discovery: &dyn Discovery) {
+    let sys = actix::System::new("gu-lan");
+
+    Arbiter::spawn(
+        future::join_all(
+            instances
+                .split(',')
+                .map(|service| discovery.discover(service))
+                .collect::<Vec<_>>(),
+        )
+        .and_then(|r| Ok(format_instances_table(&r.into_iter().flatten().collect())))
+        .map_err(|e| error!("error! {}", e))
+        .then(|_| Ok(System::current().stop())),
+    );
+
+    let _ = sys.run();
+}
+
 enum LanCommand {
     None,
     List(String),
+    Discover(String, Box<dyn Discovery + Send + Sync>),
 }
 
 pub struct LanModule {
@@ -86,6 +103,25 @@ impl Module for LanModule {
             .short("I")
             .help("Queries mDNS server about some instance types (comma-separated, e.g. hub,provider)")
             .takes_value(true);
+        let dns_server = Arg::with_name("dns_server")
+            .long("dns-server")
+            .help("Uses unicast DNS-SD queries to the given server (IP:PORT) instead of mDNS")
+            .takes_value(true)
+            .requires("domain");
+        let domain = Arg::with_name("domain")
+            .long("domain")
+            .help("Domain browsed with unicast DNS-SD")
+            .takes_value(true);
+        let registry = Arg::with_name("registry")
+            .long("registry")
+            .help("Lists instances registered at the given hub URL instead of using mDNS")
+            .takes_value(true)
+            .conflicts_with("dns_server");
+        let registry_token = Arg::with_name("registry_token")
+            .long("registry-token")
+            .help("Token configured in the registry")
+            .takes_value(true)
+            .requires("registry");
 
         app.subcommand(
             SubCommand::with_name("lan")
@@ -93,7 +129,11 @@ impl Module for LanModule {
                 .subcommand(
                     SubCommand::with_name("list")
                         .about("Lists available instances (use -I to filter results)")
-                        .arg(instance),
+                        .arg(instance)
+                        .arg(dns_server)
+                        .arg(domain)
+                        .arg(registry)
+                        .arg(registry_token),
                 )
                 .about("Shows information about all hubs and providers in the local area network"),
         )
@@ -102,11 +142,29 @@ impl Module for LanModule {
     fn args_consume(&mut self, matches: &ArgMatches) -> bool {
         if let Some(m) = matches.subcommand_matches("lan") {
             self.command = match m.subcommand() {
-                ("list", Some(m)) => LanCommand::List(
-                    m.value_of("instance_types")
+                ("list", Some(m)) => {
+                    let instances = m
+                        .value_of("instance_types")
                         .unwrap_or("hub,provider")
-                        .to_string(),
-                ),
+                        .to_string();
+
+                    if let Some(server) = m.value_of("dns_server") {
+                        let server = match server.parse() {
+                            Ok(server) => server,
+                            Err(e) => {
+                                error!("Invalid DNS server address {}: {}", server, e);
+                                return false;
+                            }
+                        };
+                        let domain = m.value_of("domain").unwrap_or_default();
+                        LanCommand::Discover(instances, Box::new(DnsSd::new(server, domain)))
+                    } else if let Some(url) = m.value_of("registry") {
+                        let token = m.value_of("registry_token").map(String::from);
+                        LanCommand::Discover(instances, Box::new(Registry::new(url).token(token)))
+                    } else {
+                        LanCommand::List(instances)
+                    }
+                }
                 _ => return false,
             };
             true
@@ -118,6 +176,7 @@ impl Module for LanModule {
     fn run<D: Decorator + Clone + 'static>(&self, _decorator: D) {
         match self.command {
             LanCommand::List(ref s) => run_client(s),
+            LanCommand::Discover(ref s, ref discovery) => run_discovery(s, discovery.as_ref()),
             _ => (),
         }
     }
@@ -157,7 +216,7 @@ fn list_hubs<S>(_r: HttpRequest<S>) -> impl Responder {
                 .map(|instance| Reply {
                     serv_type: instance.service(),
                     host_name: instance.host.clone(),
-                    addr: format_addresses(&instance.addrs_v4, &instance.ports),
+                    addr: format_addresses(&instance),
                     desc: instance.txt.join("\n"),
                 })
                 .collect::<Vec<Reply>>();
@@ -0,0 +1,224 @@
+//! Client side of the hub-hosted registry.
+//!
+//! Nodes unreachable by mDNS register themselves in a registry hosted by
+//! a hub (`/registry/{service}` endpoint) and find each other by listing it.
+//! Requests carry the token configured in the hosting hub.
+
+use std::{net::IpAddr, time::Duration};
+
+use actix::prelude::*;
+use actix_web::{self, client, http::Method, HttpMessage};
+use futures::{future, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use discovery::{Discovery, DiscoveryFuture};
+use errors::{Error, ErrorKind};
+use service::{addr_v6, ServiceDescription, ServiceInstance};
+
+/// Registrations not renewed within this time expire
+pub const REGISTRATION_TTL: Duration = Duration::from_secs(120);
+const RENEW_INTERVAL: Duration = Duration::from_secs(60);
+/// Header with the registry token
+pub const TOKEN_HEADER: &str = "X-GU-Registry-Token";
+
+/// Entry of the registry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisteredInstance {
+    /// Unique name of the instance; eg. node id
+    pub name: String,
+    pub host_name: String,
+    /// The registry fills in the address of the registering node if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    pub port: u16,
+    #[serde(default)]
+    pub txt: Vec<String>,
+}
+
+impl RegisteredInstance {
+    fn into_service_instance(self, service: &str) -> Option<ServiceInstance> {
+        let (addrs_v4, addrs_v6) = match self.ip? {
+            IpAddr::V4(ip) => (vec![ip], Vec::new()),
+            IpAddr::V6(ip) => (Vec::new(), vec![addr_v6(ip)]),
+        };
+
+        Some(ServiceInstance {
+            name: format!(
+                "{}.{}",
+                self.name,
+                ServiceDescription::from(service).to_string()
+            ),
+            host: self.host_name,
+            txt: self.txt,
+            addrs_v4,
+            addrs_v6,
+            ports: vec![self.port],
+        })
+    }
+}
+
+fn registry_url(url: &str, service: &str) -> String {
+    format!("{}/registry/{}", url.trim_end_matches('/'), service)
+}
+
+fn request(method: Method, url: &str, token: &Option<String>) -> client::ClientRequestBuilder {
+    let mut builder = client::ClientRequest::build();
+    builder.method(method).uri(url);
+    if let Some(token) = token {
+        builder.header(TOKEN_HEADER, token.as_str());
+    }
+    builder
+}
+
+fn registry_error<E: ToString>(e: E) -> Error {
+    ErrorKind::RegistryError(e.to_string()).into()
+}
+
+fn send(
+    request: Result<client::ClientRequest, actix_web::Error>,
+) -> impl Future<Item = client::ClientResponse, Error = Error> {
+    future::result(request)
+        .map_err(registry_error)
+        .and_then(|request| request.send().map_err(registry_error))
+        .and_then(|response| {
+            if response.status().is_success() {
+                Ok(response)
+            } else {
+                Err(registry_error(format!("status {}", response.status())))
+            }
+        })
+}
+
+/// Lists instances registered in a registry hosted by the hub at `url`
+#[derive(Debug, Clone)]
+pub struct Registry {
+    url: String,
+    token: Option<String>,
+}
+
+impl Registry {
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        Registry {
+            url: url.into(),
+            token: None,
+        }
+    }
+
+    pub fn token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+}
+
+impl Discovery for Registry {
+    fn discover(&self, service: &str) -> DiscoveryFuture {
+        let service = service.to_string();
+
+        Box::new(
+            send(request(Method::GET, &registry_url(&self.url, &service), &self.token).finish())
+                .and_then(|response| {
+                    response
+                        .json::<Vec<RegisteredInstance>>()
+                        .map_err(registry_error)
+                })
+                .map(move |instances| {
+                    instances
+                        .into_iter()
+                        .filter_map(|instance| instance.into_service_instance(&service))
+                        .collect()
+                }),
+        )
+    }
+}
+
+/// Keeps this node registered in hub-hosted registries
+///
+/// Registrations are renewed periodically and removed when the actor stops.
+pub struct RegistryPublisher {
+    urls: Vec<String>,
+    service: String,
+    instance: RegisteredInstance,
+    token: Option<String>,
+}
+
+impl RegistryPublisher {
+    pub fn new<S: Into<String>>(
+        urls: Vec<String>,
+        service: S,
+        instance: RegisteredInstance,
+    ) -> Self {
+        RegistryPublisher {
+            urls,
+            service: service.into(),
+            instance,
+            token: None,
+        }
+    }
+
+    pub fn token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    fn register(&self) {
+        for url in &self.urls {
+            let url = registry_url(url, &self.service);
+            Arbiter::spawn(
+                send(request(Method::PUT, &url, &self.token).json(&self.instance))
+                    .map(|_| ())
+                    .map_err(move |e| warn!("Cannot register in {}: {}", url, e)),
+            );
+        }
+    }
+
+    fn unregister(&self) {
+        for url in &self.urls {
+            let url = format!(
+                "{}/{}",
+                registry_url(url, &self.service),
+                self.instance.name
+            );
+            Arbiter::spawn(
+                send(request(Method::DELETE, &url, &self.token).finish())
+                    .map(|_| ())
+                    .map_err(move |e| warn!("Cannot unregister from {}: {}", url, e)),
+            );
+        }
+    }
+}
+
+impl Actor for RegistryPublisher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.register();
+        ctx.run_interval(RENEW_INTERVAL, |act, _ctx| act.register());
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.unregister();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn registered_instance() {
+        let instance: RegisteredInstance = serde_json::from_str(
+            r#"{"name": "0x01", "hostName": "node1", "ip": "10.0.0.1", "port": 61622}"#,
+        )
+        .unwrap();
+
+        let instance = instance.into_service_instance("hub").unwrap();
+        assert_eq!(instance.name, "0x01._gu_hub._tcp.local");
+        assert_eq!(
+            instance.socket_addr(),
+            Some("10.0.0.1:61622".parse().unwrap())
+        );
+        assert!(instance.txt.is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
     fmt::Display,
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
     result::Result as StdResult,
     str::FromStr,
 };
@@ -90,9 +90,21 @@ pub struct ServiceInstance {
     pub host: String,
     pub txt: Vec<String>,
     pub addrs_v4: Vec<Ipv4Addr>,
+    /// IPv6 addresses with the scope (interface) of link-local ones; ports
+    /// are not used
+    pub addrs_v6: Vec<SocketAddrV6>,
     pub ports: Vec<u16>,
 }
 
+/// IPv6 address of a service, without scope
+pub fn addr_v6(ip: Ipv6Addr) -> SocketAddrV6 {
+    SocketAddrV6::new(ip, 0, 0, 0)
+}
+
+fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
 impl ServiceInstance {
     pub fn extract<T, R>(&self, key: &T) -> Option<StdResult<R, R::Err>>
     where
@@ -108,6 +120,37 @@ impl ServiceInstance {
             .next()
     }
 
+    fn preferred_v6(&self) -> Option<&SocketAddrV6> {
+        self.addrs_v6
+            .iter()
+            .find(|addr| !is_link_local(addr.ip()))
+            .or_else(|| self.addrs_v6.first())
+    }
+
+    /// Preferred address of the instance: IPv4 first, then routable IPv6
+    /// and link-local IPv6 as the last resort.
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.addrs_v4
+            .first()
+            .map(|ip| IpAddr::V4(*ip))
+            .or_else(|| self.preferred_v6().map(|addr| IpAddr::V6(*addr.ip())))
+    }
+
+    /// Preferred address with the `port`, including the scope of
+    /// a link-local IPv6 address
+    pub fn socket_addr_with_port(&self, port: u16) -> Option<SocketAddr> {
+        match self.addrs_v4.first() {
+            Some(ip) => Some(SocketAddr::new(IpAddr::V4(*ip), port)),
+            None => self.preferred_v6().map(|addr| {
+                SocketAddrV6::new(*addr.ip(), port, addr.flowinfo(), addr.scope_id()).into()
+            }),
+        }
+    }
+
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        self.socket_addr_with_port(*self.ports.first()?)
+    }
+
     pub(crate) fn service(&self) -> String {
         let mut res = String::new();
         self.name.split('.').skip(1).for_each(|x| {
@@ -119,6 +162,55 @@ impl ServiceInstance {
     }
 }
 
+/// Service instance published by this node
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PublishedService {
+    /// Service type with domain; eg. "_gu_hub._tcp.local"
+    service: String,
+    /// Instance label, also used as the host label in the service domain
+    instance: String,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+
+impl PublishedService {
+    pub fn new<S: Into<String>, I: AsRef<str>>(
+        service: S,
+        instance: I,
+        port: u16,
+        txt: Vec<String>,
+    ) -> Self {
+        // labels can't contain dots
+        let instance = instance
+            .as_ref()
+            .split('.')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        PublishedService {
+            service: service.into(),
+            instance,
+            port,
+            txt,
+        }
+    }
+
+    pub fn service_name(&self) -> &str {
+        &self.service
+    }
+
+    pub fn instance_name(&self) -> String {
+        format!("{}.{}", self.instance, self.service)
+    }
+
+    /// Host name in the domain of the service; eg. "node1.local"
+    pub fn host_name(&self) -> String {
+        let domain = self.service.splitn(3, '.').nth(2).unwrap_or("local");
+        format!("{}.{}", self.instance, domain)
+    }
+}
+
 #[derive(Debug, Serialize, Default)]
 pub struct Services {
     map: HashMap<String, HashSet<ServiceInstance>>,
@@ -154,3 +246,43 @@ impl Services {
         set
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(addrs_v4: Vec<Ipv4Addr>, addrs_v6: Vec<SocketAddrV6>) -> ServiceInstance {
+        ServiceInstance {
+            name: "host._gu_hub._tcp.local".into(),
+            host: "host.local".into(),
+            txt: Vec::new(),
+            addrs_v4,
+            addrs_v6,
+            ports: vec![61622],
+        }
+    }
+
+    #[test]
+    fn preferred_address() {
+        let link_local = SocketAddrV6::new("fe80::1".parse().unwrap(), 0, 0, 2);
+        let global = addr_v6("2001:db8::1".parse().unwrap());
+
+        assert_eq!(
+            instance(vec![Ipv4Addr::new(10, 0, 0, 1)], vec![global]).ip(),
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+        );
+        assert_eq!(
+            instance(Vec::new(), vec![link_local, global]).socket_addr(),
+            Some(SocketAddr::new(IpAddr::V6(*global.ip()), 61622))
+        );
+        assert_eq!(
+            instance(Vec::new(), vec![link_local]).ip(),
+            Some(IpAddr::V6(*link_local.ip()))
+        );
+        assert_eq!(
+            instance(Vec::new(), vec![link_local]).socket_addr(),
+            Some("[fe80::1%2]:61622".parse().unwrap())
+        );
+        assert_eq!(instance(Vec::new(), Vec::new()).socket_addr(), None);
+    }
+}
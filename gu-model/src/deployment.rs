@@ -23,6 +23,9 @@ pub enum DeploymentStatus {
     /// during session removal
     #[serde(rename = "destroying")]
     DESTROYING,
+    /// hub only: vanished from the provider while it was disconnected
+    #[serde(rename = "lost")]
+    LOST,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -36,6 +39,25 @@ pub struct DeploymentInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub note: Option<String>,
     pub processes: PidSet,
+    /// image download progress of a pending deployment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadProgress>,
+    /// allowed destinations of a deployment with restricted network access
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadProgress {
+    /// hash of the image being downloaded
+    pub image_hash: String,
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    /// bytes per second
+    pub rate: u64,
+    /// estimated time left in seconds
+    pub eta: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -54,6 +76,21 @@ impl From<gu_net::rpc::peer::PeerSessionInfo> for DeploymentInfo {
             tags: peer.tags.into_iter().collect(),
             note: peer.note,
             processes: PidSet::new(),
+            download: peer.download.map(Into::into),
+            egress: peer.egress,
+        }
+    }
+}
+
+#[cfg(feature = "with-actix")]
+impl From<gu_net::rpc::peer::DownloadProgress> for DownloadProgress {
+    fn from(progress: gu_net::rpc::peer::DownloadProgress) -> Self {
+        DownloadProgress {
+            image_hash: progress.image_hash,
+            downloaded_bytes: progress.downloaded_bytes,
+            total_bytes: progress.total_bytes,
+            rate: progress.rate,
+            eta: progress.eta,
         }
     }
 }
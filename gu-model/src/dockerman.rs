@@ -14,6 +14,10 @@ pub struct CreateOptions {
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub cap_add: Vec<String>,
+    /// base64 encoded `RegistryAuth` JSON encrypted to the provider public key
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry_auth: Option<String>,
 }
 
 impl CreateOptions {
@@ -23,6 +27,13 @@ impl CreateOptions {
     }
 }
 
+/// Credentials for the registry of the deployment image
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RegistryAuth {
+    pub username: String,
+    pub password: String,
+}
+
 #[derive(Serialize, Deserialize, Hash, Clone, Eq, PartialEq)]
 pub enum VolumeDef {
     BindRw { src: String, target: String },
@@ -32,6 +43,15 @@ pub enum VolumeDef {
 pub enum NetDef {
     #[serde(rename = "host")]
     Host {},
+    /// dedicated network allowing connections only to the hubs and listed
+    /// destinations: IPv4 addresses, CIDRs or URLs. Hubs are the ones the
+    /// provider is connected to when the deployment is created; the network
+    /// has no IPv6.
+    #[serde(rename = "restricted")]
+    Restricted {
+        #[serde(default)]
+        allow: Vec<String>,
+    },
 }
 
 impl VolumeDef {
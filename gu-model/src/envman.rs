@@ -1,4 +1,4 @@
-use std::{fmt, io};
+use std::{fmt, io, time::Duration};
 
 #[cfg(feature = "with-actix")]
 use actix::prelude::*;
@@ -18,6 +18,8 @@ pub enum Error {
     IoError(String),
     NoSuchSession(String),
     NoSuchChild(String),
+    NoSuchFile(String),
+    AccessDenied(String),
     UnknownEnv(String),
 }
 
@@ -44,6 +46,8 @@ impl fmt::Display for Error {
             Error::IoError(msg) => write!(f, "IO error: {}", msg)?,
             Error::NoSuchSession(msg) => write!(f, "session not found: {}", msg)?,
             Error::NoSuchChild(msg) => write!(f, "child not found: {}", msg)?,
+            Error::NoSuchFile(path) => write!(f, "file not found: {}", path)?,
+            Error::AccessDenied(path) => write!(f, "access denied: {}", path)?,
             Error::UnknownEnv(env_id) => write!(f, "unknown exec environment: {}", env_id)?,
         }
         Ok(())
@@ -62,6 +66,9 @@ impl From<String> for Error {
 pub struct Image {
     pub url: String,
     pub hash: String,
+    /// alternative urls of the same content, used when `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
 }
 
 /// Message for session creation: local provisioning: downloads and unpacks the binaries
@@ -79,6 +86,10 @@ pub struct CreateSession<Options = ()> {
 
 pub type GenericCreateSession = CreateSession<::serde_json::Value>;
 
+/// Session creation includes the image download; requests not delivered
+/// within this time are dropped
+pub const CREATE_SESSION_TIMEOUT: Duration = Duration::from_secs(3600);
+
 #[cfg(feature = "with-actix")]
 impl<Options> PublicMessage for CreateSession<Options> {
     const ID: u32 = 37;
@@ -107,6 +118,10 @@ impl PublicMessage for SessionUpdate {
 pub enum ResourceFormat {
     Raw,
     Tar,
+    TarGz,
+    TarZstd,
+    /// packed through a temporary file on the provider
+    Zip,
 }
 
 impl Default for ResourceFormat {
@@ -147,6 +162,10 @@ pub enum Command {
         file_path: String,
         #[serde(default)]
         format: ResourceFormat,
+        /// expected hash of the downloaded content, e.g. `SHA1:<hex value>`
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hash: Option<String>,
     },
     #[serde(rename_all = "camelCase")]
     UploadFile {
@@ -155,13 +174,117 @@ pub enum Command {
         #[serde(default)]
         format: ResourceFormat,
     },
+    /// packs workspace files matching any of `patterns` (and none of `exclude`)
+    /// into a single archive; returns JSON list of the files included
+    #[serde(rename_all = "camelCase")]
+    UploadFiles {
+        uri: String,
+        patterns: Vec<String>,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        exclude: Vec<String>,
+        format: ResourceFormat,
+    },
     #[serde(rename_all = "camelCase")]
     WriteFile {
-        content: String,
+        content: FileData,
         file_path: String,
+        /// unix permission bits, e.g. `0o755`
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+    },
+    /// returns file content (up to `max_bytes`) as command output
+    #[serde(rename_all = "camelCase")]
+    ReadFile {
+        file_path: String,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        max_bytes: Option<u64>,
+        #[serde(default)]
+        encoding: ContentEncoding,
     },
 }
 
+/// Default limit for `Command::ReadFile` output
+pub const DEFAULT_READ_LIMIT: u64 = 1024 * 1024;
+
+/// File content sent inline with a command.
+///
+/// Serialized as a plain string, an array of bytes or `{"base64": "..."}`.
+#[derive(Clone, Serialize, Deserialize, Hash, Eq, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum FileData {
+    Text(String),
+    Bytes(Vec<u8>),
+    Base64 { base64: String },
+}
+
+impl FileData {
+    pub fn base64(bytes: impl AsRef<[u8]>) -> Self {
+        FileData::Base64 {
+            base64: base64::encode(bytes.as_ref()),
+        }
+    }
+
+    pub fn into_bytes(self) -> Result<Vec<u8>, String> {
+        match self {
+            FileData::Text(text) => Ok(text.into_bytes()),
+            FileData::Bytes(bytes) => Ok(bytes),
+            FileData::Base64 { base64 } => {
+                base64::decode(&base64).map_err(|e| format!("invalid base64 content: {}", e))
+            }
+        }
+    }
+}
+
+impl From<String> for FileData {
+    fn from(text: String) -> Self {
+        FileData::Text(text)
+    }
+}
+
+impl From<Vec<u8>> for FileData {
+    fn from(bytes: Vec<u8>) -> Self {
+        FileData::Bytes(bytes)
+    }
+}
+
+/// Representation of file content returned by `Command::ReadFile`
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentEncoding {
+    Text,
+    Base64,
+}
+
+impl Default for ContentEncoding {
+    fn default() -> Self {
+        ContentEncoding::Text
+    }
+}
+
+impl ContentEncoding {
+    /// Encodes the file content; `truncated` content may end in the middle
+    /// of a UTF-8 character, which is then left out of the text.
+    pub fn encode(self, bytes: Vec<u8>, truncated: bool) -> Result<String, String> {
+        match self {
+            ContentEncoding::Text => String::from_utf8(bytes).or_else(|e| {
+                let error = e.utf8_error();
+                match (truncated, error.error_len()) {
+                    (true, None) => {
+                        let mut bytes = e.into_bytes();
+                        bytes.truncate(error.valid_up_to());
+                        String::from_utf8(bytes).map_err(|e| e.to_string())
+                    }
+                    _ => Err("file is not valid UTF-8 text, use base64 encoding".to_string()),
+                }
+            }),
+            ContentEncoding::Base64 => Ok(base64::encode(&bytes)),
+        }
+    }
+}
+
 #[cfg(feature = "with-actix")]
 impl Message for SessionUpdate {
     type Result = Result<Vec<String>, Vec<String>>;
@@ -180,6 +303,20 @@ impl Message for GetSessions {
     type Result = Result<Vec<PeerSessionInfo>, ()>;
 }
 
+/// Returns the hex encoded public key deployment secrets are encrypted to
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct GetPublicKey {}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for GetPublicKey {
+    const ID: u32 = 45;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for GetPublicKey {
+    type Result = Result<String, String>;
+}
+
 /// Message for session destruction: clean local resources and kill all child processes
 #[derive(Serialize, Deserialize)]
 pub struct DestroySession {
@@ -196,6 +333,73 @@ impl Message for DestroySession {
     type Result = Result<String, Error>;
 }
 
+/// Operation on a file or directory inside a deployment workspace
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum FsOp {
+    /// directory listing or (ranged) file content
+    #[serde(rename_all = "camelCase")]
+    Get {
+        #[serde(default)]
+        offset: u64,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        length: Option<u64>,
+    },
+    Stat,
+    Delete,
+}
+
+/// Message for remote access to files of a deployment.
+/// `path` is relative to the deployment workspace and cannot escape it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceFs {
+    pub session_id: String,
+    pub path: String,
+    pub op: FsOp,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for WorkspaceFs {
+    const ID: u32 = 41;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for WorkspaceFs {
+    type Result = Result<FsResult, Error>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FileInfo {
+    pub name: String,
+    /// path relative to the workspace root
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FileContent {
+    pub offset: u64,
+    pub total_size: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum FsResult {
+    Listing(Vec<FileInfo>),
+    Content(FileContent),
+    Stat(FileInfo),
+    Deleted,
+}
+
 #[cfg(test)]
 mod test {
     use serde_json;
@@ -223,6 +427,7 @@ mod test {
         assert_eq!(c.env_type, "hd");
         assert_eq!(c.image.url, "http://some.url/file.tgz");
         assert_eq!(c.image.hash, "12345");
+        assert!(c.image.mirrors.is_empty());
         assert_eq!(c.tags.len(), 1);
         assert_eq!(c.tags[0], "lato");
     }
@@ -289,4 +494,98 @@ mod test {
             panic!("DelTags command expected");
         }
     }
+
+    #[test]
+    fn test_workspace_fs_deserialization() {
+        // given
+        let json = r#"
+        {
+            "sessionId":"hd::4c562af4-db3f-4e57-8fac-cf30249db682",
+            "path":"out/frame_0001.png",
+            "op":{"get":{"offset":1024}}
+        }"#;
+
+        // when
+        let m: WorkspaceFs = serde_json::from_str(json).unwrap();
+
+        // then
+        assert_eq!(m.path, "out/frame_0001.png");
+        if let FsOp::Get { offset, length } = m.op {
+            assert_eq!(offset, 1024);
+            assert_eq!(length, None);
+        } else {
+            panic!("Get op expected");
+        }
+    }
+
+    #[test]
+    fn test_write_file_content_deserialization() {
+        // given
+        let json = r#"
+        [
+            {"writeFile":{"filePath":"spec.json","content":"{}"}},
+            {"writeFile":{"filePath":"run.sh","content":{"base64":"IyEvYmluL3No"},"mode":493}},
+            {"writeFile":{"filePath":"raw.bin","content":[0,159,255]}}
+        ]"#;
+
+        // when
+        let commands: Vec<Command> = serde_json::from_str(json).unwrap();
+
+        // then
+        let contents: Vec<(Vec<u8>, Option<u32>)> = commands
+            .into_iter()
+            .map(|command| match command {
+                Command::WriteFile { content, mode, .. } => (content.into_bytes().unwrap(), mode),
+                _ => panic!("WriteFile command expected"),
+            })
+            .collect();
+        assert_eq!(contents[0], (b"{}".to_vec(), None));
+        assert_eq!(contents[1], (b"#!/bin/sh".to_vec(), Some(0o755)));
+        assert_eq!(contents[2], (vec![0, 159, 255], None));
+    }
+
+    #[test]
+    fn test_upload_files_deserialization() {
+        // given
+        let json = r#"{"uploadFiles":{"uri":"http://hub/blobs/1","patterns":["output/*.png"],"format":"tarGz"}}"#;
+
+        // when
+        let command: Command = serde_json::from_str(json).unwrap();
+
+        // then
+        match command {
+            Command::UploadFiles {
+                patterns,
+                exclude,
+                format,
+                ..
+            } => {
+                assert_eq!(patterns, vec!["output/*.png".to_string()]);
+                assert!(exclude.is_empty());
+                assert_eq!(format, ResourceFormat::TarGz);
+            }
+            _ => panic!("UploadFiles command expected"),
+        }
+    }
+
+    #[test]
+    fn test_read_file_encoding() {
+        assert_eq!(
+            ContentEncoding::Base64
+                .encode(vec![0, 159, 255], false)
+                .unwrap(),
+            "AJ//"
+        );
+        assert!(ContentEncoding::Text
+            .encode(vec![0, 159, 255], false)
+            .is_err());
+        assert_eq!(
+            ContentEncoding::Text.encode(b"{}".to_vec(), false).unwrap(),
+            "{}"
+        );
+        // "zażółć" cut in the middle of "ł"
+        let cut = "zażółć".as_bytes()[..7].to_vec();
+        assert!(ContentEncoding::Text.encode(cut.clone(), false).is_err());
+        assert_eq!(ContentEncoding::Text.encode(cut, true).unwrap(), "zażó");
+    }
 }
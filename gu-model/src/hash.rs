@@ -58,7 +58,7 @@ fn to_hex(ch: u8) -> Result<u8, Error> {
     match ch {
         b'0'..=b'9' => Ok(ch - b'0'),
         b'a'..=b'f' => Ok(ch - b'a' + 10),
-        b'A'..=b'F' => Ok(ch - b'a' + 10),
+        b'A'..=b'F' => Ok(ch - b'A' + 10),
         _ => Err(Error::BadChar(ch)),
     }
 }
@@ -234,6 +234,10 @@ mod test {
             b"golem2",
             "SHA3:7ba62e92095980b4fd8a743d608d8a5b0b0224105ddab845845b7c622c60f248",
         );
+        test_value(
+            b"golem2",
+            "SHA3:7BA62E92095980B4FD8A743D608D8A5B0B0224105DDAB845845B7C622C60F248",
+        );
         test_value(b"golem2", "SHA3:9fa5c15b117a49c638aa438e2b6e33601360732e8d1f776535d93e21f733dd501c9756fa2feb508d3daf180253ecc1ef");
         test_value(b"golem1", "SHA3:e43d55ac264ee607918a78561e1f45779b192c747f5844d08a63697314ccf2445edb823cd6bbe14782a40a932176bcda9f35c097cbf49872095205ad102a7960")
     }
@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+/// Options of host direct deployments
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateOptions {
+    /// network access of deployments run in the sandbox
+    #[serde(default)]
+    pub network: bool,
+}
@@ -0,0 +1,64 @@
+//! Resource utilisation telemetry of providers.
+
+#[cfg(feature = "with-actix")]
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "with-actix")]
+use gu_net::rpc::PublicMessage;
+
+use super::Map;
+
+/// Resource usage averaged over a sampling interval
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    /// percent of a single core; exceeds 100 on multiple cores
+    pub cpu: f32,
+    /// resident memory in bytes
+    pub memory: u64,
+    /// bytes per second
+    pub disk_read: u64,
+    /// bytes per second
+    pub disk_write: u64,
+    /// bytes per second; reported for the whole provider only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net_rx: Option<u64>,
+    /// bytes per second; reported for the whole provider only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net_tx: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSample {
+    /// end of the sampling interval
+    pub ts: DateTime<Utc>,
+    /// length of the sampling interval in seconds
+    pub interval: u32,
+    pub global: Usage,
+    /// by deployment id
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub deployments: Map<String, Usage>,
+}
+
+/// Asks for samples recorded by the provider; only deployments owned by the
+/// asking hub are included.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GetMetrics {
+    /// samples taken after this time only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for GetMetrics {
+    const ID: u32 = 42;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for GetMetrics {
+    type Result = Result<Vec<MetricsSample>, String>;
+}
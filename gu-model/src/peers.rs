@@ -1,7 +1,11 @@
+#[cfg(feature = "with-actix")]
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[cfg(feature = "with-actix")]
-use gu_net::NodeId;
+use gu_net::{rpc::PublicMessage, NodeId};
 
 #[cfg(not(feature = "with-actix"))]
 type NodeId = String;
@@ -18,6 +22,13 @@ pub struct PeerInfo {
     pub peer_addr: String,
     #[serde(default)]
     pub tags: Tags,
+    /// execution environments as `name:status`, announced on handshake and
+    /// updated by the provider on change
+    #[serde(default)]
+    pub exec_envs: Vec<String>,
+    /// last state reported by a provider with an availability policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,4 +45,121 @@ pub struct PeerDetails {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[serde(default)]
     pub sessions: Vec<DeploymentInfo>,
+    /// not reported by providers without an availability policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub availability: Option<Availability>,
+    /// current status of each execution environment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub exec_envs: Option<BTreeMap<String, EnvStatus>>,
+    /// hex encoded key to encrypt deployment secrets, like registry credentials
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+/// State of the provider availability policy
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Availability {
+    /// new deployments are accepted
+    pub available: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// running deployments are paused until the provider is available again
+    #[serde(default)]
+    pub paused: bool,
+    /// last change of the state
+    pub since: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct GetAvailability {}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for GetAvailability {
+    const ID: u32 = 43;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for GetAvailability {
+    type Result = Result<Availability, String>;
+}
+
+/// Sent by a provider to connected hubs when its availability changes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailabilityChanged {
+    pub availability: Availability,
+    /// deployments of the hub about to be stopped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stopping: Vec<String>,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for AvailabilityChanged {
+    const ID: u32 = 46;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for AvailabilityChanged {
+    type Result = ();
+}
+
+/// Health and load of a provider execution environment
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum EnvStatus {
+    Ready,
+    Working,
+    Paused,
+    Disabled,
+    /// the environment cannot run deployments, e.g. its daemon is down
+    Unavailable {
+        reason: String,
+    },
+}
+
+impl EnvStatus {
+    /// Short form used in the handshake
+    pub fn code(&self) -> &'static str {
+        match self {
+            EnvStatus::Ready => "ready",
+            EnvStatus::Working => "working",
+            EnvStatus::Paused => "paused",
+            EnvStatus::Disabled => "disabled",
+            EnvStatus::Unavailable { .. } => "unavailable",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct GetEnvStatus {}
+
+/// Sent by a provider to connected hubs when statuses of its execution
+/// environments change
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvStatusChanged {
+    pub exec_envs: BTreeMap<String, EnvStatus>,
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for EnvStatusChanged {
+    const ID: u32 = 47;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for EnvStatusChanged {
+    type Result = ();
+}
+
+#[cfg(feature = "with-actix")]
+impl PublicMessage for GetEnvStatus {
+    const ID: u32 = 44;
+}
+
+#[cfg(feature = "with-actix")]
+impl Message for GetEnvStatus {
+    type Result = Result<BTreeMap<String, EnvStatus>, String>;
 }
@@ -57,6 +57,7 @@ pub enum RpcStatus {
     Event = 2,
     NoDestination = 100,
     BadFormat = 101,
+    Expired = 102,
 }
 
 impl Default for RpcStatus {
@@ -73,6 +74,7 @@ impl From<i32> for RpcStatus {
             2 => RpcStatus::Event,
             100 => RpcStatus::NoDestination,
             101 => RpcStatus::BadFormat,
+            102 => RpcStatus::Expired,
             _ => Self::default(),
         }
     }
@@ -86,6 +88,7 @@ impl<'a> From<&'a str> for RpcStatus {
             "Event" => RpcStatus::Event,
             "NoDestination" => RpcStatus::NoDestination,
             "BadFormat" => RpcStatus::BadFormat,
+            "Expired" => RpcStatus::Expired,
             _ => Self::default(),
         }
     }
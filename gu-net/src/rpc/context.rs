@@ -1,6 +1,6 @@
 use super::{
     error::{Error, ErrorKind},
-    message::{self, public_destination, DestinationId},
+    message::{self, public_destination, DestinationId, NodeId},
     router::{self, MessageRouter},
 };
 use actix::{dev::*, prelude::*};
@@ -137,6 +137,25 @@ where
         })
     }
 
+    /// Like `bind`, but the actor receives messages together with the sender
+    pub fn bind_from_peer<T: any::Any + Send>(&mut self, destination_id: u32)
+    where
+        A: Handler<FromPeer<T>>,
+        T: Message + DeserializeOwned,
+        T::Result: Serialize + Send,
+        A::Context: ToEnvelope<A, FromPeer<T>>,
+    {
+        let addr = self.address();
+        let endpoint = Box::new(PeerAddrWrapper {
+            addr,
+            message: PhantomData,
+        });
+        MessageRouter::from_registry().do_send(router::BindDestination {
+            destination_id: public_destination(destination_id),
+            endpoint,
+        })
+    }
+
     pub fn register<T: any::Any + Send>(
         &mut self,
     ) -> impl Future<Item = DestinationId, Error = Error>
@@ -185,35 +204,102 @@ where
         message: message::RouteMessage<String>,
         ctx: &mut <MessageRouter as Actor>::Context,
     ) {
-        let m = message.clone();
-
-        match message.from_json() {
-            Err(err) => {
-                error!("bad format! {}", err);
-                if let Some(msg) = message::EmitMessage::reply(
-                    &m,
-                    message::TransportResult::bad_request(format!("{}", err)),
-                ) {
-                    ctx.notify(msg)
-                }
-            }
-            Ok(message) => {
-                let m = message.unit();
-                debug!("message parsed!");
-                let f = actix::fut::wrap_future(self.addr.send(message.body))
-                    .then(move |r, act, ctx| match r {
-                        Ok(b) => fut::ok(serde_json::to_string(&b).unwrap()),
-                        Err(e) => fut::err(()),
-                    })
-                    .and_then(move |r, act, ctx: &mut <MessageRouter as Actor>::Context| {
-                        m.do_reply(r, |reply| ctx.notify(reply));
-                        fut::ok(())
-                    })
-                    .map_err(|e, act, ctx| println!("error: {:?}", e));
-                ctx.spawn(f);
-                //ctx.spawn(f.into_actor(self));
+        let addr = self.addr.clone();
+        dispatch(message, ctx, move |message: message::RouteMessage<T>| {
+            addr.send(message.body)
+        })
+    }
+}
+
+/// Message received from a remote node
+pub struct FromPeer<T> {
+    pub sender: NodeId,
+    pub body: T,
+}
+
+impl<T: Message> Message for FromPeer<T> {
+    type Result = T::Result;
+}
+
+struct PeerAddrWrapper<A, T>
+where
+    A: Actor + Handler<FromPeer<T>>,
+    T: Message + DeserializeOwned,
+    T::Result: Serialize,
+{
+    addr: Addr<A>,
+    message: PhantomData<T>,
+}
+
+unsafe impl<A, T> Send for PeerAddrWrapper<A, T>
+where
+    A: Actor + Handler<FromPeer<T>>,
+    T: Message + DeserializeOwned,
+    T::Result: Serialize,
+{
+}
+
+impl<A, T> router::LocalEndpoint for PeerAddrWrapper<A, T>
+where
+    A: Actor + Handler<FromPeer<T>>,
+    T: Message + DeserializeOwned + Send + 'static,
+    T::Result: Serialize + Send,
+    A::Context: ToEnvelope<A, FromPeer<T>>,
+{
+    fn handle(
+        &mut self,
+        message: message::RouteMessage<String>,
+        ctx: &mut <MessageRouter as Actor>::Context,
+    ) {
+        let addr = self.addr.clone();
+        dispatch(message, ctx, move |message: message::RouteMessage<T>| {
+            addr.send(FromPeer {
+                sender: message.sender,
+                body: message.body,
+            })
+        })
+    }
+}
+
+/// Parses the message, passes it on with `send` and replies with the result
+fn dispatch<T, R, F, Fut>(
+    message: message::RouteMessage<String>,
+    ctx: &mut <MessageRouter as Actor>::Context,
+    send: F,
+) where
+    T: DeserializeOwned,
+    R: Serialize,
+    F: FnOnce(message::RouteMessage<T>) -> Fut,
+    Fut: Future<Item = R, Error = MailboxError> + 'static,
+{
+    let m = message.clone();
+
+    match message.from_json() {
+        Err(err) => {
+            error!("bad format! {}", err);
+            if let Some(msg) = message::EmitMessage::reply(
+                &m,
+                message::TransportResult::bad_request(format!("{}", err)),
+            ) {
+                ctx.notify(msg)
             }
         }
+        Ok(message) => {
+            let m = message.unit();
+            debug!("message parsed!");
+            let f = actix::fut::wrap_future(send(message))
+                .then(move |r, act, ctx| match r {
+                    Ok(b) => fut::ok(serde_json::to_string(&b).unwrap()),
+                    Err(e) => fut::err(()),
+                })
+                .and_then(move |r, act, ctx: &mut <MessageRouter as Actor>::Context| {
+                    m.do_reply(r, |reply| ctx.notify(reply));
+                    fut::ok(())
+                })
+                .map_err(|e, act, ctx| error!("dispatch error: {:?}", e));
+            ctx.spawn(f);
+            //ctx.spawn(f.into_actor(self));
+        }
     }
 }
 
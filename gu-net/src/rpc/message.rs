@@ -12,6 +12,7 @@ use std::{
     cell::RefCell,
     collections::HashMap,
     io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 pub type NodeId = super::super::NodeId;
@@ -62,10 +63,36 @@ pub fn public_destination(destination_id: u32) -> DestinationId {
     v
 }
 
+/// Milliseconds since the unix epoch; unit of `ts` and `expires`
+pub fn timestamp() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    now.as_secs() * 1000 + u64::from(now.subsec_millis())
+}
+
+/// Expiration by the local clock of a message received with the sender's
+/// `ts` and `expires`; only their difference is used, so clock skew between
+/// nodes does not matter
+pub fn local_expiry(ts: Option<u64>, expires: Option<u64>, now: u64) -> Option<u64> {
+    match (ts, expires) {
+        (Some(ts), Some(expires)) => Some(now + expires.saturating_sub(ts)),
+        // without the send time the remaining time is unknown
+        _ => None,
+    }
+}
+
+#[inline]
+fn is_expired(expires: Option<u64>, now: u64) -> bool {
+    expires.map_or(false, |expires| expires <= now)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TransportError {
     NoDestination,
     BadFormat(String),
+    Expired,
 }
 
 impl Into<error::Error> for TransportError {
@@ -73,6 +100,7 @@ impl Into<error::Error> for TransportError {
         match self {
             TransportError::NoDestination => error::ErrorKind::NoDestination.into(),
             TransportError::BadFormat(s) => error::ErrorKind::BadFormat(s).into(),
+            TransportError::Expired => error::ErrorKind::Expired.into(),
         }
     }
 }
@@ -94,6 +122,11 @@ impl<B> TransportResult<B> {
     pub fn bad_request<T: Into<String>>(msg: T) -> Self {
         TransportResult::Err(TransportError::BadFormat(msg.into()))
     }
+
+    #[inline]
+    pub const fn expired() -> Self {
+        TransportResult::Err(TransportError::Expired)
+    }
 }
 
 impl<T> Into<Result<T, error::Error>> for TransportResult<T> {
@@ -125,6 +158,10 @@ pub struct RouteMessage<B> {
 }
 
 impl<B> RouteMessage<B> {
+    pub fn is_expired(&self, now: u64) -> bool {
+        is_expired(self.expires, now)
+    }
+
     pub fn do_reply<T, F: FnOnce(EmitMessage<T>)>(&self, arg: T, f: F) {
         if let Some(msg) = EmitMessage::reply(self, TransportResult::Reply(arg)) {
             f(msg)
@@ -195,6 +232,7 @@ impl<B: BinPack> BinPack for TransportResult<B> {
                 let err_code = match e {
                     TransportError::NoDestination => 10u8,
                     TransportError::BadFormat(_s) => 11u8,
+                    TransportError::Expired => 12u8,
                 };
                 w.write_u8(err_code)
             }
@@ -207,6 +245,10 @@ impl<B: BinPack> BinPack for TransportResult<B> {
 }
 
 impl<B> EmitMessage<B> {
+    pub fn is_expired(&self, now: u64) -> bool {
+        is_expired(self.expires, now)
+    }
+
     pub fn reply<BX>(msg: &RouteMessage<BX>, body: TransportResult<B>) -> Option<Self> {
         debug!("build reply for {:?}", msg.unit());
         match msg.reply_to.clone() {
@@ -218,7 +260,7 @@ impl<B> EmitMessage<B> {
                         .clone()
                         .unwrap_or_else(|| msg.msg_id.clone()),
                 ),
-                ts: 0,
+                ts: msg.ts,
                 reply_to: None,
                 expires: msg.expires.clone(),
                 body,
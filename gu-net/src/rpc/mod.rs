@@ -23,6 +23,7 @@ mod error {
             Canceled
             NoDestination
             BadFormat(s : String)
+            Expired
             Proto(e : quick_protobuf::Error)
         }
 
@@ -52,10 +53,11 @@ mod error {
 }
 
 pub use self::{
-    context::{start_actor, RemotingContext},
+    context::{start_actor, FromPeer, RemotingContext},
     error::Error as RpcError,
     message::{
-        gen_destination_id, public_destination, DestinationId, EmitMessage, MessageId, RouteMessage,
+        gen_destination_id, public_destination, timestamp, DestinationId, EmitMessage, MessageId,
+        RouteMessage,
     },
     registry::RemotingSystemService,
     remoting::{peer, PublicMessage},
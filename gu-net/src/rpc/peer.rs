@@ -1,8 +1,13 @@
 use super::super::NodeId;
 use actix::prelude::*;
+use gu_base::metrics::{self, Gauge};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
+lazy_static! {
+    static ref CONNECTED_PEERS: Gauge = metrics::gauge("gu_connected_peers", "Connected peers");
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct PeerInfo {
@@ -11,6 +16,13 @@ pub struct PeerInfo {
     pub node_id: NodeId,
     pub sessions: Vec<PeerSessionInfo>,
     pub tags: Vec<String>,
+    /// execution environments as `name:status`, announced on handshake and
+    /// updated by the provider on change
+    #[serde(default)]
+    pub exec_envs: Vec<String>,
+    /// last state reported by a provider with an availability policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available: Option<bool>,
 }
 
 pub enum State {
@@ -48,8 +60,28 @@ pub struct PeerSessionInfo {
     pub tags: Vec<String>,
     pub note: Option<String>,
     pub processes: HashSet<String>,
+    /// image download progress of a pending session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadProgress>,
+    /// allowed destinations of a deployment with restricted network access
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadProgress {
+    /// hash of the image being downloaded
+    pub image_hash: String,
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    /// bytes per second
+    pub rate: u64,
+    /// estimated time left in seconds
+    pub eta: Option<u64>,
 }
 
+/// Sent once per peer connection: `Update` on handshake, `Delete` when it ends
 #[derive(Serialize, Deserialize)]
 pub enum UpdatePeer {
     Update(PeerInfo),
@@ -60,8 +92,50 @@ impl Message for UpdatePeer {
     type Result = ();
 }
 
+/// Availability reported by a connected peer
+pub struct SetAvailable(pub NodeId, pub bool);
+
+impl Message for SetAvailable {
+    type Result = ();
+}
+
+/// Execution environments reported by a connected peer as `name:status`
+pub struct SetExecEnvs(pub NodeId, pub Vec<String>);
+
+impl Message for SetExecEnvs {
+    type Result = ();
+}
+
+/// Peer connection state change sent to subscribers
+#[derive(Clone, Debug)]
+pub enum PeerEvent {
+    Connected(NodeId),
+    Disconnected(NodeId),
+}
+
+impl Message for PeerEvent {
+    type Result = ();
+}
+
+pub struct Subscribe(pub Recipient<PeerEvent>);
+
+impl Message for Subscribe {
+    type Result = ();
+}
+
 pub struct PeerManager {
     peers: HashMap<NodeId, PeerInfo>,
+    /// a reconnected peer can complete its handshake before the stale
+    /// connection times out, so the peer is removed with its last connection
+    connections: HashMap<NodeId, usize>,
+    subscribers: Vec<Recipient<PeerEvent>>,
+}
+
+impl PeerManager {
+    fn notify(&mut self, event: PeerEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.do_send(event.clone()).is_ok());
+    }
 }
 
 impl Actor for PeerManager {
@@ -72,6 +146,8 @@ impl Default for PeerManager {
     fn default() -> Self {
         PeerManager {
             peers: HashMap::new(),
+            connections: HashMap::new(),
+            subscribers: Vec::new(),
         }
     }
 }
@@ -86,15 +162,61 @@ impl Handler<UpdatePeer> for PeerManager {
     fn handle(&mut self, msg: UpdatePeer, ctx: &mut Self::Context) {
         match msg {
             UpdatePeer::Update(info) => {
-                let _ = self.peers.insert(info.node_id, info);
+                let node_id = info.node_id;
+                let _ = self.peers.insert(node_id, info);
+                *self.connections.entry(node_id).or_insert(0) += 1;
+                CONNECTED_PEERS.set(self.peers.len() as isize);
+                // resynchronise on every handshake; the stale connection
+                // of a reconnecting peer may be still around
+                self.notify(PeerEvent::Connected(node_id));
             }
             UpdatePeer::Delete(node_id) => {
-                let _ = self.peers.remove(&node_id);
+                let last = match self.connections.get_mut(&node_id) {
+                    Some(count) if *count > 1 => {
+                        *count -= 1;
+                        false
+                    }
+                    _ => true,
+                };
+                if last {
+                    let _ = self.connections.remove(&node_id);
+                    let _ = self.peers.remove(&node_id);
+                    CONNECTED_PEERS.set(self.peers.len() as isize);
+                    self.notify(PeerEvent::Disconnected(node_id));
+                }
             }
         }
     }
 }
 
+impl Handler<SetAvailable> for PeerManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetAvailable, _ctx: &mut Self::Context) {
+        if let Some(peer) = self.peers.get_mut(&msg.0) {
+            peer.available = Some(msg.1);
+        }
+    }
+}
+
+impl Handler<SetExecEnvs> for PeerManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetExecEnvs, _ctx: &mut Self::Context) {
+        if let Some(peer) = self.peers.get_mut(&msg.0) {
+            peer.exec_envs = msg.1;
+        }
+    }
+}
+
+impl Handler<Subscribe> for PeerManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _ctx: &mut Self::Context) {
+        self.subscribers.push(msg.0);
+    }
+}
+
 pub struct ListPeers;
 
 impl Message for ListPeers {
@@ -6,6 +6,7 @@ use futures::prelude::*;
 use gu_actix::prelude::*;
 use serde::{de::DeserializeOwned, Serialize};
 use std::marker::PhantomData;
+use std::time::Duration;
 
 pub trait PublicMessage: Message {
     const ID: u32;
@@ -23,6 +24,7 @@ impl RemoteNode {
             node_id: self.0,
             destination_id,
             reply: ReplyRouter::from_registry(),
+            timeout: None,
             marker: PhantomData,
         }
     }
@@ -32,6 +34,7 @@ impl RemoteNode {
             node_id: self.0,
             destination_id: public_destination(T::ID),
             reply: ReplyRouter::from_registry(),
+            timeout: None,
             marker: PhantomData,
         }
     }
@@ -41,9 +44,19 @@ pub struct RemoteEndpoint<T> {
     node_id: NodeId,
     destination_id: DestinationId,
     reply: actix::Addr<ReplyRouter>,
+    timeout: Option<Duration>,
     marker: PhantomData<T>,
 }
 
+impl<T> RemoteEndpoint<T> {
+    /// Requests not delivered within the timeout are dropped, and calls
+    /// without reply by then fail with `SendError::Expired`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
 impl<T> RemoteEndpoint<T>
 where
     T: Message + Send + Serialize + 'static,
@@ -51,7 +64,12 @@ where
 {
     pub fn send(&self, msg: T) -> impl Future<Item = T::Result, Error = reply::SendError> {
         self.reply
-            .send(CallRemote(self.node_id, self.destination_id.clone(), msg))
+            .send(CallRemote(
+                self.node_id,
+                self.destination_id.clone(),
+                msg,
+                self.timeout,
+            ))
             .flatten_fut()
     }
 }
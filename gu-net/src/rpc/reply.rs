@@ -9,7 +9,8 @@ use super::{
     context::RemotingContext,
     gen_destination_id,
     message::{
-        DestinationId, EmitMessage, MessageId, RouteMessage, TransportError, TransportResult,
+        timestamp, DestinationId, EmitMessage, MessageId, RouteMessage, TransportError,
+        TransportResult,
     },
     router::{BindReplyDestination, LocalReplyEndpoint, MessageRouter},
 };
@@ -17,7 +18,12 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json;
 
 use futures::unsync::oneshot;
-use std::{collections::HashMap, error::Error, fmt, io};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, io,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug)]
 pub enum SendError {
@@ -27,6 +33,8 @@ pub enum SendError {
     MailBox(MailboxError),
     NoDestination,
     Canceled,
+    /// no reply before the message expired
+    Expired,
 }
 
 impl SendError {
@@ -50,6 +58,7 @@ impl Error for SendError {
             SendError::ParseBody(None, _) => "remote parse error",
             SendError::MailBox(e) => "mailbox error",
             SendError::NoDestination => "no destination",
+            SendError::Expired => "expired",
         }
     }
 
@@ -72,6 +81,7 @@ impl fmt::Display for SendError {
             SendError::MailBox(e) => write!(f, "mailbox {}", e),
             SendError::Canceled => write!(f, "canceled"),
             SendError::NoDestination => write!(f, "no destination"),
+            SendError::Expired => write!(f, "message expired"),
         }
     }
 }
@@ -82,10 +92,47 @@ impl From<MailboxError> for SendError {
     }
 }
 
+/// Pending replies are checked for expiration this often
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Expects a reply until `expires`, by the monotonic clock of this node
+struct PendingReply {
+    expires: Option<Instant>,
+    tx: oneshot::Sender<Result<String, TransportError>>,
+}
+
 pub struct ReplyRouter {
     router: Addr<MessageRouter>,
     destination_id: DestinationId,
-    reply_map: HashMap<MessageId, oneshot::Sender<RouteMessage<Result<String, TransportError>>>>,
+    reply_map: HashMap<MessageId, PendingReply>,
+}
+
+impl ReplyRouter {
+    fn expect_reply(
+        &mut self,
+        msg_id: MessageId,
+        expires: Option<Instant>,
+    ) -> oneshot::Receiver<Result<String, TransportError>> {
+        let (tx, rx) = oneshot::channel();
+        self.reply_map.insert(msg_id, PendingReply { expires, tx });
+        rx
+    }
+
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<MessageId> = self
+            .reply_map
+            .iter()
+            .filter(|(_, pending)| pending.expires.map_or(false, |expires| expires <= now))
+            .map(|(msg_id, _)| msg_id.clone())
+            .collect();
+
+        for msg_id in expired {
+            if let Some(pending) = self.reply_map.remove(&msg_id) {
+                let _ = pending.tx.send(Err(TransportError::Expired));
+            }
+        }
+    }
 }
 
 impl LocalReplyEndpoint for Addr<ReplyRouter> {
@@ -101,7 +148,9 @@ impl LocalReplyEndpoint for Addr<ReplyRouter> {
 impl Actor for ReplyRouter {
     type Context = Context<Self>;
 
-    fn started(&mut self, ctx: &mut Self::Context) {}
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(EXPIRE_INTERVAL, |act, _ctx| act.remove_expired());
+    }
 }
 
 impl Supervised for ReplyRouter {}
@@ -136,11 +185,17 @@ impl Handler<RouteMessage<Result<String, TransportError>>> for ReplyRouter {
     ) -> Self::Result {
         debug!("got message to route: {:?}", msg);
         let msg_id = msg.msg_id.clone();
-        if let Some(tx) = self
+        if let Some(pending) = self
             .reply_map
             .remove(&msg.correlation_id.clone().unwrap_or_else(|| msg_id))
         {
-            tx.send(msg);
+            let body = if msg.is_expired(timestamp()) {
+                warn!("dropping expired reply");
+                Err(TransportError::Expired)
+            } else {
+                msg.body
+            };
+            let _ = pending.tx.send(body);
         } else {
             warn!("unhandled message");
             debug!("keys: {:?}", self.reply_map);
@@ -148,7 +203,8 @@ impl Handler<RouteMessage<Result<String, TransportError>>> for ReplyRouter {
     }
 }
 
-pub struct CallRemote<T>(pub NodeId, pub DestinationId, pub T)
+/// Remote call; the optional timeout sets expiration of the request
+pub struct CallRemote<T>(pub NodeId, pub DestinationId, pub T, pub Option<Duration>)
 where
     T: Message;
 
@@ -166,6 +222,7 @@ fn parse_body<T: DeserializeOwned>(
         Ok(msg) => msg,
         Err(TransportError::NoDestination) => return Err(SendError::NoDestination),
         Err(TransportError::BadFormat(msg)) => return Err(SendError::ParseBody(None, msg)),
+        Err(TransportError::Expired) => return Err(SendError::Expired),
     };
 
     Ok(match serde_json::from_str(body.as_ref()) {
@@ -192,6 +249,11 @@ where
         };
 
         let node_id = msg.0;
+        let ts = timestamp();
+        let expires = msg
+            .3
+            .map(|timeout| ts + timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis()));
+        let reply_expires = msg.3.map(|timeout| Instant::now() + timeout);
 
         ActorResponse::r#async(
             MessageRouter::from_registry()
@@ -199,9 +261,9 @@ where
                     dest_node: msg.0,
                     destination: msg.1,
                     correlation_id: None,
-                    ts: 0,
+                    ts,
                     reply_to: Some(self.destination_id.clone()),
-                    expires: None,
+                    expires,
                     body: TransportResult::Request(body),
                 })
                 .flatten_fut()
@@ -210,15 +272,10 @@ where
                     _ => SendError::body(e),
                 })
                 .into_actor(self)
-                .and_then(|msg_id, act, ctx| {
-                    use futures::unsync::oneshot;
-                    let (tx, rx) = oneshot::channel();
-                    act.reply_map.insert(msg_id, tx);
-
-                    rx.map_err(|_| SendError::Canceled)
-                        .and_then(|route_msg: RouteMessage<Result<String, TransportError>>| {
-                            parse_body(route_msg.body)
-                        })
+                .and_then(move |msg_id, act, ctx| {
+                    act.expect_reply(msg_id, reply_expires)
+                        .map_err(|_| SendError::Canceled)
+                        .and_then(parse_body)
                         .flatten_fut()
                         .into_actor(act)
                 }),
@@ -244,10 +301,7 @@ impl Handler<CallRemoteUntyped> for ReplyRouter {
         use smallvec::SmallVec;
         let cid: [u8; 8] = thread_rng().gen();
 
-        use futures::unsync::oneshot;
-
-        let (tx, rx) = oneshot::channel();
-        self.reply_map.insert(cid.into(), tx);
+        let rx = self.expect_reply(cid.into(), None);
         let node_id = msg.0;
 
         ActorResponse::r#async(
@@ -269,10 +323,31 @@ impl Handler<CallRemoteUntyped> for ReplyRouter {
                 .into_actor(self)
                 .and_then(move |msg_id, act, ctx| {
                     rx.map_err(|_| SendError::Canceled)
-                        .and_then(|route_msg| parse_body(route_msg.body))
+                        .and_then(parse_body)
                         .flatten_fut()
                         .into_actor(act)
                 }),
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pending_reply_expires() {
+        let _sys = System::new("test");
+        let mut router = ReplyRouter::default();
+        let expired = router.expect_reply(MessageId::from_slice(&[1u8; 8]), Some(Instant::now()));
+        let _pending = router.expect_reply(MessageId::from_slice(&[2u8; 8]), None);
+
+        router.remove_expired();
+
+        match expired.wait() {
+            Ok(Err(TransportError::Expired)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(router.reply_map.len(), 1);
+    }
+}
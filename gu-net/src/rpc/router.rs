@@ -1,11 +1,31 @@
 use super::{error, message::*, util::*};
 use actix::{fut, prelude::*};
 use futures::prelude::*;
+use gu_base::metrics::{self, Counter};
 use std::{
     collections::HashMap,
     io::{self, Write},
 };
 
+lazy_static! {
+    static ref ROUTED: Counter = metrics::counter(
+        "gu_rpc_messages_routed_total",
+        "RPC messages delivered to local endpoints or sent to peers"
+    );
+    static ref EXPIRED: Counter = failed("expired");
+    static ref NO_DESTINATION: Counter = failed("no_destination");
+    static ref NOT_CONNECTED: Counter = failed("not_connected");
+    static ref SEND_ERROR: Counter = failed("send_error");
+}
+
+fn failed(reason: &str) -> Counter {
+    metrics::labeled_counter(
+        "gu_rpc_messages_failed_total",
+        "RPC messages which could not be routed",
+        &[("reason", reason)],
+    )
+}
+
 pub struct MessageRouter {
     destinations: HashMap<DestinationId, Box<dyn LocalEndpoint + 'static>>,
     reply_destinations: HashMap<DestinationId, Box<dyn LocalReplyEndpoint + 'static>>,
@@ -83,13 +103,22 @@ impl Handler<RouteMessage<String>> for MessageRouter {
     fn handle(&mut self, msg: RouteMessage<String>, ctx: &mut Self::Context) -> Self::Result {
         //let destination = msg.destination.clone();
         debug!("handling dest: {:?}", msg.destination);
-        if let Some(v) = self.destinations.get_mut(&msg.destination) {
+        if msg.is_expired(timestamp()) {
+            warn!("dropping expired message to {:?}", msg.destination);
+            EXPIRED.inc();
+            if let Some(r) = EmitMessage::reply(&msg, TransportResult::expired()) {
+                ctx.notify(r);
+            }
+        } else if let Some(v) = self.destinations.get_mut(&msg.destination) {
+            ROUTED.inc();
             v.handle(msg, ctx);
         } else if let Some(r) = EmitMessage::reply(&msg, TransportResult::no_destination()) {
             error!("no dest: {:?}", msg.destination);
+            NO_DESTINATION.inc();
             ctx.notify(r);
         } else {
             error!("no dest: {:?} and no reply", msg.destination);
+            NO_DESTINATION.inc();
         }
     }
 }
@@ -98,17 +127,31 @@ impl Handler<EmitMessage<String>> for MessageRouter {
     type Result = ActorResponse<MessageRouter, MessageId, error::Error>;
 
     fn handle(&mut self, msg: EmitMessage<String>, ctx: &mut Self::Context) -> Self::Result {
+        // replies are passed on, so callers learn about the expiration
+        if let TransportResult::Request(_) = msg.body {
+            if msg.is_expired(timestamp()) {
+                warn!("dropping expired request to {:?}", msg.dest_node);
+                EXPIRED.inc();
+                return ActorResponse::reply(Err(error::ErrorKind::Expired.into()));
+            }
+        }
+
         let dest_node = msg.dest_node.clone();
         let f = if let Some(v) = self.remotes.get_mut(&msg.dest_node) {
             v.send(msg).then(|r| match r {
                 Err(e) => {
                     error!("emit err: {}", e);
+                    SEND_ERROR.inc();
                     Err(e.into())
                 }
-                Ok(v) => v,
+                Ok(v) => {
+                    ROUTED.inc();
+                    v
+                }
             })
         } else {
             error!("endpoint not connected: {:?}", msg.dest_node);
+            NOT_CONNECTED.inc();
             return ActorResponse::reply(Err(error::ErrorKind::NotConnected.into()));
         };
 
@@ -179,3 +222,53 @@ impl Handler<AddEndpoint> for MessageRouter {
         self.remotes.insert(msg.node_id, msg.recipient);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl LocalEndpoint for Recorder {
+        fn handle(&mut self, message: RouteMessage<String>, _ctx: &mut Context<MessageRouter>) {
+            self.0.lock().unwrap().push(message.body)
+        }
+    }
+
+    fn message(body: &str, expires: u64) -> RouteMessage<String> {
+        RouteMessage {
+            msg_id: gen_destination_id(),
+            sender: "0x44f1d51fe4cbb49d9f6733618082c5af7459b83f"
+                .parse()
+                .unwrap(),
+            destination: public_destination(1),
+            reply_to: None,
+            correlation_id: None,
+            ts: timestamp(),
+            expires: Some(expires),
+            body: body.into(),
+        }
+    }
+
+    #[test]
+    fn test_drop_expired() {
+        let mut sys = System::new("test");
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let router = MessageRouter::default().start();
+
+        router.do_send(BindDestination {
+            destination_id: public_destination(1),
+            endpoint: Box::new(Recorder(delivered.clone())),
+        });
+        let now = timestamp();
+        sys.block_on(
+            router
+                .send(message("expired", now - 1))
+                .and_then(|()| router.send(message("live", now + 60_000))),
+        )
+        .unwrap();
+
+        assert_eq!(*delivered.lock().unwrap(), vec!["live".to_string()]);
+    }
+}
@@ -5,7 +5,10 @@
 use super::{
     super::proto::wire,
     error,
-    message::{EmitMessage, MessageId, NodeId, RouteMessage, TransportError, TransportResult},
+    message::{
+        local_expiry, timestamp, EmitMessage, MessageId, NodeId, RouteMessage, TransportError,
+        TransportResult,
+    },
     monitor,
     peer::{self, PeerManager},
     router::{AddEndpoint, DelEndpoint, MessageRouter},
@@ -15,17 +18,31 @@ use actix_web::{self, ws, HttpRequest, HttpResponse};
 use futures::{future, prelude::*};
 use gu_actix::flatten::FlattenFuture;
 use quick_protobuf::serialize_into_vec;
-use std::{borrow::Cow, marker::PhantomData, net, ops::Add, time};
+use std::{borrow::Cow, marker::PhantomData, net, ops::Add, sync::RwLock, time};
 
+lazy_static! {
+    static ref EXEC_ENVS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+}
+
+/// Sets execution environments announced to hubs on handshake.
+///
+/// Each entry has the form `name:status`.
+pub fn set_exec_envs(exec_envs: Vec<String>) {
+    *EXEC_ENVS.write().unwrap() = exec_envs;
+}
+
+/// Route message stamped with the local receive time
 fn rpc_to_route<T>(peer_node_id: NodeId, rpc: wire::RpcMessage, body: T) -> RouteMessage<T> {
+    let now = timestamp();
+
     RouteMessage {
         msg_id: rpc.message_id.as_ref().into(),
         sender: peer_node_id,
         destination: rpc.destination_id.as_ref().into(),
         reply_to: rpc.reply_to.map(|v| v.as_ref().into()),
         correlation_id: rpc.correlation_id.map(|v| v.as_ref().into()),
-        ts: rpc.ts.unwrap_or(0),
-        expires: rpc.expires,
+        ts: now,
+        expires: local_expiry(rpc.ts, rpc.expires, now),
         body,
     }
 }
@@ -50,6 +67,10 @@ fn route_rpc_message(peer_node_id: NodeId, mut rpc: wire::RpcMessage) {
             let body: Result<String, TransportError> = Err(TransportError::BadFormat(msg.into()));
             MessageRouter::from_registry().do_send(rpc_to_route(peer_node_id, rpc, body))
         }
+        (wire::RpcStatus::Expired, _) => {
+            let body: Result<String, TransportError> = Err(TransportError::Expired);
+            MessageRouter::from_registry().do_send(rpc_to_route(peer_node_id, rpc, body))
+        }
         _ => return (),
     }
 }
@@ -59,6 +80,7 @@ struct Worker<S: 'static> {
     node_id: NodeId,
     peer_node_id: Option<NodeId>,
     peer_addr: Option<net::SocketAddr>,
+    peer_exec_envs: Vec<String>,
     pong_ts: Option<time::Instant>,
 }
 
@@ -69,6 +91,7 @@ impl<S> Worker<S> {
             node_id,
             peer_node_id: None,
             peer_addr,
+            peer_exec_envs: Vec::new(),
             pong_ts: None,
         }
     }
@@ -100,6 +123,8 @@ impl<S> Worker<S> {
             node_id: self.peer_node_id.unwrap(),
             sessions: Vec::new(),
             tags: Vec::new(),
+            exec_envs: self.peer_exec_envs.clone(),
+            available: None,
         }))
     }
 }
@@ -142,6 +167,8 @@ impl<S> StreamHandler<ws::Message, ws::ProtocolError> for Worker<S> {
                             info!("handshake for: {:?}", hello);
                             let mut peer_node_id: NodeId = hello.node_id.into();
                             self.peer_node_id = Some(peer_node_id);
+                            self.peer_exec_envs =
+                                hello.exec_envs.iter().map(|e| e.to_string()).collect();
                             self.reply_init(ctx);
                             self.add_endpoint(ctx);
                         }
@@ -203,6 +230,7 @@ impl<S> Handler<EmitMessage<String>> for Worker<S> {
                 wire::RpcStatus::BadFormat,
                 Some(Cow::Borrowed(err_msg.as_ref())),
             ),
+            TransportResult::Err(TransportError::Expired) => (wire::RpcStatus::Expired, None),
         };
 
         let msg = wire::RpcMessage {
@@ -238,6 +266,16 @@ impl Client {
             node_id: self.peer_node_id.unwrap(),
             recipient: ctx.address().recipient(),
         });
+        // lets the provider reach the hubs it is connected to
+        PeerManager::from_registry().do_send(peer::UpdatePeer::Update(peer::PeerInfo {
+            node_name: String::new(),
+            peer_addr: None,
+            node_id: self.peer_node_id.unwrap(),
+            sessions: Vec::new(),
+            tags: Vec::new(),
+            exec_envs: Vec::new(),
+            available: None,
+        }))
     }
 
     fn connect(uri: &str, node_id: NodeId) -> impl Future<Item = Addr<Client>, Error = ()> {
@@ -274,6 +312,7 @@ impl Actor for Client {
         use smallvec;
 
         let m: [u8; 8] = thread_rng().gen();
+        let exec_envs = EXEC_ENVS.read().unwrap().clone();
 
         let hello = wire::Hello {
             role: wire::Role::PROVIDER,
@@ -284,7 +323,10 @@ impl Actor for Client {
             os: None,
             max_ram: None,
             max_storage: None,
-            exec_envs: Vec::new(),
+            exec_envs: exec_envs
+                .iter()
+                .map(|e| Cow::Borrowed(e.as_ref()))
+                .collect(),
         };
         self.writer.binary(serialize_into_vec(&hello).unwrap());
 
@@ -296,6 +338,12 @@ impl Actor for Client {
             }
         });
     }
+
+    fn stopped(&mut self, _ctx: &mut <Self as Actor>::Context) {
+        if let Some(peer_id) = self.peer_node_id.take() {
+            PeerManager::from_registry().do_send(peer::UpdatePeer::Delete(peer_id))
+        }
+    }
 }
 
 #[derive(Message)]
@@ -383,6 +431,7 @@ impl Handler<EmitMessage<String>> for Client {
             TransportResult::Err(TransportError::BadFormat(ref err_msg)) => {
                 (wire::RpcStatus::BadFormat, Some(Cow::Borrowed(err_msg)))
             }
+            TransportResult::Err(TransportError::Expired) => (wire::RpcStatus::Expired, None),
         };
 
         let msg = wire::RpcMessage {
@@ -408,10 +457,35 @@ impl Handler<EmitMessage<String>> for Client {
     }
 }
 
+const MAX_RECONNECT_DELAY: time::Duration = time::Duration::from_secs(60);
+
+/// Connections lost sooner than this count as failed attempts
+const MIN_CONNECTION_UPTIME: time::Duration = time::Duration::from_secs(30);
+
+/// Delay before the next connection attempt; doubles with each failure
+fn reconnect_delay(failures: u32) -> time::Duration {
+    time::Duration::from_secs(1 << failures.min(6)).min(MAX_RECONNECT_DELAY)
+}
+
+/// Failures counted after losing a connection that was up for `uptime`
+fn failures_after_lost(failures: u32, uptime: time::Duration) -> u32 {
+    if uptime >= MIN_CONNECTION_UPTIME {
+        0
+    } else {
+        failures.saturating_add(1)
+    }
+}
+
 pub struct ConnectionSupervisor {
     node_id: NodeId,
     peer_address: net::SocketAddr,
     connection: Option<Addr<Client>>,
+    /// a connection attempt is in progress
+    connecting: bool,
+    /// when the current connection was established
+    connected_at: Option<time::Instant>,
+    failures: u32,
+    next_attempt: time::Instant,
 }
 
 pub fn start_connection(
@@ -422,6 +496,10 @@ pub fn start_connection(
         node_id,
         peer_address,
         connection: None,
+        connecting: false,
+        connected_at: None,
+        failures: 0,
+        next_attempt: time::Instant::now(),
     }
     .start()
 }
@@ -433,28 +511,51 @@ impl ConnectionSupervisor {
                 if addr.connected() {
                     Some(addr)
                 } else {
-                    warn!("actor is down");
+                    let uptime = self
+                        .connected_at
+                        .take()
+                        .map(|connected_at| connected_at.elapsed())
+                        .unwrap_or_default();
+                    self.failures = failures_after_lost(self.failures, uptime);
+                    let delay = reconnect_delay(self.failures);
+                    self.next_attempt = time::Instant::now() + delay;
+                    warn!(
+                        "connection to {} lost, reconnecting in {}s",
+                        self.peer_address,
+                        delay.as_secs()
+                    );
                     None
                 }
             }
             None => None,
         };
 
-        if self.connection.is_some() {
+        let now = time::Instant::now();
+        if self.connection.is_some() || self.connecting || now < self.next_attempt {
             return;
         }
+        self.connecting = true;
 
         ctx.spawn(
             Client::connect(&format!("http://{}/ws/", &self.peer_address), self.node_id)
                 .into_actor(self)
                 .map(|r, act: &mut ConnectionSupervisor, ctx| {
                     debug!("set connection!");
+                    act.connecting = false;
                     act.connection = Some(r);
+                    // failures are reset once the connection proves stable
+                    act.connected_at = Some(time::Instant::now());
                 })
                 .map_err(|err, act, ctx| {
+                    act.connecting = false;
+                    act.failures += 1;
+                    let delay = reconnect_delay(act.failures);
+                    act.next_attempt = time::Instant::now() + delay;
                     error!(
-                        "fatal, restart, {:?}, peer address: {}",
-                        &err, act.peer_address
+                        "fatal, restart in {}s, {:?}, peer address: {}",
+                        delay.as_secs(),
+                        &err,
+                        act.peer_address
                     );
                 }),
         );
@@ -525,6 +626,22 @@ mod test {
     use super::wire::*;
     use quick_protobuf::*;
     use std::borrow::Cow;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(super::reconnect_delay(0), Duration::from_secs(1));
+        assert_eq!(super::reconnect_delay(3), Duration::from_secs(8));
+        assert_eq!(super::reconnect_delay(6), Duration::from_secs(60));
+        assert_eq!(super::reconnect_delay(100), Duration::from_secs(60));
+
+        // connections dropped right away back off like failed attempts
+        let dropped = super::failures_after_lost(0, Duration::from_secs(1));
+        assert_eq!(super::reconnect_delay(dropped), Duration::from_secs(2));
+        let dropped = super::failures_after_lost(dropped, Duration::from_secs(1));
+        assert_eq!(super::reconnect_delay(dropped), Duration::from_secs(4));
+        assert_eq!(super::failures_after_lost(5, Duration::from_secs(30)), 0);
+    }
 
     #[test]
     fn test_rpc_message() {
@@ -547,4 +664,51 @@ mod test {
 
         assert_eq!(deserialize_from_slice::<RpcMessage>(&buf).unwrap(), rpc)
     }
+
+    #[test]
+    fn test_expired_message() {
+        let rpc: RpcMessage = RpcMessage {
+            message_id: Cow::Borrowed(&[1u8; 8]),
+            destination_id: Cow::Borrowed(&[2u8; 8]),
+            correlation_id: None,
+            reply_to: None,
+            ts: Some(1_000),
+            expires: Some(2_000),
+            status: RpcStatus::Expired,
+            payload: None,
+        };
+
+        let buf = serialize_into_vec(&rpc).unwrap();
+        let msg = deserialize_from_slice::<RpcMessage>(&buf).unwrap();
+
+        assert_eq!(msg.status, RpcStatus::Expired);
+        assert_eq!(msg.expires, Some(2_000));
+        assert_eq!(RpcStatus::from("Expired"), RpcStatus::Expired);
+    }
+
+    #[test]
+    fn test_local_expiry() {
+        let rpc: RpcMessage = RpcMessage {
+            message_id: Cow::Borrowed(&[1u8; 8]),
+            destination_id: Cow::Borrowed(&[2u8; 8]),
+            correlation_id: None,
+            reply_to: None,
+            // sender clock far behind
+            ts: Some(1_000),
+            expires: Some(31_000),
+            status: RpcStatus::Request,
+            payload: None,
+        };
+
+        let msg = super::rpc_to_route(
+            "0x44f1d51fe4cbb49d9f6733618082c5af7459b83f"
+                .parse()
+                .unwrap(),
+            rpc,
+            (),
+        );
+        assert_eq!(msg.expires, Some(msg.ts + 30_000));
+        assert!(!msg.is_expired(msg.ts + 29_999));
+        assert_eq!(super::local_expiry(None, Some(31_000), 5_000), None);
+    }
 }
@@ -0,0 +1,565 @@
+//! Blocking packing and unpacking of archives in all supported `ResourceFormat`s.
+//!
+//! Tar based formats are processed as streams. Zip keeps its central directory
+//! at the end of the archive, so packing into zip goes through a temporary file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use glob::{MatchOptions, Pattern};
+use gu_model::envman::ResourceFormat;
+use uuid::Uuid;
+
+const ZSTD_LEVEL: i32 = 3;
+
+fn zip_error(e: zip::result::ZipError) -> io::Error {
+    match e {
+        zip::result::ZipError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+fn not_an_archive() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "raw format is not an archive")
+}
+
+/// Lists files and directories under `root` as paths relative to it.
+/// Directories precede their contents.
+pub fn list_entries(root: &Path) -> io::Result<Vec<PathBuf>> {
+    fn scan(root: &Path, dir: &Path, entries: &mut Vec<PathBuf>) -> io::Result<()> {
+        let mut children = fs::read_dir(root.join(dir))?
+            .map(|entry| entry.map(|entry| dir.join(entry.file_name())))
+            .collect::<io::Result<Vec<_>>>()?;
+        children.sort();
+        for child in children {
+            let is_dir = fs::symlink_metadata(root.join(&child))?.is_dir();
+            entries.push(child.clone());
+            if is_dir {
+                scan(root, &child, entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut entries = Vec::new();
+    scan(root, Path::new(""), &mut entries)?;
+    Ok(entries)
+}
+
+/// Moves the content of the `from` directory into `to`, replacing existing
+/// files and merging directories.
+pub fn move_into(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        match fs::symlink_metadata(&target) {
+            Ok(ref meta) if is_dir && meta.is_dir() => move_into(&entry.path(), &target)?,
+            Ok(ref meta) if meta.is_dir() => {
+                fs::remove_dir_all(&target)?;
+                fs::rename(entry.path(), &target)?
+            }
+            Ok(_) => {
+                fs::remove_file(&target)?;
+                fs::rename(entry.path(), &target)?
+            }
+            Err(_) => fs::rename(entry.path(), &target)?,
+        }
+    }
+    Ok(())
+}
+
+/// Selects files by glob patterns relative to the workspace.
+///
+/// `*` does not cross directory boundaries, `**` does. Selected files are
+/// named relative to `base_dir`, the deepest directory shared by all patterns.
+#[derive(Debug, Clone)]
+pub struct FileSelector {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    base_dir: PathBuf,
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn normalize_pattern(pattern: &str) -> Result<PathBuf, String> {
+    let mut path = PathBuf::new();
+    for component in Path::new(pattern).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir | Component::RootDir => (),
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(format!("pattern outside of workspace: {}", pattern))
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn compile_pattern(pattern: &str) -> Result<Pattern, String> {
+    let path = normalize_pattern(pattern)?;
+    Pattern::new(&zip_name(&path)).map_err(|e| format!("invalid pattern {}: {}", pattern, e))
+}
+
+fn literal_dir(pattern: &str) -> Result<PathBuf, String> {
+    let path = normalize_pattern(pattern)?;
+    let mut components = path.components().peekable();
+    let mut dir = PathBuf::new();
+    while let Some(component) = components.next() {
+        let name = component.as_os_str().to_string_lossy();
+        // last component names the file itself
+        if components.peek().is_none() || name.contains(|c| "*?[".contains(c)) {
+            break;
+        }
+        dir.push(component);
+    }
+    Ok(dir)
+}
+
+fn common_dir(a: &Path, b: &Path) -> PathBuf {
+    a.components()
+        .zip(b.components())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a)
+        .collect()
+}
+
+impl FileSelector {
+    pub fn new(patterns: &[String], exclude: &[String]) -> Result<Self, String> {
+        if patterns.is_empty() {
+            return Err("no file patterns given".into());
+        }
+        let mut base_dir: Option<PathBuf> = None;
+        for pattern in patterns {
+            let dir = literal_dir(pattern)?;
+            base_dir = Some(match base_dir {
+                Some(base_dir) => common_dir(&base_dir, &dir),
+                None => dir,
+            });
+        }
+
+        Ok(FileSelector {
+            include: patterns
+                .iter()
+                .map(|p| compile_pattern(p))
+                .collect::<Result<_, _>>()?,
+            exclude: exclude
+                .iter()
+                .map(|p| compile_pattern(p))
+                .collect::<Result<_, _>>()?,
+            base_dir: base_dir.unwrap_or_default(),
+        })
+    }
+
+    /// Directory (relative to the workspace) all selected files are in.
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    /// Checks path relative to `base_dir`.
+    pub fn matches(&self, rel_path: &Path) -> bool {
+        let name = zip_name(&self.base_dir.join(rel_path));
+        self.include
+            .iter()
+            .any(|p| p.matches_with(&name, MATCH_OPTIONS))
+            && !self
+                .exclude
+                .iter()
+                .any(|p| p.matches_with(&name, MATCH_OPTIONS))
+    }
+
+    /// Lists matching files under `dir`, the location of `base_dir`.
+    pub fn select(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in list_entries(dir)? {
+            // symlinks are left out, they may point outside of the workspace
+            if fs::symlink_metadata(dir.join(&entry))?.is_file() && self.matches(&entry) {
+                files.push(entry)
+            }
+        }
+        Ok(files)
+    }
+
+    /// Copies matching entries of the tar read from `input` into `output`.
+    ///
+    /// Entry names in `input` start with the name of `base_dir`, as in archives
+    /// returned by docker; with an empty `base_dir` they are relative to it.
+    pub fn filter_tar<R: Read, W: Write>(
+        &self,
+        input: R,
+        output: W,
+    ) -> io::Result<(W, Vec<PathBuf>)> {
+        let mut archive = tar::Archive::new(input);
+        let mut builder = tar::Builder::new(output);
+        let mut files = Vec::new();
+        let prefix_len = self.base_dir.components().last().into_iter().count();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let rel_path: PathBuf = entry
+                .path()?
+                .components()
+                .filter(|c| match c {
+                    Component::Normal(_) => true,
+                    _ => false,
+                })
+                .skip(prefix_len)
+                .collect();
+            if !rel_path.as_os_str().is_empty() && self.matches(&rel_path) {
+                let mut header = entry.header().clone();
+                builder.append_data(&mut header, &rel_path, &mut entry)?;
+                files.push(rel_path);
+            }
+        }
+        Ok((builder.into_inner()?, files))
+    }
+}
+
+fn zip_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn tar_entries<W: Write>(root: &Path, entries: &[PathBuf], output: W) -> io::Result<W> {
+    let mut builder = tar::Builder::new(output);
+    builder.follow_symlinks(false);
+    for entry in entries {
+        builder.append_path_with_name(root.join(entry), entry)?;
+    }
+    builder.into_inner()
+}
+
+fn zip_entries<W: Write + Seek>(root: &Path, entries: &[PathBuf], output: W) -> io::Result<W> {
+    let mut zip = zip::ZipWriter::new(output);
+    for entry in entries {
+        let meta = fs::symlink_metadata(root.join(entry))?;
+        if meta.file_type().is_symlink() {
+            continue;
+        }
+        let options = zip::write::FileOptions::default();
+        #[cfg(unix)]
+        let options = {
+            use std::os::unix::fs::PermissionsExt;
+            options.unix_permissions(meta.permissions().mode())
+        };
+        if meta.is_dir() {
+            zip.add_directory(zip_name(entry), options)
+                .map_err(zip_error)?;
+        } else {
+            zip.start_file(zip_name(entry), options)
+                .map_err(zip_error)?;
+            io::copy(&mut File::open(root.join(entry))?, &mut zip)?;
+        }
+    }
+    zip.finish().map_err(zip_error)
+}
+
+/// Runs `f` on a temporary file and copies its content into `output`.
+fn through_temp_file<W, F>(mut output: W, f: F) -> io::Result<W>
+where
+    W: Write,
+    F: FnOnce(File) -> io::Result<File>,
+{
+    let tmp_path = std::env::temp_dir().join(format!("gu-archive-{}", Uuid::new_v4()));
+    let result = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .and_then(f)
+        .and_then(|mut file| {
+            file.seek(SeekFrom::Start(0))?;
+            io::copy(&mut file, &mut output)
+        });
+    let _ = fs::remove_file(&tmp_path);
+    result.map(|_| output)
+}
+
+/// Packs `entries` (relative to `root`) into `output`.
+pub fn pack_entries<W: Write>(
+    format: ResourceFormat,
+    root: &Path,
+    entries: &[PathBuf],
+    output: W,
+) -> io::Result<W> {
+    match format {
+        ResourceFormat::Raw => Err(not_an_archive()),
+        ResourceFormat::Tar => tar_entries(root, entries, output),
+        ResourceFormat::TarGz => tar_entries(
+            root,
+            entries,
+            GzEncoder::new(output, Compression::default()),
+        )?
+        .finish(),
+        ResourceFormat::TarZstd => tar_entries(
+            root,
+            entries,
+            zstd::stream::write::Encoder::new(output, ZSTD_LEVEL)?,
+        )?
+        .finish(),
+        ResourceFormat::Zip => through_temp_file(output, |file| zip_entries(root, entries, file)),
+    }
+}
+
+/// Packs a directory, or a single file, into `output`.
+pub fn pack<W: Write>(format: ResourceFormat, input_path: &Path, output: W) -> io::Result<W> {
+    if input_path.is_dir() {
+        pack_entries(format, input_path, &list_entries(input_path)?, output)
+    } else {
+        let file_name = input_path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"))?;
+        let root = input_path.parent().unwrap_or_else(|| Path::new(""));
+        pack_entries(format, root, &[PathBuf::from(file_name)], output)
+    }
+}
+
+fn unzip<R: Read>(mut input: R, output_path: &Path) -> io::Result<()> {
+    while let Some(mut file) = zip::read::read_zipfile_from_stream(&mut input).map_err(zip_error)? {
+        let path = output_path.join(file.sanitized_name());
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut file, &mut File::create(&path)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(mode) = file.unix_mode() {
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Unpacks archive read from `input` into `output_path` directory.
+pub fn unpack<R: Read>(format: ResourceFormat, input: R, output_path: &Path) -> io::Result<()> {
+    fs::create_dir_all(output_path)?;
+    match format {
+        ResourceFormat::Raw => Err(not_an_archive()),
+        ResourceFormat::Tar => tar::Archive::new(input).unpack(output_path),
+        ResourceFormat::TarGz => tar::Archive::new(GzDecoder::new(input)).unpack(output_path),
+        ResourceFormat::TarZstd => {
+            tar::Archive::new(zstd::stream::read::Decoder::new(input)?).unpack(output_path)
+        }
+        ResourceFormat::Zip => unzip(input, output_path),
+    }
+}
+
+/// Converts archive read from `input` into plain tar written to `output`.
+pub fn to_tar<R: Read, W: Write>(format: ResourceFormat, input: R, output: W) -> io::Result<W> {
+    fn copy<R: Read, W: Write>(mut input: R, mut output: W) -> io::Result<W> {
+        io::copy(&mut input, &mut output).map(|_| output)
+    }
+
+    match format {
+        ResourceFormat::Raw => Err(not_an_archive()),
+        ResourceFormat::Tar => copy(input, output),
+        ResourceFormat::TarGz => copy(GzDecoder::new(input), output),
+        ResourceFormat::TarZstd => copy(zstd::stream::read::Decoder::new(input)?, output),
+        ResourceFormat::Zip => {
+            let mut input = input;
+            let mut builder = tar::Builder::new(output);
+            while let Some(mut file) =
+                zip::read::read_zipfile_from_stream(&mut input).map_err(zip_error)?
+            {
+                let mut header = tar::Header::new_ustar();
+                header.set_uid(0);
+                header.set_gid(0);
+                if file.is_dir() {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    header.set_mode(file.unix_mode().unwrap_or(0o755));
+                } else {
+                    header.set_size(file.size());
+                    header.set_mode(file.unix_mode().unwrap_or(0o644));
+                }
+                header.set_path(file.sanitized_name())?;
+                header.set_cksum();
+                builder.append(&header, &mut file)?;
+            }
+            builder.into_inner()
+        }
+    }
+}
+
+/// Converts plain tar read from `input` into the archive `format` written to `output`.
+pub fn from_tar<R: Read, W: Write>(format: ResourceFormat, input: R, output: W) -> io::Result<W> {
+    fn copy<R: Read, W: Write>(mut input: R, mut output: W) -> io::Result<W> {
+        io::copy(&mut input, &mut output).map(|_| output)
+    }
+
+    match format {
+        ResourceFormat::Raw => Err(not_an_archive()),
+        ResourceFormat::Tar => copy(input, output),
+        ResourceFormat::TarGz => {
+            copy(input, GzEncoder::new(output, Compression::default()))?.finish()
+        }
+        ResourceFormat::TarZstd => copy(
+            input,
+            zstd::stream::write::Encoder::new(output, ZSTD_LEVEL)?,
+        )?
+        .finish(),
+        ResourceFormat::Zip => through_temp_file(output, move |file| {
+            let mut archive = tar::Archive::new(input);
+            let mut zip = zip::ZipWriter::new(file);
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let name = zip_name(&entry.path()?);
+                let options =
+                    zip::write::FileOptions::default().unix_permissions(entry.header().mode()?);
+                if entry.header().entry_type().is_dir() {
+                    zip.add_directory(name, options).map_err(zip_error)?;
+                } else if entry.header().entry_type().is_file() {
+                    zip.start_file(name, options).map_err(zip_error)?;
+                    io::copy(&mut entry, &mut zip)?;
+                }
+            }
+            zip.finish().map_err(zip_error)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_dir;
+
+    const FORMATS: [ResourceFormat; 4] = [
+        ResourceFormat::Tar,
+        ResourceFormat::TarGz,
+        ResourceFormat::TarZstd,
+        ResourceFormat::Zip,
+    ];
+
+    const INPUT: &[(&str, &[u8])] = &[
+        ("input/log.txt", b"rendering done"),
+        ("input/frames/frame_0001.png", &[7u8; 10_000]),
+    ];
+
+    fn assert_unpacked(dir: &Path) {
+        assert_eq!(fs::read(dir.join("log.txt")).unwrap(), b"rendering done");
+        assert_eq!(
+            fs::read(dir.join("frames/frame_0001.png")).unwrap(),
+            vec![7u8; 10_000]
+        );
+    }
+
+    #[test]
+    fn list_entries_is_relative() {
+        let dir = test_dir("archive/list", INPUT);
+
+        assert_eq!(
+            list_entries(&dir.join("input")).unwrap(),
+            vec![
+                PathBuf::from("frames"),
+                PathBuf::from("frames/frame_0001.png"),
+                PathBuf::from("log.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn select_files() {
+        let dir = test_dir("archive/select", INPUT);
+        fs::write(dir.join("input/frames/frame_0002.png"), b"").unwrap();
+        fs::write(dir.join("input/frames/frame_0002.exr"), b"").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/etc/hostname", dir.join("input/frames/frame_0003.png"))
+            .unwrap();
+
+        let selector = FileSelector::new(
+            &["/input/frames/*.png".into(), "input/frames/*.exr".into()],
+            &["**/frame_0001.*".into()],
+        )
+        .unwrap();
+
+        assert_eq!(selector.base_dir(), Path::new("input/frames"));
+        assert_eq!(
+            selector.select(&dir.join("input/frames")).unwrap(),
+            vec![
+                PathBuf::from("frame_0002.exr"),
+                PathBuf::from("frame_0002.png")
+            ]
+        );
+        assert!(FileSelector::new(&["../*".into()], &[]).is_err());
+    }
+
+    #[test]
+    fn filter_tar() {
+        let dir = test_dir("archive/filter", INPUT);
+        // docker archives entries under the name of the requested directory
+        let tar = pack(ResourceFormat::Tar, &dir.join("input"), Vec::new()).unwrap();
+        let selector = FileSelector::new(&["out/**/*.png".into()], &[]).unwrap();
+
+        let (filtered, files) = selector.filter_tar(tar.as_slice(), Vec::new()).unwrap();
+        unpack(
+            ResourceFormat::Tar,
+            filtered.as_slice(),
+            &dir.join("output"),
+        )
+        .unwrap();
+
+        assert_eq!(files, vec![PathBuf::from("frame_0001.png")]);
+        assert!(dir.join("output/frame_0001.png").exists());
+
+        // root level patterns, the archive content isn't prefixed
+        let selector = FileSelector::new(&["*.txt".into()], &[]).unwrap();
+        let (_, files) = selector.filter_tar(tar.as_slice(), Vec::new()).unwrap();
+        assert_eq!(files, vec![PathBuf::from("log.txt")]);
+    }
+
+    #[test]
+    fn move_into_merges() {
+        let dir = test_dir("archive/move", INPUT);
+        fs::create_dir_all(dir.join("output/frames")).unwrap();
+        fs::write(dir.join("output/frames/frame_0000.png"), b"").unwrap();
+        fs::write(dir.join("output/log.txt"), b"rendering").unwrap();
+
+        move_into(&dir.join("input"), &dir.join("output")).unwrap();
+
+        assert_unpacked(&dir.join("output"));
+        assert!(dir.join("output/frames/frame_0000.png").exists());
+    }
+
+    #[test]
+    fn pack_and_unpack() {
+        for format in FORMATS.iter().cloned() {
+            let dir = test_dir(&format!("archive/pack-{:?}", format), INPUT);
+            let packed = pack(format, &dir.join("input"), Vec::new()).unwrap();
+
+            unpack(format, packed.as_slice(), &dir.join("output")).unwrap();
+
+            assert_unpacked(&dir.join("output"));
+        }
+    }
+
+    #[test]
+    fn convert_through_tar() {
+        for format in FORMATS.iter().cloned() {
+            let dir = test_dir(&format!("archive/convert-{:?}", format), INPUT);
+            let tar = pack(ResourceFormat::Tar, &dir.join("input"), Vec::new()).unwrap();
+
+            let packed = from_tar(format, tar.as_slice(), Vec::new()).unwrap();
+            let tar = to_tar(format, packed.as_slice(), Vec::new()).unwrap();
+            unpack(ResourceFormat::Tar, tar.as_slice(), &dir.join("output")).unwrap();
+
+            assert_unpacked(&dir.join("output"));
+        }
+    }
+}
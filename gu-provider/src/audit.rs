@@ -0,0 +1,625 @@
+//! Audit log of what hubs did on this machine.
+//!
+//! Deployments created and destroyed, commands, downloaded URIs, spawned
+//! executables and workspace file access are appended as JSON lines to
+//! `<work dir>/audit/audit.log`, which is rotated by size. Entries are
+//! written with the outcome, including requests rejected before they ran.
+//! `gu-provider audit` shows the entries.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use actix::prelude::*;
+use futures::prelude::*;
+use log::error;
+use prettytable::{cell, row};
+use serde::{Deserialize, Serialize};
+
+use gu_actix::prelude::*;
+use gu_base::{cli, App, Arg, ArgMatches, Decorator, Module, SubCommand};
+use gu_model::chrono::{DateTime, Utc};
+use gu_model::envman::{Command, FsOp, WorkspaceFs};
+use gu_net::NodeId;
+use gu_persist::config::{ConfigManager, ConfigModule, GetConfig, HasSectionId};
+
+const LOG_NAME: &str = "audit.log";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AuditConfig {
+    /// size in bytes after which the log is rotated
+    max_file_size: u64,
+    /// number of files kept, including the current one
+    max_files: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+impl HasSectionId for AuditConfig {
+    const SECTION_ID: &'static str = "audit";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum AuditEvent {
+    #[serde(rename_all = "camelCase")]
+    CreateSession {
+        env_type: String,
+        image: String,
+        name: String,
+    },
+    /// command without details worth recording
+    Command {
+        command: String,
+    },
+    Download {
+        uri: String,
+        file_path: String,
+    },
+    Upload {
+        uri: String,
+    },
+    Exec {
+        executable: String,
+        args: Vec<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    WriteFile {
+        file_path: String,
+    },
+    /// listing, reading, stat or removal of a workspace file
+    WorkspaceFs {
+        op: String,
+        path: String,
+    },
+    DestroySession,
+}
+
+impl AuditEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            AuditEvent::CreateSession { .. } => "createSession",
+            AuditEvent::Command { .. } => "command",
+            AuditEvent::Download { .. } => "download",
+            AuditEvent::Upload { .. } => "upload",
+            AuditEvent::Exec { .. } => "exec",
+            AuditEvent::WriteFile { .. } => "writeFile",
+            AuditEvent::WorkspaceFs { .. } => "workspaceFs",
+            AuditEvent::DestroySession => "destroySession",
+        }
+    }
+
+    fn details(&self) -> String {
+        match self {
+            AuditEvent::CreateSession {
+                env_type,
+                image,
+                name,
+            } => format!("{} {} ({})", env_type, image, name),
+            AuditEvent::Command { command } => command.clone(),
+            AuditEvent::Download { uri, file_path } => format!("{} -> {}", uri, file_path),
+            AuditEvent::Upload { uri } => uri.clone(),
+            AuditEvent::Exec { executable, args } => format!("{} {}", executable, args.join(" "))
+                .trim_end()
+                .into(),
+            AuditEvent::WriteFile { file_path } => file_path.clone(),
+            AuditEvent::WorkspaceFs { op, path } => format!("{} {}", op, path),
+            AuditEvent::DestroySession => String::new(),
+        }
+    }
+}
+
+impl<'a> From<&'a Command> for AuditEvent {
+    fn from(command: &'a Command) -> Self {
+        let command_name = |name: &str| AuditEvent::Command {
+            command: name.into(),
+        };
+
+        match command {
+            Command::Exec {
+                executable, args, ..
+            }
+            | Command::Start { executable, args } => AuditEvent::Exec {
+                executable: executable.clone(),
+                args: args.clone(),
+            },
+            Command::DownloadFile { uri, file_path, .. } => AuditEvent::Download {
+                uri: uri.clone(),
+                file_path: file_path.clone(),
+            },
+            Command::UploadFile { uri, .. } | Command::UploadFiles { uri, .. } => {
+                AuditEvent::Upload { uri: uri.clone() }
+            }
+            Command::WriteFile { file_path, .. } => AuditEvent::WriteFile {
+                file_path: file_path.clone(),
+            },
+            Command::Open => command_name("open"),
+            Command::Close => command_name("close"),
+            Command::Stop { .. } => command_name("stop"),
+            Command::Wait => command_name("wait"),
+            Command::AddTags(_) => command_name("addTags"),
+            Command::DelTags(_) => command_name("delTags"),
+            Command::ReadFile { .. } => command_name("readFile"),
+        }
+    }
+}
+
+impl<'a> From<&'a WorkspaceFs> for AuditEvent {
+    fn from(msg: &'a WorkspaceFs) -> Self {
+        let op = match msg.op {
+            FsOp::Get { .. } => "get",
+            FsOp::Stat => "stat",
+            FsOp::Delete => "delete",
+        };
+
+        AuditEvent::WorkspaceFs {
+            op: op.into(),
+            path: msg.path.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub ts: DateTime<Utc>,
+    /// hub that requested the action
+    pub hub: NodeId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn log_path(dir: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => dir.join(LOG_NAME),
+        index => dir.join(format!("{}.{}", LOG_NAME, index)),
+    }
+}
+
+fn log_dir() -> PathBuf {
+    ConfigModule::new().work_dir().join("audit")
+}
+
+/// Appends entries to the current file
+struct LogWriter {
+    dir: PathBuf,
+    config: AuditConfig,
+    /// open file and its size
+    file: Option<(File, u64)>,
+}
+
+impl LogWriter {
+    fn new(dir: PathBuf, config: AuditConfig) -> Self {
+        LogWriter {
+            dir,
+            config,
+            file: None,
+        }
+    }
+
+    fn write(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let line_len = line.len() as u64;
+
+        let size = self.open()?;
+        if size > 0 && size + line_len > self.config.max_file_size {
+            self.rotate()?;
+            self.open()?;
+        }
+
+        let (file, size) = self.file.as_mut().unwrap();
+        file.write_all(&line)?;
+        *size += line_len;
+        Ok(())
+    }
+
+    /// Opens the current file if needed; returns its size
+    fn open(&mut self) -> io::Result<u64> {
+        if let Some((_, size)) = self.file {
+            return Ok(size);
+        }
+        fs::create_dir_all(&self.dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path(&self.dir, 0))?;
+        let size = file.metadata()?.len();
+        self.file = Some((file, size));
+        Ok(size)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        let keep = self.config.max_files.saturating_sub(1);
+
+        remove_if_exists(&log_path(&self.dir, keep))?;
+        for index in (0..keep).rev() {
+            let from = log_path(&self.dir, index);
+            if from.exists() {
+                fs::rename(from, log_path(&self.dir, index + 1))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+/// Reads entries from all files, oldest first
+fn read_entries(dir: &Path) -> io::Result<Vec<AuditEntry>> {
+    let mut files = Vec::new();
+    for index in 0.. {
+        let path = log_path(dir, index);
+        if !path.exists() {
+            break;
+        }
+        files.push(path);
+    }
+
+    let mut entries = Vec::new();
+    for path in files.iter().rev() {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => error!("{}: invalid entry: {}", path.display(), e),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+struct AuditLog {
+    writer: LogWriter,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog {
+            writer: LogWriter::new(log_dir(), AuditConfig::default()),
+        }
+    }
+}
+
+impl Actor for AuditLog {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // entries recorded before the config is loaded are held in the mailbox
+        ctx.wait(
+            ConfigManager::from_registry()
+                .send(GetConfig::new())
+                .flatten_fut()
+                .map_err(|e| error!("audit config: {}", e))
+                .into_actor(self)
+                .map(|config: Arc<AuditConfig>, act, _| {
+                    act.writer.config = config.as_ref().clone()
+                }),
+        );
+    }
+}
+
+impl Supervised for AuditLog {}
+impl SystemService for AuditLog {}
+
+struct Record(AuditEntry);
+
+impl Message for Record {
+    type Result = ();
+}
+
+impl Handler<Record> for AuditLog {
+    type Result = ();
+
+    fn handle(&mut self, msg: Record, _ctx: &mut Self::Context) -> Self::Result {
+        if let Err(e) = self.writer.write(&msg.0) {
+            error!("audit log: {}", e)
+        }
+    }
+}
+
+/// Appends an entry to the audit log
+pub fn record(hub: NodeId, deployment: Option<String>, event: AuditEvent, error: Option<String>) {
+    AuditLog::from_registry().do_send(Record(AuditEntry {
+        ts: Utc::now(),
+        hub,
+        deployment,
+        event,
+        error,
+    }))
+}
+
+/// Errors of commands run in order until the first failure, as reported by
+/// `SessionUpdate`; commands after the failed one didn't run and are left out
+fn command_errors(count: usize, result: &Result<Vec<String>, Vec<String>>) -> Vec<Option<String>> {
+    match result {
+        Ok(_) => vec![None; count],
+        Err(outputs) => {
+            let succeeded = outputs.len().saturating_sub(1).min(count);
+            let mut errors = vec![None; succeeded];
+            if succeeded < count {
+                errors.push(Some(
+                    outputs
+                        .last()
+                        .cloned()
+                        .unwrap_or_else(|| "environment unavailable".into()),
+                ));
+            }
+            errors
+        }
+    }
+}
+
+/// Appends entries of commands that ran, with their outcome
+pub fn record_commands(
+    hub: NodeId,
+    deployment: &str,
+    events: Vec<AuditEvent>,
+    result: &Result<Vec<String>, Vec<String>>,
+) {
+    let errors = command_errors(events.len(), result);
+    for (event, error) in events.into_iter().zip(errors) {
+        record(hub, Some(deployment.into()), event, error)
+    }
+}
+
+/// Appends entries of commands rejected before any of them ran
+pub fn record_rejected(hub: NodeId, deployment: &str, events: Vec<AuditEvent>, reason: &str) {
+    for event in events {
+        record(hub, Some(deployment.into()), event, Some(reason.into()))
+    }
+}
+
+#[derive(Default)]
+struct Filter {
+    hub: Option<NodeId>,
+    deployment: Option<String>,
+    event: Option<String>,
+    since: Option<DateTime<Utc>>,
+    last: Option<usize>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.hub.map_or(true, |hub| entry.hub == hub)
+            && self
+                .deployment
+                .as_ref()
+                .map_or(true, |id| entry.deployment.as_ref() == Some(id))
+            && self
+                .event
+                .as_ref()
+                .map_or(true, |event| entry.event.name() == event)
+            && self.since.map_or(true, |since| entry.ts >= since)
+    }
+}
+
+fn show(filter: &Filter) {
+    let entries = match read_entries(&log_dir()) {
+        Ok(entries) => entries,
+        Err(e) => return eprintln!("cannot read audit log: {}", e),
+    };
+    let mut entries: Vec<AuditEntry> = entries.into_iter().filter(|e| filter.matches(e)).collect();
+    if let Some(last) = filter.last {
+        let skip = entries.len().saturating_sub(last);
+        entries.drain(..skip);
+    }
+
+    cli::format_table(
+        row!["Time", "Hub", "Deployment", "Event", "Details", "Error"],
+        || "No audit entries",
+        entries.into_iter().map(|entry| {
+            row![
+                entry.ts.to_rfc3339(),
+                entry.hub.to_string(),
+                entry.deployment.unwrap_or_default(),
+                entry.event.name(),
+                entry.event.details(),
+                entry.error.unwrap_or_default()
+            ]
+        }),
+    )
+}
+
+struct AuditModule {
+    filter: Option<Filter>,
+}
+
+impl Module for AuditModule {
+    fn args_declare<'a, 'b>(&self, app: App<'a, 'b>) -> App<'a, 'b> {
+        app.subcommand(
+            SubCommand::with_name("audit")
+                .about("Shows what hubs did on this machine")
+                .arg(
+                    Arg::with_name("hub")
+                        .long("hub")
+                        .value_name("node_id")
+                        .help("Only entries of the hub"),
+                )
+                .arg(
+                    Arg::with_name("deployment")
+                        .long("deployment")
+                        .value_name("deployment_id")
+                        .help("Only entries of the deployment"),
+                )
+                .arg(
+                    Arg::with_name("event")
+                        .long("event")
+                        .possible_values(&[
+                            "createSession",
+                            "command",
+                            "download",
+                            "upload",
+                            "exec",
+                            "writeFile",
+                            "workspaceFs",
+                            "destroySession",
+                        ])
+                        .help("Only entries of the kind"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .value_name("time")
+                        .help("Only entries since the RFC 3339 time, e.g. 2019-01-31T12:00:00Z"),
+                )
+                .arg(
+                    Arg::with_name("last")
+                        .long("last")
+                        .short("n")
+                        .value_name("count")
+                        .help("Only the most recent entries"),
+                ),
+        )
+    }
+
+    fn args_consume(&mut self, matches: &ArgMatches) -> bool {
+        let m = match matches.subcommand_matches("audit") {
+            Some(m) => m,
+            None => return false,
+        };
+
+        let filter = (|| -> Result<Filter, String> {
+            Ok(Filter {
+                hub: match m.value_of("hub") {
+                    Some(hub) => Some(hub.parse().map_err(|_| format!("invalid hub: {}", hub))?),
+                    None => None,
+                },
+                deployment: m.value_of("deployment").map(Into::into),
+                event: m.value_of("event").map(Into::into),
+                since: match m.value_of("since") {
+                    Some(since) => Some(
+                        DateTime::parse_from_rfc3339(since)
+                            .map_err(|e| format!("invalid time {}: {}", since, e))?
+                            .with_timezone(&Utc),
+                    ),
+                    None => None,
+                },
+                last: match m.value_of("last") {
+                    Some(last) => Some(
+                        last.parse()
+                            .map_err(|_| format!("invalid count: {}", last))?,
+                    ),
+                    None => None,
+                },
+            })
+        })();
+
+        match filter {
+            Ok(filter) => self.filter = Some(filter),
+            Err(e) => eprintln!("{}", e),
+        }
+        true
+    }
+
+    fn run<D: Decorator + Clone + 'static>(&self, _decorator: D) {
+        if let Some(ref filter) = self.filter {
+            show(filter)
+        }
+    }
+}
+
+pub fn module() -> impl Module {
+    AuditModule { filter: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_dir;
+
+    fn entry(event: AuditEvent) -> AuditEntry {
+        AuditEntry {
+            ts: "2019-01-31T12:00:00Z".parse().unwrap(),
+            hub: "0x875f272d3b9e7b55a5784a131a60bf3d7a42c73c"
+                .parse()
+                .unwrap(),
+            deployment: Some("hd::1".into()),
+            event,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_entry_format() {
+        let command = Command::Exec {
+            executable: "gu-render".into(),
+            args: vec!["-o".into(), "out.png".into()],
+            working_dir: None,
+        };
+        let json = serde_json::to_value(entry(AuditEvent::from(&command))).unwrap();
+
+        assert_eq!(json["event"], "exec");
+        assert_eq!(json["executable"], "gu-render");
+        assert_eq!(json["deployment"], "hd::1");
+        assert!(json.get("error").is_none());
+
+        let parsed: AuditEntry = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.event.details(), "gu-render -o out.png");
+    }
+
+    #[test]
+    fn test_command_errors() {
+        let outputs = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            command_errors(2, &Ok(outputs(&["a", "b"]))),
+            vec![None, None]
+        );
+        // the second of three commands failed, the third didn't run
+        assert_eq!(
+            command_errors(3, &Err(outputs(&["a", "failed"]))),
+            vec![None, Some("failed".to_string())]
+        );
+        assert_eq!(
+            command_errors(2, &Err(Vec::new())),
+            vec![Some("environment unavailable".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = test_dir("audit/rotation", &[]);
+        let line_len = serde_json::to_vec(&entry(AuditEvent::DestroySession))
+            .unwrap()
+            .len() as u64
+            + 1;
+        let mut writer = LogWriter::new(
+            dir.clone(),
+            AuditConfig {
+                max_file_size: 2 * line_len,
+                max_files: 2,
+            },
+        );
+
+        for _ in 0..5 {
+            writer.write(&entry(AuditEvent::DestroySession)).unwrap();
+        }
+
+        assert!(log_path(&dir, 1).exists());
+        assert!(!log_path(&dir, 2).exists());
+        // the oldest file is dropped: 1 + 2 entries remain
+        assert_eq!(read_entries(&dir).unwrap().len(), 3);
+    }
+}
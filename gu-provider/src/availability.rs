@@ -0,0 +1,538 @@
+//! Availability policy.
+//!
+//! Desktop providers may take work only while their owner doesn't need the
+//! machine: after it has been idle for a while, within configured time
+//! windows or on AC power. When the provider becomes unavailable, running
+//! deployments are kept, paused or stopped.
+
+use std::{
+    borrow::Cow, collections::BTreeMap, fs, io, path::Path, process::Command, sync::Arc,
+    time::Duration,
+};
+
+use actix::prelude::*;
+use futures::{future, prelude::*};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use gu_actix::prelude::*;
+use gu_model::chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Utc, Weekday};
+use gu_model::peers::{Availability, AvailabilityChanged, GetAvailability};
+use gu_net::rpc::{
+    peer::{ListPeers, PeerManager},
+    remoting::peer,
+    PublicMessage, RemotingContext, RemotingSystemService,
+};
+use gu_persist::config::{ConfigManager, GetConfig, HasSectionId};
+
+use crate::{envman, metrics, server, status};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AvailabilityConfig {
+    /// available after the user has been idle for this many minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    idle_minutes: Option<u64>,
+    /// available within any of the windows
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    time_windows: Vec<TimeWindow>,
+    /// available on AC power
+    #[serde(default)]
+    on_ac_power: bool,
+    #[serde(default)]
+    when_unavailable: WhenUnavailable,
+}
+
+impl HasSectionId for AvailabilityConfig {
+    const SECTION_ID: &'static str = "availability";
+}
+
+/// What happens to running deployments when the provider becomes unavailable
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum WhenUnavailable {
+    Keep,
+    /// suspend processes and freeze containers until available again
+    Pause,
+    Stop,
+}
+
+impl Default for WhenUnavailable {
+    fn default() -> Self {
+        WhenUnavailable::Keep
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TimeWindow {
+    /// every day if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    days: Vec<Weekday>,
+    from: NaiveTime,
+    /// a window ending before it starts spans midnight
+    to: NaiveTime,
+}
+
+impl TimeWindow {
+    fn on_day(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, now: NaiveDateTime) -> bool {
+        let (day, time) = (now.weekday(), now.time());
+
+        if self.from <= self.to {
+            self.on_day(day) && self.from <= time && time < self.to
+        } else {
+            (self.on_day(day) && time >= self.from) || (self.on_day(day.pred()) && time < self.to)
+        }
+    }
+}
+
+/// Current state of the machine
+struct Probe {
+    now: NaiveDateTime,
+    /// unknown on unsupported platforms
+    idle: Option<Duration>,
+    on_ac_power: Option<bool>,
+}
+
+impl Probe {
+    fn current() -> Self {
+        Probe {
+            now: Local::now().naive_local(),
+            idle: idle_time(),
+            on_ac_power: on_ac_power(),
+        }
+    }
+}
+
+impl AvailabilityConfig {
+    /// Available if any of the configured conditions holds; the error lists
+    /// conditions not met
+    fn evaluate(&self, probe: &Probe) -> Result<(), String> {
+        let mut unmet = Vec::new();
+
+        if let Some(minutes) = self.idle_minutes {
+            match probe.idle {
+                Some(idle) if idle >= Duration::from_secs(minutes * 60) => return Ok(()),
+                Some(_) => unmet.push("user active".to_string()),
+                None => unmet.push("idle time unknown".to_string()),
+            }
+        }
+        if !self.time_windows.is_empty() {
+            if self.time_windows.iter().any(|w| w.contains(probe.now)) {
+                return Ok(());
+            }
+            unmet.push("outside time windows".to_string());
+        }
+        if self.on_ac_power {
+            match probe.on_ac_power {
+                Some(true) => return Ok(()),
+                Some(false) => unmet.push("on battery".to_string()),
+                None => unmet.push("power source unknown".to_string()),
+            }
+        }
+
+        match unmet.is_empty() {
+            true => Ok(()),
+            false => Err(unmet.join(", ")),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn idle_time() -> Option<Duration> {
+    // X11 sessions
+    let xprintidle = Command::new("xprintidle")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+        .map(Duration::from_millis);
+
+    // like `w`, input on a terminal updates its access time
+    xprintidle.or_else(|| {
+        let ttys = fs::read_dir("/dev/pts")
+            .into_iter()
+            .chain(fs::read_dir("/dev"))
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.parse::<u32>().is_ok()
+                    || (name.starts_with("tty") && name[3..].parse::<u32>().is_ok())
+            });
+
+        ttys.filter_map(|entry| entry.metadata().ok()?.accessed().ok()?.elapsed().ok())
+            .min()
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn idle_time() -> Option<Duration> {
+    let output = Command::new("ioreg")
+        .args(&["-c", "IOHIDSystem", "-d", "4"])
+        .output()
+        .ok()?;
+
+    parse_hid_idle_time(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn idle_time() -> Option<Duration> {
+    None
+}
+
+#[allow(unused)]
+fn parse_hid_idle_time(ioreg: &str) -> Option<Duration> {
+    let line = ioreg
+        .lines()
+        .find(|line| line.contains("\"HIDIdleTime\""))?;
+    let nanos: u64 = line.rsplit('=').next()?.trim().parse().ok()?;
+
+    Some(Duration::from_nanos(nanos))
+}
+
+#[cfg(target_os = "linux")]
+fn on_ac_power() -> Option<bool> {
+    let read = |path: &Path, name: &str| {
+        fs::read_to_string(path.join(name))
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default()
+    };
+    let mains: Vec<bool> = fs::read_dir("/sys/class/power_supply")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| read(path, "type") == "Mains")
+        .map(|path| read(&path, "online") == "1")
+        .collect();
+
+    // desktops have no power supply entries
+    Some(mains.is_empty() || mains.into_iter().any(|online| online))
+}
+
+#[cfg(target_os = "macos")]
+fn on_ac_power() -> Option<bool> {
+    let output = Command::new("pmset").args(&["-g", "batt"]).output().ok()?;
+
+    parse_pmset_power_source(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn on_ac_power() -> Option<bool> {
+    None
+}
+
+#[allow(unused)]
+fn parse_pmset_power_source(pmset: &str) -> Option<bool> {
+    let source = pmset.lines().next()?.split('\'').nth(1)?;
+
+    Some(source == "AC Power")
+}
+
+/// Suspends (`true`) or resumes all deployments of an environment
+pub struct PauseDeployments(pub bool);
+
+impl Message for PauseDeployments {
+    type Result = Result<(), String>;
+}
+
+struct AddEnv(Cow<'static, str>, Recipient<PauseDeployments>);
+
+impl Message for AddEnv {
+    type Result = ();
+}
+
+/// Registers an environment to pause when the provider becomes unavailable
+pub fn register_env<S: Into<Cow<'static, str>>>(
+    env_type: S,
+    recipient: Recipient<PauseDeployments>,
+) {
+    AvailabilityManager::from_registry().do_send(AddEnv(env_type.into(), recipient))
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, frozen: bool) -> io::Result<()> {
+    let signal = if frozen { libc::SIGSTOP } else { libc::SIGCONT };
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        return Ok(());
+    }
+    match io::Error::last_os_error() {
+        // exited since the process table was read
+        ref e if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+        e => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn signal(_pid: u32, _frozen: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "pausing processes is not supported",
+    ))
+}
+
+/// Stops or continues the process trees of deployments
+pub(crate) fn signal_processes(
+    pids: Vec<u32>,
+    frozen: bool,
+) -> impl Future<Item = (), Error = String> {
+    gu_hdman::download::cpu_pool().spawn_fn(move || -> Result<_, String> {
+        for pid in metrics::process_tree(&pids).map_err(|e| e.to_string())? {
+            signal(pid, frozen).map_err(|e| format!("process {}: {}", pid, e))?;
+        }
+        Ok(())
+    })
+}
+
+/// Tells connected hubs about the change; `stop` also lists the deployments
+/// of each hub that are going to be stopped
+fn notify_hubs(availability: Availability, stop: bool) -> impl Future<Item = (), Error = ()> {
+    PeerManager::from_registry()
+        .send(ListPeers)
+        .map_err(|e| error!("listing hubs: {}", e))
+        .and_then(move |hubs| {
+            future::join_all(hubs.into_iter().map(move |hub| {
+                let node_id = hub.node_id;
+                let availability = availability.clone();
+                let stopping = match stop {
+                    true => future::Either::A(
+                        envman::owned_deployments(node_id)
+                            .map(|ids| ids.into_iter().collect())
+                            .map_err(|e| e.to_string()),
+                    ),
+                    false => future::Either::B(future::ok(Vec::new())),
+                };
+
+                stopping
+                    .and_then(move |stopping| {
+                        peer(node_id)
+                            .into_endpoint()
+                            .timeout(NOTIFY_TIMEOUT)
+                            .send(AvailabilityChanged {
+                                availability,
+                                stopping,
+                            })
+                            .map_err(|e| e.to_string())
+                    })
+                    .then(move |r| {
+                        if let Err(e) = r {
+                            warn!("notifying hub {:?}: {}", node_id, e);
+                        }
+                        Ok::<_, ()>(())
+                    })
+            }))
+            .map(|_| ())
+        })
+}
+
+struct AvailabilityManager {
+    state: Availability,
+    envs: BTreeMap<Cow<'static, str>, Recipient<PauseDeployments>>,
+}
+
+impl Default for AvailabilityManager {
+    fn default() -> Self {
+        AvailabilityManager {
+            state: Availability {
+                available: true,
+                reason: None,
+                paused: false,
+                since: Utc::now(),
+            },
+            envs: BTreeMap::new(),
+        }
+    }
+}
+
+impl AvailabilityManager {
+    fn check(&mut self, ctx: &mut <Self as Actor>::Context) {
+        ctx.spawn(
+            ConfigManager::from_registry()
+                .send(GetConfig::new())
+                .flatten_fut()
+                .map_err(|e| error!("availability config: {}", e))
+                // probing runs commands like `xprintidle`
+                .and_then(|config: Arc<AvailabilityConfig>| {
+                    gu_hdman::download::cpu_pool()
+                        .spawn_fn(|| Ok::<_, ()>(Probe::current()))
+                        .map(move |probe| (config, probe))
+                })
+                .into_actor(self)
+                .map(|(config, probe), act, ctx| {
+                    act.update(config.evaluate(&probe), config.when_unavailable, ctx)
+                }),
+        );
+    }
+
+    fn freeze_all(&self, frozen: bool) -> impl Future<Item = (), Error = ()> {
+        future::join_all(self.envs.clone().into_iter().map(move |(env, recipient)| {
+            recipient.send(PauseDeployments(frozen)).then(move |r| {
+                let action = if frozen { "pause" } else { "resume" };
+                match r {
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => error!("cannot {} {} deployments: {}", action, env, e),
+                    Err(e) => error!("cannot {} {} deployments: {}", action, env, e),
+                }
+                Ok::<_, ()>(())
+            })
+        }))
+        .map(|_| ())
+    }
+
+    fn update(
+        &mut self,
+        result: Result<(), String>,
+        when_unavailable: WhenUnavailable,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let available = result.is_ok();
+        self.state.reason = result.err();
+        if available == self.state.available {
+            return;
+        }
+
+        match &self.state.reason {
+            Some(reason) => info!("provider unavailable: {}", reason),
+            None => info!("provider available"),
+        }
+        self.state.available = available;
+        self.state.since = Utc::now();
+        server::set_available(available);
+        status::announce();
+
+        let frozen = match (available, when_unavailable) {
+            (true, _) if self.state.paused => Some(false),
+            (false, WhenUnavailable::Pause) => Some(true),
+            _ => None,
+        };
+        if let Some(frozen) = frozen {
+            self.state.paused = frozen;
+            ctx.spawn(self.freeze_all(frozen).into_actor(self));
+        }
+
+        let stop = !available && when_unavailable == WhenUnavailable::Stop;
+        let notify = notify_hubs(self.state.clone(), stop);
+        if stop {
+            // hubs learn which deployments go away before they do
+            ctx.spawn(
+                notify
+                    .then(|_| envman::destroy_all())
+                    .map(|count| info!("stopped {} deployments", count))
+                    .map_err(|e| error!("stopping deployments: {}", e))
+                    .into_actor(self),
+            );
+        } else {
+            ctx.spawn(notify.into_actor(self));
+        }
+    }
+}
+
+impl Actor for AvailabilityManager {
+    type Context = RemotingContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.bind::<GetAvailability>(GetAvailability::ID);
+        self.check(ctx);
+        ctx.run_interval(CHECK_INTERVAL, |act, ctx| act.check(ctx));
+    }
+}
+
+impl RemotingSystemService for AvailabilityManager {}
+
+impl Handler<AddEnv> for AvailabilityManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddEnv, _ctx: &mut Self::Context) {
+        self.envs.insert(msg.0, msg.1);
+    }
+}
+
+impl Handler<GetAvailability> for AvailabilityManager {
+    type Result = Result<Availability, String>;
+
+    fn handle(&mut self, _msg: GetAvailability, _ctx: &mut Self::Context) -> Self::Result {
+        Ok(self.state.clone())
+    }
+}
+
+pub fn start() {
+    let _ = AvailabilityManager::from_registry();
+}
+
+pub(crate) fn current() -> impl Future<Item = Availability, Error = String> {
+    AvailabilityManager::from_registry()
+        .send(GetAvailability::default())
+        .map_err(|e| e.to_string())
+        .and_then(|r| r)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn config(json: &str) -> AvailabilityConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_time_window() {
+        let office =
+            config(r#"{"timeWindows": [{"days": ["Mon"], "from": "18:00:00", "to": "08:00:00"}]}"#);
+        let window = &office.time_windows[0];
+
+        // 2019-01-07 is Monday
+        assert!(window.contains(at("2019-01-07 22:00")));
+        assert!(window.contains(at("2019-01-08 07:59")));
+        assert!(!window.contains(at("2019-01-08 08:00")));
+        assert!(!window.contains(at("2019-01-07 07:00")));
+        assert!(!window.contains(at("2019-01-08 22:00")));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let probe = |idle_secs, on_ac_power| Probe {
+            now: at("2019-01-07 12:00"),
+            idle: Some(Duration::from_secs(idle_secs)),
+            on_ac_power: Some(on_ac_power),
+        };
+
+        assert_eq!(config("{}").evaluate(&probe(0, false)), Ok(()));
+
+        let policy = config(r#"{"idleMinutes": 10, "onAcPower": true}"#);
+        assert_eq!(policy.evaluate(&probe(600, false)), Ok(()));
+        assert_eq!(policy.evaluate(&probe(0, true)), Ok(()));
+        assert_eq!(
+            policy.evaluate(&probe(0, false)),
+            Err("user active, on battery".to_string())
+        );
+        assert_eq!(policy.when_unavailable, WhenUnavailable::Keep);
+    }
+
+    #[test]
+    fn test_platform_parsers() {
+        assert_eq!(
+            parse_hid_idle_time("    | |   \"HIDIdleTime\" = 5000000000\n"),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            parse_pmset_power_source("Now drawing from 'Battery Power'\n -InternalBattery-0"),
+            Some(false)
+        );
+        assert_eq!(
+            parse_pmset_power_source("Now drawing from 'AC Power'\n"),
+            Some(true)
+        );
+    }
+}
@@ -12,7 +12,7 @@ use gu_actix::flatten::FlattenFuture;
 use gu_base::{self, cli, AppSettings, Arg, ArgMatches, Decorator, Module, SubCommand};
 use gu_lan::{
     actor::{Continuous, MdnsActor, SubscribeInstance},
-    NewInstance, ServiceDescription, Subscription,
+    find_hubs, Discovery, NewInstance, ServiceDescription, Subscription,
 };
 use gu_net::{
     rpc::{
@@ -30,8 +30,12 @@ use std::{
     collections::{HashMap, HashSet},
     iter::FromIterator,
     net::SocketAddr,
+    time::Duration,
 };
 
+/// Interval of hub lookups with discovery backends other than mDNS
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
 pub fn module() -> ConnectModule {
     ConnectModule { state: State::None }
 }
@@ -497,10 +501,13 @@ pub struct ConnectManager {
     node_id: NodeId,
     connections: HashMap<SocketAddr, Addr<ConnectionSupervisor>>,
     subscription: Option<Subscription>,
+    /// Unicast discovery backends used in auto mode besides mDNS
+    discovery: Vec<Box<dyn Discovery>>,
+    discovery_handle: Option<SpawnHandle>,
 }
 
 impl ConnectManager {
-    pub fn init<I>(id: NodeId, hubs: I) -> Self
+    pub fn init<I>(id: NodeId, hubs: I, discovery: Vec<Box<dyn Discovery>>) -> Self
     where
         I: IntoIterator<Item = SocketAddr>,
     {
@@ -508,6 +515,8 @@ impl ConnectManager {
             node_id: id,
             connections: HashMap::new(),
             subscription: None,
+            discovery,
+            discovery_handle: None,
         };
 
         hubs.into_iter().for_each(|hub| manager.connect_to(hub));
@@ -540,6 +549,16 @@ impl ConnectManager {
         self.connections.insert(addr, supervisor);
     }
 
+    fn discover_hubs(&mut self, ctx: &mut Context<Self>) {
+        for discovery in &self.discovery {
+            ctx.spawn(find_hubs(discovery.as_ref()).into_actor(self).map(
+                |hubs, act: &mut Self, _ctx| {
+                    hubs.into_iter().for_each(|hub| act.connect_to(hub.address))
+                },
+            ));
+        }
+    }
+
     fn disconnect(&mut self, addr: SocketAddr) -> impl Future<Item = Option<()>, Error = String> {
         if let Some(supervisor) = self.connections.remove(&addr) {
             future::Either::A(
@@ -562,11 +581,7 @@ impl Handler<NewInstance> for ConnectManager {
     type Result = ();
 
     fn handle(&mut self, msg: NewInstance, _ctx: &mut Context<Self>) -> () {
-        if let (Some(ip), Some(port)) = (msg.data.addrs_v4.first(), msg.data.ports.first()) {
-            use std::net::IpAddr;
-
-            let ip = IpAddr::V4(*ip);
-            let sock = SocketAddr::new(ip, *port);
+        if let Some(sock) = msg.data.socket_addr() {
             self.connect_to(sock);
         } else {
             error!("Invalid mDNS instance")
@@ -625,6 +640,16 @@ impl Handler<AutoMdns> for ConnectManager {
     type Result = ActorResponse<Self, Option<()>, String>;
 
     fn handle(&mut self, msg: AutoMdns, ctx: &mut Context<Self>) -> Self::Result {
+        if msg.0 && self.discovery_handle.is_none() && !self.discovery.is_empty() {
+            self.discover_hubs(ctx);
+            self.discovery_handle =
+                Some(ctx.run_interval(DISCOVERY_INTERVAL, |act, ctx| act.discover_hubs(ctx)));
+        } else if !msg.0 {
+            if let Some(handle) = self.discovery_handle.take() {
+                ctx.cancel_future(handle);
+            }
+        }
+
         if msg.0 && self.subscription.is_none() {
             ActorResponse::r#async(
                 MdnsActor::<Continuous>::from_registry()
@@ -96,6 +96,10 @@ impl<T: IntoDeployInfo + Destroy + GetStatus> DeployManager<T> {
             .collect()
     }
 
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&String, &T)> + 'a {
+        self.deploys.iter()
+    }
+
     pub fn values_mut<'a>(&'a mut self) -> impl Iterator<Item = &mut T> + 'a {
         self.deploys.values_mut().into_iter()
     }
@@ -3,6 +3,7 @@
 use std::borrow::Cow;
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use actix::prelude::*;
 use actix_web::http::StatusCode;
@@ -20,21 +21,36 @@ use gu_base::daemon_lib::{DaemonCommand, DaemonHandler};
 use gu_base::SubCommand;
 use gu_model::dockerman::{CreateOptions, NetDef, VolumeDef};
 use gu_model::envman::*;
+use gu_model::hash::DynContentChecker;
 use gu_net::rpc::peer::PeerSessionInfo;
 use gu_net::rpc::peer::PeerSessionStatus;
+use gu_net::rpc::RemotingSystemService;
 use gu_persist::config::ConfigModule;
 
+use crate::archive::FileSelector;
+use crate::availability;
+use crate::egress::{self, EgressNetwork};
 use crate::provision;
+use crate::registry::{self, PullSource};
+use crate::status;
 use crate::workspace::{Workspace, WorkspacesManager};
 
 use super::deployment::{DeployManager, Destroy, IntoDeployInfo};
 use super::envman;
+use super::metrics;
+
+/// Oldest docker engine API the container options are supported on
+const MIN_API_VERSION: (u32, u32) = (1, 25);
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 // Actor.
 struct DockerMan {
     docker_api: Option<Box<dyn DockerApi>>,
     deploys: DeployManager<DockerSession>,
     workspaces_man: WorkspacesManager,
+    /// reason from the last health check
+    unavailable: Option<String>,
 }
 
 impl DockerMan {
@@ -43,14 +59,60 @@ impl DockerMan {
             docker_api: None,
             deploys: DeployManager::default(),
             workspaces_man,
+            unavailable: Some("docker daemon not checked yet".into()),
         })
     }
+
+    fn check_health(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let api = match self.docker_api {
+            Some(ref api) => api,
+            None => return,
+        };
+
+        let check = api
+            .version()
+            .map_err(|e| format!("docker daemon unreachable: {}", e))
+            .and_then(|version| match version.api_version() {
+                Some(api_version) => check_api_version(api_version),
+                None => Err("docker daemon did not report API version".into()),
+            });
+
+        ctx.spawn(
+            check
+                .into_actor(self)
+                .then(|result, act: &mut DockerMan, _| {
+                    let unavailable = result.and_then(|()| act.workspaces_man.check()).err();
+                    if unavailable != act.unavailable {
+                        match unavailable {
+                            Some(ref reason) => warn!("docker unavailable: {}", reason),
+                            None => info!("docker available"),
+                        }
+                        act.unavailable = unavailable;
+                    }
+                    fut::ok(())
+                }),
+        );
+    }
+}
+
+fn check_api_version(api_version: &str) -> Result<(), String> {
+    let mut parts = api_version.split('.').map(|part| part.parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) if (major, minor) >= MIN_API_VERSION => Ok(()),
+        (Some(Ok(_)), Some(Ok(_))) => Err(format!(
+            "docker API {} older than {}.{}",
+            api_version, MIN_API_VERSION.0, MIN_API_VERSION.1
+        )),
+        _ => Err(format!("invalid docker API version: {}", api_version)),
+    }
 }
 
 struct DockerSession {
     workspace: Workspace,
     container: async_docker::communicate::Container,
     status: PeerSessionStatus,
+    /// dedicated network with restricted egress
+    network: Option<EgressNetwork>,
 }
 
 impl DockerSession {
@@ -133,6 +195,7 @@ impl DockerSession {
         &mut self,
         content: bytes::Bytes,
         file_path: String,
+        mode: Option<u32>,
     ) -> impl Future<Item = String, Error = String> {
         let mut outf = Vec::new();
 
@@ -157,7 +220,7 @@ impl DockerSession {
             let mut header = tar::Header::new_ustar();
             header.set_size(content.len() as u64);
             header.set_path(&rel_path)?;
-            header.set_mode(0o644);
+            header.set_mode(mode.unwrap_or(0o644));
             header.set_uid(0);
             header.set_gid(0);
             header.set_cksum();
@@ -186,16 +249,43 @@ impl DockerSession {
         )
     }
 
+    fn do_read_file(
+        &mut self,
+        file_path: String,
+        max_bytes: Option<u64>,
+        encoding: ContentEncoding,
+    ) -> impl Future<Item = String, Error = String> {
+        let max_bytes = max_bytes.unwrap_or(DEFAULT_READ_LIMIT) as usize;
+        let data = self
+            .container
+            .archive_get(file_path.as_str())
+            .map_err(|e| e.to_string());
+
+        provision::untar_single_file_stream(data)
+            .and_then(move |(file_size, stream)| {
+                stream
+                    .fold(Vec::new(), move |mut content, chunk| {
+                        let remaining = max_bytes.saturating_sub(content.len());
+                        content.extend_from_slice(&chunk[..remaining.min(chunk.len())]);
+                        Ok::<_, String>(content)
+                    })
+                    .map(move |content| (file_size > content.len() as u64, content))
+            })
+            .and_then(move |(truncated, content)| encoding.encode(content, truncated))
+    }
+
     fn do_download(
         &mut self,
         url: String,
         file_path: String,
         format: ResourceFormat,
+        checker: Option<Box<dyn DynContentChecker>>,
     ) -> impl Future<Item = String, Error = String> {
         use futures::sync::mpsc;
         use std::io;
 
         let mut untar_path = PathBuf::from(file_path.clone());
+        let verified = checker.is_some();
 
         let non_dir = self
             .container
@@ -218,12 +308,20 @@ impl DockerSession {
                     non_dir
                         .and_then(|_| name.ok_or("Invalid filename".to_string()))
                         .map(move |filename| {
-                            provision::tarred_download_stream(url.as_str(), filename)
+                            provision::tarred_download_stream(url.as_str(), filename, checker)
                         })
                         .flatten_stream(),
                 )
             }
-            ResourceFormat::Tar => Box::new(provision::download_stream(url.as_str())),
+            format => provision::archive_to_tar_stream(
+                format,
+                provision::download_stream(url.as_str(), checker),
+            ),
+        };
+        // docker extracts what it gets, so content is verified in whole first
+        let stream: Box<dyn Stream<Item = bytes::Bytes, Error = String>> = match verified {
+            true => Box::new(provision::spooled_stream(stream)),
+            false => stream,
         };
 
         let untar_path = match untar_path.to_str() {
@@ -289,9 +387,12 @@ impl DockerSession {
                             .and_then(|r| r.send().map_err(|e| e.to_string())),
                     )
                 }
-                ResourceFormat::Tar => Box::new(
+                format => Box::new(
                     client::put(&url)
-                        .streaming(data.map_err(|e| actix_web::error::ErrorInternalServerError(e)))
+                        .streaming(
+                            provision::tar_to_archive_stream(format, data)
+                                .map_err(|e| actix_web::error::ErrorInternalServerError(e)),
+                        )
                         .into_future()
                         .map_err(|e| e.to_string())
                         .and_then(|r| r.send().map_err(|e| e.to_string())),
@@ -306,6 +407,59 @@ impl DockerSession {
             }
         })
     }
+
+    fn do_upload_files(
+        &mut self,
+        url: String,
+        selector: FileSelector,
+        format: ResourceFormat,
+    ) -> impl Future<Item = String, Error = String> {
+        use actix_web::client;
+
+        if format == ResourceFormat::Raw {
+            return future::Either::A(future::err(
+                "multiple files can not be uploaded in raw format".to_string(),
+            ));
+        }
+        let base_dir = match selector.base_dir().to_str() {
+            // archive of the root directory content, without a name prefix
+            Some("") => "/.".to_owned(),
+            Some(base_dir) => base_dir.to_owned(),
+            None => {
+                return future::Either::A(future::err("Invalid unicode in filepath".to_string()))
+            }
+        };
+
+        let data = self
+            .container
+            .archive_get(base_dir.as_str())
+            .map_err(|e| e.to_string());
+        let (data, files) = provision::filter_tar_stream(selector, data);
+
+        let upload = client::put(&url)
+            .streaming(
+                provision::tar_to_archive_stream(format, data)
+                    .map_err(|e| actix_web::error::ErrorInternalServerError(e)),
+            )
+            .into_future()
+            .map_err(|e| e.to_string())
+            .and_then(|r| r.send().map_err(|e| e.to_string()))
+            .and_then(|res| {
+                if res.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(format!("Unsuccessful file upload: {}", res.status()))
+                }
+            });
+
+        future::Either::B(upload.join(files).and_then(|(_, files)| {
+            if files.is_empty() {
+                Err("no files match given patterns".to_string())
+            } else {
+                serde_json::to_string(&files).map_err(|e| e.to_string())
+            }
+        }))
+    }
 }
 
 impl IntoDeployInfo for DockerSession {
@@ -317,6 +471,8 @@ impl IntoDeployInfo for DockerSession {
             tags: self.workspace.tags(),
             note: None,
             processes: HashSet::new(),
+            download: None,
+            egress: self.network.as_ref().map(EgressNetwork::egress),
         }
     }
 }
@@ -325,6 +481,7 @@ impl Destroy for DockerSession {
     fn destroy(&mut self) -> Box<dyn Future<Item = (), Error = Error>> {
         let workspace = self.workspace.clone();
         let container_copy = self.container.clone();
+        let network = self.network.clone();
         Box::new(
             self.container
                 .stop(None)
@@ -353,6 +510,10 @@ impl Destroy for DockerSession {
                         }
                     }
                 })
+                .and_then(move |_| match network {
+                    Some(network) => future::Either::A(network.remove().map_err(Error::Error)),
+                    None => future::Either::B(future::ok(())),
+                })
                 .and_then(move |_| {
                     workspace
                         .clear_dir()
@@ -383,10 +544,135 @@ impl DockerMan {
             .with_host_config(host_config)
     }
 
-    fn pull_config(url: String) -> async_docker::build::PullOptions {
-        async_docker::build::PullOptions::builder()
-            .image(url)
-            .build()
+    /// Credentials are sent in the X-Registry-Auth header of the request
+    fn pull_config(source: &PullSource) -> async_docker::build::PullOptions {
+        let mut builder = async_docker::build::PullOptions::builder();
+        builder.image(source.image.clone());
+        if let Some(auth) = source.auth() {
+            builder.auth(
+                async_docker::build::RegistryAuth::builder()
+                    .username(auth.username.clone())
+                    .password(auth.password.clone())
+                    .server_address(source.server_address())
+                    .build(),
+            );
+        }
+        builder.build()
+    }
+
+    /// Pulls the image from the first source that succeeds; returns the
+    /// reference the image was pulled with
+    fn pull_image(
+        &self,
+        sources: Vec<PullSource>,
+    ) -> Box<dyn Future<Item = String, Error = Error>> {
+        let api = match self.docker_api {
+            Some(ref api) => api,
+            None => {
+                return Box::new(future::err(Error::Error(
+                    "Docker API not initialized properly".into(),
+                )))
+            }
+        };
+
+        let mut pull: Option<Box<dyn Future<Item = String, Error = String>>> = None;
+        for source in sources {
+            let image = source.image.clone();
+            let failed = format!("pull {} failed", image);
+            let next = api
+                .images()
+                .pull(&Self::pull_config(&source))
+                .for_each(|x| Ok(debug!("{:?}", x)))
+                .map_err(move |e| format!("{}: {}", failed, e))
+                .map(move |()| image);
+
+            pull = Some(match pull {
+                None => Box::new(next),
+                Some(previous) => Box::new(previous.or_else(move |e| {
+                    warn!("{}", e);
+                    next
+                })),
+            });
+        }
+
+        match pull {
+            Some(pull) => Box::new(pull.map_err(Error::IoError)),
+            None => Box::new(future::err(Error::Error("no image source".into()))),
+        }
+    }
+
+    fn create_container(
+        &self,
+        image: String,
+        host_config: async_docker::models::HostConfig,
+    ) -> impl Future<Item = String, Error = Error> {
+        let opts = Self::container_config(image, host_config);
+        info!("config: {:?}", &opts);
+
+        match self.docker_api {
+            Some(ref api) => future::Either::A(
+                api.containers()
+                    .create(&opts)
+                    .map(|c| c.id().to_owned())
+                    .map_err(|e| Error::IoError(format!("{}", e))),
+            ),
+            None => future::Either::B(future::err(Error::Error(
+                "Docker API not initialized properly".into(),
+            ))),
+        }
+    }
+
+    /// Removes the egress networks and chains left by a previous run
+    fn remove_leftover_networks(&self, ctx: &mut <Self as Actor>::Context) {
+        let api = match self.docker_api {
+            Some(ref api) => api,
+            None => return,
+        };
+
+        ctx.spawn(
+            fut::wrap_future(
+                api.networks()
+                    .list(&Default::default())
+                    .map_err(|e| format!("listing networks failed: {}", e)),
+            )
+            .and_then(|networks, act: &mut DockerMan, _| {
+                let removals: Vec<_> = networks
+                    .into_iter()
+                    .filter(|network| {
+                        network
+                            .labels
+                            .as_ref()
+                            .map_or(false, |labels| labels.contains_key(egress::LABEL))
+                    })
+                    .filter_map(|network| {
+                        let id = network.id;
+                        act.docker_api.as_ref().map(|api| {
+                            info!("removing leftover network {}", id);
+                            api.network(Cow::from(id.clone()))
+                                .delete()
+                                .then(move |result| {
+                                    if let Err(e) = result {
+                                        warn!("removing network {} failed: {}", id, e);
+                                    }
+                                    Ok(())
+                                })
+                        })
+                    })
+                    .collect();
+                fut::wrap_future(future::join_all(removals).map(|_| ()))
+            })
+            .then(|result, _, _| {
+                if let Err(e) = result {
+                    warn!("{}", e);
+                }
+                fut::wrap_future(egress::remove_leftover_chains().then(|result| {
+                    if let Err(e) = result {
+                        warn!("{}", e);
+                    }
+                    Ok(())
+                }))
+            }),
+        );
     }
 
     fn binds_and_workspace(&self, msg: &CreateSession<CreateOptions>) -> (Vec<String>, Workspace) {
@@ -412,14 +698,25 @@ impl Actor for DockerMan {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut <Self as Actor>::Context) {
+        // kept running without the API to report why docker is unavailable
+        status::StatusManager::from_registry().do_send(status::AddProvider::new(
+            "docker",
+            ctx.address().recipient(),
+        ));
+
         match new_docker(None) {
             Ok(docker_api) => {
                 self.docker_api = Some(docker_api);
-                envman::register("docker", ctx.address())
+                envman::register("docker", ctx.address());
+                metrics::register_source("docker", ctx.address().recipient());
+                availability::register_env("docker", ctx.address().recipient());
+                self.remove_leftover_networks(ctx);
+                self.check_health(ctx);
+                ctx.run_interval(HEALTH_CHECK_INTERVAL, |act, ctx| act.check_health(ctx));
             }
             Err(e) => {
                 error!("docker start failed: {}", e);
-                ctx.stop()
+                self.unavailable = Some(format!("docker start failed: {}", e));
             }
         }
     }
@@ -439,8 +736,15 @@ impl Handler<CreateSession<CreateOptions>> for DockerMan {
     ) -> <Self as Handler<CreateSession<CreateOptions>>>::Result {
         debug!("create session for: {}", &msg.image.url);
 
+        if let Some(ref reason) = self.unavailable {
+            return ActorResponse::reply(Err(Error::Error(format!(
+                "docker unavailable: {}",
+                reason
+            ))));
+        }
+
         match self.docker_api {
-            Some(ref api) => {
+            Some(_) => {
                 let Image { url, .. } = msg.image.clone();
 
                 let (binds, workspace) = self.binds_and_workspace(&msg);
@@ -452,30 +756,67 @@ impl Handler<CreateSession<CreateOptions>> for DockerMan {
                     .with_binds(binds)
                     .with_cap_add(msg.options.cap_add.clone());
 
-                let host_config = match msg.options.net {
-                    Some(NetDef::Host {}) => host_config.with_network_mode("host".to_string()),
-                    _ => host_config,
-                };
-
-                let opts = Self::container_config(url.clone(), host_config);
-                info!("config: {:?}", &opts);
-
-                let pull_image_fut = api.images().pull(&Self::pull_config(url));
-                let create_container_fut = api.containers().create(&opts);
+                // the container joins the restricted network once it is set up
+                let destinations = match msg.options.net {
+                    Some(NetDef::Restricted { ref allow }) => {
+                        future::Either::A(egress::resolve_all(allow.clone()).map(Some))
+                    }
+                    _ => future::Either::B(future::ok(None)),
+                }
+                .map_err(|e| Error::Error(format!("network setup failed: {}", e)));
+                let sources = registry::pull_sources(url, msg.options.registry_auth.clone())
+                    .map_err(|e| Error::Error(format!("image sources: {}", e)));
+                let net = msg.options.net.clone();
+
+                let network = fut::wrap_future(destinations.join(sources)).and_then(
+                    |(destinations, sources), act: &mut DockerMan, _| {
+                        let network = match (destinations, &act.docker_api) {
+                            (Some(destinations), Some(api)) => future::Either::A(
+                                destinations.create(api.as_ref()).map(Some).map_err(|e| {
+                                    Error::Error(format!("network setup failed: {}", e))
+                                }),
+                            ),
+                            _ => future::Either::B(future::ok(None)),
+                        };
+                        fut::wrap_future(network.map(move |network| (network, sources)))
+                    },
+                );
+
+                let pull_and_create = network.and_then(
+                    move |(network, sources): (Option<EgressNetwork>, Vec<PullSource>),
+                          act: &mut DockerMan,
+                          _| {
+                        let host_config = match (net, &network) {
+                            (Some(NetDef::Host {}), _) => {
+                                host_config.with_network_mode("host".to_string())
+                            }
+                            (_, Some(network)) => host_config.with_network_mode(network.name()),
+                            _ => host_config,
+                        };
 
-                let pull_and_create = pull_image_fut
-                    .for_each(|x| Ok(debug!("{:?}", x)))
-                    .and_then(|_| create_container_fut)
-                    .map(|c| c.id().to_owned())
-                    .map_err(|e| Error::IoError(format!("{}", e)));
+                        fut::wrap_future(act.pull_image(sources))
+                            .and_then(move |image, act: &mut DockerMan, _| {
+                                fut::wrap_future(act.create_container(image, host_config))
+                            })
+                            .then(move |result, _, _| match (result, network) {
+                                (Err(e), Some(network)) => fut::Either::B(fut::wrap_future(
+                                    network.remove().then(move |_| Err(e)),
+                                )),
+                                (result, network) => {
+                                    fut::Either::A(fut::result(result.map(|id| (id, network))))
+                                }
+                            })
+                    },
+                );
 
-                ActorResponse::r#async(fut::wrap_future(pull_and_create).and_then(
-                    move |id, act: &mut DockerMan, _| {
+                ActorResponse::r#async(pull_and_create.and_then(
+                    move |(id, network), act: &mut DockerMan, _| {
                         if let Some(ref api) = act.docker_api {
                             let mut deploy = DockerSession {
                                 workspace,
                                 container: api.container(Cow::from(id.clone())),
                                 status: PeerSessionStatus::CREATED,
+                                network,
                             };
                             let maybe_start = if msg.options.autostart {
                                 info!("Autostarting the container");
@@ -548,9 +889,17 @@ fn run_command(
             uri,
             file_path,
             format,
-        } => docker_man.run_for_deployment(session_id, |deployment| {
-            deployment.do_download(uri, file_path, format)
-        }),
+            hash,
+        } => match hash
+            .as_ref()
+            .map(|hash| provision::content_checker(hash))
+            .transpose()
+        {
+            Ok(checker) => docker_man.run_for_deployment(session_id, |deployment| {
+                deployment.do_download(uri, file_path, format, checker)
+            }),
+            Err(e) => Box::new(fut::err(e)),
+        },
         Command::UploadFile {
             uri,
             file_path,
@@ -558,9 +907,34 @@ fn run_command(
         } => docker_man.run_for_deployment(session_id, |deployment| {
             deployment.do_upload(uri, file_path, format)
         }),
-        Command::WriteFile { content, file_path } => {
-            docker_man.run_for_deployment(session_id, |d| d.write_file(content.into(), file_path))
-        }
+        Command::UploadFiles {
+            uri,
+            patterns,
+            exclude,
+            format,
+        } => match FileSelector::new(&patterns, &exclude) {
+            Ok(selector) => docker_man.run_for_deployment(session_id, |deployment| {
+                deployment.do_upload_files(uri, selector, format)
+            }),
+            Err(e) => Box::new(fut::err(e)),
+        },
+        Command::WriteFile {
+            content,
+            file_path,
+            mode,
+        } => match content.into_bytes() {
+            Ok(content) => docker_man.run_for_deployment(session_id, |d| {
+                d.write_file(content.into(), file_path, mode)
+            }),
+            Err(e) => Box::new(fut::err(e)),
+        },
+        Command::ReadFile {
+            file_path,
+            max_bytes,
+            encoding,
+        } => docker_man.run_for_deployment(session_id, |deployment| {
+            deployment.do_read_file(file_path, max_bytes, encoding)
+        }),
         Command::AddTags(tags) => Box::new(fut::result(
             docker_man
                 .deploys
@@ -664,6 +1038,101 @@ impl Handler<DestroySession> for DockerMan {
     }
 }
 
+impl Handler<metrics::GetUsageSources> for DockerMan {
+    type Result = ActorResponse<DockerMan, Vec<(String, metrics::UsageSource)>, String>;
+
+    fn handle(&mut self, _msg: metrics::GetUsageSources, _ctx: &mut Self::Context) -> Self::Result {
+        let stats = self
+            .deploys
+            .iter()
+            .map(|(id, deploy)| {
+                let id = id.clone();
+                // the first entry of the stats stream has the current counters
+                deploy
+                    .container
+                    .stats()
+                    .into_future()
+                    .map_err(|(e, _)| e.to_string())
+                    .and_then(|(stats, _)| stats.ok_or_else(|| "no stats".to_string()))
+                    .and_then(|stats| serde_json::to_value(stats).map_err(|e| e.to_string()))
+                    .then(move |r| {
+                        Ok::<_, String>(match r {
+                            Ok(stats) => Some((id, metrics::UsageSource::DockerStats(stats))),
+                            Err(e) => {
+                                debug!("stats of {}: {}", id, e);
+                                None
+                            }
+                        })
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        ActorResponse::r#async(
+            future::join_all(stats)
+                .map(|sources| sources.into_iter().flatten().collect())
+                .into_actor(self),
+        )
+    }
+}
+
+impl Handler<availability::PauseDeployments> for DockerMan {
+    type Result = ActorResponse<DockerMan, (), String>;
+
+    fn handle(
+        &mut self,
+        msg: availability::PauseDeployments,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let calls = self
+            .deploys
+            .iter()
+            .map(|(id, deploy)| {
+                let id = id.clone();
+                match msg.0 {
+                    true => deploy.container.pause(),
+                    false => deploy.container.unpause(),
+                }
+                .then(move |r| Ok::<_, String>(r.err().map(|e| format!("{}: {}", id, e))))
+            })
+            .collect::<Vec<_>>();
+
+        ActorResponse::r#async(
+            future::join_all(calls)
+                .and_then(|errors| {
+                    let errors: Vec<_> = errors.into_iter().flatten().collect();
+                    match errors.is_empty() {
+                        true => Ok(()),
+                        false => Err(errors.join(", ")),
+                    }
+                })
+                .into_actor(self),
+        )
+    }
+}
+
+impl Handler<envman::GetWorkspacePath> for DockerMan {
+    type Result = Result<PathBuf, Error>;
+
+    fn handle(&mut self, msg: envman::GetWorkspacePath, _ctx: &mut Self::Context) -> Self::Result {
+        self.deploys
+            .deploy(&msg.session_id)
+            .map(|deploy| deploy.workspace.path().clone())
+    }
+}
+
+impl Handler<status::GetEnvStatus> for DockerMan {
+    type Result = MessageResult<status::GetEnvStatus>;
+
+    fn handle(&mut self, _msg: status::GetEnvStatus, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(match self.unavailable {
+            Some(ref reason) => status::EnvStatus::Unavailable {
+                reason: reason.clone(),
+            },
+            None => self.deploys.status(),
+        })
+    }
+}
+
 struct Init {
     should_run: bool,
 }
@@ -721,3 +1190,16 @@ impl gu_base::Module for Init {
 pub fn module() -> impl gu_base::Module {
     Init { should_run: false }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::check_api_version;
+
+    #[test]
+    fn test_check_api_version() {
+        assert_eq!(check_api_version("1.25"), Ok(()));
+        assert_eq!(check_api_version("1.40"), Ok(()));
+        assert!(check_api_version("1.24").is_err());
+        assert!(check_api_version("1.x").is_err());
+    }
+}
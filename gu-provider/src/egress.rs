@@ -0,0 +1,513 @@
+//! Egress control of docker deployments with restricted network access.
+//!
+//! Each such deployment runs on a dedicated bridge network. Traffic coming
+//! from the bridge, both forwarded and sent to the provider host, passes a
+//! per-deployment iptables chain accepting replies, connections to the hubs
+//! and to the declared destinations, and rejecting everything else. The
+//! chains filter IPv4 only, so the network is created without IPv6.
+//! Networks and chains left by a previous run are removed on startup.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+
+use actix::prelude::*;
+use async_docker::{self, DockerApi};
+use futures::{prelude::*, stream};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio_process::CommandExt;
+use uuid::Uuid;
+
+use gu_actix::prelude::*;
+use gu_persist::config::{ConfigManager, GetConfig, HasSectionId};
+
+use crate::connect::ListSockets;
+use crate::server::ProviderServer;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct EgressConfig {
+    iptables: PathBuf,
+}
+
+impl Default for EgressConfig {
+    fn default() -> Self {
+        EgressConfig {
+            iptables: "iptables".into(),
+        }
+    }
+}
+
+impl HasSectionId for EgressConfig {
+    const SECTION_ID: &'static str = "egress";
+}
+
+/// Destination deployments are allowed to connect to
+#[derive(Clone, Debug, PartialEq)]
+struct Destination {
+    /// IPv4 network in CIDR notation
+    net: String,
+    /// TCP port; any port and protocol if not set
+    port: Option<u16>,
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{} tcp:{}", self.net, port),
+            None => write!(f, "{}", self.net),
+        }
+    }
+}
+
+impl Destination {
+    fn from_socket_addr(addr: SocketAddr) -> Option<Self> {
+        match addr.ip() {
+            IpAddr::V4(ip) => Some(Destination {
+                net: format!("{}/32", ip),
+                port: Some(addr.port()),
+            }),
+            IpAddr::V6(_) => None,
+        }
+    }
+}
+
+fn parse_net(entry: &str) -> Result<String, String> {
+    let (addr, prefix) = match entry.find('/') {
+        Some(pos) => (
+            &entry[..pos],
+            entry[pos + 1..]
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= 32)
+                .ok_or_else(|| format!("invalid prefix length: {}", entry))?,
+        ),
+        None => (entry, 32),
+    };
+    let addr: Ipv4Addr = addr
+        .parse()
+        .map_err(|_| format!("invalid IPv4 address or network: {}", entry))?;
+
+    Ok(format!("{}/{}", addr, prefix))
+}
+
+/// Host and port of the URL
+fn parse_url(url: &str) -> Result<(String, u16), String> {
+    let pos = url
+        .find("://")
+        .ok_or_else(|| format!("invalid URL: {}", url))?;
+    let (scheme, rest) = (&url[..pos], &url[pos + 3..]);
+    let authority = rest
+        .split(|c| c == '/' || c == '?' || c == '#')
+        .next()
+        .unwrap_or_default();
+    let host_port = authority.rsplit('@').next().unwrap_or_default();
+
+    let (host, port) = match host_port.rfind(':') {
+        Some(pos) => (
+            &host_port[..pos],
+            Some(
+                host_port[pos + 1..]
+                    .parse::<u16>()
+                    .map_err(|_| format!("invalid port: {}", url))?,
+            ),
+        ),
+        None => (host_port, None),
+    };
+    let port = match (port, scheme) {
+        (Some(port), _) => port,
+        (None, "http") | (None, "ws") => 80,
+        (None, "https") | (None, "wss") => 443,
+        (None, _) => return Err(format!("no port given: {}", url)),
+    };
+    if host.is_empty() {
+        return Err(format!("no host given: {}", url));
+    }
+
+    Ok((host.to_string(), port))
+}
+
+/// Destinations of an allow-list entry; blocks on name resolution
+fn resolve(entry: &str) -> Result<Vec<Destination>, String> {
+    if !entry.contains("://") {
+        return Ok(vec![Destination {
+            net: parse_net(entry)?,
+            port: None,
+        }]);
+    }
+
+    let (host, port) = parse_url(entry)?;
+    let destinations: Vec<Destination> = (host.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|e| format!("{}: {}", host, e))?
+        .filter_map(Destination::from_socket_addr)
+        .collect();
+    if destinations.is_empty() {
+        return Err(format!("{}: no IPv4 address", host));
+    }
+    Ok(destinations)
+}
+
+/// Label of the networks created for deployments
+pub const LABEL: &str = "gu-provider.egress";
+
+const CHAIN_PREFIX: &str = "GU-";
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Runs the program; returns its output
+fn run(program: &Path, args: Vec<String>) -> impl Future<Item = String, Error = String> {
+    let command_line = format!("{} {}", program.display(), args.join(" "));
+    debug!("running: {}", command_line);
+
+    process::Command::new(program)
+        .args(&args)
+        .output_async()
+        .map_err(|e| e.to_string())
+        .and_then(|output| match output.status.success() {
+            true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        })
+        .map_err(move |e| format!("{} failed: {}", command_line, e))
+}
+
+/// Chain filtering the traffic coming from a bridge
+#[derive(Clone, Debug)]
+struct Filter {
+    chain: String,
+    bridge: String,
+    destinations: Vec<Destination>,
+}
+
+impl Filter {
+    fn rules(&self) -> Vec<Vec<String>> {
+        let (bridge, chain) = (&self.bridge, &self.chain);
+        let mut rules = vec![
+            args(&["-w", "-N", chain]),
+            args(&[
+                "-w",
+                "-A",
+                chain,
+                "-m",
+                "conntrack",
+                "--ctstate",
+                "ESTABLISHED,RELATED",
+                "-j",
+                "RETURN",
+            ]),
+        ];
+        for destination in &self.destinations {
+            let mut rule = args(&["-w", "-A", chain, "-d", &destination.net]);
+            if let Some(port) = destination.port {
+                rule.extend(args(&["-p", "tcp", "--dport", &port.to_string()]));
+            }
+            rule.extend(args(&["-j", "RETURN"]));
+            rules.push(rule);
+        }
+        rules.push(args(&["-w", "-A", chain, "-j", "REJECT"]));
+        // docker evaluates DOCKER-USER before its own forwarding rules
+        rules.push(args(&[
+            "-w",
+            "-I",
+            "DOCKER-USER",
+            "-i",
+            bridge,
+            "-j",
+            chain,
+        ]));
+        rules.push(args(&["-w", "-I", "INPUT", "-i", bridge, "-j", chain]));
+        rules
+    }
+
+    fn removal_rules(&self) -> Vec<Vec<String>> {
+        let (bridge, chain) = (&self.bridge, &self.chain);
+        vec![
+            args(&["-w", "-D", "DOCKER-USER", "-i", bridge, "-j", chain]),
+            args(&["-w", "-D", "INPUT", "-i", bridge, "-j", chain]),
+            args(&["-w", "-F", chain]),
+            args(&["-w", "-X", chain]),
+        ]
+    }
+}
+
+/// Rules removing the chains found in an `iptables -S` listing, jumps first
+fn leftover_rules(listing: &str) -> Vec<Vec<String>> {
+    let mut jumps = Vec::new();
+    let mut chains = Vec::new();
+
+    for line in listing.lines() {
+        let rule: Vec<&str> = line.split_whitespace().collect();
+        if rule.len() == 2 && rule[0] == "-N" && rule[1].starts_with(CHAIN_PREFIX) {
+            chains.push(args(&["-w", "-F", rule[1]]));
+            chains.push(args(&["-w", "-X", rule[1]]));
+        } else if rule.first() == Some(&"-A")
+            && rule
+                .windows(2)
+                .any(|w| w[0] == "-j" && w[1].starts_with(CHAIN_PREFIX))
+        {
+            let mut removal = args(&["-w", "-D"]);
+            removal.extend(args(&rule[1..]));
+            jumps.push(removal);
+        }
+    }
+
+    jumps.extend(chains);
+    jumps
+}
+
+fn run_all(iptables: PathBuf, rules: Vec<Vec<String>>) -> impl Future<Item = (), Error = String> {
+    stream::iter_ok(rules).for_each(move |rule| run(&iptables, rule).map(|_| ()))
+}
+
+/// Runs the rules, logging the failing ones
+fn run_logged(
+    iptables: PathBuf,
+    rules: Vec<Vec<String>>,
+) -> impl Future<Item = (), Error = String> {
+    stream::iter_ok(rules).for_each(move |rule| {
+        run(&iptables, rule).then(|result| {
+            if let Err(e) = result {
+                warn!("{}", e);
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Dedicated network of a deployment with restricted egress
+#[derive(Clone)]
+pub struct EgressNetwork {
+    config: Arc<EgressConfig>,
+    name: String,
+    network: async_docker::communicate::Network,
+    filter: Filter,
+}
+
+impl EgressNetwork {
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Allowed destinations, as reported in deployment info
+    pub fn egress(&self) -> Vec<String> {
+        self.filter
+            .destinations
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn setup(&self) -> impl Future<Item = (), Error = String> {
+        let network = self.clone();
+
+        run_all(self.config.iptables.clone(), self.filter.rules())
+            .or_else(move |e| network.remove().then(move |_| Err(e)))
+    }
+
+    /// Removes the rules and the network; containers have to be removed first
+    pub fn remove(&self) -> impl Future<Item = (), Error = String> {
+        let network = self.network.clone();
+        let name = self.name.clone();
+
+        run_logged(self.config.iptables.clone(), self.filter.removal_rules()).and_then(move |()| {
+            network
+                .delete()
+                .map_err(move |e| format!("removing network {} failed: {}", name, e))
+        })
+    }
+}
+
+fn config() -> impl Future<Item = Arc<EgressConfig>, Error = String> {
+    ConfigManager::from_registry()
+        .send(GetConfig::new())
+        .flatten_fut()
+        .map_err(|e| e.to_string())
+}
+
+/// Destinations of a deployment network, resolved before it is created
+pub(crate) struct Destinations {
+    config: Arc<EgressConfig>,
+    destinations: Vec<Destination>,
+}
+
+/// Resolves the hubs the provider is connected to and the listed
+/// destinations
+pub(crate) fn resolve_all(allow: Vec<String>) -> impl Future<Item = Destinations, Error = String> {
+    let hubs = ProviderServer::from_registry()
+        .send(ListSockets)
+        .map_err(|e| e.to_string())
+        .and_then(|r| r);
+    let destinations = gu_hdman::download::cpu_pool().spawn_fn(move || -> Result<_, String> {
+        let mut destinations = Vec::new();
+        for entry in &allow {
+            destinations.extend(resolve(entry)?);
+        }
+        Ok(destinations)
+    });
+
+    config().join3(hubs, destinations).map(
+        |(config, hubs, mut destinations): (Arc<EgressConfig>, _, Vec<Destination>)| {
+            destinations.extend(
+                hubs.into_iter()
+                    .filter_map(|(addr, _)| Destination::from_socket_addr(addr)),
+            );
+            Destinations {
+                config,
+                destinations,
+            }
+        },
+    )
+}
+
+impl Destinations {
+    /// Creates a network allowing connections to the destinations
+    pub(crate) fn create(
+        self,
+        api: &dyn DockerApi,
+    ) -> impl Future<Item = EgressNetwork, Error = String> {
+        let Destinations {
+            config,
+            destinations,
+        } = self;
+        let id = Uuid::new_v4().to_simple().to_string()[..12].to_string();
+        let name = format!("gu-egress-{}", id);
+        let options = create_options(&name);
+        let network = api.network(Cow::from(name.clone()));
+
+        api.networks()
+            .create(&options)
+            .map_err(|e| format!("network create failed: {}", e))
+            .and_then(move |info| {
+                let network = EgressNetwork {
+                    config,
+                    name,
+                    network,
+                    filter: Filter {
+                        chain: format!("{}{}", CHAIN_PREFIX, id),
+                        bridge: bridge_name(&info.id),
+                        destinations,
+                    },
+                };
+                network.setup().map(move |()| network)
+            })
+    }
+}
+
+fn create_options(name: &str) -> async_docker::build::NetworkCreateOptions {
+    let mut labels = HashMap::new();
+    labels.insert(LABEL, "");
+    async_docker::build::NetworkCreateOptions::builder(name)
+        .driver("bridge")
+        .enable_ipv6(false)
+        .label(labels)
+        .build()
+}
+
+/// Interface docker creates for a bridge network
+fn bridge_name(network_id: &str) -> String {
+    format!("br-{}", network_id.get(..12).unwrap_or(network_id))
+}
+
+/// Removes the chains left by a previous run; networks are removed by the
+/// docker manager
+pub(crate) fn remove_leftover_chains() -> impl Future<Item = (), Error = String> {
+    config().and_then(|config| {
+        let iptables = config.iptables.clone();
+        run(&iptables, args(&["-w", "-S"]))
+            .and_then(move |listing| run_logged(iptables, leftover_rules(&listing)))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_net("10.20.0.0/16"), Ok("10.20.0.0/16".into()));
+        assert_eq!(parse_net("192.168.1.7"), Ok("192.168.1.7/32".into()));
+        assert!(parse_net("10.0.0.0/33").is_err());
+        assert!(parse_net("fd00::/8").is_err());
+
+        assert_eq!(
+            parse_url("https://user@example.com/data?x=1"),
+            Ok(("example.com".into(), 443))
+        );
+        assert_eq!(
+            parse_url("http://10.0.0.1:8080"),
+            Ok(("10.0.0.1".into(), 8080))
+        );
+        assert!(parse_url("ftp://example.com/file").is_err());
+        assert!(parse_url("example.com").is_err());
+    }
+
+    #[test]
+    fn test_rules() {
+        let filter = Filter {
+            chain: "GU-0123456789ab".into(),
+            bridge: bridge_name("0123456789abcdef0123"),
+            destinations: vec![
+                resolve("10.20.0.0/16").unwrap().remove(0),
+                Destination::from_socket_addr("192.168.1.7:61622".parse().unwrap()).unwrap(),
+            ],
+        };
+        let rules: Vec<String> = filter.rules().iter().map(|r| r.join(" ")).collect();
+
+        assert_eq!(filter.bridge, "br-0123456789ab");
+        let options: serde_json::Value = serde_json::from_str(
+            &create_options("gu-egress-0123456789ab")
+                .serialize()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(options["EnableIPv6"], false);
+        assert_eq!(
+            filter.destinations[1].to_string(),
+            "192.168.1.7/32 tcp:61622"
+        );
+        assert_eq!(
+            rules[2..5],
+            [
+                "-w -A GU-0123456789ab -d 10.20.0.0/16 -j RETURN",
+                "-w -A GU-0123456789ab -d 192.168.1.7/32 -p tcp --dport 61622 -j RETURN",
+                "-w -A GU-0123456789ab -j REJECT",
+            ]
+        );
+        assert_eq!(
+            rules.last().unwrap(),
+            "-w -I INPUT -i br-0123456789ab -j GU-0123456789ab"
+        );
+    }
+
+    #[test]
+    fn test_leftover_rules() {
+        let listing = "-P INPUT ACCEPT\n\
+                       -N DOCKER-USER\n\
+                       -N GU-0123456789ab\n\
+                       -A INPUT -i br-0123456789ab -j GU-0123456789ab\n\
+                       -A DOCKER-USER -i br-0123456789ab -j GU-0123456789ab\n\
+                       -A DOCKER-USER -j RETURN\n\
+                       -A GU-0123456789ab -j REJECT --reject-with icmp-port-unreachable\n";
+        let rules: Vec<String> = leftover_rules(listing)
+            .iter()
+            .map(|r| r.join(" "))
+            .collect();
+
+        assert_eq!(
+            rules,
+            [
+                "-w -D INPUT -i br-0123456789ab -j GU-0123456789ab",
+                "-w -D DOCKER-USER -i br-0123456789ab -j GU-0123456789ab",
+                "-w -F GU-0123456789ab",
+                "-w -X GU-0123456789ab",
+            ]
+        );
+    }
+}
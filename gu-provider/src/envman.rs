@@ -1,16 +1,31 @@
 //! Execution environment manager.
 //!
+//! Deployments are owned by the hub that created them; other hubs can
+//! neither list nor modify them.
+//!
 
 use actix::prelude::*;
 use futures::{future, prelude::*};
+use futures_cpupool::CpuPool;
 use gu_actix::prelude::*;
+use gu_base::metrics;
 use gu_model::envman::*;
 use gu_net::rpc::peer::PeerSessionInfo;
-use gu_net::rpc::{PublicMessage, RemotingContext, RemotingSystemService};
+use gu_net::rpc::{FromPeer, PublicMessage, RemotingContext, RemotingSystemService};
+use gu_net::NodeId;
+use log::error;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value as JsonValue;
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::audit::{self, AuditEvent};
+use crate::availability;
+use crate::permission;
+use crate::registry;
+use crate::workspace_fs;
 
 /// Actor
 #[derive(Default)]
@@ -19,16 +34,44 @@ struct EnvMan {
     session_update_map: BTreeMap<String, Recipient<SessionUpdate>>,
     get_sessions_map: BTreeMap<String, Recipient<GetSessions>>,
     destroy_session_map: BTreeMap<String, Recipient<DestroySession>>,
+    workspace_map: BTreeMap<String, Recipient<GetWorkspacePath>>,
+    fs_pool: Option<CpuPool>,
+    /// hub that created the deployment, by deployment id
+    owners: HashMap<String, NodeId>,
+}
+
+impl EnvMan {
+    fn fs_pool(&mut self) -> &CpuPool {
+        self.fs_pool.get_or_insert_with(|| CpuPool::new(1))
+    }
+
+    fn is_owner(&self, session_id: &str, node_id: NodeId) -> bool {
+        self.owners.get(session_id) == Some(&node_id)
+    }
+
+    fn owned_count(&self, node_id: NodeId) -> usize {
+        self.owners
+            .values()
+            .filter(|owner| **owner == node_id)
+            .count()
+    }
+
+    fn update_metrics(&self) {
+        metrics::gauge("gu_provider_deployments", "Deployments created by hubs")
+            .set(self.owners.len() as isize);
+    }
 }
 
 impl Actor for EnvMan {
     type Context = RemotingContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.bind::<CreateSession<JsonValue>>(CreateSession::<JsonValue>::ID);
-        ctx.bind::<SessionUpdate>(SessionUpdate::ID);
-        ctx.bind::<GetSessions>(GetSessions::ID);
-        ctx.bind::<DestroySession>(DestroySession::ID);
+        ctx.bind_from_peer::<CreateSession<JsonValue>>(CreateSession::<JsonValue>::ID);
+        ctx.bind_from_peer::<SessionUpdate>(SessionUpdate::ID);
+        ctx.bind_from_peer::<GetSessions>(GetSessions::ID);
+        ctx.bind_from_peer::<DestroySession>(DestroySession::ID);
+        ctx.bind_from_peer::<WorkspaceFs>(WorkspaceFs::ID);
+        ctx.bind::<GetPublicKey>(GetPublicKey::ID);
     }
 }
 
@@ -38,6 +81,15 @@ pub trait EnvManService {
     type CreateOptions: Serialize + DeserializeOwned + Default + Send;
 }
 
+/// Asks exec environment for workspace directory of given deployment
+pub struct GetWorkspacePath {
+    pub session_id: String,
+}
+
+impl Message for GetWorkspacePath {
+    type Result = Result<PathBuf, Error>;
+}
+
 trait CreateSender {
     fn send(&self, msg: CreateSession<JsonValue>) -> Box<dyn Future<Item = String, Error = Error>>;
 }
@@ -86,11 +138,13 @@ where
     T: Handler<CreateSession<Options>>
         + Handler<SessionUpdate>
         + Handler<GetSessions>
-        + Handler<DestroySession>,
+        + Handler<DestroySession>
+        + Handler<GetWorkspacePath>,
     T::Context: actix::dev::ToEnvelope<T, CreateSession<T::CreateOptions>>,
     T::Context: actix::dev::ToEnvelope<T, SessionUpdate>,
     T::Context: actix::dev::ToEnvelope<T, GetSessions>,
     T::Context: actix::dev::ToEnvelope<T, DestroySession>,
+    T::Context: actix::dev::ToEnvelope<T, GetWorkspacePath>,
 {
     type Result = ();
 
@@ -109,7 +163,8 @@ where
         self.get_sessions_map
             .insert(env_type.clone(), msg.address.clone().recipient());
         self.destroy_session_map
-            .insert(env_type, msg.address.recipient());
+            .insert(env_type.clone(), msg.address.clone().recipient());
+        self.workspace_map.insert(env_type, msg.address.recipient());
     }
 }
 
@@ -120,27 +175,87 @@ fn extract_prefix(s: &str) -> Result<(&str, &str), Error> {
     return Err(Error::NoSuchSession(s.to_owned()));
 }
 
-impl Handler<CreateSession<JsonValue>> for EnvMan {
+impl Handler<FromPeer<CreateSession<JsonValue>>> for EnvMan {
     type Result = ActorResponse<EnvMan, String, Error>;
 
-    fn handle(&mut self, msg: CreateSession<JsonValue>, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(
+        &mut self,
+        msg: FromPeer<CreateSession<JsonValue>>,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let FromPeer { sender, body: msg } = msg;
         let env_type = msg.env_type.clone();
-        if let Some(address) = self.create_map.get(&env_type) {
-            return ActorResponse::r#async(
-                address
-                    .send(msg)
-                    .and_then(move |session_id| Ok(format!("{}::{}", env_type, session_id)))
-                    .into_actor(self),
-            );
+        let audit_event = AuditEvent::CreateSession {
+            env_type: env_type.clone(),
+            image: msg.image.url.clone(),
+            name: msg.name.clone(),
+        };
+        if !self.create_map.contains_key(&env_type) {
+            let err = Error::UnknownEnv(env_type);
+            audit::record(sender, None, audit_event, Some(err.to_string()));
+            return ActorResponse::reply(Err(err));
         }
-        ActorResponse::reply(Err(Error::UnknownEnv(env_type)))
+
+        ActorResponse::r#async(
+            availability::current()
+                .map_err(Error::Error)
+                .and_then(|state| match state.available {
+                    true => Ok(()),
+                    false => Err(Error::AccessDenied(format!(
+                        "provider unavailable: {}",
+                        state.reason.unwrap_or_default()
+                    ))),
+                })
+                .and_then(move |()| permission::resource_share(sender).map_err(Error::Error))
+                .into_actor(self)
+                .and_then(move |share, act: &mut EnvMan, _ctx| {
+                    match share.max_deployments {
+                        Some(max) if act.owned_count(sender) >= max => {
+                            return fut::Either::A(fut::err(Error::AccessDenied(format!(
+                                "deployment limit of {} reached",
+                                max
+                            ))));
+                        }
+                        _ => (),
+                    }
+                    let create = match act.create_map.get(&env_type) {
+                        Some(address) => address.send(msg),
+                        None => return fut::Either::A(fut::err(Error::UnknownEnv(env_type))),
+                    };
+                    fut::Either::B(create.into_actor(act).map(
+                        move |session_id, act: &mut EnvMan, _ctx| {
+                            let session_id = format!("{}::{}", env_type, session_id);
+                            act.owners.insert(session_id.clone(), sender);
+                            act.update_metrics();
+                            session_id
+                        },
+                    ))
+                })
+                .then(move |r, _act, _ctx| {
+                    match r {
+                        Ok(ref session_id) => {
+                            audit::record(sender, Some(session_id.clone()), audit_event, None)
+                        }
+                        Err(ref e) => audit::record(sender, None, audit_event, Some(e.to_string())),
+                    }
+                    fut::result(r)
+                }),
+        )
     }
 }
 
-impl Handler<SessionUpdate> for EnvMan {
+impl Handler<FromPeer<SessionUpdate>> for EnvMan {
     type Result = ActorResponse<EnvMan, Vec<String>, Vec<String>>;
 
-    fn handle(&mut self, msg: SessionUpdate, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: FromPeer<SessionUpdate>, _ctx: &mut Self::Context) -> Self::Result {
+        let FromPeer { sender, body: msg } = msg;
+        let events: Vec<AuditEvent> = msg.commands.iter().map(AuditEvent::from).collect();
+        if !self.is_owner(&msg.session_id, sender) {
+            let err = Error::NoSuchSession(msg.session_id.clone()).to_string();
+            audit::record_rejected(sender, &msg.session_id, events, &err);
+            return ActorResponse::reply(Err(vec![err]));
+        }
+        let owned_id = msg.session_id.clone();
         let (prefix, session_id) = match extract_prefix(&msg.session_id) {
             Ok(v) => v,
             Err(_e) => {
@@ -148,14 +263,26 @@ impl Handler<SessionUpdate> for EnvMan {
             }
         };
 
+        let duration = metrics::labeled_summary(
+            "gu_provider_command_duration_seconds",
+            "Time spent running deployment commands",
+            &[("env", prefix)],
+        );
+        let start = Instant::now();
+
         match self.session_update_map.get(prefix) {
             Some(r) => ActorResponse::r#async(
                 r.send(SessionUpdate {
-                    session_id: session_id.into(),
+                    session_id: session_id.to_owned(),
                     commands: msg.commands,
                 })
                 .map_err(|_e| Vec::new())
                 .flatten_fut()
+                .then(move |r| {
+                    duration.observe(start.elapsed());
+                    audit::record_commands(sender, &owned_id, events, &r);
+                    r
+                })
                 .into_actor(self),
             ),
             None => ActorResponse::reply(Err(Vec::new())),
@@ -163,10 +290,11 @@ impl Handler<SessionUpdate> for EnvMan {
     }
 }
 
-impl Handler<GetSessions> for EnvMan {
+impl Handler<FromPeer<GetSessions>> for EnvMan {
     type Result = ActorResponse<EnvMan, Vec<PeerSessionInfo>, ()>;
 
-    fn handle(&mut self, _msg: GetSessions, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: FromPeer<GetSessions>, _ctx: &mut Self::Context) -> Self::Result {
+        let sender = msg.sender;
         fn add_sessions_prefix(
             prefix: String,
             sessions: Vec<PeerSessionInfo>,
@@ -196,19 +324,39 @@ impl Handler<GetSessions> for EnvMan {
 
         ActorResponse::r#async(
             j.and_then(|v: Vec<Vec<PeerSessionInfo>>| Ok(v.into_iter().flatten().collect()))
-                .into_actor(self),
+                .into_actor(self)
+                .map(
+                    move |sessions: Vec<PeerSessionInfo>, act: &mut EnvMan, _ctx| {
+                        sessions
+                            .into_iter()
+                            .filter(|session| act.is_owner(&session.id, sender))
+                            .collect()
+                    },
+                ),
         )
     }
 }
 
-impl Handler<DestroySession> for EnvMan {
+impl Handler<FromPeer<DestroySession>> for EnvMan {
     type Result = ActorResponse<EnvMan, String, Error>;
 
     fn handle(
         &mut self,
-        msg: DestroySession,
+        msg: FromPeer<DestroySession>,
         _ctx: &mut Self::Context,
-    ) -> <Self as Handler<DestroySession>>::Result {
+    ) -> <Self as Handler<FromPeer<DestroySession>>>::Result {
+        let FromPeer { sender, body: msg } = msg;
+        if !self.is_owner(&msg.session_id, sender) {
+            let err = Error::NoSuchSession(msg.session_id.clone());
+            audit::record(
+                sender,
+                Some(msg.session_id),
+                AuditEvent::DestroySession,
+                Some(err.to_string()),
+            );
+            return ActorResponse::reply(Err(err));
+        }
+        let owned_id = msg.session_id.clone();
         let (prefix, session_id) = match extract_prefix(&msg.session_id) {
             Ok(v) => v,
             Err(e) => return ActorResponse::reply(Err(e)),
@@ -222,13 +370,179 @@ impl Handler<DestroySession> for EnvMan {
                         ..msg
                     })
                     .flatten_fut()
-                    .into_actor(self),
+                    .into_actor(self)
+                    .then(move |r, act: &mut EnvMan, _ctx| {
+                        let error = r.as_ref().err().map(ToString::to_string);
+                        // the environment may have lost the deployment already
+                        if let Ok(_) | Err(Error::NoSuchSession(_)) = r {
+                            act.owners.remove(&owned_id);
+                            act.update_metrics();
+                        }
+                        audit::record(sender, Some(owned_id), AuditEvent::DestroySession, error);
+                        fut::result(r)
+                    }),
             ),
             None => ActorResponse::reply(Err(Error::UnknownEnv(prefix.into()))),
         }
     }
 }
 
+impl Handler<FromPeer<WorkspaceFs>> for EnvMan {
+    type Result = ActorResponse<EnvMan, FsResult, Error>;
+
+    fn handle(&mut self, msg: FromPeer<WorkspaceFs>, _ctx: &mut Self::Context) -> Self::Result {
+        let FromPeer { sender, body: msg } = msg;
+        let audit_event = AuditEvent::from(&msg);
+        if !self.is_owner(&msg.session_id, sender) {
+            let err = Error::NoSuchSession(msg.session_id.clone());
+            audit::record(
+                sender,
+                Some(msg.session_id),
+                audit_event,
+                Some(err.to_string()),
+            );
+            return ActorResponse::reply(Err(err));
+        }
+        let owned_id = msg.session_id.clone();
+        let (prefix, session_id) = match extract_prefix(&msg.session_id) {
+            Ok(v) => v,
+            Err(e) => return ActorResponse::reply(Err(e)),
+        };
+
+        let address = match self.workspace_map.get(prefix) {
+            Some(address) => address.clone(),
+            None => return ActorResponse::reply(Err(Error::UnknownEnv(prefix.into()))),
+        };
+        let session_id = session_id.to_owned();
+        let pool = self.fs_pool().clone();
+        let WorkspaceFs { path, op, .. } = msg;
+
+        ActorResponse::r#async(
+            address
+                .send(GetWorkspacePath { session_id })
+                .flatten_fut()
+                .and_then(move |root| pool.spawn_fn(move || workspace_fs::run(&root, &path, op)))
+                .then(move |r| {
+                    let error = r.as_ref().err().map(ToString::to_string);
+                    audit::record(sender, Some(owned_id), audit_event, error);
+                    r
+                })
+                .into_actor(self),
+        )
+    }
+}
+
+impl Handler<GetPublicKey> for EnvMan {
+    type Result = ActorResponse<EnvMan, String, String>;
+
+    fn handle(&mut self, _msg: GetPublicKey, _ctx: &mut Self::Context) -> Self::Result {
+        ActorResponse::r#async(registry::public_key().into_actor(self))
+    }
+}
+
+/// Lists ids of deployments owned by the hub
+struct OwnedBy(NodeId);
+
+impl Message for OwnedBy {
+    type Result = HashSet<String>;
+}
+
+impl Handler<OwnedBy> for EnvMan {
+    type Result = MessageResult<OwnedBy>;
+
+    fn handle(&mut self, msg: OwnedBy, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            self.owners
+                .iter()
+                .filter(|(_, owner)| **owner == msg.0)
+                .map(|(id, _)| id.clone())
+                .collect(),
+        )
+    }
+}
+
+pub fn owned_deployments(
+    node_id: NodeId,
+) -> impl Future<Item = HashSet<String>, Error = MailboxError> {
+    EnvMan::from_registry().send(OwnedBy(node_id))
+}
+
+/// Hub that created the deployment
+struct OwnerOf(String);
+
+impl Message for OwnerOf {
+    type Result = Option<NodeId>;
+}
+
+impl Handler<OwnerOf> for EnvMan {
+    type Result = MessageResult<OwnerOf>;
+
+    fn handle(&mut self, msg: OwnerOf, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.owners.get(&msg.0).cloned())
+    }
+}
+
+pub fn deployment_owner(
+    deployment_id: String,
+) -> impl Future<Item = Option<NodeId>, Error = MailboxError> {
+    EnvMan::from_registry().send(OwnerOf(deployment_id))
+}
+
+/// Destroys deployments of all hubs
+struct DestroyAll;
+
+impl Message for DestroyAll {
+    type Result = Result<usize, String>;
+}
+
+impl Handler<DestroyAll> for EnvMan {
+    type Result = ActorResponse<EnvMan, usize, String>;
+
+    fn handle(&mut self, _msg: DestroyAll, _ctx: &mut Self::Context) -> Self::Result {
+        let destroy = self
+            .owners
+            .keys()
+            .filter_map(|owned_id| {
+                let (prefix, session_id) = extract_prefix(owned_id).ok()?;
+                let address = self.destroy_session_map.get(prefix)?;
+                let owned_id = owned_id.clone();
+
+                Some(
+                    address
+                        .send(DestroySession {
+                            session_id: session_id.into(),
+                        })
+                        .then(move |r| {
+                            match r {
+                                Ok(Ok(_)) => (),
+                                Ok(Err(e)) => error!("destroy {}: {}", owned_id, e),
+                                Err(e) => error!("destroy {}: {}", owned_id, e),
+                            }
+                            Ok::<_, String>(owned_id)
+                        }),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        ActorResponse::r#async(future::join_all(destroy).into_actor(self).map(
+            |destroyed, act: &mut EnvMan, _ctx| {
+                for owned_id in &destroyed {
+                    act.owners.remove(owned_id);
+                }
+                act.update_metrics();
+                destroyed.len()
+            },
+        ))
+    }
+}
+
+pub fn destroy_all() -> impl Future<Item = usize, Error = String> {
+    EnvMan::from_registry()
+        .send(DestroyAll)
+        .map_err(|e| e.to_string())
+        .and_then(|r| r)
+}
+
 pub fn register<A, IntoCowStr, Options>(env_type: IntoCowStr, address: Addr<A>)
 where
     IntoCowStr: Into<Cow<'static, str>>,
@@ -237,11 +551,13 @@ where
     A: Handler<CreateSession<Options>>
         + Handler<SessionUpdate>
         + Handler<GetSessions>
-        + Handler<DestroySession>,
+        + Handler<DestroySession>
+        + Handler<GetWorkspacePath>,
     A::Context: actix::dev::ToEnvelope<A, CreateSession<A::CreateOptions>>,
     A::Context: actix::dev::ToEnvelope<A, SessionUpdate>,
     A::Context: actix::dev::ToEnvelope<A, GetSessions>,
     A::Context: actix::dev::ToEnvelope<A, DestroySession>,
+    A::Context: actix::dev::ToEnvelope<A, GetWorkspacePath>,
 {
     EnvMan::from_registry().do_send(Register {
         env_type: env_type.into(),
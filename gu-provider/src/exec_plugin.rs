@@ -1,6 +1,6 @@
 use crate::deployment::{DeployManager, Destroy, IntoDeployInfo};
 use crate::workspace::{Workspace, WorkspacesManager};
-use crate::{envman, status};
+use crate::{envman, metrics, status};
 use actix::prelude::*;
 use gu_hdman::process_pool::{self as pp, KillAll, ProcessPool};
 use gu_model::envman::{Command, CreateSession, DestroySession, GetSessions, SessionUpdate};
@@ -10,13 +10,16 @@ use std::process;
 use std::{fs, io};
 use tokio_process::CommandExt;
 
+use crate::archive::FileSelector;
 use crate::envman::EnvManService;
+use crate::provision::{content_checker, read_file_inline, set_file_mode, upload_files_step};
 use crate::status::GetEnvStatus;
 use futures::{Future, IntoFuture};
 use gu_base::{Decorator, Module};
 use gu_hdman::image_manager;
 use gu_model::envman::Error as EnvError;
 use gu_net::rpc::peer::{PeerSessionInfo, PeerSessionStatus};
+use gu_net::rpc::RemotingSystemService;
 use gu_persist::config::ConfigModule;
 use std::fs::OpenOptions;
 
@@ -40,6 +43,24 @@ impl PluginMan {
             workspaces_man,
         }
     }
+
+    /// Checks that the plugin executable is present
+    fn check(&self) -> Result<(), String> {
+        let metadata =
+            fs::metadata(&self.exec).map_err(|e| format!("{}: {}", self.exec.display(), e))?;
+        if !metadata.is_file() {
+            return Err(format!("{}: not a file", self.exec.display()));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if metadata.permissions().mode() & 0o111 == 0 {
+                return Err(format!("{}: not executable", self.exec.display()));
+            }
+        }
+        self.workspaces_man.check()
+    }
 }
 
 impl Actor for PluginMan {
@@ -47,6 +68,7 @@ impl Actor for PluginMan {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         envman::register(std::borrow::Cow::Owned(self.code.clone()), ctx.address());
+        metrics::register_source(self.code.clone(), ctx.address().recipient());
 
         status::StatusManager::from_registry().do_send(status::AddProvider::new(
             self.code.clone(),
@@ -84,6 +106,8 @@ impl IntoDeployInfo for PlugSession {
             tags,
             note,
             processes,
+            download: None,
+            egress: None,
         }
     }
 }
@@ -299,6 +323,38 @@ impl Handler<SessionUpdate> for PluginMan {
                         uri,
                         file_path,
                         format,
+                        hash,
+                    } => {
+                        let checker =
+                            match hash.as_ref().map(|hash| content_checker(hash)).transpose() {
+                                Ok(checker) => checker,
+                                Err(e) => return Box::new(futures::future::err(e)),
+                            };
+                        Box::new(
+                            resolve_path(
+                                &exec,
+                                &image_path,
+                                &work_dir,
+                                &spec_path,
+                                file_path.as_ref(),
+                            )
+                            .and_then(move |resp: ResolveResult| match resp {
+                                ResolveResult::ResolvedPath(output_path) => {
+                                    crate::provision::download_step(
+                                        &uri,
+                                        output_path.into(),
+                                        format,
+                                        checker,
+                                    )
+                                }
+                            })
+                            .and_then(|_| Ok("downloaded".into())),
+                        )
+                    }
+                    Command::WriteFile {
+                        content,
+                        file_path,
+                        mode,
                     } => Box::new(
                         resolve_path(
                             &exec,
@@ -307,14 +363,21 @@ impl Handler<SessionUpdate> for PluginMan {
                             &spec_path,
                             file_path.as_ref(),
                         )
-                        .and_then(move |resp: ResolveResult| match resp {
+                        .and_then(move |resp| match resp {
                             ResolveResult::ResolvedPath(output_path) => {
-                                crate::provision::download_step(&uri, output_path.into(), format)
+                                let output_path = PathBuf::from(output_path);
+                                fs::write(&output_path, content.into_bytes()?)
+                                    .and_then(|_| set_file_mode(&output_path, mode))
+                                    .map_err(|e| e.to_string())?;
+                                Ok("OK".to_string())
                             }
-                        })
-                        .and_then(|_| Ok("downloaded".into())),
+                        }),
                     ),
-                    Command::WriteFile { content, file_path } => Box::new(
+                    Command::ReadFile {
+                        file_path,
+                        max_bytes,
+                        encoding,
+                    } => Box::new(
                         resolve_path(
                             &exec,
                             &image_path,
@@ -322,10 +385,9 @@ impl Handler<SessionUpdate> for PluginMan {
                             &spec_path,
                             file_path.as_ref(),
                         )
-                        .and_then(|resp| match resp {
-                            ResolveResult::ResolvedPath(output_path) => {
-                                fs::write(output_path, content).map_err(|e| e.to_string())?;
-                                Ok("OK".to_string())
+                        .and_then(move |resp| match resp {
+                            ResolveResult::ResolvedPath(input_path) => {
+                                read_file_inline(Path::new(&input_path), max_bytes, encoding)
                             }
                         }),
                     ),
@@ -348,6 +410,34 @@ impl Handler<SessionUpdate> for PluginMan {
                         })
                         .and_then(|_| Ok("uploaded".into())),
                     ),
+                    Command::UploadFiles {
+                        uri,
+                        patterns,
+                        exclude,
+                        format,
+                    } => {
+                        let selector = match FileSelector::new(&patterns, &exclude) {
+                            Ok(selector) => selector,
+                            Err(e) => return Box::new(futures::future::err(e)),
+                        };
+                        Box::new(
+                            resolve_path(
+                                &exec,
+                                &image_path,
+                                &work_dir,
+                                &spec_path,
+                                selector.base_dir(),
+                            )
+                            .and_then(move |resp| match resp {
+                                ResolveResult::ResolvedPath(dir) => {
+                                    upload_files_step(&uri, dir.into(), selector, format)
+                                }
+                            })
+                            .and_then(|files| {
+                                serde_json::to_string(&files).map_err(|e| e.to_string())
+                            }),
+                        )
+                    }
                     Command::Close => {
                         Box::new(futures::future::err("Close not implemented".into()))
                     }
@@ -435,11 +525,44 @@ impl Handler<DestroySession> for PluginMan {
     }
 }
 
+impl Handler<envman::GetWorkspacePath> for PluginMan {
+    type Result = Result<PathBuf, EnvError>;
+
+    fn handle(&mut self, msg: envman::GetWorkspacePath, _ctx: &mut Self::Context) -> Self::Result {
+        self.deploys
+            .deploy(&msg.session_id)
+            .map(|session| session.workspace.path().clone())
+    }
+}
+
+impl Handler<metrics::GetUsageSources> for PluginMan {
+    type Result = ActorResponse<Self, Vec<(String, metrics::UsageSource)>, String>;
+
+    fn handle(&mut self, _: metrics::GetUsageSources, _ctx: &mut Self::Context) -> Self::Result {
+        let sources = self.deploys.iter().map(|(id, session)| {
+            let id = id.clone();
+            session.pool.send(pp::List).map(move |pids| {
+                let pids = pids.into_iter().map(u32::from).collect();
+                (id, metrics::UsageSource::Processes(pids))
+            })
+        });
+
+        ActorResponse::r#async(
+            futures::future::join_all(sources.collect::<Vec<_>>())
+                .map_err(|e| e.to_string())
+                .into_actor(self),
+        )
+    }
+}
+
 impl Handler<status::GetEnvStatus> for PluginMan {
     type Result = MessageResult<status::GetEnvStatus>;
 
     fn handle(&mut self, _: GetEnvStatus, _ctx: &mut Self::Context) -> Self::Result {
-        MessageResult(self.deploys.status())
+        MessageResult(match self.check() {
+            Ok(()) => self.deploys.status(),
+            Err(reason) => status::EnvStatus::Unavailable { reason },
+        })
     }
 }
 
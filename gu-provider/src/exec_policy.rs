@@ -0,0 +1,348 @@
+//! Policy for programs run by hubs in hd deployments.
+//!
+//! Executables are resolved in the deployment workspace, following symlinks,
+//! right before they are spawned, and must not escape it. When rules are
+//! configured, a hub may only run executables (and arguments) allowed by a
+//! rule for the hub or its access level. Commands always run in a directory
+//! of the workspace.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::prelude::*;
+use glob::{MatchOptions, Pattern};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use gu_actix::prelude::*;
+use gu_model::envman::Error;
+use gu_net::NodeId;
+use gu_persist::config::{ConfigManager, GetConfig, HasSectionId};
+
+use crate::envman;
+use crate::permission::{self, AccessLevel};
+use crate::workspace_fs;
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExecPolicyConfig {
+    /// allows executables resolving outside the deployment workspace
+    #[serde(default)]
+    allow_outside_workspace: bool,
+    /// any executable is allowed if no rules are configured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rules: Vec<ExecRule>,
+}
+
+impl HasSectionId for ExecPolicyConfig {
+    const SECTION_ID: &'static str = "execPolicy";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExecRule {
+    /// applies to all hubs if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    hubs: Vec<NodeId>,
+    /// applies to hubs of any access level if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    access_level: Option<AccessLevel>,
+    /// glob patterns of executable paths relative to the workspace
+    executables: Vec<Glob>,
+    /// glob patterns every argument has to match; any arguments if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    args: Option<Vec<Glob>>,
+}
+
+/// Pattern compiled when the config is loaded; an invalid one is reported
+/// when used, so that it doesn't fail loading the config
+#[derive(Clone)]
+struct Glob {
+    source: String,
+    compiled: Result<Pattern, String>,
+}
+
+impl Glob {
+    fn new(source: String) -> Self {
+        let compiled =
+            Pattern::new(&source).map_err(|e| format!("invalid pattern {}: {}", source, e));
+        Glob { source, compiled }
+    }
+
+    fn matches(&self, value: &str, options: MatchOptions) -> Result<bool, String> {
+        match self.compiled {
+            Ok(ref pattern) => Ok(pattern.matches_with(value, options)),
+            Err(ref e) => Err(e.clone()),
+        }
+    }
+}
+
+impl fmt::Debug for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.source, f)
+    }
+}
+
+impl Serialize for Glob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Glob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Glob::new)
+    }
+}
+
+const EXECUTABLE_MATCH: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn matches_any(patterns: &[Glob], value: &str, options: MatchOptions) -> Result<bool, String> {
+    for pattern in patterns {
+        if pattern.matches(value, options)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+impl ExecRule {
+    fn applies_to(&self, hub: &NodeId, access_level: AccessLevel) -> bool {
+        (self.hubs.is_empty() || self.hubs.contains(hub))
+            && self
+                .access_level
+                .map_or(true, |level| level == access_level)
+    }
+
+    fn allows(&self, executable: &str, args: &[String]) -> Result<bool, String> {
+        if !matches_any(&self.executables, executable, EXECUTABLE_MATCH)? {
+            return Ok(false);
+        }
+        match self.args {
+            Some(ref patterns) => {
+                for arg in args {
+                    if !matches_any(patterns, arg, MatchOptions::new())? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            None => Ok(true),
+        }
+    }
+}
+
+impl ExecPolicyConfig {
+    /// Resolves the executable as hd deployments run it: relative to the
+    /// workspace, with symlinks followed
+    fn resolve(&self, root: &Path, executable: &str) -> Result<PathBuf, String> {
+        match workspace_fs::resolve(root, executable) {
+            Ok(path) => Ok(path),
+            Err(Error::AccessDenied(_)) if self.allow_outside_workspace => root
+                .join(executable.trim_start_matches('/'))
+                .canonicalize()
+                .map_err(|e| e.to_string()),
+            Err(Error::AccessDenied(_)) => Err("executable outside of the workspace".into()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Checks the resolved path: relative to the workspace, or absolute if
+    /// outside of it
+    fn check(
+        &self,
+        hub: &NodeId,
+        access_level: AccessLevel,
+        root: &Path,
+        path: &Path,
+        args: &[String],
+    ) -> Result<(), String> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+        let root = root.canonicalize().map_err(|e| e.to_string())?;
+        let path = path.strip_prefix(&root).unwrap_or(path).to_string_lossy();
+
+        for rule in self
+            .rules
+            .iter()
+            .filter(|r| r.applies_to(hub, access_level))
+        {
+            if rule.allows(&path, args)? {
+                return Ok(());
+            }
+        }
+        Err("not allowed for the hub".into())
+    }
+}
+
+/// Path to spawn for the executable of the hd deployment, allowed by the
+/// policy for the hub owning it. Exactly this path has to be spawned, so
+/// that files written or unpacked since can't change what runs.
+pub(crate) fn resolve_executable(
+    deployment_id: String,
+    root: PathBuf,
+    executable: String,
+    args: Vec<String>,
+) -> impl Future<Item = String, Error = String> {
+    let name = executable.clone();
+
+    envman::deployment_owner(deployment_id.clone())
+        .map_err(|e| e.to_string())
+        .and_then(move |owner| owner.ok_or_else(|| format!("no owner of {}", deployment_id)))
+        .and_then(|hub| {
+            ConfigManager::from_registry()
+                .send(GetConfig::new())
+                .flatten_fut()
+                .map_err(|e| e.to_string())
+                .join(permission::access_level(hub))
+                .map(move |(config, access_level)| (hub, config, access_level))
+        })
+        .and_then(
+            move |(hub, config, access_level): (_, Arc<ExecPolicyConfig>, _)| {
+                let path = config.resolve(&root, &executable)?;
+                config.check(&hub, access_level, &root, &path, &args)?;
+                path.into_os_string()
+                    .into_string()
+                    .map_err(|path| format!("not an UTF-8 path: {:?}", path))
+            },
+        )
+        .map_err(move |e| format!("policy violation: {}: {}", name, e))
+}
+
+/// Directory in the workspace to run a command in; the workspace itself if
+/// not given
+pub(crate) fn resolve_working_dir(
+    root: &Path,
+    working_dir: Option<String>,
+) -> Result<PathBuf, String> {
+    let working_dir = match working_dir {
+        Some(working_dir) => working_dir,
+        None => return Ok(root.to_owned()),
+    };
+
+    workspace_fs::resolve(root, &working_dir)
+        .map_err(|e| match e {
+            Error::AccessDenied(_) => "working directory outside of the workspace".into(),
+            e => e.to_string(),
+        })
+        .map_err(|e| format!("policy violation: {}: {}", working_dir, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::test_dir;
+    use std::fs;
+
+    fn hub() -> NodeId {
+        "0x875f272d3b9e7b55a5784a131a60bf3d7a42c73c"
+            .parse()
+            .unwrap()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    const FILES: &[(&str, &[u8])] = &[("bin/render", b"")];
+
+    #[test]
+    #[cfg(unix)]
+    fn test_resolve() {
+        let root = test_dir("exec_policy/resolve", FILES);
+        std::os::unix::fs::symlink("/bin/sh", root.join("bin/sh")).unwrap();
+        std::os::unix::fs::symlink("render", root.join("bin/alias")).unwrap();
+        let config = ExecPolicyConfig::default();
+        let resolve = |executable| {
+            config.resolve(&root, executable).map(|path| {
+                path.strip_prefix(root.canonicalize().unwrap())
+                    .unwrap()
+                    .to_owned()
+            })
+        };
+
+        assert_eq!(resolve("/bin/render"), Ok("bin/render".into()));
+        assert_eq!(resolve("./bin/alias"), Ok("bin/render".into()));
+        assert!(resolve("bin/sh").is_err());
+        assert!(resolve("bin/../../sh").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let config: ExecPolicyConfig = serde_json::from_str(
+            r#"{"rules": [
+                {"accessLevel": 1, "executables": ["bin/render"], "args": ["-*", "*.blend"]},
+                {"accessLevel": 2, "executables": ["bin/*"]}
+            ]}"#,
+        )
+        .unwrap();
+        let root = test_dir("exec_policy/check", FILES);
+        let check = |level, executable: &str, a: &[&str]| {
+            config
+                .resolve(&root, executable)
+                .and_then(|path| config.check(&hub(), level, &root, &path, &args(a)))
+        };
+
+        assert!(check(AccessLevel::Sandbox, "bin/render", &["-f", "scene.blend"]).is_ok());
+        assert!(check(AccessLevel::Sandbox, "bin/render", &["/etc/passwd"]).is_err());
+        assert!(check(AccessLevel::FullAccess, "/bin/render", &["-c", "id"]).is_ok());
+        assert!(check(AccessLevel::FullAccess, "bin/missing", &[]).is_err());
+        assert!(check(AccessLevel::FullAccess, "bin/../../sh", &[]).is_err());
+
+        // the link matches bin/*, what it points to doesn't
+        #[cfg(unix)]
+        {
+            fs::create_dir_all(root.join("lib")).unwrap();
+            fs::write(root.join("lib/tool"), "").unwrap();
+            std::os::unix::fs::symlink("../lib/tool", root.join("bin/tool")).unwrap();
+            assert!(check(AccessLevel::FullAccess, "bin/tool", &[]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let config: ExecPolicyConfig =
+            serde_json::from_str(r#"{"rules": [{"executables": ["bin/[render"]}]}"#).unwrap();
+        let root = test_dir("exec_policy/invalid_pattern", FILES);
+        let path = config.resolve(&root, "bin/render").unwrap();
+
+        assert!(config
+            .check(&hub(), AccessLevel::FullAccess, &root, &path, &[])
+            .unwrap_err()
+            .starts_with("invalid pattern"));
+    }
+
+    #[test]
+    fn test_no_rules() {
+        let config = ExecPolicyConfig::default();
+        let root = test_dir("exec_policy/no_rules", FILES);
+
+        assert!(config.resolve(&root, "bin/render").is_ok());
+        assert!(config.resolve(&root, "../render").is_err());
+    }
+
+    #[test]
+    fn test_working_dir() {
+        let root = test_dir("exec_policy/working_dir", FILES);
+        let resolve = |working_dir: &str| {
+            resolve_working_dir(&root, Some(working_dir.into())).map(|path| {
+                path.strip_prefix(root.canonicalize().unwrap())
+                    .unwrap()
+                    .to_owned()
+            })
+        };
+
+        assert_eq!(resolve_working_dir(&root, None), Ok(root.clone()));
+        assert_eq!(resolve("bin"), Ok("bin".into()));
+        assert_eq!(resolve("/bin"), Ok("bin".into()));
+        assert!(resolve("../../..")
+            .unwrap_err()
+            .starts_with("policy violation"));
+        assert!(resolve("bin/../..").is_err());
+    }
+}
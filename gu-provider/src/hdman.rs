@@ -15,15 +15,20 @@ use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 
 use gu_actix::prelude::*;
-use gu_hdman::image_manager;
+use gu_hdman::{download::ProgressStatus, image_manager};
 use gu_model::envman::*;
+use gu_model::hash::DynContentChecker;
+use gu_model::hdman::CreateOptions;
 use gu_net::rpc::{
-    peer::{PeerSessionInfo, PeerSessionStatus},
+    peer::{DownloadProgress, PeerSessionInfo, PeerSessionStatus},
     *,
 };
 use gu_persist::config::ConfigModule;
 
+use crate::archive::FileSelector;
 use crate::deployment::{DeployManager, Destroy, IntoDeployInfo};
+use crate::exec_policy;
+use crate::sandbox;
 
 /**
 
@@ -31,10 +36,14 @@ Host direct manager.
 
 */
 use super::id::generate_new_id;
-use super::provision::{download_step, untgz, upload_step};
+use super::provision::{
+    content_checker, download_step, read_file_inline, set_file_mode, untgz, upload_files_step,
+    upload_step,
+};
 use super::workspace::{Workspace, WorkspacesManager};
+use super::workspace_fs;
 use super::{
-    envman, status,
+    availability, envman, metrics, status,
     sync_exec::{Exec, ExecResult, SyncExecManager},
 };
 
@@ -47,10 +56,33 @@ impl IntoDeployInfo for HdSessionInfo {
             tags: self.workspace.tags(),
             note: self.note.clone(),
             processes: self.processes.keys().cloned().collect(),
+            download: match self.status {
+                PeerSessionStatus::PENDING => image_manager::download_progress(&self.image_hash)
+                    .map(|progress| download_progress(&self.image_hash, progress)),
+                _ => None,
+            },
+            egress: None,
         }
     }
 }
 
+fn download_progress(image_hash: &str, progress: ProgressStatus) -> DownloadProgress {
+    let eta = match (progress.total_to_download, progress.rate) {
+        (Some(total), rate) if rate > 0 => {
+            Some(total.saturating_sub(progress.downloaded_bytes) / rate)
+        }
+        _ => None,
+    };
+
+    DownloadProgress {
+        image_hash: image_hash.into(),
+        downloaded_bytes: progress.downloaded_bytes,
+        total_bytes: progress.total_to_download,
+        rate: progress.rate,
+        eta,
+    }
+}
+
 impl Destroy for HdSessionInfo {
     fn destroy(&mut self) -> Box<dyn Future<Item = (), Error = Error>> {
         debug!("killing all running child processes");
@@ -78,7 +110,7 @@ pub struct HdMan {
 }
 
 impl envman::EnvManService for HdMan {
-    type CreateOptions = ();
+    type CreateOptions = Option<CreateOptions>;
 }
 
 impl Actor for HdMan {
@@ -86,6 +118,8 @@ impl Actor for HdMan {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         envman::register("hd", ctx.address());
+        metrics::register_source("hd", ctx.address().recipient());
+        availability::register_env("hd", ctx.address().recipient());
 
         status::StatusManager::from_registry().do_send(status::AddProvider::new(
             "hostDirect",
@@ -183,9 +217,13 @@ impl HdMan {
 struct HdSessionInfo {
     workspace: Workspace,
     status: PeerSessionStatus,
+    /// hash of the session image
+    image_hash: String,
     /// used to determine proper status when last child is finished
     dirty: bool,
     note: Option<String>,
+    /// network access when run in the sandbox
+    network: bool,
     config_files: HashSet<PathBuf>,
     processes: HashMap<String, process::Child>,
 }
@@ -198,25 +236,16 @@ impl HdSessionInfo {
         self.status = PeerSessionStatus::RUNNING;
         id
     }
-
-    fn get_session_exec_path(&self, executable: &String) -> String {
-        self.workspace
-            .path()
-            .join(executable.trim_start_matches('/'))
-            .into_os_string()
-            .into_string()
-            .unwrap()
-    }
 }
 
-impl Handler<CreateSession> for HdMan {
+impl Handler<CreateSession<Option<CreateOptions>>> for HdMan {
     type Result = ActorResponse<HdMan, String, Error>;
 
     fn handle(
         &mut self,
-        msg: CreateSession,
+        msg: CreateSession<Option<CreateOptions>>,
         _ctx: &mut Self::Context,
-    ) -> <Self as Handler<CreateSession>>::Result {
+    ) -> Self::Result {
         let session_id = self.deploys.generate_session_id();
         let image_hash =
             match gu_model::hash::ParsedHash::from_hash_bytes(msg.image.hash.as_bytes()) {
@@ -247,8 +276,10 @@ impl Handler<CreateSession> for HdMan {
         let session = HdSessionInfo {
             workspace,
             status: PeerSessionStatus::PENDING,
+            image_hash: msg.image.hash.clone(),
             dirty: false,
             note: msg.note,
+            network: msg.options.unwrap_or_default().network,
             processes: HashMap::new(),
             config_files: HashSet::new(),
         };
@@ -301,23 +332,39 @@ fn run_command(
             args,
             working_dir,
         } => {
-            let executable = session.get_session_exec_path(&executable);
             let session_id = session_id.clone();
             let session_dir = session.workspace.path().to_owned();
-            let cwd = session_dir.join(working_dir.unwrap_or_default());
+            let cwd = match exec_policy::resolve_working_dir(&session_dir, working_dir) {
+                Ok(cwd) => cwd,
+                Err(e) => return Box::new(fut::err(e)),
+            };
+            let resolve = exec_policy::resolve_executable(
+                format!("hd::{}", session_id),
+                session_dir.clone(),
+                executable,
+                args.clone(),
+            );
 
-            info!("executing sync: {} {:?}", executable, args);
             Box::new(
-                fut::wrap_future(
-                    SyncExecManager::from_registry()
-                        .send(Exec::Run {
-                            executable,
-                            args,
-                            cwd,
-                        })
-                        .flatten_fut()
-                        .map_err(move |e| e.to_string()),
-                )
+                fut::wrap_future(resolve.join(sandbox::for_deployment(
+                    format!("hd::{}", session_id),
+                    session_dir,
+                    session.network,
+                )))
+                .and_then(|(executable, sandbox), _act: &mut HdMan, _ctx| {
+                    info!("executing sync: {} {:?}", executable, args);
+                    fut::wrap_future(
+                        SyncExecManager::from_registry()
+                            .send(Exec::Run {
+                                executable,
+                                args,
+                                cwd,
+                                sandbox,
+                            })
+                            .flatten_fut()
+                            .map_err(move |e| e.to_string()),
+                    )
+                })
                 .and_then(move |res, act: &mut HdMan, _ctx| {
                     info!("sync cmd result: {:?}", res);
                     let result = if let ExecResult::Run(output) = res {
@@ -337,22 +384,40 @@ fn run_command(
             )
         }
         Command::Start { executable, args } => {
-            let executable = session.get_session_exec_path(&executable);
-
-            info!("executing async: {} {:?}", executable, args);
-            // TODO: critical section
-            // TODO: env::set_current_dir(&base_dir)?;
+            let session_dir = session.workspace.path().to_owned();
+            let resolve = exec_policy::resolve_executable(
+                format!("hd::{}", session_id),
+                session_dir.clone(),
+                executable,
+                args.clone(),
+            );
 
-            let child_res = process::Command::new(&executable)
-                .args(&args)
-                .spawn()
-                .map_err(|e| Error::IoError(e.to_string()))
-                .map(|child| session.insert_process(child));
+            Box::new(
+                fut::wrap_future(resolve.join(sandbox::for_deployment(
+                    format!("hd::{}", session_id),
+                    session_dir.clone(),
+                    session.network,
+                )))
+                .and_then(move |(executable, sandbox), act: &mut HdMan, _ctx| {
+                    info!("executing async: {} {:?}", executable, args);
+                    let command =
+                        sandbox::command(sandbox.as_ref(), &executable, &args, &session_dir);
+                    let session = match act.get_session_mut(&session_id) {
+                        Ok(session) => session,
+                        Err(e) => return fut::err(e.to_string()),
+                    };
 
-            Box::new(match child_res {
-                Ok(id) => fut::ok(id),
-                Err(e) => fut::err(e.to_string()),
-            })
+                    fut::result(
+                        command
+                            .and_then(|mut command| {
+                                command
+                                    .spawn()
+                                    .map_err(|e| Error::IoError(e.to_string()).to_string())
+                            })
+                            .map(|child| session.insert_process(child)),
+                    )
+                }),
+            )
         }
         Command::Stop { child_id } => {
             let session_id = session_id.clone();
@@ -395,14 +460,28 @@ fn run_command(
             uri,
             file_path,
             format,
+            hash,
         } => {
+            let checker = match hash.as_ref().map(|hash| content_checker(hash)).transpose() {
+                Ok(checker) => checker,
+                Err(e) => return Box::new(fut::err(e)),
+            };
             let path = session.workspace.path().join(file_path);
-            Box::new(fut::wrap_future(handle_download_file(uri, path, format)))
+            Box::new(fut::wrap_future(handle_download_file(
+                uri, path, format, checker,
+            )))
         }
-        Command::WriteFile { content, file_path } => {
+        Command::WriteFile {
+            content,
+            file_path,
+            mode,
+        } => {
             let path = session.workspace.path().join(file_path);
+            let bytes = match content.into_bytes() {
+                Ok(bytes) => bytes,
+                Err(e) => return Box::new(fut::err(e)),
+            };
             let create_new = session.config_files.insert(path.clone());
-            let bytes = content.into_bytes();
             Box::new(fut::wrap_future(gu_hdman::download::cpu_pool().spawn_fn(
                 move || {
                     use std::io::prelude::*;
@@ -414,16 +493,31 @@ fn run_command(
                     let mut f = OpenOptions::new()
                         .create_new(true)
                         .write(true)
-                        .open(path)
+                        .open(&path)
                         .map_err(|e| format!("io: {}", e))?;
 
                     f.write_all(bytes.as_ref())
                         .map_err(|e| format!("io: {}", e))?;
+                    set_file_mode(&path, mode).map_err(|e| format!("io: {}", e))?;
 
                     Ok("OK".to_string())
                 },
             )))
         }
+        Command::ReadFile {
+            file_path,
+            max_bytes,
+            encoding,
+        } => {
+            let root = session.workspace.path().to_owned();
+            Box::new(fut::wrap_future(gu_hdman::download::cpu_pool().spawn_fn(
+                move || {
+                    let path =
+                        workspace_fs::resolve(&root, &file_path).map_err(|e| e.to_string())?;
+                    read_file_inline(&path, max_bytes, encoding)
+                },
+            )))
+        }
         Command::UploadFile {
             uri,
             file_path,
@@ -432,6 +526,22 @@ fn run_command(
             let path = session.workspace.path().join(file_path);
             Box::new(fut::wrap_future(handle_upload_file(uri, path, format)))
         }
+        Command::UploadFiles {
+            uri,
+            patterns,
+            exclude,
+            format,
+        } => {
+            let selector = match FileSelector::new(&patterns, &exclude) {
+                Ok(selector) => selector,
+                Err(e) => return Box::new(fut::err(e)),
+            };
+            let dir = session.workspace.path().join(selector.base_dir());
+            Box::new(fut::wrap_future(
+                upload_files_step(&uri, dir, selector, format)
+                    .and_then(|files| serde_json::to_string(&files).map_err(|e| e.to_string())),
+            ))
+        }
         Command::AddTags(tags) => Box::new({
             session.workspace.add_tags(tags);
             fut::ok(format!(
@@ -495,8 +605,9 @@ fn handle_download_file(
     url: String,
     file_path: PathBuf,
     format: ResourceFormat,
+    checker: Option<Box<dyn DynContentChecker>>,
 ) -> impl Future<Item = String, Error = String> {
-    download_step(url.as_ref(), file_path, format)
+    download_step(url.as_ref(), file_path, format, checker)
         .and_then(move |_| Ok(format!("{:?} file downloaded", url)))
         .map_err(|e| e.to_string())
 }
@@ -538,6 +649,49 @@ impl Handler<DestroySession> for HdMan {
     }
 }
 
+impl Handler<envman::GetWorkspacePath> for HdMan {
+    type Result = Result<PathBuf, Error>;
+
+    fn handle(&mut self, msg: envman::GetWorkspacePath, _ctx: &mut Self::Context) -> Self::Result {
+        self.deploys
+            .deploy(&msg.session_id)
+            .map(|session| session.workspace.path().clone())
+    }
+}
+
+impl Handler<metrics::GetUsageSources> for HdMan {
+    type Result = Result<Vec<(String, metrics::UsageSource)>, String>;
+
+    fn handle(&mut self, _msg: metrics::GetUsageSources, _ctx: &mut Self::Context) -> Self::Result {
+        Ok(self
+            .deploys
+            .iter()
+            .map(|(id, session)| {
+                let pids = session.processes.values().map(process::Child::id).collect();
+                (id.clone(), metrics::UsageSource::Processes(pids))
+            })
+            .collect())
+    }
+}
+
+impl Handler<availability::PauseDeployments> for HdMan {
+    type Result = ActorResponse<HdMan, (), String>;
+
+    fn handle(
+        &mut self,
+        msg: availability::PauseDeployments,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let pids = self
+            .deploys
+            .iter()
+            .flat_map(|(_, session)| session.processes.values().map(process::Child::id))
+            .collect();
+
+        ActorResponse::r#async(availability::signal_processes(pids, msg.0).into_actor(self))
+    }
+}
+
 impl Handler<status::GetEnvStatus> for HdMan {
     type Result = MessageResult<status::GetEnvStatus>;
 
@@ -546,6 +700,9 @@ impl Handler<status::GetEnvStatus> for HdMan {
         _msg: status::GetEnvStatus,
         _ctx: &mut Self::Context,
     ) -> <Self as Handler<status::GetEnvStatus>>::Result {
-        MessageResult(self.deploys.status())
+        MessageResult(match self.workspaces_man.check() {
+            Ok(()) => self.deploys.status(),
+            Err(reason) => status::EnvStatus::Unavailable { reason },
+        })
     }
 }
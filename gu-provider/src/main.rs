@@ -5,23 +5,36 @@ extern crate futures;
 
 use gu_base::*;
 
+mod archive;
+mod audit;
+mod availability;
 mod connect;
 mod deployment;
 pub mod envman;
+mod exec_policy;
 mod fchain;
 #[cfg(feature = "env-hd")]
 mod hdman;
 mod id;
+mod metrics;
 mod permission;
 mod provision;
+#[cfg_attr(not(feature = "env-docker"), allow(dead_code))]
+mod registry;
+mod sandbox;
 mod server;
 mod status;
 mod sync_exec;
 mod sync_stream;
+#[cfg(test)]
+mod testing;
 mod workspace;
+mod workspace_fs;
 
 #[cfg(feature = "env-docker")]
 mod dockerman;
+#[cfg(feature = "env-docker")]
+mod egress;
 
 #[cfg(not(feature = "env-docker"))]
 mod dockerman {
@@ -85,6 +98,8 @@ fn inner() {
             .chain(status::module())
             .chain(connect::module())
             .chain(permission::module())
+            .chain(audit::module())
+            .chain(gu_base::metrics::module())
             .chain(AutocompleteModule::new())
             .chain(server::ServerModule::new()),
     );
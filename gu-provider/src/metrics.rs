@@ -0,0 +1,597 @@
+//! Resource utilisation sampler.
+//!
+//! Every `SAMPLE_INTERVAL` procfs counters of the whole provider and of
+//! every deployment (process trees or docker container stats) are turned
+//! into usage rates. The last hour of samples is kept in memory.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use actix::prelude::*;
+use futures::{future, prelude::*};
+use log::{debug, error};
+use serde_json::Value as JsonValue;
+
+use gu_model::chrono::Utc;
+use gu_model::metrics::{GetMetrics, MetricsSample, Usage};
+use gu_net::rpc::{FromPeer, PublicMessage, RemotingContext, RemotingSystemService};
+
+use crate::envman;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// one hour of samples
+const HISTORY_LEN: usize = 360;
+const SECTOR_SIZE: u64 = 512;
+
+#[cfg(unix)]
+fn sysconf(name: libc::c_int) -> Option<u64> {
+    match unsafe { libc::sysconf(name) } {
+        value if value > 0 => Some(value as u64),
+        _ => None,
+    }
+}
+
+/// USER_HZ, the unit of procfs cpu times
+#[cfg(unix)]
+fn ticks_per_sec() -> u64 {
+    sysconf(libc::_SC_CLK_TCK).unwrap_or(100)
+}
+
+#[cfg(not(unix))]
+fn ticks_per_sec() -> u64 {
+    100
+}
+
+#[cfg(unix)]
+fn page_size() -> u64 {
+    sysconf(libc::_SC_PAGESIZE).unwrap_or(4096)
+}
+
+#[cfg(not(unix))]
+fn page_size() -> u64 {
+    4096
+}
+
+/// Where usage of a deployment is read from
+pub enum UsageSource {
+    /// process trees rooted at the given pids
+    Processes(Vec<u32>),
+    /// response of the docker container stats API
+    DockerStats(JsonValue),
+}
+
+/// Asks an execution environment for usage sources of its deployments
+pub struct GetUsageSources;
+
+impl Message for GetUsageSources {
+    type Result = Result<Vec<(String, UsageSource)>, String>;
+}
+
+struct AddSource(Cow<'static, str>, Recipient<GetUsageSources>);
+
+impl Message for AddSource {
+    type Result = ();
+}
+
+/// Registers deployments of the environment for sampling
+pub fn register_source<S: Into<Cow<'static, str>>>(
+    env_type: S,
+    source: Recipient<GetUsageSources>,
+) {
+    MetricsSampler::from_registry().do_send(AddSource(env_type.into(), source))
+}
+
+pub fn start() {
+    let _ = MetricsSampler::from_registry();
+}
+
+/// Cumulative counters
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+struct Counters {
+    cpu_ticks: u64,
+    memory: u64,
+    disk_read: u64,
+    disk_write: u64,
+    net_rx: u64,
+    net_tx: u64,
+}
+
+impl Counters {
+    fn usage_since(&self, prev: &Counters, elapsed: Duration, with_net: bool) -> Usage {
+        let secs = (elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9).max(1e-3);
+        let rate = |now: u64, prev: u64| (now.saturating_sub(prev) as f64 / secs) as u64;
+
+        Usage {
+            cpu: (self.cpu_ticks.saturating_sub(prev.cpu_ticks) as f64 * 100.0
+                / (secs * ticks_per_sec() as f64)) as f32,
+            memory: self.memory,
+            disk_read: rate(self.disk_read, prev.disk_read),
+            disk_write: rate(self.disk_write, prev.disk_write),
+            net_rx: if with_net {
+                Some(rate(self.net_rx, prev.net_rx))
+            } else {
+                None
+            },
+            net_tx: if with_net {
+                Some(rate(self.net_tx, prev.net_tx))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let ticks: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|v| v.parse().ok())
+        .collect();
+
+    // user nice system idle iowait irq softirq steal; guest time is in user
+    Some(
+        ticks
+            .iter()
+            .take(8)
+            .enumerate()
+            .filter(|(idx, _)| *idx != 3 && *idx != 4)
+            .map(|(_, v)| v)
+            .sum(),
+    )
+}
+
+fn parse_used_memory(meminfo: &str) -> Option<u64> {
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(name))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|kb| kb.parse::<u64>().ok())
+    };
+
+    Some((field("MemTotal:")? - field("MemAvailable:")?) * 1024)
+}
+
+/// Bytes read and written by whole disks
+fn parse_diskstats(diskstats: &str, disks: &[String]) -> (u64, u64) {
+    diskstats
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 || !disks.iter().any(|disk| disk == fields[2]) {
+                return None;
+            }
+            Some((
+                fields[5].parse::<u64>().ok()?,
+                fields[9].parse::<u64>().ok()?,
+            ))
+        })
+        .fold((0, 0), |(read, written), (r, w)| {
+            (read + r * SECTOR_SIZE, written + w * SECTOR_SIZE)
+        })
+}
+
+/// Bytes received and sent by all interfaces but loopback
+fn parse_net_dev(net_dev: &str) -> (u64, u64) {
+    net_dev
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let mut it = line.splitn(2, ':');
+            let iface = it.next()?.trim();
+            if iface == "lo" {
+                return None;
+            }
+            let fields: Vec<u64> = it
+                .next()?
+                .split_whitespace()
+                .filter_map(|v| v.parse().ok())
+                .collect();
+            Some((*fields.get(0)?, *fields.get(8)?))
+        })
+        .fold((0, 0), |(rx, tx), (r, t)| (rx + r, tx + t))
+}
+
+fn global_counters(root: &Path) -> io::Result<Counters> {
+    let proc_dir = root.join("proc");
+    let disks: Vec<String> = fs::read_dir(root.join("sys/block"))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with("loop") && !name.starts_with("ram"))
+        .collect();
+    let (disk_read, disk_write) =
+        parse_diskstats(&fs::read_to_string(proc_dir.join("diskstats"))?, &disks);
+    let (net_rx, net_tx) = parse_net_dev(&fs::read_to_string(proc_dir.join("net/dev"))?);
+
+    Ok(Counters {
+        cpu_ticks: parse_cpu_ticks(&fs::read_to_string(proc_dir.join("stat"))?).unwrap_or(0),
+        memory: parse_used_memory(&fs::read_to_string(proc_dir.join("meminfo"))?).unwrap_or(0),
+        disk_read,
+        disk_write,
+        net_rx,
+        net_tx,
+    })
+}
+
+#[derive(Default, Debug, PartialEq)]
+struct ProcStat {
+    ppid: u32,
+    ticks: u64,
+    rss: u64,
+}
+
+fn parse_proc_stat(stat: &str) -> Option<ProcStat> {
+    // the command name may contain spaces and parentheses
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |idx: usize| fields.get(idx).and_then(|v| v.parse::<u64>().ok());
+
+    Some(ProcStat {
+        ppid: field(1)? as u32,
+        ticks: field(11)? + field(12)?,
+        rss: field(21)? * page_size(),
+    })
+}
+
+/// Snapshot of all processes
+struct ProcTable {
+    stats: HashMap<u32, ProcStat>,
+    children: HashMap<u32, Vec<u32>>,
+}
+
+impl ProcTable {
+    fn scan(root: &Path) -> io::Result<Self> {
+        let mut stats = HashMap::new();
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+
+        for entry in fs::read_dir(root.join("proc"))? {
+            let entry = entry?;
+            let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+                Ok(pid) => pid,
+                Err(_) => continue,
+            };
+            // processes may exit while scanning
+            if let Some(stat) = fs::read_to_string(entry.path().join("stat"))
+                .ok()
+                .and_then(|stat| parse_proc_stat(&stat))
+            {
+                children.entry(stat.ppid).or_default().push(pid);
+                stats.insert(pid, stat);
+            }
+        }
+
+        Ok(ProcTable { stats, children })
+    }
+
+    /// Processes of the trees rooted at `pids`
+    fn tree(&self, pids: &[u32]) -> Vec<u32> {
+        let mut tree = Vec::new();
+        let mut queue: Vec<u32> = pids.to_vec();
+
+        while let Some(pid) = queue.pop() {
+            if !self.stats.contains_key(&pid) {
+                continue;
+            }
+            tree.push(pid);
+            if let Some(children) = self.children.get(&pid) {
+                queue.extend(children);
+            }
+        }
+
+        tree
+    }
+
+    fn tree_counters(&self, root: &Path, pids: &[u32]) -> Counters {
+        let mut counters = Counters::default();
+
+        for pid in self.tree(pids) {
+            let stat = &self.stats[&pid];
+            counters.cpu_ticks += stat.ticks;
+            counters.memory += stat.rss;
+
+            // readable for own processes only
+            if let Ok(io) = fs::read_to_string(root.join(format!("proc/{}/io", pid))) {
+                for line in io.lines() {
+                    let mut kv = line.split(':');
+                    let (key, value) = match (kv.next(), kv.next()) {
+                        (Some(key), Some(value)) => (key, value.trim().parse().unwrap_or(0)),
+                        _ => continue,
+                    };
+                    match key {
+                        "read_bytes" => counters.disk_read += value,
+                        "write_bytes" => counters.disk_write += value,
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        counters
+    }
+}
+
+/// Pids of all processes in the trees rooted at `pids`
+pub fn process_tree(pids: &[u32]) -> io::Result<Vec<u32>> {
+    Ok(ProcTable::scan(Path::new("/"))?.tree(pids))
+}
+
+/// Counters of a docker container from its stats; block io entries are
+/// capitalised with cgroup v1 and lowercase with v2
+fn container_counters(stats: &JsonValue) -> Counters {
+    let number = |pointer: &str| stats.pointer(pointer).and_then(JsonValue::as_u64);
+    let (disk_read, disk_write) = stats
+        .pointer("/blkio_stats/io_service_bytes_recursive")
+        .and_then(JsonValue::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some((entry["op"].as_str()?, entry["value"].as_u64()?)))
+        .fold((0, 0), |(read, written), (op, bytes)| {
+            match op.to_ascii_lowercase().as_str() {
+                "read" => (read + bytes, written),
+                "write" => (read, written + bytes),
+                _ => (read, written),
+            }
+        });
+
+    Counters {
+        // nanoseconds
+        cpu_ticks: number("/cpu_stats/cpu_usage/total_usage").unwrap_or(0)
+            / (1_000_000_000 / ticks_per_sec()),
+        memory: number("/memory_stats/usage").unwrap_or(0),
+        disk_read,
+        disk_write,
+        ..Counters::default()
+    }
+}
+
+/// Reads counters of the provider and of deployments; blocks on procfs
+fn read_counters(
+    root: &Path,
+    sources: Vec<(String, UsageSource)>,
+) -> (Counters, HashMap<String, Counters>) {
+    let global = global_counters(root).unwrap_or_else(|e| {
+        debug!("global counters: {}", e);
+        Counters::default()
+    });
+
+    let procs = match sources.iter().any(|(_, source)| match source {
+        UsageSource::Processes(_) => true,
+        _ => false,
+    }) {
+        true => ProcTable::scan(root)
+            .map_err(|e| debug!("procfs: {}", e))
+            .ok(),
+        false => None,
+    };
+    let deployments = sources
+        .into_iter()
+        .filter_map(|(id, source)| {
+            let counters = match source {
+                UsageSource::Processes(pids) => procs.as_ref()?.tree_counters(root, &pids),
+                UsageSource::DockerStats(stats) => container_counters(&stats),
+            };
+            Some((id, counters))
+        })
+        .collect();
+
+    (global, deployments)
+}
+
+#[derive(Default)]
+struct MetricsSampler {
+    sources: BTreeMap<Cow<'static, str>, Recipient<GetUsageSources>>,
+    samples: VecDeque<MetricsSample>,
+    last: Option<(Instant, Counters, HashMap<String, Counters>)>,
+}
+
+impl MetricsSampler {
+    /// Usage sources of all environments with ids of deployments prefixed
+    /// as in `EnvMan`
+    fn usage_sources(&self) -> impl Future<Item = Vec<(String, UsageSource)>, Error = ()> {
+        future::join_all(self.sources.clone().into_iter().map(|(env, source)| {
+            source.send(GetUsageSources).then(move |r| {
+                let sources = match r {
+                    Ok(Ok(sources)) => sources,
+                    Ok(Err(e)) => {
+                        error!("usage sources of {}: {}", env, e);
+                        Vec::new()
+                    }
+                    Err(e) => {
+                        error!("usage sources of {}: {}", env, e);
+                        Vec::new()
+                    }
+                };
+                Ok::<_, ()>(
+                    sources
+                        .into_iter()
+                        .map(|(id, source)| (format!("{}::{}", env, id), source))
+                        .collect::<Vec<_>>(),
+                )
+            })
+        }))
+        .map(|sources| sources.into_iter().flatten().collect())
+    }
+
+    fn sample(&mut self, ctx: &mut <Self as Actor>::Context) {
+        ctx.spawn(
+            self.usage_sources()
+                .and_then(|sources| {
+                    gu_hdman::download::cpu_pool().spawn_fn(move || -> Result<_, ()> {
+                        let (global, deployments) = read_counters(Path::new("/"), sources);
+                        Ok((Instant::now(), global, deployments))
+                    })
+                })
+                .into_actor(self)
+                .map(|(now, global, deployments), act, _ctx| act.record(now, global, deployments)),
+        );
+    }
+
+    fn record(&mut self, now: Instant, global: Counters, deployments: HashMap<String, Counters>) {
+        if let Some((ts, prev_global, prev_deployments)) = self.last.take() {
+            let elapsed = now.duration_since(ts);
+            self.samples.push_back(MetricsSample {
+                ts: Utc::now(),
+                interval: elapsed.as_secs() as u32,
+                global: global.usage_since(&prev_global, elapsed, true),
+                deployments: deployments
+                    .iter()
+                    .filter_map(|(id, counters)| {
+                        let prev = prev_deployments.get(id)?;
+                        Some((id.clone(), counters.usage_since(prev, elapsed, false)))
+                    })
+                    .collect(),
+            });
+            while self.samples.len() > HISTORY_LEN {
+                self.samples.pop_front();
+            }
+        }
+        self.last = Some((now, global, deployments));
+    }
+}
+
+impl Actor for MetricsSampler {
+    type Context = RemotingContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.bind_from_peer::<GetMetrics>(GetMetrics::ID);
+        ctx.run_interval(SAMPLE_INTERVAL, |act, ctx| act.sample(ctx));
+    }
+}
+
+impl RemotingSystemService for MetricsSampler {}
+
+impl Handler<AddSource> for MetricsSampler {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddSource, _ctx: &mut Self::Context) {
+        self.sources.insert(msg.0, msg.1);
+    }
+}
+
+impl Handler<FromPeer<GetMetrics>> for MetricsSampler {
+    type Result = ActorResponse<MetricsSampler, Vec<MetricsSample>, String>;
+
+    fn handle(&mut self, msg: FromPeer<GetMetrics>, _ctx: &mut Self::Context) -> Self::Result {
+        let since = msg.body.since;
+
+        ActorResponse::r#async(
+            envman::owned_deployments(msg.sender)
+                .map_err(|e| e.to_string())
+                .into_actor(self)
+                .map(move |owned, act, _ctx| {
+                    act.samples
+                        .iter()
+                        .filter(|sample| since.map(|since| sample.ts > since).unwrap_or(true))
+                        .map(|sample| MetricsSample {
+                            deployments: sample
+                                .deployments
+                                .iter()
+                                .filter(|(id, _)| owned.contains(*id))
+                                .map(|(id, usage)| (id.clone(), usage.clone()))
+                                .collect(),
+                            ..sample.clone()
+                        })
+                        .collect()
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_stat() {
+        let stat = "1234 (my (app) x) S 1000 1234 1000 0 -1 4194560 1234 0 0 0 \
+                    150 50 0 0 20 0 4 0 12345 204800000 2560 18446744073709551615";
+
+        assert_eq!(
+            parse_proc_stat(stat),
+            Some(ProcStat {
+                ppid: 1000,
+                ticks: 200,
+                rss: 2560 * page_size(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_global() {
+        let stat = "cpu  100 10 50 1000 20 5 5 0 0 0\ncpu0 50 5 25 500 10 2 3 0 0 0\n";
+        assert_eq!(parse_cpu_ticks(stat), Some(170));
+
+        let meminfo = "MemTotal:       16000000 kB\nMemFree:         2000000 kB\n\
+                       MemAvailable:    6000000 kB\n";
+        assert_eq!(parse_used_memory(meminfo), Some(10_000_000 * 1024));
+
+        let diskstats = "   8       0 sda 100 0 2000 50 200 0 4000 80 0 100 130\n\
+                         \x20  8       1 sda1 90 0 1800 45 190 0 3800 75 0 90 120\n\
+                         \x20  7       0 loop0 5 0 10 0 0 0 0 0 0 0 0\n";
+        assert_eq!(
+            parse_diskstats(diskstats, &["sda".to_string()]),
+            (2000 * SECTOR_SIZE, 4000 * SECTOR_SIZE)
+        );
+
+        let net_dev = "Inter-|   Receive                            |  Transmit\n \
+                       face |bytes    packets errs drop fifo frame compressed multicast|bytes\n    \
+                       lo: 5000 50 0 0 0 0 0 0 5000 50 0 0 0 0 0 0\n  \
+                       eth0: 1000 10 0 0 0 0 0 0 2000 20 0 0 0 0 0 0\n";
+        assert_eq!(parse_net_dev(net_dev), (1000, 2000));
+    }
+
+    #[test]
+    fn test_container_counters() {
+        let stats = serde_json::json!({
+            "cpu_stats": {"cpu_usage": {"total_usage": 2_000_000_000u64}},
+            "memory_stats": {"usage": 1024},
+            "blkio_stats": {"io_service_bytes_recursive": [
+                {"major": 8, "minor": 0, "op": "read", "value": 100},
+                {"major": 8, "minor": 0, "op": "write", "value": 200},
+                {"major": 8, "minor": 16, "op": "Read", "value": 10}
+            ]}
+        });
+
+        let counters = container_counters(&stats);
+        assert_eq!(counters.cpu_ticks, 2 * ticks_per_sec());
+        assert_eq!(counters.memory, 1024);
+        assert_eq!((counters.disk_read, counters.disk_write), (110, 200));
+    }
+
+    #[test]
+    fn test_usage_since() {
+        let prev = Counters {
+            cpu_ticks: 1000,
+            memory: 100,
+            disk_read: 0,
+            disk_write: 1000,
+            net_rx: 0,
+            net_tx: 0,
+        };
+        let now = Counters {
+            cpu_ticks: 1500,
+            memory: 200,
+            disk_read: 5000,
+            disk_write: 500,
+            net_rx: 10_000,
+            net_tx: 0,
+        };
+
+        let usage = now.usage_since(&prev, Duration::from_secs(5), false);
+        assert_eq!(usage.cpu, 100.0);
+        assert_eq!(usage.memory, 200);
+        assert_eq!(usage.disk_read, 1000);
+        // counters of exited processes drop out
+        assert_eq!(usage.disk_write, 0);
+        assert_eq!(usage.net_rx, None);
+        assert_eq!(
+            now.usage_since(&prev, Duration::from_secs(5), true).net_rx,
+            Some(2000)
+        );
+    }
+}
@@ -25,9 +25,9 @@ use crate::connect::{
 use crate::server::ConnectMode;
 use futures::future;
 
-#[derive(Serialize_repr, Deserialize_repr, Clone, PartialEq, Eq, Hash, Copy)]
+#[derive(Serialize_repr, Deserialize_repr, Clone, PartialEq, Eq, Hash, Copy, Debug)]
 #[repr(u8)]
-enum AccessLevel {
+pub(crate) enum AccessLevel {
     NoAccess = 0,
     Sandbox = 1,
     FullAccess = 2,
@@ -55,6 +55,18 @@ struct PermissionConfig {
     permissions: HashSet<Permission>,
     #[serde(default)]
     saved_hub_desc: HashMap<NodeId, HubDesc>,
+    /// limits of deployments owned by managing hubs
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    shares: HashMap<NodeId, ResourceShare>,
+}
+
+/// Share of provider resources available to a single hub
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResourceShare {
+    /// no limit if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_deployments: Option<usize>,
 }
 
 impl PermissionConfig {
@@ -66,6 +78,7 @@ impl PermissionConfig {
             permissions,
             allow_any: self.allow_any,
             saved_hub_desc: self.saved_hub_desc.clone(),
+            shares: self.shares.clone(),
         }
     }
 
@@ -84,6 +97,14 @@ impl PermissionConfig {
             })
     }
 
+    /// Level of a managing hub or the level granted to any hub
+    fn access_level(&self, node_id: &NodeId) -> AccessLevel {
+        match self.highest_permission(node_id) {
+            AccessLevel::NoAccess => self.allow_any,
+            level => level,
+        }
+    }
+
     fn is_managed_by(&self, node_id: &NodeId) -> bool {
         self.highest_permission(node_id) != AccessLevel::NoAccess
     }
@@ -123,6 +144,20 @@ fn config_future(
         .flatten_fut()
 }
 
+/// Resource share configured for the hub
+pub(crate) fn resource_share(node_id: NodeId) -> impl Future<Item = ResourceShare, Error = String> {
+    config_future()
+        .map(move |c: Arc<PermissionConfig>| c.shares.get(&node_id).cloned().unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+/// Access level granted to the hub
+pub(crate) fn access_level(node_id: NodeId) -> impl Future<Item = AccessLevel, Error = String> {
+    config_future()
+        .map(move |c: Arc<PermissionConfig>| c.access_level(&node_id))
+        .map_err(|e| e.to_string())
+}
+
 fn list_saved_hubs_future() -> impl Future<Item = String, Error = ()> {
     config_future()
         .and_then(move |c: Arc<PermissionConfig>| {
@@ -1,27 +1,104 @@
 use std::{
     fs,
+    io::{self, Read},
     path::{Path, PathBuf},
-    time,
+    thread, time,
 };
 
 use actix_web::client::ClientResponse;
 use actix_web::http::header;
 use actix_web::HttpMessage;
-use futures::{future, prelude::*};
-use log::{debug, info};
+use futures::{future, prelude::*, sync::oneshot};
+use log::{debug, error, info};
+use uuid::Uuid;
 
-use gu_actix::{async_result, async_try};
+use gu_actix::async_try;
+use gu_actix::pipe::{self, SyncReader, WriteError};
 use gu_base::files::read_async;
 use gu_base::files::{untgz_async, write_async};
-use gu_model::envman::ResourceFormat;
+use gu_model::envman::{ContentEncoding, ResourceFormat, DEFAULT_READ_LIMIT};
+use gu_model::hash::{ContentChecker, DynContentChecker, ParsedHash};
 
+use crate::archive::{self, FileSelector};
+
+/// Content checker for the expected `hash` (`<algorithm>:<hex value>`).
+pub fn content_checker(hash: &str) -> Result<Box<dyn DynContentChecker>, String> {
+    ParsedHash::from_hash_bytes(hash.as_bytes())
+        .and_then(|hash| hash.checker())
+        .map_err(|e| format!("invalid hash {}: {}", hash, e))
+}
+
+/// Stream verifying content hash of the `inner` stream.
+///
+/// The last chunk is held back until the whole content is verified, so
+/// consumers never get complete content that fails the check.
+pub struct HashChecked<S> {
+    inner: S,
+    checker: Option<Box<dyn DynContentChecker>>,
+    pending: Option<bytes::Bytes>,
+}
+
+impl<S> HashChecked<S> {
+    pub fn new(inner: S, checker: Box<dyn DynContentChecker>) -> Self {
+        HashChecked {
+            inner,
+            checker: Some(checker),
+            pending: None,
+        }
+    }
+}
+
+impl<S: Stream<Item = bytes::Bytes, Error = String>> Stream for HashChecked<S> {
+    type Item = bytes::Bytes;
+    type Error = String;
+
+    fn poll(&mut self) -> Poll<Option<bytes::Bytes>, String> {
+        loop {
+            let checker = match self.checker.as_mut() {
+                Some(checker) => checker,
+                None => return Ok(Async::Ready(self.pending.take())),
+            };
+            match self.inner.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    checker.update(chunk.as_ref());
+                    if let Some(prev) = self.pending.replace(chunk) {
+                        return Ok(Async::Ready(Some(prev)));
+                    }
+                }
+                Async::Ready(None) => {
+                    if !self.checker.take().map(|c| c.verify()).unwrap_or(true) {
+                        self.pending = None;
+                        return Err("downloaded content does not match expected hash".into());
+                    }
+                    return Ok(Async::Ready(self.pending.take()));
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+fn checked_payload(
+    resp: &ClientResponse,
+    checker: Option<Box<dyn DynContentChecker>>,
+) -> Box<dyn Stream<Item = bytes::Bytes, Error = String>> {
+    let payload = resp.payload().map_err(|e| e.to_string());
+    match checker {
+        Some(checker) => Box::new(HashChecked::new(payload, checker)),
+        None => Box::new(payload),
+    }
+}
+
+/// Downloads `url` into `output_path`. Verifies content against `checker`
+/// when given; on mismatch a downloaded raw file is removed and archives
+/// are not unpacked at all.
 pub fn download_step(
     url: &str,
     output_path: PathBuf,
     format: ResourceFormat,
+    checker: Option<Box<dyn DynContentChecker>>,
 ) -> impl Future<Item = (), Error = String> {
     use actix_web::client;
-    use tar_async::decode::full;
 
     let client_request = async_try!(client::ClientRequest::get(url)
         .finish()
@@ -29,7 +106,7 @@ pub fn download_step(
 
     let dir_name = match format {
         ResourceFormat::Raw => output_path.parent().unwrap(),
-        ResourceFormat::Tar => output_path.as_ref(),
+        _ => output_path.as_ref(),
     };
 
     if !dir_name.exists() {
@@ -40,42 +117,23 @@ pub fn download_step(
         client_request
             .send()
             .map_err(|e| format!("send download request: {}", e))
-            .and_then(move |resp| {
-                match format {
-                    ResourceFormat::Raw => future::Either::A(
-                        write_async(resp.payload(), output_path)
-                            .map_err(|_| "writing downloaded file failed".to_string()),
-                    ),
-                    ResourceFormat::Tar => future::Either::B(
-                        full::decode_tar(resp.payload())
-                            .map_err(|e| format!("tar: {}", e))
-                            .for_each(move |entry| {
-                                let entry_type = entry.header().entry_type().clone();
-                                let path: PathBuf = async_try!(entry
-                                    .header()
-                                    .path()
-                                    .map_err(|e| format!("payload err: {}", e)))
-                                .to_owned();
-                                eprintln!("tar-path:{}", path.display());
-
-                                if entry_type.is_dir() {
-                                    // is directory
-                                    let dir_name = output_path.join(path);
-                                    if !dir_name.exists() {
-                                        let _ = async_try!(fs::create_dir_all(dir_name)
-                                            .map_err(|e| format!("io: {}", e)));
-                                    }
-                                    future::Either::B(future::ok(()))
-                                } else if entry_type.is_file() {
-                                    let out_file = output_path.join(path);
-                                    async_result!(write_async(entry, out_file))
-                                } else {
-                                    // if entry.header().path() { }
-                                    future::Either::B(future::ok(()))
-                                }
-                            }),
-                    ),
+            .and_then(move |resp| match format {
+                ResourceFormat::Raw => {
+                    let file_path = output_path.clone();
+                    future::Either::A(
+                        write_async(checked_payload(&resp, checker), output_path).map_err(
+                            move |e| {
+                                let _ = fs::remove_file(&file_path);
+                                format!("writing downloaded file failed: {}", e)
+                            },
+                        ),
+                    )
                 }
+                format => future::Either::B(unpack_stream(
+                    format,
+                    checked_payload(&resp, checker),
+                    output_path,
+                )),
             }),
     )
 }
@@ -85,18 +143,60 @@ pub fn upload_step(
     input_path: PathBuf,
     format: ResourceFormat,
 ) -> impl Future<Item = String, Error = String> {
-    use actix_web::{client, error::ErrorInternalServerError};
-
     debug!(
         "streaming from {:?} to {} format: {:?}",
         &input_path, url, format
     );
     let source_stream: Box<dyn Stream<Item = bytes::Bytes, Error = String>> = match format {
-        ResourceFormat::Tar => Box::new(stream_tar(input_path)),
         ResourceFormat::Raw => Box::new(stream_raw(input_path)),
+        format => Box::new(stream_archive(input_path, format)),
     };
     let url_desc = url.to_owned();
 
+    put_stream(url, source_stream).map(move |_| format!("{:?} file uploaded", url_desc))
+}
+
+/// Uploads files selected from `dir` (the location of `selector.base_dir()`)
+/// as a single archive. Returns names of the files included.
+pub fn upload_files_step(
+    url: &str,
+    dir: PathBuf,
+    selector: FileSelector,
+    format: ResourceFormat,
+) -> impl Future<Item = Vec<String>, Error = String> {
+    if format == ResourceFormat::Raw {
+        return future::Either::B(future::err(
+            "multiple files can not be uploaded in raw format".to_string(),
+        ));
+    }
+    let files = async_try!(selector.select(&dir).map_err(|e| e.to_string()));
+    if files.is_empty() {
+        return future::Either::B(future::err("no files match given patterns".to_string()));
+    }
+    debug!("streaming {} files from {:?} to {}", files.len(), &dir, url);
+
+    let names = files
+        .iter()
+        .map(|file| file.to_string_lossy().into_owned())
+        .collect();
+    let (mut tx, rx) = pipe::sync_to_async(5);
+
+    thread::spawn(move || {
+        if let Err(e) = archive::pack_entries(format, &dir, &files, &mut tx) {
+            error!("Error while building the archive: {}", e);
+            let _ = tx.send(Err(e));
+        }
+    });
+
+    future::Either::A(put_stream(url, rx.map_err(|e| e.to_string())).map(|_| names))
+}
+
+fn put_stream<S>(url: &str, source_stream: S) -> impl Future<Item = (), Error = String>
+where
+    S: Stream<Item = bytes::Bytes, Error = String> + 'static,
+{
+    use actix_web::{client, error::ErrorInternalServerError};
+
     future::result(
         client::put(url).streaming(source_stream.map_err(|x| ErrorInternalServerError(x))),
     )
@@ -104,7 +204,7 @@ pub fn upload_step(
     .and_then(|req| req.send().map_err(|e| e.to_string()))
     .and_then(move |res| {
         if res.status().is_success() {
-            Ok(format!("{:?} file uploaded", url_desc))
+            Ok(())
         } else {
             Err(format!("Unsuccessful file upload: {}", res.status()))
         }
@@ -112,20 +212,21 @@ pub fn upload_step(
 }
 
 pub fn stream_tar(input_path: PathBuf) -> impl Stream<Item = bytes::Bytes, Error = String> {
-    use gu_actix::pipe;
-    use std::thread;
-    use tar::Builder;
+    stream_archive(input_path, ResourceFormat::Tar)
+}
 
-    let (tx, rx) = pipe::sync_to_async(5);
+/// Packs directory (or a single file) into an archive streamed as it is built.
+pub fn stream_archive(
+    input_path: PathBuf,
+    format: ResourceFormat,
+) -> impl Stream<Item = bytes::Bytes, Error = String> {
+    let (mut tx, rx) = pipe::sync_to_async(5);
 
     thread::spawn(move || {
-        let mut builder = Builder::new(tx);
-        builder
-            .append_dir_all(".", &input_path)
-            .unwrap_or_else(|e| {
-                panic!("Error while building the archive: {}", e);
-            });
-        builder.finish().unwrap();
+        if let Err(e) = archive::pack(format, &input_path, &mut tx) {
+            error!("Error while building the archive: {}", e);
+            let _ = tx.send(Err(e));
+        }
     });
 
     rx.map_err(|e| {
@@ -134,6 +235,185 @@ pub fn stream_tar(input_path: PathBuf) -> impl Stream<Item = bytes::Bytes, Error
     })
 }
 
+fn input_error(e: String) -> WriteError<io::Error> {
+    WriteError::Other(io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Feeds blocking `f` with the `input` stream on a separate thread.
+fn sync_consume<S, F, R>(input: S, f: F) -> impl Future<Item = R, Error = String>
+where
+    S: Stream<Item = bytes::Bytes, Error = String>,
+    F: FnOnce(SyncReader<bytes::Bytes, io::Error>) -> io::Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    let (tx, rx) = pipe::async_to_sync(16);
+    let (result_tx, result_rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let _ = result_tx.send(f(rx));
+    });
+
+    input.map_err(input_error).forward(tx).then(move |forward| {
+        result_rx
+            .map_err(|_| "archive worker failed".to_string())
+            .and_then(move |result| match (result, forward) {
+                (Err(e), _) => Err(e.to_string()),
+                (Ok(_), Err(e)) => Err(e.to_string()),
+                (Ok(v), Ok(_)) => Ok(v),
+            })
+    })
+}
+
+/// Transforms the `input` stream with blocking `f` run on a separate thread.
+fn sync_transform<S, F>(input: S, f: F) -> impl Stream<Item = bytes::Bytes, Error = String>
+where
+    S: Stream<Item = bytes::Bytes, Error = String> + 'static,
+    F: FnOnce(
+            SyncReader<bytes::Bytes, io::Error>,
+            &mut pipe::Writer<bytes::Bytes, io::Error>,
+        ) -> io::Result<()>
+        + Send
+        + 'static,
+{
+    let (in_tx, in_rx) = pipe::async_to_sync(16);
+    let (mut out_tx, out_rx) = pipe::sync_to_async(16);
+
+    thread::spawn(move || {
+        if let Err(e) = f(in_rx, &mut out_tx) {
+            let _ = out_tx.send(Err(e));
+        }
+    });
+
+    actix::Arbiter::spawn(input.map_err(input_error).forward(in_tx).then(|r| {
+        if let Err(e) = r {
+            error!("archive input: {}", e);
+        }
+        Ok(())
+    }));
+
+    out_rx.map_err(|e| e.to_string())
+}
+
+/// Unpacks archive from the `input` stream into a staging directory next to
+/// `output_path`, and moves its content into place only once the whole
+/// stream is consumed, so a failing stream leaves `output_path` untouched.
+pub fn unpack_stream<S>(
+    format: ResourceFormat,
+    input: S,
+    output_path: PathBuf,
+) -> impl Future<Item = (), Error = String>
+where
+    S: Stream<Item = bytes::Bytes, Error = String>,
+{
+    let staging = output_path.with_file_name(format!(".gu-unpack-{}", Uuid::new_v4().to_simple()));
+    let (unpack_path, cleanup_path) = (staging.clone(), staging.clone());
+
+    sync_consume(input, move |reader| {
+        archive::unpack(format, reader, &unpack_path)
+    })
+    .and_then(move |()| {
+        gu_hdman::download::cpu_pool().spawn_fn(move || {
+            archive::move_into(&staging, &output_path)
+                .map_err(|e| format!("moving unpacked files: {}", e))
+        })
+    })
+    .then(move |result| {
+        let _ = fs::remove_dir_all(&cleanup_path);
+        result
+    })
+}
+
+/// Temporary file removed when dropped
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Streams `input` once it is complete. The content is collected in
+/// a temporary file first, so consumers get nothing from a failing stream.
+pub fn spooled_stream<S>(input: S) -> impl Stream<Item = bytes::Bytes, Error = String>
+where
+    S: Stream<Item = bytes::Bytes, Error = String>,
+{
+    let temp_file =
+        TempFile(std::env::temp_dir().join(format!("gu-spool-{}", Uuid::new_v4().to_simple())));
+
+    write_async(input, temp_file.0.clone())
+        .map(move |()| {
+            // the file is removed once the stream is dropped
+            read_async(temp_file.0.clone()).map(move |chunk| {
+                let _ = &temp_file;
+                chunk
+            })
+        })
+        .flatten_stream()
+}
+
+/// Filters tar stream with `selector`. The returned future resolves
+/// to names of the files passed through once the stream is consumed.
+pub fn filter_tar_stream<S>(
+    selector: FileSelector,
+    input: S,
+) -> (
+    impl Stream<Item = bytes::Bytes, Error = String>,
+    impl Future<Item = Vec<String>, Error = String>,
+)
+where
+    S: Stream<Item = bytes::Bytes, Error = String> + 'static,
+{
+    let (files_tx, files_rx) = oneshot::channel();
+    let stream = sync_transform(input, move |reader, writer| {
+        let (_, files) = selector.filter_tar(reader, writer)?;
+        let _ = files_tx.send(
+            files
+                .into_iter()
+                .map(|file| file.to_string_lossy().into_owned())
+                .collect(),
+        );
+        Ok(())
+    });
+
+    (
+        stream,
+        files_rx.map_err(|_| "filtering archive failed".to_string()),
+    )
+}
+
+/// Converts archive stream in given `format` into plain tar stream.
+pub fn archive_to_tar_stream<S>(
+    format: ResourceFormat,
+    input: S,
+) -> Box<dyn Stream<Item = bytes::Bytes, Error = String>>
+where
+    S: Stream<Item = bytes::Bytes, Error = String> + 'static,
+{
+    match format {
+        ResourceFormat::Tar => Box::new(input),
+        format => Box::new(sync_transform(input, move |reader, writer| {
+            archive::to_tar(format, reader, writer).map(|_| ())
+        })),
+    }
+}
+
+/// Converts plain tar stream into archive stream in given `format`.
+pub fn tar_to_archive_stream<S>(
+    format: ResourceFormat,
+    input: S,
+) -> Box<dyn Stream<Item = bytes::Bytes, Error = String>>
+where
+    S: Stream<Item = bytes::Bytes, Error = String> + 'static,
+{
+    match format {
+        ResourceFormat::Tar => Box::new(input),
+        format => Box::new(sync_transform(input, move |reader, writer| {
+            archive::from_tar(format, reader, writer).map(|_| ())
+        })),
+    }
+}
+
 fn stream_raw(input_path: PathBuf) -> impl Stream<Item = bytes::Bytes, Error = String> {
     read_async(input_path)
 }
@@ -211,6 +491,7 @@ fn content_length(r: &ClientResponse) -> Result<u64, String> {
 fn response_to_tarred_stream<P>(
     resp: ClientResponse,
     path: P,
+    checker: Option<Box<dyn DynContentChecker>>,
 ) -> impl Stream<Item = bytes::Bytes, Error = String> + 'static
 where
     P: AsRef<Path>,
@@ -230,13 +511,7 @@ where
         Ok(bytes::Bytes::from(header))
     });
 
-    futures::stream::once(header).chain(resp.payload().map_err(|e| e.to_string()))
-}
-
-fn response_to_stream(
-    resp: ClientResponse,
-) -> impl Stream<Item = bytes::Bytes, Error = String> + 'static {
-    resp.payload().map_err(|e| e.to_string())
+    futures::stream::once(header).chain(checked_payload(&resp, checker))
 }
 
 fn inner_download_stream<F, S>(
@@ -244,7 +519,7 @@ fn inner_download_stream<F, S>(
     function: F,
 ) -> impl Stream<Item = bytes::Bytes, Error = String> + 'static
 where
-    F: Fn(ClientResponse) -> S + 'static,
+    F: FnOnce(ClientResponse) -> S + 'static,
     S: Stream<Item = bytes::Bytes, Error = String> + 'static,
 {
     use actix_web::client;
@@ -259,22 +534,55 @@ where
         .flatten_stream()
 }
 
-pub fn download_stream(url: &str) -> impl Stream<Item = bytes::Bytes, Error = String> + 'static {
-    inner_download_stream(url, response_to_stream)
+pub fn download_stream(
+    url: &str,
+    checker: Option<Box<dyn DynContentChecker>>,
+) -> impl Stream<Item = bytes::Bytes, Error = String> + 'static {
+    inner_download_stream(url, move |resp| checked_payload(&resp, checker))
 }
 
 pub fn tarred_download_stream<P>(
     url: &str,
     filename: P,
+    checker: Option<Box<dyn DynContentChecker>>,
 ) -> impl Stream<Item = bytes::Bytes, Error = String> + 'static
 where
-    P: AsRef<Path> + Clone + 'static,
+    P: AsRef<Path> + 'static,
 {
     inner_download_stream(url, move |resp| {
-        response_to_tarred_stream(resp, filename.clone())
+        response_to_tarred_stream(resp, filename, checker)
     })
 }
 
+/// Sets unix permission bits of the file; no-op on other platforms.
+pub fn set_file_mode(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    match mode {
+        #[cfg(unix)]
+        Some(mode) => {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Reads up to `max_bytes` of the file and encodes them as command output.
+pub fn read_file_inline(
+    path: &Path,
+    max_bytes: Option<u64>,
+    encoding: ContentEncoding,
+) -> Result<String, String> {
+    let limit = max_bytes.unwrap_or(DEFAULT_READ_LIMIT);
+    let mut bytes = Vec::new();
+    // one more byte tells if the content is truncated
+    fs::File::open(path)
+        .and_then(|f| f.take(limit.saturating_add(1)).read_to_end(&mut bytes))
+        .map_err(|e| format!("io: {}", e))?;
+    let truncated = bytes.len() as u64 > limit;
+    bytes.truncate(limit as usize);
+    encoding.encode(bytes, truncated)
+}
+
 pub fn untgz<P: AsRef<Path> + ToOwned>(
     input_path: P,
     output_path: P,
@@ -287,3 +595,97 @@ pub fn untgz<P: AsRef<Path> + ToOwned>(
 
     untgz_async(input_path, output_path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_dir;
+
+    const HASH: &str = "SHA1:c04e69c52dc35d93389a23189c333d150cadd719";
+
+    fn chunks(chunks: &[&'static str]) -> impl Stream<Item = bytes::Bytes, Error = String> {
+        futures::stream::iter_ok(
+            chunks
+                .iter()
+                .map(|&chunk| bytes::Bytes::from(chunk))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn hash_checked_stream() {
+        let content =
+            HashChecked::new(chunks(&["ala", "makota\n"]), content_checker(HASH).unwrap())
+                .concat2()
+                .wait()
+                .unwrap();
+        assert_eq!(content.as_ref(), b"alamakota\n");
+
+        let mut stream = HashChecked::new(
+            chunks(&["ala", "makota!\n"]),
+            content_checker(HASH).unwrap(),
+        )
+        .wait();
+        assert_eq!(stream.next().unwrap().unwrap().as_ref(), b"ala");
+        assert!(stream.next().unwrap().is_err());
+
+        assert!(content_checker("c04e69c52dc35d93389a23189c333d150cadd719").is_err());
+    }
+
+    #[test]
+    fn unpack_on_hash_mismatch() {
+        let dir = test_dir("provision/staged", &[("input/frame.png", b"alamakota\n")]);
+        let tar = archive::pack(ResourceFormat::Tar, &dir.join("input"), Vec::new()).unwrap();
+        let tar = || futures::stream::once(Ok(bytes::Bytes::from(tar.clone())));
+
+        let bad_hash = "SHA1:0000000000000000000000000000000000000000";
+        let unpacked = unpack_stream(
+            ResourceFormat::Tar,
+            HashChecked::new(tar(), content_checker(bad_hash).unwrap()),
+            dir.join("output"),
+        )
+        .wait();
+        assert!(unpacked.is_err());
+        assert!(!dir.join("output/frame.png").exists());
+
+        unpack_stream(ResourceFormat::Tar, tar(), dir.join("output"))
+            .wait()
+            .unwrap();
+        assert_eq!(
+            fs::read(dir.join("output/frame.png")).unwrap(),
+            b"alamakota\n"
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn unpack_skips_escaping_entries() {
+        let dir = test_dir("provision/escaping", &[]);
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in &[
+            ("../evil.txt", &b"escaped"[..]),
+            ("frame.png", b"alamakota\n"),
+        ] {
+            // the builder refuses such paths, so the name is set directly
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *content).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+
+        unpack_stream(
+            ResourceFormat::Tar,
+            futures::stream::once(Ok(bytes::Bytes::from(tar))),
+            dir.join("output"),
+        )
+        .wait()
+        .unwrap();
+
+        assert!(dir.join("output/frame.png").exists());
+        assert!(!dir.join("evil.txt").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
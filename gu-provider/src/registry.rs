@@ -0,0 +1,207 @@
+//! Docker registry access: credentials and mirrors.
+//!
+//! Images are pulled through the configured mirrors of their registry first,
+//! then from the registry itself. Credentials, from the provider config or
+//! supplied by the hub, are passed with the pull request only, so docker
+//! never stores them.
+
+use std::collections::HashMap;
+use std::iter;
+use std::sync::Arc;
+
+use actix::prelude::*;
+use ethkey::prelude::*;
+use futures::{future, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use gu_actix::prelude::*;
+use gu_model::dockerman::RegistryAuth;
+use gu_persist::config::{ConfigManager, GetConfig, HasSectionId};
+
+use crate::server;
+
+const DOCKER_HUB: &str = "docker.io";
+/// server address of docker hub credentials
+const DOCKER_HUB_AUTH_KEY: &str = "https://index.docker.io/v1/";
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct RegistryConfig {
+    /// by registry host, e.g. `registry.example.com:5000` or `docker.io`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    credentials: HashMap<String, RegistryAuth>,
+    /// mirror hosts by registry host
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    mirrors: HashMap<String, Vec<String>>,
+}
+
+impl HasSectionId for RegistryConfig {
+    const SECTION_ID: &'static str = "registries";
+}
+
+/// Registry host and repository path of the image reference
+fn split_reference(image: &str) -> (String, String) {
+    match image.find('/') {
+        Some(pos) if image[..pos] == *"index.docker.io" => {
+            (DOCKER_HUB.into(), image[pos + 1..].to_string())
+        }
+        Some(pos)
+            if image[..pos].contains('.')
+                || image[..pos].contains(':')
+                || image[..pos] == *"localhost" =>
+        {
+            (image[..pos].to_string(), image[pos + 1..].to_string())
+        }
+        Some(_) => (DOCKER_HUB.into(), image.to_string()),
+        None => (DOCKER_HUB.into(), format!("library/{}", image)),
+    }
+}
+
+/// Image reference to pull, with credentials of its registry
+pub(crate) struct PullSource {
+    pub image: String,
+    registry: String,
+    auth: Option<RegistryAuth>,
+}
+
+impl PullSource {
+    pub(crate) fn auth(&self) -> Option<&RegistryAuth> {
+        self.auth.as_ref()
+    }
+
+    /// Registry the credentials are for, as docker identifies it
+    pub(crate) fn server_address(&self) -> &str {
+        match self.registry.as_str() {
+            DOCKER_HUB => DOCKER_HUB_AUTH_KEY,
+            registry => registry,
+        }
+    }
+}
+
+fn sources(
+    config: &RegistryConfig,
+    image: &str,
+    hub_auth: Option<RegistryAuth>,
+) -> Vec<PullSource> {
+    let (registry, path) = split_reference(image);
+
+    config
+        .mirrors
+        .get(&registry)
+        .into_iter()
+        .flatten()
+        .map(|mirror| PullSource {
+            image: format!("{}/{}", mirror, path),
+            registry: mirror.clone(),
+            auth: config.credentials.get(mirror).cloned(),
+        })
+        .chain(iter::once(PullSource {
+            image: image.to_string(),
+            // hub credentials are never sent to mirrors
+            auth: hub_auth.or_else(|| config.credentials.get(&registry).cloned()),
+            registry,
+        }))
+        .collect()
+}
+
+fn decrypt_auth(key: &EthAccount, encrypted: &str) -> Result<RegistryAuth, String> {
+    let encrypted =
+        base64::decode(encrypted).map_err(|e| format!("invalid registry auth: {}", e))?;
+    let plain = key
+        .decrypt(&encrypted)
+        .map_err(|e| format!("cannot decrypt registry auth: {}", e))?;
+    serde_json::from_slice(&plain).map_err(|e| format!("invalid registry auth: {}", e))
+}
+
+/// Sources to pull the image from, in order of preference
+pub(crate) fn pull_sources(
+    image: String,
+    registry_auth: Option<String>,
+) -> impl Future<Item = Vec<PullSource>, Error = String> {
+    let config = ConfigManager::from_registry()
+        .send(GetConfig::new())
+        .flatten_fut()
+        .map_err(|e| e.to_string());
+    let hub_auth = match registry_auth {
+        Some(encrypted) => future::Either::A(server::node_key().and_then(move |key| {
+            gu_hdman::download::cpu_pool()
+                .spawn_fn(move || decrypt_auth(&key, &encrypted).map(Some))
+        })),
+        None => future::Either::B(future::ok(None)),
+    };
+
+    config
+        .join(hub_auth)
+        .map(move |(config, hub_auth): (Arc<RegistryConfig>, _)| sources(&config, &image, hub_auth))
+}
+
+/// Hex encoded public key of the node
+pub(crate) fn public_key() -> impl Future<Item = String, Error = String> {
+    server::node_key().map(|key| {
+        key.public()
+            .bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn auth(username: &str) -> RegistryAuth {
+        RegistryAuth {
+            username: username.into(),
+            password: "secret".into(),
+        }
+    }
+
+    #[test]
+    fn test_split_reference() {
+        assert_eq!(
+            split_reference("ubuntu:18.04"),
+            ("docker.io".into(), "library/ubuntu:18.04".into())
+        );
+        assert_eq!(
+            split_reference("golemfactory/blender"),
+            ("docker.io".into(), "golemfactory/blender".into())
+        );
+        assert_eq!(
+            split_reference("registry.example.com:5000/render/blender:2.80"),
+            (
+                "registry.example.com:5000".into(),
+                "render/blender:2.80".into()
+            )
+        );
+        assert_eq!(
+            split_reference("localhost/blender"),
+            ("localhost".into(), "blender".into())
+        );
+    }
+
+    #[test]
+    fn test_sources() {
+        let mut config = RegistryConfig::default();
+        config
+            .mirrors
+            .insert("docker.io".into(), vec!["mirror.local:5000".into()]);
+        config
+            .credentials
+            .insert("registry.example.com".into(), auth("provider"));
+
+        let hub = sources(&config, "ubuntu", None);
+        assert_eq!(hub.len(), 2);
+        assert_eq!(hub[0].image, "mirror.local:5000/library/ubuntu");
+        assert!(hub.iter().all(|source| source.auth().is_none()));
+
+        let private = sources(&config, "registry.example.com/render", None);
+        assert_eq!(private.len(), 1);
+        assert_eq!(private[0].auth.as_ref().unwrap().username, "provider");
+
+        let supplied = sources(&config, "registry.example.com/render", Some(auth("hub")));
+        assert_eq!(supplied[0].auth.as_ref().unwrap().username, "hub");
+        assert_eq!(supplied[0].server_address(), "registry.example.com");
+        assert_eq!(hub[1].server_address(), "https://index.docker.io/v1/");
+    }
+}
@@ -0,0 +1,407 @@
+//! Sandbox for hd deployments of hubs with the sandbox access level.
+//!
+//! On Linux, programs run through bubblewrap in new user, mount, pid, ipc and
+//! network namespaces. Only the workspace (with the unpacked image) is
+//! writable, system directories needed by dynamically linked programs are
+//! mounted read-only, and a seccomp filter denies syscalls that could be used
+//! to leave the sandbox. Other platforms don't run sandboxed programs at all.
+
+#[cfg(target_os = "linux")]
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+
+use futures::{future, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use gu_actix::prelude::*;
+use gu_persist::config::{ConfigManager, GetConfig, HasSectionId};
+
+use crate::envman;
+use crate::permission::{self, AccessLevel};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SandboxConfig {
+    /// bubblewrap executable
+    bwrap: PathBuf,
+    /// host paths mounted read-only, if present
+    ro_binds: Vec<PathBuf>,
+    /// compiled BPF program used instead of the built-in filter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seccomp_filter: Option<PathBuf>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig {
+            bwrap: "bwrap".into(),
+            ro_binds: ["/usr", "/lib", "/lib64", "/bin", "/sbin"]
+                .iter()
+                .map(PathBuf::from)
+                .collect(),
+            seccomp_filter: None,
+        }
+    }
+}
+
+impl HasSectionId for SandboxConfig {
+    const SECTION_ID: &'static str = "sandbox";
+}
+
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    config: Arc<SandboxConfig>,
+    workspace: PathBuf,
+    network: bool,
+}
+
+/// Sandbox for programs of the deployment; `None` if its hub has full access
+pub(crate) fn for_deployment(
+    deployment_id: String,
+    workspace: PathBuf,
+    network: bool,
+) -> impl Future<Item = Option<Sandbox>, Error = String> {
+    envman::deployment_owner(deployment_id)
+        .map_err(|e| e.to_string())
+        .and_then(|owner| match owner {
+            Some(owner) => future::Either::A(permission::access_level(owner)),
+            // unknown owner gets no more than the sandbox
+            None => future::Either::B(future::ok(AccessLevel::Sandbox)),
+        })
+        .and_then(move |access_level| match access_level {
+            AccessLevel::FullAccess => future::Either::A(future::ok(None)),
+            _ => future::Either::B(
+                ConfigManager::from_registry()
+                    .send(GetConfig::new())
+                    .flatten_fut()
+                    .map_err(|e| e.to_string())
+                    .map(move |config: Arc<SandboxConfig>| {
+                        Some(Sandbox {
+                            config,
+                            workspace,
+                            network,
+                        })
+                    }),
+            ),
+        })
+}
+
+/// Command running the executable, in the sandbox if given
+pub(crate) fn command(
+    sandbox: Option<&Sandbox>,
+    executable: &str,
+    args: &[String],
+    cwd: &Path,
+) -> Result<process::Command, String> {
+    match sandbox {
+        Some(sandbox) => sandbox.command(executable, args, cwd),
+        None => {
+            let mut command = process::Command::new(executable);
+            command.current_dir(cwd).args(args);
+            Ok(command)
+        }
+    }
+}
+
+impl Sandbox {
+    /// Namespaces and mounts of the sandbox
+    #[cfg(target_os = "linux")]
+    fn bwrap_args(&self, cwd: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = [
+            "--unshare-user",
+            "--unshare-pid",
+            "--unshare-ipc",
+            "--unshare-uts",
+            "--unshare-cgroup-try",
+            "--die-with-parent",
+            "--new-session",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+
+        if self.network {
+            args.extend(
+                ["--ro-bind-try", "/etc/resolv.conf", "/etc/resolv.conf"]
+                    .iter()
+                    .map(OsString::from),
+            );
+        } else {
+            args.push("--unshare-net".into());
+        }
+        for path in &self.config.ro_binds {
+            args.extend(vec!["--ro-bind-try".into(), path.into(), path.into()]);
+        }
+        args.extend(vec![
+            "--bind".into(),
+            OsString::from(&self.workspace),
+            OsString::from(&self.workspace),
+        ]);
+        args.extend(
+            ["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"]
+                .iter()
+                .map(OsString::from),
+        );
+        args.extend(vec!["--chdir".into(), OsString::from(cwd)]);
+        args
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn command(
+        &self,
+        executable: &str,
+        args: &[String],
+        cwd: &Path,
+    ) -> Result<process::Command, String> {
+        let mut command = process::Command::new(&self.config.bwrap);
+        command.args(self.bwrap_args(cwd));
+
+        let filter = match self.config.seccomp_filter {
+            Some(ref path) => {
+                std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            None => seccomp::deny_filter()?,
+        };
+        seccomp::pass_filter(&mut command, &filter)
+            .map_err(|e| format!("seccomp filter: {}", e))?;
+
+        command.arg("--").arg(executable).args(args);
+        Ok(command)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn command(
+        &self,
+        _executable: &str,
+        _args: &[String],
+        _cwd: &Path,
+    ) -> Result<process::Command, String> {
+        Err("sandbox is not supported on this platform".into())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    fn sandbox(network: bool) -> Sandbox {
+        Sandbox {
+            config: Arc::new(SandboxConfig::default()),
+            workspace: "/tmp/gu/hd/1".into(),
+            network,
+        }
+    }
+
+    #[test]
+    fn test_bwrap_args() {
+        let args = sandbox(false).bwrap_args(Path::new("/tmp/gu/hd/1/out"));
+        let has = |expected: &[&str]| {
+            let expected: Vec<OsString> = expected.iter().map(OsString::from).collect();
+            args.windows(expected.len())
+                .any(|window| window == &expected[..])
+        };
+
+        assert!(has(&["--unshare-user", "--unshare-pid"]));
+        assert!(has(&["--unshare-net"]));
+        assert!(has(&["--bind", "/tmp/gu/hd/1", "/tmp/gu/hd/1"]));
+        assert!(has(&["--ro-bind-try", "/usr", "/usr"]));
+        assert!(has(&["--chdir", "/tmp/gu/hd/1/out"]));
+        assert!(!has(&["--bind", "/", "/"]));
+
+        let args = sandbox(true).bwrap_args(Path::new("/tmp/gu/hd/1"));
+        assert!(!args.contains(&OsString::from("--unshare-net")));
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::os::unix::{io::AsRawFd, io::FromRawFd, process::CommandExt};
+    use std::process;
+
+    /// file descriptor bwrap reads the filter from
+    const FILTER_FD: i32 = 3;
+
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JEQ_K: u16 = 0x15;
+    const BPF_JGE_K: u16 = 0x35;
+    const BPF_JSET_K: u16 = 0x45;
+    const BPF_RET_K: u16 = 0x06;
+    /// longest program the kernel accepts
+    const BPF_MAXINSNS: usize = 4096;
+
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    const EPERM: u32 = 1;
+
+    /// offsets in `struct seccomp_data`
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+    /// lower half of the first argument on little endian architectures
+    const ARG0_OFFSET: u32 = 16;
+
+    /// CLONE_NEWNS, CLONE_NEWCGROUP, CLONE_NEWUTS, CLONE_NEWIPC,
+    /// CLONE_NEWUSER, CLONE_NEWPID and CLONE_NEWNET
+    const CLONE_NEW_FLAGS: u32 = 0x7e02_0000;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    /// ptrace, mount, umount2, swapon, swapoff, reboot, init_module,
+    /// delete_module, finit_module, kexec_load, kexec_file_load, add_key,
+    /// request_key, keyctl, unshare, setns, pivot_root, chroot, acct,
+    /// quotactl, perf_event_open, bpf, userfaultfd, open_by_handle_at,
+    /// process_vm_readv, process_vm_writev, clone3 (its flags can't be
+    /// inspected), io_uring_setup, io_uring_enter, io_uring_register (they
+    /// bypass the filter)
+    #[cfg(target_arch = "x86_64")]
+    const DENIED: &[u32] = &[
+        101, 165, 166, 167, 168, 169, 175, 176, 313, 246, 320, 248, 249, 250, 272, 308, 155, 161,
+        163, 179, 298, 321, 323, 304, 310, 311, 435, 425, 426, 427,
+    ];
+    #[cfg(target_arch = "x86_64")]
+    const CLONE: u32 = 56;
+    /// x32 syscalls have this bit set
+    #[cfg(target_arch = "x86_64")]
+    const DENIED_FROM: Option<u32> = Some(0x4000_0000);
+
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+    #[cfg(target_arch = "aarch64")]
+    const DENIED: &[u32] = &[
+        117, 40, 39, 224, 225, 142, 105, 106, 273, 104, 294, 217, 218, 219, 97, 268, 41, 51, 89,
+        60, 241, 280, 282, 265, 270, 271, 435, 425, 426, 427,
+    ];
+    #[cfg(target_arch = "aarch64")]
+    const CLONE: u32 = 220;
+    #[cfg(target_arch = "aarch64")]
+    const DENIED_FROM: Option<u32> = None;
+
+    fn instruction(program: &mut Vec<u8>, code: u16, jt: u8, jf: u8, k: u32) {
+        program.extend_from_slice(&code.to_ne_bytes());
+        program.push(jt);
+        program.push(jf);
+        program.extend_from_slice(&k.to_ne_bytes());
+    }
+
+    /// Built-in filter: denies syscalls in `DENIED`, and `clone` creating
+    /// namespaces, with `EPERM`
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn deny_filter() -> Result<Vec<u8>, String> {
+        let checks: Vec<(u16, u32)> = DENIED_FROM
+            .into_iter()
+            .map(|nr| (BPF_JGE_K, nr))
+            .chain(DENIED.iter().map(|nr| (BPF_JEQ_K, *nr)))
+            .collect();
+        let mut program = Vec::new();
+
+        instruction(&mut program, BPF_LD_W_ABS, 0, 0, ARCH_OFFSET);
+        instruction(&mut program, BPF_JEQ_K, 1, 0, AUDIT_ARCH);
+        instruction(&mut program, BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS);
+        instruction(&mut program, BPF_LD_W_ABS, 0, 0, NR_OFFSET);
+        for (i, (code, nr)) in checks.iter().enumerate() {
+            // jumps over the remaining checks, the clone check and the allow
+            // instruction
+            instruction(&mut program, *code, (checks.len() - i + 3) as u8, 0, *nr);
+        }
+        instruction(&mut program, BPF_JEQ_K, 0, 2, CLONE);
+        instruction(&mut program, BPF_LD_W_ABS, 0, 0, ARG0_OFFSET);
+        instruction(&mut program, BPF_JSET_K, 1, 0, CLONE_NEW_FLAGS);
+        instruction(&mut program, BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW);
+        instruction(&mut program, BPF_RET_K, 0, 0, SECCOMP_RET_ERRNO | EPERM);
+        Ok(program)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn deny_filter() -> Result<Vec<u8>, String> {
+        Err("no built-in seccomp filter for this architecture".into())
+    }
+
+    /// Passes the filter to bwrap through a pipe; it's written at once, so
+    /// filters that don't fit in the pipe buffer are rejected instead of
+    /// blocking the caller
+    pub fn pass_filter(command: &mut process::Command, filter: &[u8]) -> io::Result<()> {
+        if filter.is_empty() || filter.len() % 8 != 0 || filter.len() > BPF_MAXINSNS * 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid BPF program of {} bytes", filter.len()),
+            ));
+        }
+
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (read, mut write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        // reduced to a page for users over the pipe buffer limit
+        let capacity = unsafe { libc::fcntl(write.as_raw_fd(), libc::F_GETPIPE_SZ) };
+        if capacity < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if filter.len() > capacity as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "filter of {} bytes exceeds the pipe buffer of {}",
+                    filter.len(),
+                    capacity
+                ),
+            ));
+        }
+        write.write_all(filter)?;
+        drop(write);
+
+        command.arg("--seccomp").arg(FILTER_FD.to_string());
+        unsafe {
+            command.pre_exec(move || {
+                let fd = read.as_raw_fd();
+                let result = if fd == FILTER_FD {
+                    libc::fcntl(fd, libc::F_SETFD, 0)
+                } else {
+                    libc::dup2(fd, FILTER_FD)
+                };
+                if result < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod test {
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        #[test]
+        fn test_deny_filter() {
+            let program = super::deny_filter().unwrap();
+            let len = program.len() / 8;
+            let (checks, denied_from) = (super::DENIED.len(), super::DENIED_FROM.iter().count());
+
+            assert_eq!(program.len() % 8, 0);
+            assert_eq!(len, 4 + denied_from + checks + 3 + 2);
+            // the first check jumps to the deny instruction
+            assert_eq!(program[4 * 8 + 2] as usize, checks + denied_from + 3);
+            assert_eq!(
+                4 * 8 + 8 + program[4 * 8 + 2] as usize * 8,
+                program.len() - 8
+            );
+            // clone with namespace flags jumps over the allow instruction too
+            let jset = (len - 3) * 8;
+            assert_eq!(u16::from_ne_bytes([program[jset], program[jset + 1]]), 0x45);
+            assert_eq!(program[jset + 2], 1);
+        }
+
+        #[test]
+        fn test_pass_filter() {
+            let mut command = std::process::Command::new("true");
+
+            assert!(super::pass_filter(&mut command, &[0; 7]).is_err());
+            assert!(super::pass_filter(&mut command, &[0; 8 * 4097]).is_err());
+            assert!(super::pass_filter(&mut command, &[0; 8]).is_ok());
+        }
+    }
+}
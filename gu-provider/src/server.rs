@@ -18,7 +18,8 @@ use gu_base::daemon_lib::{DaemonCommand, DaemonHandler};
 #[cfg(windows)]
 use gu_base::SubCommand;
 use gu_base::{Decorator, Module};
-use gu_lan::MdnsPublisher;
+use gu_hdman::download::{self, RateLimit};
+use gu_lan::{Discovery, DnsSd, MdnsPublisher, RegisteredInstance, Registry, RegistryPublisher};
 use gu_net::{rpc, NodeId};
 use gu_persist::{
     config::{ConfigManager, ConfigModule, GetConfig, HasSectionId},
@@ -32,6 +33,7 @@ use crate::connect::{
 };
 #[cfg(feature = "env-hd")]
 use crate::hdman::HdMan;
+use crate::{availability, metrics};
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -46,6 +48,29 @@ pub(crate) struct ProviderConfig {
     publish_service: bool,
     #[serde(default = "ProviderConfig::default_connect_mode")]
     pub(crate) connect_mode: ConnectMode,
+    /// Unicast DNS-SD used to find hubs in auto connect mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dns_sd: Option<DnsSdConfig>,
+    /// Hubs hosting registries; the provider registers with them and
+    /// finds hubs listed there in auto connect mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    registry_urls: Vec<String>,
+    /// Token configured in the registries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    registry_token: Option<String>,
+    /// TCP address serving `/metrics`, which is on the local socket otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metrics_addr: Option<SocketAddr>,
+    /// Bandwidth limit shared by all image downloads
+    #[serde(default, skip_serializing_if = "RateLimit::is_unlimited")]
+    download_limit: RateLimit,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct DnsSdConfig {
+    server: SocketAddr,
+    domain: String,
 }
 
 impl Default for ProviderConfig {
@@ -56,6 +81,11 @@ impl Default for ProviderConfig {
             hub_addrs: HashSet::new(),
             publish_service: true,
             connect_mode: Self::default_connect_mode(),
+            dns_sd: None,
+            registry_urls: Vec::new(),
+            registry_token: None,
+            metrics_addr: None,
+            download_limit: RateLimit::default(),
         }
     }
 }
@@ -88,6 +118,20 @@ impl ProviderConfig {
     fn default_connect_mode() -> ConnectMode {
         ConnectMode::Manual
     }
+
+    /// Discovery backends used besides mDNS
+    fn discovery(&self) -> Vec<Box<dyn Discovery>> {
+        let mut discovery: Vec<Box<dyn Discovery>> = Vec::new();
+        if let Some(ref dns_sd) = self.dns_sd {
+            discovery.push(Box::new(DnsSd::new(dns_sd.server, dns_sd.domain.clone())));
+        }
+        for url in &self.registry_urls {
+            discovery.push(Box::new(
+                Registry::new(url.clone()).token(self.registry_token.clone()),
+            ));
+        }
+        discovery
+    }
 }
 
 impl HasSectionId for ProviderConfig {
@@ -112,7 +156,7 @@ impl ServerModule {
     }
 }
 
-fn get_node_id(keys: Box<EthAccount>) -> NodeId {
+fn get_node_id(keys: &EthAccount) -> NodeId {
     let node_id = NodeId::from(keys.address().as_ref());
     info!("node_id={:?}", node_id);
     node_id
@@ -188,6 +232,8 @@ impl Module for ServerModule {
 
             #[cfg(feature = "env-hd")]
             let _ = HdMan::start(config_module);
+            metrics::start();
+            availability::start();
 
             ProviderServer::from_registry().do_send(InitServer {
                 decorator,
@@ -207,14 +253,21 @@ impl Module for ServerModule {
 #[derive(Default)]
 pub struct ProviderServer {
     node_id: Option<NodeId>,
+    /// loaded once on init; decrypts deployment secrets
+    keys: Option<Arc<EthAccount>>,
     p2p_port: Option<u16>,
     mdns_publisher: MdnsPublisher,
+    registry_publisher: Option<Addr<RegistryPublisher>>,
     connections: Option<Addr<ConnectManager>>,
+    publish: bool,
+    /// set by the availability policy
+    unavailable: bool,
 }
 
 impl ProviderServer {
     fn publish_service(&mut self, publish: bool) {
-        match publish {
+        self.publish = publish;
+        match publish && !self.unavailable {
             true => self.mdns_publisher.start(),
             false => self.mdns_publisher.stop(),
         }
@@ -245,6 +298,50 @@ impl Handler<PublishMdns> for ProviderServer {
     }
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+struct SetAvailable(bool);
+
+impl Handler<SetAvailable> for ProviderServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetAvailable, _ctx: &mut Context<Self>) -> () {
+        self.unavailable = !msg.0;
+        // otherwise applied on init
+        if self.node_id.is_some() {
+            let publish = self.publish;
+            self.publish_service(publish)
+        }
+    }
+}
+
+/// Advertises the provider only when available
+pub(crate) fn set_available(available: bool) {
+    ProviderServer::from_registry().do_send(SetAvailable(available))
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Arc<EthAccount>, String>")]
+struct GetNodeKey;
+
+impl Handler<GetNodeKey> for ProviderServer {
+    type Result = Result<Arc<EthAccount>, String>;
+
+    fn handle(&mut self, _msg: GetNodeKey, _ctx: &mut Context<Self>) -> Self::Result {
+        self.keys
+            .clone()
+            .ok_or_else(|| "node key not loaded".to_string())
+    }
+}
+
+/// Key the node id is derived from
+pub(crate) fn node_key() -> impl Future<Item = Arc<EthAccount>, Error = String> {
+    ProviderServer::from_registry()
+        .send(GetNodeKey)
+        .map_err(|e| e.to_string())
+        .and_then(|r| r)
+}
+
 #[derive(Message, Clone)]
 #[rtype(result = "Result<(), ()>")]
 struct InitServer<D: Decorator> {
@@ -323,7 +420,19 @@ impl<D: Decorator + 'static> Handler<InitServer<D>> for ProviderServer {
                         let _ = server.bind(config.p2p_addr()).unwrap().start();
                     }
 
-                    act.node_id = Some(get_node_id(keys));
+                    if let Some(addr) = config.metrics_addr {
+                        let metrics_server =
+                            server::new(|| gu_base::metrics::module().decorate_webapp(App::new()));
+                        match metrics_server.bind(addr) {
+                            Ok(metrics_server) => {
+                                let _ = metrics_server.start();
+                            }
+                            Err(e) => error!("Cannot serve metrics at {}: {}", addr, e),
+                        }
+                    }
+
+                    act.node_id = Some(get_node_id(&keys));
+                    act.keys = Some(Arc::from(keys));
                     act.p2p_port = Some(config.p2p_port);
 
                     // Init mDNS publisher
@@ -334,8 +443,32 @@ impl<D: Decorator + 'static> Handler<InitServer<D>> for ProviderServer {
                     );
                     act.publish_service(config.publish_service);
 
+                    download::rate_limiter().set_limit(config.download_limit.clone());
+
+                    if !config.registry_urls.is_empty() {
+                        let node_id = act.node_id.unwrap();
+                        act.registry_publisher = Some(
+                            RegistryPublisher::new(
+                                config.registry_urls.clone(),
+                                "provider",
+                                RegisteredInstance {
+                                    name: node_id.to_string(),
+                                    host_name: hostname::get_hostname()
+                                        .unwrap_or_else(|| "unknown".to_string()),
+                                    ip: None,
+                                    port: config.p2p_port,
+                                    txt: vec![format!("node_id={}", node_id)],
+                                },
+                            )
+                            .token(config.registry_token.clone())
+                            .start(),
+                        );
+                    }
+
+                    let discovery = config.discovery();
                     let connect =
-                        ConnectManager::init(act.node_id.unwrap(), config.hub_addrs).start();
+                        ConnectManager::init(act.node_id.unwrap(), config.hub_addrs, discovery)
+                            .start();
                     connect.do_send(AutoMdns(config.connect_mode == ConnectMode::Auto));
                     act.connections = Some(connect);
 
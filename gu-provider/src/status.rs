@@ -1,13 +1,29 @@
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 use actix::prelude::*;
 use actix_web::{self, App, AsyncResponder, HttpRequest, HttpResponse, Responder};
 use futures::{future, prelude::*};
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 
 use gu_base::Module;
+use gu_model::peers::{self, Availability};
+use gu_net::rpc::{
+    peer::{ListPeers, PeerManager},
+    remoting::peer,
+    ws, PublicMessage, RemotingContext, RemotingSystemService,
+};
 use std::borrow::Cow;
 
+use crate::availability;
+
+pub use gu_model::peers::EnvStatus;
+
+/// How often env statuses are checked for changes to announce
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub fn module() -> impl Module {
     StatusModule
 }
@@ -35,6 +51,7 @@ impl Module for StatusModule {
 #[derive(Serialize)]
 struct StatusBody {
     envs: BTreeMap<String, EnvStatus>,
+    availability: Availability,
 }
 
 fn status_handler<S: 'static>(_r: &HttpRequest<S>) -> impl Responder {
@@ -42,21 +59,29 @@ fn status_handler<S: 'static>(_r: &HttpRequest<S>) -> impl Responder {
         .send(ListEnvStatus)
         .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e)))
         .and_then(|envs| match envs {
-            Ok(envs) => Ok(HttpResponse::Ok().json(StatusBody { envs })),
+            Ok(envs) => Ok(envs),
             Err(e) => Err(actix_web::error::ErrorInternalServerError(format!(
                 "err: {}",
                 e
             ))),
         })
+        .join(
+            availability::current()
+                .map_err(|e| actix_web::error::ErrorInternalServerError(format!("err: {}", e))),
+        )
+        .and_then(|(envs, availability)| {
+            Ok(HttpResponse::Ok().json(StatusBody { envs, availability }))
+        })
         .responder()
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
-pub enum EnvStatus {
-    Ready,
-    Working,
-    Paused,
-    Disabled,
+/// Status as restricted by the availability policy
+fn under(status: EnvStatus, availability: &Availability) -> EnvStatus {
+    match status {
+        EnvStatus::Working if availability.paused => EnvStatus::Paused,
+        EnvStatus::Ready if !availability.available => EnvStatus::Disabled,
+        status => status,
+    }
 }
 
 pub struct GetEnvStatus;
@@ -89,10 +114,107 @@ impl AddProvider {
 #[derive(Default)]
 pub struct StatusManager {
     providers: BTreeMap<Cow<'static, str>, Recipient<GetEnvStatus>>,
+    /// statuses last sent to hubs
+    announced: Option<BTreeMap<String, EnvStatus>>,
+}
+
+impl StatusManager {
+    /// Statuses of all environments restricted by the availability policy
+    fn env_status(&self) -> impl Future<Item = BTreeMap<String, EnvStatus>, Error = String> {
+        future::join_all(
+            self.providers
+                .clone()
+                .into_iter()
+                .map(move |(env_name, env_addr)| {
+                    let name = env_name.to_string();
+                    env_addr.send(GetEnvStatus).and_then(move |s| Ok((name, s)))
+                }),
+        )
+        .map_err(|e| format!("{}", e))
+        .join(availability::current())
+        .and_then(|(envs, availability)| {
+            Ok(envs
+                .into_iter()
+                .map(|(name, status)| (name, under(status, &availability)))
+                .collect())
+        })
+    }
+
+    /// Updates env statuses sent on handshake and pushes changes to
+    /// connected hubs
+    fn announce(&mut self, ctx: &mut <Self as Actor>::Context) {
+        ctx.spawn(
+            self.env_status()
+                .map_err(|e| warn!("env status: {}", e))
+                .into_actor(self)
+                .map(|envs, act, ctx| {
+                    if act.announced.as_ref() == Some(&envs) {
+                        return;
+                    }
+                    ws::set_exec_envs(
+                        envs.iter()
+                            .map(|(name, status)| format!("{}:{}", name, status.code()))
+                            .collect(),
+                    );
+                    ctx.spawn(notify_hubs(envs.clone()).into_actor(act));
+                    act.announced = Some(envs);
+                }),
+        );
+    }
+}
+
+fn notify_hubs(exec_envs: BTreeMap<String, EnvStatus>) -> impl Future<Item = (), Error = ()> {
+    PeerManager::from_registry()
+        .send(ListPeers)
+        .map_err(|e| warn!("listing hubs: {}", e))
+        .and_then(move |hubs| {
+            future::join_all(hubs.into_iter().map(move |hub| {
+                let node_id = hub.node_id;
+                peer(node_id)
+                    .into_endpoint()
+                    .timeout(NOTIFY_TIMEOUT)
+                    .send(peers::EnvStatusChanged {
+                        exec_envs: exec_envs.clone(),
+                    })
+                    // older hubs don't know the message
+                    .then(move |r| {
+                        if let Err(e) = r {
+                            debug!("notifying hub {:?}: {}", node_id, e);
+                        }
+                        Ok::<_, ()>(())
+                    })
+            }))
+            .map(|_| ())
+        })
+}
+
+struct Announce;
+
+impl Message for Announce {
+    type Result = ();
+}
+
+impl Handler<Announce> for StatusManager {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Announce, ctx: &mut Self::Context) {
+        self.announce(ctx);
+    }
+}
+
+/// Announces statuses now instead of at the next interval, e.g. after
+/// the availability has changed
+pub fn announce() {
+    StatusManager::from_registry().do_send(Announce)
 }
 
 impl Actor for StatusManager {
-    type Context = Context<Self>;
+    type Context = RemotingContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.bind::<peers::GetEnvStatus>(peers::GetEnvStatus::ID);
+        ctx.run_interval(ANNOUNCE_INTERVAL, |act, ctx| act.announce(ctx));
+    }
 }
 
 impl Handler<AddProvider> for StatusManager {
@@ -101,9 +223,10 @@ impl Handler<AddProvider> for StatusManager {
     fn handle(
         &mut self,
         msg: AddProvider,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) -> <Self as Handler<AddProvider>>::Result {
         self.providers.insert(msg.0, msg.1);
+        self.announce(ctx);
     }
 }
 
@@ -111,23 +234,16 @@ impl Handler<ListEnvStatus> for StatusManager {
     type Result = ActorResponse<StatusManager, BTreeMap<String, EnvStatus>, String>;
 
     fn handle(&mut self, _msg: ListEnvStatus, _ctx: &mut Self::Context) -> Self::Result {
-        ActorResponse::r#async(
-            future::join_all(self.providers.clone().into_iter().map(
-                move |(env_name, env_addr)| {
-                    let name = env_name.to_string();
-                    env_addr.send(GetEnvStatus).and_then(move |s| Ok((name, s)))
-                },
-            ))
-            .and_then(
-                |envs| -> Result<BTreeMap<String, EnvStatus>, actix::MailboxError> {
-                    Ok(envs.into_iter().collect())
-                },
-            )
-            .map_err(|e| format!("{}", e))
-            .into_actor(self),
-        )
+        ActorResponse::r#async(self.env_status().into_actor(self))
+    }
+}
+
+impl Handler<peers::GetEnvStatus> for StatusManager {
+    type Result = ActorResponse<StatusManager, BTreeMap<String, EnvStatus>, String>;
+
+    fn handle(&mut self, _msg: peers::GetEnvStatus, _ctx: &mut Self::Context) -> Self::Result {
+        ActorResponse::r#async(self.env_status().into_actor(self))
     }
 }
 
-impl Supervised for StatusManager {}
-impl SystemService for StatusManager {}
+impl RemotingSystemService for StatusManager {}
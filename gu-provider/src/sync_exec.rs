@@ -6,6 +6,8 @@ use log::debug;
 use error::*;
 use gu_actix::*;
 
+use crate::sandbox::{self, Sandbox};
+
 pub mod error {
     use std::{io, process};
 
@@ -65,6 +67,8 @@ pub enum Exec {
         executable: String,
         args: Vec<String>,
         cwd: PathBuf,
+        /// runs the executable in the sandbox if set
+        sandbox: Option<Sandbox>,
     },
     Kill(process::Child),
 }
@@ -104,13 +108,11 @@ impl Handler<Exec> for SyncExec {
                 executable,
                 args,
                 cwd,
+                sandbox,
             } => {
                 // TODO: critical section
                 // TODO: env::set_current_dir(&base_dir)?;
-                let output = process::Command::new(&executable)
-                    .current_dir(&cwd)
-                    .args(&args)
-                    .output();
+                let output = sandbox::command(sandbox.as_ref(), &executable, &args, &cwd)?.output();
                 match output {
                     Ok(output) => {
                         if output.status.success() {
@@ -159,7 +161,8 @@ mod test {
                     .send(Exec::Run {
                         executable: "/bin/ls".into(),
                         args: vec!["/1234567890asdfghjkl".into()],
-                        cwd: "/".into()
+                        cwd: "/".into(),
+                        sandbox: None,
                     }).flatten_fut()
                     .and_then(|o: ExecResult| match o {
                         ExecResult::Run(o) => {
@@ -185,6 +188,7 @@ mod test {
                         executable: "/bin/echo".into(),
                         args: vec!["zima".into()],
                         cwd: "/".into(),
+                        sandbox: None,
                     })
                     .flatten_fut()
                     .and_then(|o: ExecResult| match o {
@@ -213,6 +217,7 @@ mod test {
                         executable: "/bin/pwd".into(),
                         args: vec![],
                         cwd: "/var/tmp".into(),
+                        sandbox: None,
                     })
                     .flatten_fut()
                     .and_then(|o: ExecResult| match o {
@@ -0,0 +1,18 @@
+//! Helpers shared by unit tests.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Fresh directory of the test under the system temp dir, with the files
+/// given by their relative paths
+pub fn test_dir(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let dir = std::env::temp_dir().join("gu-unlimited/tests").join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
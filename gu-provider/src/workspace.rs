@@ -44,6 +44,22 @@ impl WorkspacesManager {
             self.path.join(Uuid::new_v4().to_string()),
         )
     }
+
+    /// Checks that new workspaces can be created; permission bits don't
+    /// tell about read-only mounts, full disks or ACLs, so it creates a file
+    pub fn check(&self) -> Result<(), String> {
+        let probe = self.path.join(format!(".check-{}", Uuid::new_v4()));
+
+        fs::create_dir_all(&self.path)
+            .and_then(|_| {
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&probe)
+            })
+            .and_then(|_| fs::remove_file(&probe))
+            .map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
 }
 
 type Set<K> = BTreeSet<K>;
@@ -139,8 +155,10 @@ impl Workspace {
 
 #[cfg(test)]
 mod tests {
-    use crate::workspace::Workspace;
+    use crate::testing::test_dir;
+    use crate::workspace::{Workspace, WorkspacesManager};
     use gu_model::dockerman::VolumeDef;
+    use std::fs;
     use std::path::PathBuf;
 
     #[test]
@@ -176,4 +194,16 @@ mod tests {
         work.remove_tags(["tag1".to_string()].to_vec());
         assert_eq!(work.tags(), ["tag2".to_string()].to_vec());
     }
+
+    #[test]
+    fn check() {
+        let man = WorkspacesManager {
+            namespace: "check".into(),
+            path: test_dir("workspace/check", &[]),
+        };
+
+        assert_eq!(man.check(), Ok(()));
+        // the probe file is removed
+        assert_eq!(fs::read_dir(&man.path).unwrap().count(), 0);
+    }
 }
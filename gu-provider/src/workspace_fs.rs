@@ -0,0 +1,214 @@
+//! Remote access to files inside deployment workspaces.
+//!
+//! All paths are resolved relative to the workspace root and are rejected
+//! when they (or a symlink they go through) point outside of it.
+
+use std::fs::{self, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use gu_model::chrono::{DateTime, Utc};
+use gu_model::envman::{Error, FileContent, FileInfo, FsOp, FsResult};
+
+/// Upper limit for a single content read.
+pub const MAX_READ_SIZE: u64 = 1024 * 1024;
+
+fn io_error(path: &str, e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::NotFound => Error::NoSuchFile(path.into()),
+        io::ErrorKind::PermissionDenied => Error::AccessDenied(path.into()),
+        _ => Error::IoError(format!("{}: {}", path, e)),
+    }
+}
+
+/// Resolves `path` inside the `root` directory.
+pub fn resolve(root: &Path, path: &str) -> Result<PathBuf, Error> {
+    let mut rel_path = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => rel_path.push(name),
+            Component::CurDir | Component::RootDir => (),
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(Error::AccessDenied(path.into()))
+            }
+        }
+    }
+
+    let root = root.canonicalize().map_err(|e| io_error(path, e))?;
+    let full_path = root
+        .join(rel_path)
+        .canonicalize()
+        .map_err(|e| io_error(path, e))?;
+
+    if full_path.starts_with(&root) {
+        Ok(full_path)
+    } else {
+        Err(Error::AccessDenied(path.into()))
+    }
+}
+
+fn file_info(root: &Path, path: &Path, meta: &Metadata) -> FileInfo {
+    FileInfo {
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into())
+            .unwrap_or_default(),
+        path: path
+            .strip_prefix(root)
+            .map(|rel_path| rel_path.to_string_lossy().into())
+            .unwrap_or_default(),
+        is_dir: meta.is_dir(),
+        size: if meta.is_dir() { 0 } else { meta.len() },
+        modified: meta.modified().ok().map(DateTime::<Utc>::from),
+    }
+}
+
+fn list_dir(root: &Path, dir: &Path, path: &str) -> Result<Vec<FileInfo>, Error> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| io_error(path, e))? {
+        let entry = entry.map_err(|e| io_error(path, e))?;
+        let meta = entry.metadata().map_err(|e| io_error(path, e))?;
+        entries.push(file_info(root, &entry.path(), &meta));
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn read_range(
+    file_path: &Path,
+    path: &str,
+    offset: u64,
+    length: Option<u64>,
+) -> Result<FileContent, Error> {
+    let mut file = fs::File::open(file_path).map_err(|e| io_error(path, e))?;
+    let total_size = file.metadata().map_err(|e| io_error(path, e))?.len();
+    if offset > total_size {
+        return Err(Error::IncorrectOptions(format!(
+            "offset {} beyond end of file ({} bytes)",
+            offset, total_size
+        )));
+    }
+    let length = length
+        .unwrap_or(MAX_READ_SIZE)
+        .min(MAX_READ_SIZE)
+        .min(total_size - offset);
+
+    let mut data = Vec::with_capacity(length as usize);
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.take(length).read_to_end(&mut data))
+        .map_err(|e| io_error(path, e))?;
+
+    Ok(FileContent {
+        offset,
+        total_size,
+        data,
+    })
+}
+
+/// Executes `op` on `path` inside the workspace `root`. Blocking.
+pub fn run(root: &Path, path: &str, op: FsOp) -> Result<FsResult, Error> {
+    let root = root.canonicalize().map_err(|e| io_error(path, e))?;
+    let full_path = resolve(&root, path)?;
+    let meta = fs::metadata(&full_path).map_err(|e| io_error(path, e))?;
+
+    match op {
+        FsOp::Stat => Ok(FsResult::Stat(file_info(&root, &full_path, &meta))),
+        FsOp::Get { .. } if meta.is_dir() => {
+            Ok(FsResult::Listing(list_dir(&root, &full_path, path)?))
+        }
+        FsOp::Get { offset, length } => Ok(FsResult::Content(read_range(
+            &full_path, path, offset, length,
+        )?)),
+        FsOp::Delete if full_path == root => Err(Error::AccessDenied(path.into())),
+        FsOp::Delete => {
+            if meta.is_dir() {
+                fs::remove_dir_all(&full_path)
+            } else {
+                fs::remove_file(&full_path)
+            }
+            .map_err(|e| io_error(path, e))?;
+            Ok(FsResult::Deleted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_dir;
+
+    const FILES: &[(&str, &[u8])] = &[("out/result.txt", b"0123456789")];
+
+    #[test]
+    fn escaping_paths_are_denied() {
+        let root = test_dir("workspace-fs/escape", FILES);
+
+        assert!(resolve(&root, "out/result.txt").is_ok());
+        assert!(resolve(&root, "/out/./result.txt").is_ok());
+        match resolve(&root, "../escape/out/result.txt") {
+            Err(Error::AccessDenied(_)) => (),
+            r => panic!("access denied expected, got {:?}", r),
+        }
+        match resolve(&root, "out/missing.txt") {
+            Err(Error::NoSuchFile(_)) => (),
+            r => panic!("file not found expected, got {:?}", r),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_outside_are_denied() {
+        let root = test_dir("workspace-fs/symlink", FILES);
+        std::os::unix::fs::symlink("/etc", root.join("etc")).unwrap();
+
+        match run(&root, "etc/passwd", FsOp::Stat) {
+            Err(Error::AccessDenied(_)) => (),
+            r => panic!("access denied expected, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn ranged_read_and_listing() {
+        let root = test_dir("workspace-fs/read", FILES);
+
+        match run(
+            &root,
+            "out/result.txt",
+            FsOp::Get {
+                offset: 2,
+                length: Some(3),
+            },
+        ) {
+            Ok(FsResult::Content(content)) => {
+                assert_eq!(content.total_size, 10);
+                assert_eq!(content.data, b"234".to_vec());
+            }
+            r => panic!("content expected, got {:?}", r),
+        }
+
+        match run(
+            &root,
+            "out",
+            FsOp::Get {
+                offset: 0,
+                length: None,
+            },
+        ) {
+            Ok(FsResult::Listing(entries)) => {
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].name, "result.txt");
+                assert_eq!(entries[0].size, 10);
+            }
+            r => panic!("listing expected, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn delete() {
+        let root = test_dir("workspace-fs/delete", FILES);
+
+        assert!(run(&root, "", FsOp::Delete).is_err());
+        run(&root, "out", FsOp::Delete).unwrap();
+        assert!(!root.join("out").exists());
+    }
+}